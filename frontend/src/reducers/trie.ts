@@ -234,7 +234,7 @@ function handleSetStorage(
   );
   const newStorage = MapActions.set(state.storage, changes);
   const newNodes = Immutable.Map<string, TrieNode>().withMutations((nodes) => {
-    trieWalk(nodes, wasm.values());
+    trieWalk(nodes, wasm.values({ hex: true }));
   });
   store.clear();
   return StateFactory({
@@ -261,7 +261,7 @@ function handleDeleteStorage(
   const newEntries = MapActions.remove<PortableEntry>(state.values, key.hex);
   const newStorage = MapActions.set(state.storage, changes);
   const newNodes = Immutable.Map<string, TrieNode>().withMutations((nodes) => {
-    trieWalk(nodes, wasm.values());
+    trieWalk(nodes, wasm.values({ hex: true }));
   });
   store.clear();
   return StateFactory({
@@ -300,7 +300,7 @@ function selectedReducer(
 
 function trieWalk(
   nodes: Immutable.Map<string, TrieNode>,
-  next: JSMerklePatriciaTrie,
+  next: JSMerklePatriciaTrie<string>,
 ) {
   const encoded = next.raw_bytes!;
   const hash = next.id ?? encoded;
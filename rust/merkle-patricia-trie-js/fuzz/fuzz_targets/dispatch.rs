@@ -0,0 +1,7 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| trie::fuzz::dispatch(data));
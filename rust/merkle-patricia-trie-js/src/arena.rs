@@ -0,0 +1,151 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Heap kept alive across calls.
+//!
+//! By default every call starts from an empty heap. Once the host opts into a persistent
+//! arena, the allocations of a call outlive it and the node cache of the main trie is handed
+//! to the next call of the same namespace, until the host wipes the arena with `__ext_reset`.
+//! The cursors, see [`crate::cursor`], are kept in the arena as well. The scratch pools and
+//! the cache stop growing once warm, but the bump allocator only reuses its most recent
+//! allocation, so its heap grows with every call until `__ext_reset`. The free-list allocator
+//! reuses the freed blocks instead.
+use crate::{cache::NodeCache, host::HostFnImpl};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+struct Arena {
+    persistent: Cell<bool>,
+    /// Node cache left by the last call and the namespace it was read from.
+    cache: RefCell<Option<(Vec<u8>, NodeCache)>>,
+}
+
+impl Arena {
+    const fn new() -> Self {
+        Self { persistent: Cell::new(false), cache: RefCell::new(None) }
+    }
+}
+
+// SAFETY: the module is single threaded.
+#[cfg(target_arch = "wasm32")]
+unsafe impl Sync for Arena {}
+
+#[cfg(target_arch = "wasm32")]
+static ARENA: Arena = Arena::new();
+
+// The native tests run on several threads, each keeps its own arena.
+#[cfg(not(target_arch = "wasm32"))]
+std::thread_local! {
+    static ARENA: Arena = const { Arena::new() };
+}
+
+fn with_arena<R>(f: impl FnOnce(&Arena) -> R) -> R {
+    #[cfg(target_arch = "wasm32")]
+    return f(&ARENA);
+    #[cfg(not(target_arch = "wasm32"))]
+    ARENA.with(f)
+}
+
+pub fn is_persistent() -> bool {
+    with_arena(|arena| arena.persistent.get())
+}
+
+/// Prepares the heap of a new call, the heap of the previous call is kept when the arena is
+/// persistent. Any export can follow any other: the statics referencing the heap are cleared
+/// first, and the listings free their node buffers before they return.
+pub fn begin() {
+    crate::journal::begin();
+    crate::error::take_deferred();
+    crate::error::clear();
+    crate::scratch::begin();
+    HostFnImpl::set_namespace(&[]);
+    #[cfg(not(feature = "dlmalloc"))]
+    if is_persistent() {
+        crate::Allocator::init();
+    } else {
+        crate::Allocator::reset();
+    }
+}
+
+/// Keeps or drops the heap of the following calls, disabling it wipes the arena.
+pub fn set_persistent(persistent: bool) {
+    if !persistent {
+        wipe();
+    }
+    with_arena(|arena| arena.persistent.set(persistent));
+}
+
+/// Drops the retained cache and empties the heap.
+pub fn wipe() {
+    crate::debug_log!(Info, Memory, "wipe: heap emptied");
+    crate::cursor::close_all();
+    crate::scratch::wipe();
+    crate::journal::wipe();
+    let _cache = with_arena(|arena| arena.cache.take());
+    // The heap is reset below, there is nothing to free.
+    #[cfg(not(feature = "dlmalloc"))]
+    {
+        core::mem::forget(_cache);
+        crate::Allocator::wipe();
+    }
+}
+
+/// Drops the retained cache, which may hold nodes written by a failed call.
+pub fn discard_cache() {
+    with_arena(|arena| arena.cache.take());
+}
+
+/// Keeps the node cache of `namespace` for the next call, when the arena is persistent.
+pub fn retain_cache(namespace: &[u8], cache: NodeCache) {
+    if is_persistent() {
+        with_arena(|arena| arena.cache.replace(Some((namespace.to_vec(), cache))));
+    }
+}
+
+/// Takes the node cache retained for `namespace`, if its capacity is still `capacity`.
+pub fn take_cache(namespace: &[u8], capacity: usize) -> Option<NodeCache> {
+    let (retained, cache) = with_arena(|arena| arena.cache.take())?;
+    (retained == namespace && cache.capacity() == capacity).then_some(cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        native::{call, clear_storage, DEFAULT_HANDLE},
+        storage::write_bytes,
+    };
+
+    /// Bytes of the buffers pooled across calls and nodes of the retained cache.
+    fn retained() -> (usize, usize) {
+        let nodes =
+            with_arena(|arena| arena.cache.borrow().as_ref().map_or(0, |(_, cache)| cache.len()));
+        (crate::scratch::retained(), nodes)
+    }
+
+    #[test]
+    fn persistent_calls_keep_the_pools_and_cache_bounded() {
+        clear_storage();
+        set_persistent(true);
+        let mut usage = Vec::new();
+        for index in 0..200u32 {
+            let mut input = Vec::new();
+            write_bytes(&mut input, &index.to_be_bytes());
+            write_bytes(&mut input, &[7; 64]);
+            call(DEFAULT_HANDLE, 0, &input).unwrap();
+            call(DEFAULT_HANDLE, 3, &index.to_be_bytes()).unwrap();
+            usage.push(retained());
+        }
+        // The pools fill up during the first calls then stop growing, the cache holds the nodes
+        // read by the last call, within its default capacity of 64 nodes.
+        let bytes = usage[usage.len() - 1].0;
+        assert!(usage[100..].iter().all(|(pooled, nodes)| *pooled == bytes && *nodes <= 64));
+        assert!(usage.iter().all(|(pooled, nodes)| *pooled <= bytes && *nodes > 0));
+
+        // `__ext_reset` drops everything the arena kept.
+        crate::reset();
+        assert_eq!(retained(), (0, 0));
+        set_persistent(false);
+    }
+}
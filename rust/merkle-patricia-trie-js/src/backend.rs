@@ -0,0 +1,263 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Key-value storages behind [`ExternalDB`](crate::storage::ExternalDB).
+//!
+//! The wasm module stores everything in the JS host, [`MemoryBackend`] keeps the entries in
+//! memory instead, so the storage logic runs on native targets and in unit tests.
+use crate::{
+    abort,
+    error::{self, CallError},
+    host::{HostError, HostFnImpl},
+};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::cell::RefCell;
+
+/// Storage of the trie nodes and the reserved keys. Failures of the underlying storage abort
+/// the call, except for values the storage can't hold which fail it once it returns.
+pub trait TrieBackend {
+    /// Reads the value stored under `key`, whatever its size.
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Same as [`TrieBackend::get`], reading the value into `value` so its buffer is reused,
+    /// see [`crate::scratch`]. Returns whether the value is stored.
+    fn get_into(&self, key: &[u8], value: &mut Vec<u8>) -> bool {
+        value.clear();
+        match self.get(key) {
+            Some(stored) => {
+                value.extend_from_slice(&stored);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Reads several values at once, the host serves them in a single call.
+    fn get_batch(&self, keys: &[&[u8]]) -> Vec<Option<Vec<u8>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Whether a value is stored under `key`, without reading it.
+    fn contains(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Byte length of the value stored under `key`, without reading it.
+    fn value_size(&self, key: &[u8]) -> Option<u32> {
+        self.get(key).map(|value| value.len() as u32)
+    }
+
+    /// Reads the value stored under `key` and clears it, recording it for the rollback.
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let value = self.get(key)?;
+        self.record(key, Some(value.clone()));
+        self.clear(key);
+        Some(value)
+    }
+
+    fn set(&self, key: &[u8], value: &[u8]);
+
+    fn clear(&self, key: &[u8]);
+
+    /// Records the value `key` held before a write, restored when the call fails, see
+    /// [`crate::journal`]. The storages without rollback ignore it.
+    fn record(&self, _key: &[u8], _previous: Option<Vec<u8>>) {}
+
+    /// Writes `value` under `key`, or clears it, recording the value it replaces. The value is
+    /// read first, callers knowing it record it themselves before [`TrieBackend::set`].
+    fn write(&self, key: &[u8], value: Option<&[u8]>) {
+        self.record(key, self.get(key));
+        match value {
+            Some(value) => self.set(key, value),
+            None => self.clear(key),
+        }
+    }
+}
+
+/// Shares a storage, the child tries use the storage of their parent.
+impl<B: TrieBackend> TrieBackend for &B {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        (**self).get(key)
+    }
+
+    fn get_into(&self, key: &[u8], value: &mut Vec<u8>) -> bool {
+        (**self).get_into(key, value)
+    }
+
+    fn get_batch(&self, keys: &[&[u8]]) -> Vec<Option<Vec<u8>>> {
+        (**self).get_batch(keys)
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        (**self).contains(key)
+    }
+
+    fn value_size(&self, key: &[u8]) -> Option<u32> {
+        (**self).value_size(key)
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        (**self).take(key)
+    }
+
+    fn set(&self, key: &[u8], value: &[u8]) {
+        (**self).set(key, value)
+    }
+
+    fn clear(&self, key: &[u8]) {
+        (**self).clear(key)
+    }
+
+    fn record(&self, key: &[u8], previous: Option<Vec<u8>>) {
+        (**self).record(key, previous)
+    }
+}
+
+/// Storage of the JS host, every key is prefixed with the namespace of the current call.
+pub struct HostBackend;
+
+impl TrieBackend for HostBackend {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match HostFnImpl::get_storage_vec(key) {
+            Ok(value) => Some(value),
+            Err(HostError::KeyNotFound) => None,
+            Err(error) => abort!("get: get_storage failed: {error}"),
+        }
+    }
+
+    fn get_into(&self, key: &[u8], value: &mut Vec<u8>) -> bool {
+        match HostFnImpl::get_storage_into(key, value) {
+            Ok(()) => true,
+            Err(HostError::KeyNotFound) => false,
+            Err(error) => abort!("get: get_storage failed: {error}"),
+        }
+    }
+
+    fn get_batch(&self, keys: &[&[u8]]) -> Vec<Option<Vec<u8>>> {
+        if HostFnImpl::version() < 2 {
+            return keys.iter().map(|key| self.get(key)).collect();
+        }
+        match HostFnImpl::get_storage_batch(keys) {
+            Ok(values) => values,
+            Err(error) => abort!("get_batch: get_storage_batch failed: {error}"),
+        }
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        if HostFnImpl::version() < 2 {
+            return self.get(key).is_some();
+        }
+        match HostFnImpl::contains_storage(key) {
+            Ok(contains) => contains,
+            Err(error) => abort!("contains: contains_storage failed: {error}"),
+        }
+    }
+
+    fn value_size(&self, key: &[u8]) -> Option<u32> {
+        if HostFnImpl::version() < 3 {
+            return self.get(key).map(|value| value.len() as u32);
+        }
+        match HostFnImpl::value_size(key) {
+            Ok(size) => Some(size),
+            Err(HostError::KeyNotFound) => None,
+            Err(error) => abort!("value_size: value_size failed: {error}"),
+        }
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if HostFnImpl::version() < 4 {
+            let value = self.get(key)?;
+            self.record(key, Some(value.clone()));
+            self.clear(key);
+            return Some(value);
+        }
+        // The host function records the value taken.
+        match HostFnImpl::take_storage(key) {
+            Ok(value) => Some(value),
+            Err(HostError::KeyNotFound) => None,
+            Err(error) => abort!("take: take_storage failed: {error}"),
+        }
+    }
+
+    fn set(&self, key: &[u8], value: &[u8]) {
+        crate::debug_log!(
+            Trace,
+            Storage,
+            "set: {} bytes under {} byte key",
+            value.len(),
+            key.len()
+        );
+        match HostFnImpl::set_storage(key, value) {
+            Ok(()) => {},
+            Err(HostError::StorageFull) => error::defer(CallError::ValueTooLarge),
+            Err(error) => abort!("set: set_storage failed: {error}"),
+        }
+    }
+
+    fn clear(&self, key: &[u8]) {
+        crate::debug_log!(Trace, Storage, "clear: {} byte key", key.len());
+        if let Err(error) = HostFnImpl::clear_storage(key) {
+            abort!("clear: clear_storage failed: {error}");
+        }
+    }
+
+    fn record(&self, key: &[u8], previous: Option<Vec<u8>>) {
+        HostFnImpl::journal_write(key, previous);
+    }
+}
+
+/// Storage kept in memory.
+#[derive(Default)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct MemoryBackend {
+    entries: RefCell<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(test)]
+    pub fn entries(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        self.entries.borrow().clone()
+    }
+}
+
+impl TrieBackend for MemoryBackend {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.borrow().get(key).cloned()
+    }
+
+    fn get_into(&self, key: &[u8], value: &mut Vec<u8>) -> bool {
+        value.clear();
+        let entries = self.entries.borrow();
+        let Some(stored) = entries.get(key) else {
+            return false;
+        };
+        value.extend_from_slice(stored);
+        true
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.entries.borrow().contains_key(key)
+    }
+
+    fn value_size(&self, key: &[u8]) -> Option<u32> {
+        self.entries.borrow().get(key).map(|value| value.len() as u32)
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.borrow_mut().remove(key)
+    }
+
+    fn set(&self, key: &[u8], value: &[u8]) {
+        self.entries.borrow_mut().insert(key.to_vec(), value.to_vec());
+    }
+
+    fn clear(&self, key: &[u8]) {
+        self.entries.borrow_mut().remove(key);
+    }
+}
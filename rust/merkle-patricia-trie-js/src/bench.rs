@@ -0,0 +1,140 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Workload timed inside the module.
+//!
+//! Inserts generated keys and values in the current trie, reads them back and removes some of
+//! them, each operation committed on its own like the calls of the host. Writes are kept in an
+//! overlay, as the dry runs do, so the storage isn't changed, while the nodes of the current
+//! trie are read from the host.
+use crate::{
+    error::{CallError, CallResult},
+    fail,
+    hash::xxhash64,
+    host::HostFnImpl,
+    metrics::Snapshot,
+    primitives::H256,
+    storage::{trie_key, ExternalDB, OverlayDB, TrieLayout},
+    trie::call_error,
+};
+use alloc::vec::Vec;
+use trie_db::{TrieDBMutBuilder, TrieMut};
+
+/// Seeds of the generated keys and values.
+const KEY_SEED: u64 = 0;
+const VALUE_SEED: u64 = 1;
+
+/// Scripted workload, decoded from the `u32` fields in declaration order.
+struct Workload {
+    inserts: u32,
+    key_size: u32,
+    value_size: u32,
+    reads: u32,
+    removals: u32,
+}
+
+impl Workload {
+    fn decode(input: &[u8]) -> Option<Self> {
+        let (fields, _) = input.as_chunks::<4>();
+        let [inserts, key_size, value_size, reads, removals] = fields.get(..5)? else {
+            return None;
+        };
+        let [inserts, key_size, value_size, reads, removals] =
+            [inserts, key_size, value_size, reads, removals]
+                .map(|field| u32::from_le_bytes(*field));
+        Some(Self { inserts, key_size, value_size, reads, removals })
+    }
+}
+
+/// `size` bytes derived from `index`, distinct indices give distinct bytes from 8 bytes on.
+fn generate(seed: u64, index: u32, size: u32) -> Vec<u8> {
+    let hash = xxhash64(seed, &index.to_le_bytes()).to_le_bytes();
+    (0..size as usize).map(|offset| hash[offset % 8] ^ (offset / 8) as u8).collect()
+}
+
+/// Times `count` operations, `run` gets the index of each one.
+fn phase(
+    output: &mut Vec<u8>,
+    count: u32,
+    mut run: impl FnMut(u32) -> CallResult<()>,
+) -> CallResult<()> {
+    let Some(start) = HostFnImpl::now() else {
+        return fail!(CallError::InvalidCall, "the host has no clock");
+    };
+    for index in 0..count {
+        run(index)?;
+    }
+    let elapsed = HostFnImpl::now().unwrap_or(start) - start;
+    output.extend_from_slice(&count.to_le_bytes());
+    output.extend_from_slice(&elapsed.to_le_bytes());
+    Ok(())
+}
+
+/// Runs the workload encoded in `input` on the trie at `root`. The output holds the number of
+/// operations and the elapsed milliseconds as a `f64` of the inserts, reads and removals, the
+/// peak heap usage as a `u32`, and the host calls of the workload, see [`crate::metrics`].
+pub fn run<L: TrieLayout>(db: &ExternalDB, root: &H256, input: &[u8]) -> CallResult<Vec<u8>> {
+    let Some(workload) = Workload::decode(input) else {
+        return fail!(CallError::InvalidInput, "invalid workload");
+    };
+    let keys: Vec<_> = (0..workload.inserts)
+        .map(|index| trie_key::<L>(&generate(KEY_SEED, index, workload.key_size)).into_owned())
+        .collect();
+    let snapshot = Snapshot::take();
+    let mut overlay = OverlayDB::<L::Hash>::new(db);
+    let mut root = *root;
+    let mut trie = TrieDBMutBuilder::<L>::from_existing(&mut overlay, &mut root).build();
+    let mut output = Vec::new();
+
+    phase(&mut output, workload.inserts, |index| {
+        let value = generate(VALUE_SEED, index, workload.value_size);
+        trie.insert(&keys[index as usize], &value)
+            .map_err(|error| call_error(error.as_ref()))?;
+        trie.commit();
+        Ok(())
+    })?;
+    let reads = if keys.is_empty() { 0 } else { workload.reads };
+    phase(&mut output, reads, |index| {
+        let key = &keys[(index % workload.inserts) as usize];
+        trie.get(key).map_err(|error| call_error(error.as_ref()))?;
+        Ok(())
+    })?;
+    phase(&mut output, workload.removals.min(workload.inserts), |index| {
+        trie.remove(&keys[index as usize]).map_err(|error| call_error(error.as_ref()))?;
+        trie.commit();
+        Ok(())
+    })?;
+    drop(trie);
+
+    #[cfg(not(feature = "dlmalloc"))]
+    let peak = crate::Allocator::stats().peak as u32;
+    #[cfg(feature = "dlmalloc")]
+    let peak = 0u32;
+    output.extend_from_slice(&peak.to_le_bytes());
+    output.extend_from_slice(&snapshot.elapsed());
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workloads_are_decoded_and_generated() {
+        let input: Vec<u8> =
+            [3u32, 16, 4, 5, 2].iter().flat_map(|field| field.to_le_bytes()).collect();
+        let workload = Workload::decode(&input).unwrap();
+        assert_eq!(workload.inserts, 3);
+        assert_eq!(workload.value_size, 4);
+        assert_eq!(workload.removals, 2);
+        assert!(Workload::decode(&input[..16]).is_none());
+
+        let key = generate(KEY_SEED, 7, 16);
+        assert_eq!(key.len(), 16);
+        assert_eq!(key, generate(KEY_SEED, 7, 16));
+        assert_ne!(key, generate(KEY_SEED, 8, 16));
+        assert_ne!(key[..8], key[8..]);
+        assert!(generate(VALUE_SEED, 0, 0).is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Inspects a trie outside the browser, running the calls of the wasm module natively.
+//!
+//! The trie is loaded from a storage dump, the length-prefixed keys and values of the host
+//! storage as written by `DefaultTrieStorage.dump`, or from a snapshot saved by
+//! `exportSnapshot`. Keys starting with `0x` are read as hex, as UTF-8 otherwise.
+use std::{process::ExitCode, str};
+use trie::native::{self, CallError, Level, Reply, DEFAULT_HANDLE};
+
+const USAGE: &str = "\
+usage: trie-inspect <dump|snapshot> <file> [--trie <name>] <command>
+
+commands:
+  get <key>         prints the value of the key
+  proof <key>...    prints the nodes proving the keys, one per line
+  stats             prints the metrics of the stored nodes
+  dot               prints the trie in the graphviz format";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn parse_key(key: &str) -> Result<Vec<u8>, String> {
+    let Some(digits) = key.strip_prefix("0x") else {
+        return Ok(key.as_bytes().to_vec());
+    };
+    if digits.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits in {key}"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<_, _>>()
+        .map_err(|error| format!("invalid key {key}: {error}"))
+}
+
+fn write_bytes(output: &mut Vec<u8>, bytes: &[u8]) {
+    output.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    output.extend_from_slice(bytes);
+}
+
+/// Splits the length-prefixed byte strings of `bytes`.
+fn split_list(mut bytes: &[u8]) -> Result<Vec<&[u8]>, String> {
+    let mut list = Vec::new();
+    while let Some((len, rest)) = bytes.split_first_chunk::<4>() {
+        let Some((item, rest)) = rest.split_at_checked(u32::from_le_bytes(*len) as usize) else {
+            return Err("length out of bounds".into());
+        };
+        list.push(item);
+        bytes = rest;
+    }
+    if !bytes.is_empty() {
+        return Err("truncated length prefix".into());
+    }
+    Ok(list)
+}
+
+fn call(handle: u32, code: u32, input: &[u8]) -> Result<Reply, String> {
+    native::call(handle, code, input).map_err(|error: CallError| format!("call {code}: {error:?}"))
+}
+
+/// Loads the trie of `file` and returns its handle.
+fn load(format: &str, file: &str, name: Option<&str>) -> Result<u32, String> {
+    let bytes = std::fs::read(file).map_err(|error| format!("{file}: {error}"))?;
+    match format {
+        "dump" => {
+            let entries = split_list(&bytes).map_err(|error| format!("{file}: {error}"))?;
+            let (keys, []) = entries.as_chunks::<2>() else {
+                return Err(format!("{file}: key without value"));
+            };
+            for [key, value] in keys {
+                native::set_storage(key, value);
+            }
+        },
+        "snapshot" => {
+            call(DEFAULT_HANDLE, 54, &bytes)?;
+        },
+        format => return Err(format!("unknown format {format}\n\n{USAGE}")),
+    }
+    match name {
+        Some(name) => call(DEFAULT_HANDLE, 60, name.as_bytes()).map(|reply| reply.ret as u32),
+        None => Ok(DEFAULT_HANDLE),
+    }
+}
+
+/// Prints the metrics encoded by `TrieStats::encode`.
+fn print_stats(bytes: &[u8]) -> Result<(), String> {
+    let Some((counts, mut counters)) = bytes.split_at_checked(28) else {
+        return Err("truncated stats".into());
+    };
+    let u32_at = |at: usize| u32::from_le_bytes(counts[at..at + 4].try_into().unwrap());
+    println!("nodes: {}", u32_at(0));
+    println!("bytes: {}", u64::from_le_bytes(counts[4..12].try_into().unwrap()));
+    println!("leaves: {}", u32_at(12));
+    println!("branches: {}", u32_at(16));
+    println!("extensions: {}", u32_at(20));
+    println!("values: {}", u32_at(24));
+    while let Some((pair, rest)) = counters.split_first_chunk::<8>() {
+        let counter = i32::from_le_bytes(pair[..4].try_into().unwrap());
+        let nodes = u32::from_le_bytes(pair[4..].try_into().unwrap());
+        println!("nodes referenced {counter} times: {nodes}");
+        counters = rest;
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [format, file, rest @ ..] = args else {
+        return Err(USAGE.into());
+    };
+    let (name, rest) = match rest {
+        [flag, name, rest @ ..] if flag == "--trie" => (Some(name.as_str()), rest),
+        rest => (None, rest),
+    };
+    let handle = load(format, file, name)?;
+    match rest {
+        [command, key] if command == "get" => match call(handle, 3, &parse_key(key)?)?.output {
+            Some(value) => println!("0x{}", hex(&value)),
+            None => return Err(format!("{key} not found")),
+        },
+        [command, keys @ ..] if command == "proof" && !keys.is_empty() => {
+            let mut input = call(handle, 4, &[])?.output.unwrap_or_default();
+            for key in keys {
+                write_bytes(&mut input, &parse_key(key)?);
+            }
+            let proof = call(handle, 14, &input)?.output.unwrap_or_default();
+            for node in split_list(&proof)? {
+                println!("0x{}", hex(node));
+            }
+        },
+        [command] if command == "stats" => {
+            print_stats(&call(handle, 31, &[])?.output.unwrap_or_default())?;
+        },
+        [command] if command == "dot" => {
+            let dot = call(handle, 41, &[])?.output.unwrap_or_default();
+            println!("{}", str::from_utf8(&dot).map_err(|error| error.to_string())?);
+        },
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    // The errors of the calls are printed, the debug messages aren't.
+    native::set_log_filter(Level::Warn as u32, u32::MAX);
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{message}");
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -24,9 +24,11 @@
 //! A simple bump allocator.
 //!
 //! The heap which is used by this allocator is built from pages of Wasm memory (each page
-//! is `64KiB`). We will request new pages of memory as needed until we run out of memory,
-//! at which point we will crash with an `OOM` error instead of freeing any memory.
-use crate::abort;
+//! is `64KiB`). We will request new pages of memory as needed until we run out of memory or
+//! reach the limit set by the host, at which point the call is rolled back and the host is
+//! told about the `OOM` error. Only the most recent allocation is ever freed, which covers
+//! temporary buffers released in reverse order.
+use crate::{abort, host::HostFnImpl};
 use core::alloc::{GlobalAlloc, Layout};
 
 /// A page in Wasm is `64KiB`
@@ -34,45 +36,122 @@ const PAGE_SIZE: usize = 64 * 1024;
 
 static mut INNER: Option<InnerAlloc> = None;
 
+/// Largest number of Wasm pages the heap may grow to, see [`set_max_pages`].
+static mut MAX_PAGES: usize = usize::MAX;
+
+/// Caps the Wasm memory the heap may grow to, in pages. An allocation beyond it is reported to
+/// the host, then the call traps, see [`out_of_memory`].
+pub fn set_max_pages(pages: usize) {
+    unsafe { MAX_PAGES = pages };
+}
+
+fn max_pages() -> usize {
+    unsafe { MAX_PAGES }
+}
+
+/// Reports a failed allocation of `size` bytes to the host, then aborts the call. Nothing is
+/// allocated on the way, the heap is exhausted.
+///
+/// Trapping is intentional: `GlobalAlloc` can't hand the failure back to the code that
+/// allocated, so no `CallError` can be returned. The storage writes of the call are rolled
+/// back by the abort and the host restores the initial memory before the next call, the
+/// report lets it tell the trap apart from the others.
+pub(crate) fn out_of_memory(size: usize) -> ! {
+    HostFnImpl::out_of_memory(size as u32, max_pages() as u32);
+    crate::__abort()
+}
+
 /// A bump allocator suitable for use in a Wasm environment.
 pub struct BumpAllocator;
 
+/// Heap usage of the current call, or of the last one once it returned.
+#[derive(Default)]
+pub struct MemoryStats {
+    /// Start of the next allocation.
+    pub next: usize,
+    /// End of the memory available to the heap.
+    pub upper_limit: usize,
+    /// Wasm pages requested during the call.
+    pub pages_grown: usize,
+    /// Largest number of bytes in use since the heap start.
+    pub peak: usize,
+}
+
+impl MemoryStats {
+    /// Encodes the fields as `u32` in their declaration order.
+    pub fn encode(&self) -> [u8; 16] {
+        let mut output = [0u8; 16];
+        let fields = [self.next, self.upper_limit, self.pages_grown, self.peak];
+        for (chunk, field) in output.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&(field as u32).to_le_bytes());
+        }
+        output
+    }
+}
+
 impl BumpAllocator {
+    pub fn stats() -> MemoryStats {
+        #[allow(static_mut_refs)]
+        unsafe { INNER.as_ref() }.map(InnerAlloc::stats).unwrap_or_default()
+    }
+
+    /// Empties the heap, the allocations of the previous exports must no longer be
+    /// referenced, see [`crate::arena::begin`].
     pub fn reset() {
-        unsafe {
-            #[allow(static_mut_refs)]
-            let inner = &mut INNER;
-            if inner.is_some() {
-                abort!("memory already initialized");
-            }
-            *inner = Some(InnerAlloc::new());
-        }
+        unsafe { INNER = Some(InnerAlloc::new()) };
     }
 
-    pub fn pre_allocate_buffer<R>(size: u32, callback: fn(&'static mut [u8], &mut u32) -> R) -> R {
+    /// Initializes the heap unless it already is, keeping the allocations of the previous
+    /// calls.
+    pub fn init() {
         #[allow(static_mut_refs)]
         unsafe {
-            let Some(mut inner) = INNER.take() else {
-                abort!("recursive call to pre_allocate_buffer");
-            };
-            let mut len = size;
-            let layout = Layout::from_size_align_unchecked(len as usize, 1);
-            let Some(ptr) = inner.alloc(layout) else {
-                abort!("out of memory");
-            };
-            let buffer = core::slice::from_raw_parts_mut(ptr as *mut u8, len as usize);
-            let result = callback(buffer, &mut len);
-            if len > size {
-                abort!("seg fault");
-            }
-            inner.next = ptr + (len as usize);
-            if INNER.is_some() {
-                abort!("memory initialized during pre_allocate_buffer");
-            }
-            INNER = Some(inner);
-            result
-        }
+            INNER.get_or_insert_with(InnerAlloc::new)
+        };
     }
+
+    /// Drops the heap, the memory is reused from the start by the next call.
+    pub fn wipe() {
+        unsafe { INNER = None };
+    }
+
+    pub fn pre_allocate_buffer<R>(size: u32, callback: fn(&'static mut [u8], &mut u32) -> R) -> R {
+        unsafe { pre_allocate_in(&raw mut INNER, size, callback) }
+    }
+}
+
+/// Runs `callback` on a buffer of `size` bytes carved from the heap in `slot`, the heap then
+/// keeps the `len` bytes the callback used. The heap is taken out of `slot` meanwhile, the
+/// callback must not allocate.
+///
+/// # Safety
+///
+/// `slot` is the static holding the heap of the allocator.
+pub(crate) unsafe fn pre_allocate_in<T: AsMut<InnerAlloc>, R>(
+    slot: *mut Option<T>,
+    size: u32,
+    callback: fn(&'static mut [u8], &mut u32) -> R,
+) -> R {
+    let Some(mut inner) = (*slot).take() else {
+        abort!("recursive call to pre_allocate_buffer");
+    };
+    let heap = inner.as_mut();
+    let mut len = size;
+    let layout = Layout::from_size_align_unchecked(len as usize, 1);
+    let Some(ptr) = heap.alloc(layout) else {
+        out_of_memory(len as usize);
+    };
+    let buffer = core::slice::from_raw_parts_mut(ptr as *mut u8, len as usize);
+    let result = callback(buffer, &mut len);
+    if len > size {
+        abort!("seg fault");
+    }
+    heap.next = ptr + (len as usize);
+    if (*slot).is_some() {
+        abort!("memory initialized during pre_allocate_buffer");
+    }
+    *slot = Some(inner);
+    result
 }
 
 unsafe impl GlobalAlloc for BumpAllocator {
@@ -82,35 +161,59 @@ unsafe impl GlobalAlloc for BumpAllocator {
         let inner = INNER.get_or_insert_with(InnerAlloc::new);
         match inner.alloc(layout) {
             Some(start) => start as *mut u8,
-            None => core::ptr::null_mut(),
+            None => out_of_memory(layout.size()),
         }
     }
 
-    #[inline]
-    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        // A new page in Wasm is guaranteed to already be zero initialized, so we can just
-        // use our regular `alloc` call here and save a bit of work.
-        //
-        // See: https://webassembly.github.io/spec/core/exec/modules.html#growing-memories
-        self.alloc(layout)
-    }
+    // `alloc_zeroed` keeps the default implementation: memory released by `dealloc`, or
+    // left by a previous call in a persistent arena, is reused without being zeroed.
 
     #[inline]
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[allow(static_mut_refs)]
+        if let Some(inner) = INNER.as_mut() {
+            inner.dealloc(ptr as usize, layout);
+        }
+    }
 }
 
 #[cfg_attr(any(not(target_arch = "wasm32"), test), derive(Debug, Copy, Clone))]
-struct InnerAlloc {
+pub(crate) struct InnerAlloc {
     /// Points to the start of the next available allocation.
-    next: usize,
+    pub(crate) next: usize,
 
     /// The address of the upper limit of our heap.
     upper_limit: usize,
+
+    /// The address of the start of our heap.
+    start: usize,
+
+    /// Number of pages requested since the heap was created.
+    pages_grown: usize,
+
+    /// Largest `next - start` reached since the heap was created.
+    peak: usize,
 }
 
 impl InnerAlloc {
-    fn new() -> Self {
-        Self { next: Self::heap_start(), upper_limit: Self::heap_end() }
+    pub(crate) fn new() -> Self {
+        let start = Self::heap_start();
+        Self { next: start, upper_limit: Self::heap_end(), start, pages_grown: 0, peak: 0 }
+    }
+
+    /// Heap over the `len` bytes from `start`, which grows past them as if pages followed.
+    #[cfg(all(test, feature = "free-list"))]
+    pub(crate) fn with_region(start: usize, len: usize) -> Self {
+        Self { next: start, upper_limit: start + len, start, pages_grown: 0, peak: 0 }
+    }
+
+    pub(crate) fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            next: self.next,
+            upper_limit: self.upper_limit,
+            pages_grown: self.pages_grown,
+            peak: self.peak,
+        }
     }
 
     #[cfg(any(not(target_arch = "wasm32"), test))]
@@ -171,12 +274,24 @@ impl InnerAlloc {
         Some(prev_page * PAGE_SIZE)
     }
 
+    /// Requests `pages` more pages within the limit set by the host, returns the start of
+    /// the first one.
+    fn grow(&mut self, pages: usize) -> Option<usize> {
+        if (self.upper_limit / PAGE_SIZE).saturating_add(pages) > max_pages() {
+            return None;
+        }
+        let page_start = self.request_pages(pages)?;
+        self.pages_grown += pages;
+        Some(page_start)
+    }
+
     /// Tries to allocate enough memory on the heap for the given `Layout`. If there is
-    /// not enough room on the heap it'll try and grow it by a page.
-    ///
-    /// Note: This implementation results in internal fragmentation when allocating across
-    /// pages.
-    fn alloc(&mut self, layout: Layout) -> Option<usize> {
+    /// not enough room on the heap it'll try and grow it by the missing pages, the allocation
+    /// continues from the end of the heap when the new pages follow it.
+    pub(crate) fn alloc(&mut self, layout: Layout) -> Option<usize> {
+        #[cfg(feature = "alloc-profile")]
+        crate::profile::record(layout.size());
+
         let alloc_start = self.align_ptr(&layout);
 
         let aligned_size = layout.size();
@@ -184,17 +299,36 @@ impl InnerAlloc {
         let alloc_end = alloc_start.checked_add(aligned_size)?;
 
         if alloc_end > self.upper_limit {
-            let required_pages = required_pages(aligned_size)?;
-            let page_start = self.request_pages(required_pages)?;
+            let pages = required_pages(alloc_end - self.upper_limit)?;
+            let page_start = self.grow(pages)?;
+            let mut pages_end =
+                pages.checked_mul(PAGE_SIZE).and_then(|pages| page_start.checked_add(pages))?;
+
+            let alloc_start = if page_start == self.upper_limit {
+                alloc_start
+            } else {
+                // The new pages don't follow the heap, the allocation starts on them and
+                // the tail of the previous region is abandoned.
+                self.upper_limit = pages_end;
+                let missing = aligned_size.saturating_sub(pages_end - page_start);
+                if missing > 0 {
+                    let more_pages = required_pages(missing)?;
+                    if self.grow(more_pages)? != pages_end {
+                        return None;
+                    }
+                    pages_end = pages_end.checked_add(more_pages * PAGE_SIZE)?;
+                }
+                page_start
+            };
 
-            self.upper_limit = required_pages
-                .checked_mul(PAGE_SIZE)
-                .and_then(|pages| page_start.checked_add(pages))?;
-            self.next = page_start.checked_add(aligned_size)?;
+            self.upper_limit = pages_end;
+            self.next = alloc_start.checked_add(aligned_size)?;
+            self.peak = self.peak.max(self.next - self.start);
 
-            Some(page_start)
+            Some(alloc_start)
         } else {
             self.next = alloc_end;
+            self.peak = self.peak.max(self.next - self.start);
             Some(alloc_start)
         }
     }
@@ -211,6 +345,20 @@ impl InnerAlloc {
     fn align_ptr(&self, layout: &Layout) -> usize {
         (self.next + layout.align() - 1) & !(layout.align() - 1)
     }
+
+    /// Releases an allocation when it is the most recent one, so temporary buffers freed in
+    /// reverse order are reused. Other allocations are kept until the heap is reset.
+    pub(crate) fn dealloc(&mut self, ptr: usize, layout: Layout) {
+        if ptr.checked_add(layout.size()) == Some(self.next) {
+            self.next = ptr;
+        }
+    }
+}
+
+impl AsMut<InnerAlloc> for InnerAlloc {
+    fn as_mut(&mut self) -> &mut InnerAlloc {
+        self
+    }
 }
 
 /// Calculates the number of pages of memory needed for an allocation of `size` bytes.
@@ -301,18 +449,36 @@ mod tests {
         assert_eq!(inner.next, expected_alloc_start);
 
         // Now we'll allocate two bytes which will push us over to the next page
-        let layout = Layout::new::<u16>();
-        assert_eq!(inner.alloc(layout), Some(PAGE_SIZE));
+        let layout = Layout::new::<[u8; 2]>();
+        assert_eq!(inner.alloc(layout), Some(PAGE_SIZE - 1));
 
         let expected_limit = 2 * PAGE_SIZE;
         assert_eq!(inner.upper_limit, expected_limit);
 
-        // Notice that we start the allocation on the second page, instead of making use
-        // of the remaining byte on the first page
-        let expected_alloc_start = PAGE_SIZE + size_of::<u16>();
+        // Notice that the allocation makes use of the remaining byte on the first page,
+        // since the second page follows it
+        let expected_alloc_start = PAGE_SIZE + 1;
         assert_eq!(inner.next, expected_alloc_start);
     }
 
+    #[test]
+    fn dealloc_reuses_the_last_allocation() {
+        let mut inner = InnerAlloc::new();
+
+        let layout = Layout::new::<[u8; 3]>();
+        let first = inner.alloc(layout).unwrap();
+        let second = inner.alloc(Layout::new::<u64>()).unwrap();
+        assert_eq!(second, 8);
+
+        // Only the most recent allocation is released
+        inner.dealloc(first, layout);
+        assert_eq!(inner.next, 16);
+
+        inner.dealloc(second, Layout::new::<u64>());
+        assert_eq!(inner.next, 8);
+        assert_eq!(inner.alloc(Layout::new::<u32>()), Some(8));
+    }
+
     #[test]
     fn can_alloc_multiple_pages() {
         let mut inner = InnerAlloc::new();
@@ -340,6 +506,10 @@ mod tests {
 
         let expected_alloc_start = 2 * PAGE_SIZE + size_of::<u8>();
         assert_eq!(inner.next, expected_alloc_start);
+
+        let stats = inner.stats();
+        assert_eq!(stats.pages_grown, 3);
+        assert_eq!(stats.peak, expected_alloc_start);
     }
 
     #[test]
@@ -0,0 +1,102 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Least recently used cache of the nodes read from the host storage.
+use crate::primitives::H256;
+use alloc::collections::BTreeMap;
+use trie_db::DBValue;
+
+pub struct NodeCache {
+    capacity: usize,
+    /// Cached nodes and the tick of their last use.
+    nodes: BTreeMap<H256, (u64, DBValue)>,
+    /// Cached keys by the tick of their last use, the first one is evicted first.
+    recency: BTreeMap<u64, H256>,
+    tick: u64,
+}
+
+impl NodeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, nodes: BTreeMap::new(), recency: BTreeMap::new(), tick: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether `key` is cached, without refreshing it.
+    pub fn contains(&self, key: &H256) -> bool {
+        self.nodes.contains_key(key)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    pub fn get(&mut self, key: &H256) -> Option<DBValue> {
+        let tick = self.next_tick();
+        let (used, value) = self.nodes.get_mut(key)?;
+        self.recency.remove(used);
+        self.recency.insert(tick, *key);
+        *used = tick;
+        Some(value.clone())
+    }
+
+    pub fn insert(&mut self, key: H256, value: DBValue) {
+        if self.capacity == 0 {
+            return;
+        }
+        let tick = self.next_tick();
+        if let Some((used, _)) = self.nodes.insert(key, (tick, value)) {
+            self.recency.remove(&used);
+        } else if self.nodes.len() > self.capacity {
+            if let Some((_, evicted)) = self.recency.pop_first() {
+                self.nodes.remove(&evicted);
+            }
+        }
+        self.recency.insert(tick, key);
+    }
+
+    pub fn remove(&mut self, key: &H256) {
+        if let Some((used, _)) = self.nodes.remove(key) {
+            self.recency.remove(&used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_least_recently_used_node() {
+        let key = |byte| H256::repeat_byte(byte);
+        let mut cache = NodeCache::new(2);
+        cache.insert(key(1), vec![1]);
+        cache.insert(key(2), vec![2]);
+        assert_eq!(cache.get(&key(1)), Some(vec![1]));
+        cache.insert(key(3), vec![3]);
+        assert_eq!(cache.get(&key(2)), None);
+        assert_eq!(cache.get(&key(1)), Some(vec![1]));
+        assert_eq!(cache.get(&key(3)), Some(vec![3]));
+
+        cache.remove(&key(1));
+        assert_eq!(cache.get(&key(1)), None);
+        cache.insert(key(4), vec![4]);
+        assert_eq!(cache.get(&key(3)), Some(vec![3]));
+    }
+
+    #[test]
+    fn zero_capacity_disables_the_cache() {
+        let mut cache = NodeCache::new(0);
+        cache.insert(H256::zero(), vec![0]);
+        assert_eq!(cache.get(&H256::zero()), None);
+    }
+}
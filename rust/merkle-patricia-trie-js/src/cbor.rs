@@ -0,0 +1,175 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! CBOR (RFC 8949) encoding of the decoded node graph, for hosts without the TS classes.
+//!
+//! Each node is a map with the fields of [`crate::json`]: `id`, `value` and `rawBytes` as byte
+//! strings or `null`, `nibbles` as a byte string holding one nibble per byte or `null`, `kind`,
+//! `depth`, `encodedSize`, `highlighted`, `children` as an array of `{"nibble": n, "node":
+//! {..}}` and `extension`, the single child of an extension node.
+use crate::{children::NodeId, trie::NodeArena};
+use alloc::vec::Vec;
+
+const UNSIGNED: u8 = 0;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+
+/// Encodes the node at `index` and its descendants.
+pub fn encode(index: NodeId, nodes: &NodeArena) -> Vec<u8> {
+    let mut output = Vec::with_capacity(nodes.len() * 128);
+    write_node(&mut output, index, nodes);
+    output
+}
+
+fn write_node(output: &mut Vec<u8>, index: NodeId, nodes: &NodeArena) {
+    let node = &nodes[index];
+    write_head(output, MAP, 10);
+    write_text(output, "id");
+    write_bytes(output, node.id.as_ref().map(|id| id.as_bytes()));
+    write_text(output, "kind");
+    write_text(output, node.kind.name());
+    write_text(output, "depth");
+    write_head(output, UNSIGNED, u64::from(node.depth));
+    write_text(output, "encodedSize");
+    write_head(output, UNSIGNED, node.raw_bytes.len() as u64);
+    write_text(output, "nibbles");
+    match node.nibbles().filter(|slice| !slice.is_empty()) {
+        Some(slice) => write_bytes(output, Some(&slice.iter().collect::<Vec<u8>>())),
+        None => output.push(NULL),
+    }
+    write_text(output, "value");
+    write_bytes(output, node.value.as_deref());
+    write_text(output, "rawBytes");
+    write_bytes(output, Some(&node.raw_bytes));
+    write_text(output, "highlighted");
+    output.push(if node.highlighted { TRUE } else { FALSE });
+    write_text(output, "children");
+    write_head(output, ARRAY, node.children.len() as u64);
+    for (child, nibble) in node.children.iter() {
+        write_head(output, MAP, 2);
+        write_text(output, "nibble");
+        write_head(output, UNSIGNED, u64::from(nibble));
+        write_text(output, "node");
+        write_node(output, child, nodes);
+    }
+    write_text(output, "extension");
+    match node.children.extension() {
+        Some(child) => write_node(output, child, nodes),
+        None => output.push(NULL),
+    }
+}
+
+/// Writes the major type and its argument in the shortest form.
+fn write_head(output: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    match argument {
+        0..24 => output.push(major | argument as u8),
+        24..0x100 => output.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..0x10000 => {
+            output.push(major | 25);
+            output.extend_from_slice(&(argument as u16).to_be_bytes());
+        },
+        0x10000..0x1_0000_0000 => {
+            output.push(major | 26);
+            output.extend_from_slice(&(argument as u32).to_be_bytes());
+        },
+        _ => {
+            output.push(major | 27);
+            output.extend_from_slice(&argument.to_be_bytes());
+        },
+    }
+}
+
+fn write_text(output: &mut Vec<u8>, text: &str) {
+    write_head(output, TEXT, text.len() as u64);
+    output.extend_from_slice(text.as_bytes());
+}
+
+/// Writes a byte string, or `null`.
+fn write_bytes(output: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    let Some(bytes) = bytes else {
+        output.push(NULL);
+        return;
+    };
+    write_head(output, BYTES, bytes.len() as u64);
+    output.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        children::Children,
+        trie::{NodeKind, TrieNode},
+    };
+    use trie_db::node::NibbleSlicePlan;
+
+    #[test]
+    fn heads_use_the_shortest_form() {
+        let mut output = Vec::new();
+        write_head(&mut output, UNSIGNED, 23);
+        write_head(&mut output, UNSIGNED, 24);
+        write_head(&mut output, BYTES, 500);
+        write_head(&mut output, ARRAY, 70_000);
+        assert_eq!(output, [0x17, 0x18, 0x18, 0x59, 0x01, 0xf4, 0x9a, 0x00, 0x01, 0x11, 0x70]);
+    }
+
+    #[test]
+    fn encodes_a_leaf() {
+        let leaf = TrieNode {
+            id: None,
+            kind: NodeKind::Leaf,
+            depth: 1,
+            partial: Some(NibbleSlicePlan::new(0..1, 1)),
+            value: Some(vec![7]),
+            children: Children::new(),
+            raw_bytes: vec![0xab],
+            highlighted: false,
+            truncated: false,
+            status: None,
+            references: 0,
+            matched_nibbles: None,
+            collapsed: Vec::new(),
+        };
+        let mut expected = vec![0xaa, 0x62];
+        expected.extend_from_slice(b"id");
+        expected.push(NULL);
+        expected.push(0x64);
+        expected.extend_from_slice(b"kind");
+        expected.push(0x64);
+        expected.extend_from_slice(b"leaf");
+        expected.push(0x65);
+        expected.extend_from_slice(b"depth");
+        expected.push(0x01);
+        expected.push(0x6b);
+        expected.extend_from_slice(b"encodedSize");
+        expected.push(0x01);
+        expected.push(0x67);
+        expected.extend_from_slice(b"nibbles");
+        expected.extend_from_slice(&[0x41, 0x0b]);
+        expected.push(0x65);
+        expected.extend_from_slice(b"value");
+        expected.extend_from_slice(&[0x41, 0x07]);
+        expected.push(0x68);
+        expected.extend_from_slice(b"rawBytes");
+        expected.extend_from_slice(&[0x41, 0xab]);
+        expected.push(0x6b);
+        expected.extend_from_slice(b"highlighted");
+        expected.push(FALSE);
+        expected.push(0x68);
+        expected.extend_from_slice(b"children");
+        expected.push(0x80);
+        expected.push(0x69);
+        expected.extend_from_slice(b"extension");
+        expected.push(NULL);
+        let mut nodes = NodeArena::default();
+        let leaf = nodes.push(leaf);
+        assert_eq!(encode(leaf, &nodes), expected);
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Named checkpoints of the trie.
+//!
+//! A checkpoint stores a root hash under a reserved key and owns one reference of every node
+//! reachable from it, so the nodes are not deleted when the live trie stops using them.
+use crate::{
+    backend::TrieBackend,
+    error::{CallError, CallResult},
+    fail,
+    primitives::H256,
+    storage::{ExternalDB, TrieLayout},
+    trie::for_each_node,
+};
+use alloc::vec::Vec;
+use hash_db::{HashDB, EMPTY_PREFIX};
+
+const CHECKPOINT_PREFIX: &[u8] = b":checkpoint:";
+
+fn checkpoint_key(name: &[u8]) -> Vec<u8> {
+    [CHECKPOINT_PREFIX, name].concat()
+}
+
+/// Releases one reference of every node reachable from `root`.
+pub fn release<L: TrieLayout, B: TrieBackend>(db: &mut ExternalDB<B>, root: &H256) {
+    let mut nodes = Vec::new();
+    for_each_node::<L, _>(db, root, |key| nodes.push(*key));
+    for key in nodes {
+        HashDB::<L::Hash, _>::remove(db, &key, EMPTY_PREFIX);
+    }
+}
+
+/// Creates (or replaces) the checkpoint `name` pointing at `root`.
+pub fn create<L: TrieLayout, B: TrieBackend>(db: &mut ExternalDB<B>, root: &H256, name: &[u8]) {
+    let key = checkpoint_key(name);
+    for_each_node::<L, _>(db, root, |node| db.retain(node));
+    if let Some(previous) = db.get_hash(&key) {
+        release::<L, _>(db, &previous);
+    }
+    db.set_hash(&key, root);
+}
+
+/// Moves the live trie from `root` to the checkpoint `name`, returning the new root. The
+/// checkpoint is kept, so it can be reverted to again.
+pub fn revert<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &H256,
+    name: &[u8],
+) -> CallResult<H256> {
+    let Some(checkpoint) = db.get_hash(&checkpoint_key(name)) else {
+        return fail!(CallError::KeyNotFound, key = name, "checkpoint not found");
+    };
+    // Retain the checkpoint nodes first, otherwise nodes shared by both tries would be deleted.
+    for_each_node::<L, _>(db, &checkpoint, |node| db.retain(node));
+    release::<L, _>(db, root);
+    Ok(checkpoint)
+}
+
+/// Deletes the checkpoint `name`, releasing the nodes only referenced by it.
+pub fn remove<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    name: &[u8],
+) -> CallResult<()> {
+    let key = checkpoint_key(name);
+    let Some(checkpoint) = db.get_hash(&key) else {
+        return fail!(CallError::KeyNotFound, key = name, "checkpoint not found");
+    };
+    release::<L, _>(db, &checkpoint);
+    db.clear(&key);
+    Ok(())
+}
@@ -0,0 +1,180 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Child tries, like Substrate's default child tries.
+//!
+//! The root of a child trie is stored in the parent trie under [`CHILD_STORAGE_PREFIX`]
+//! followed by the storage key of the child. A child trie is created by its first insert and
+//! removed from the parent once empty. Its nodes are stored under a keyspace of their own, so
+//! identical nodes of distinct tries keep separate counters.
+use crate::{
+    backend::TrieBackend,
+    error::{CallError, CallResult},
+    fail,
+    primitives::H256,
+    storage::{hashed_null_node, trie_key, ExternalDB, TrieLayout},
+    trie::{self, call_error},
+};
+use alloc::vec::Vec;
+use trie_db::{TrieDBMut, TrieDBMutBuilder, TrieMut};
+
+pub const CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:default:";
+const CHILD_KEYSPACE_PREFIX: &[u8] = b":child:";
+
+/// Key of the child root in the parent trie.
+fn parent_key(storage_key: &[u8]) -> Vec<u8> {
+    [CHILD_STORAGE_PREFIX, storage_key].concat()
+}
+
+/// Prefix of the nodes of a child trie, the length keeps a storage key from extending another.
+pub fn keyspace(storage_key: &[u8]) -> Vec<u8> {
+    [CHILD_KEYSPACE_PREFIX, &(storage_key.len() as u32).to_le_bytes(), storage_key].concat()
+}
+
+/// Root of the child trie `storage_key`, the empty root when it doesn't exist.
+pub fn root<L: TrieLayout, B: TrieBackend>(
+    db: &ExternalDB<B>,
+    root: &H256,
+    storage_key: &[u8],
+) -> CallResult<H256> {
+    match trie::read_value::<L>(db, root, &parent_key(storage_key))? {
+        Some(child_root) => {
+            let Ok(child_root) = <[u8; 32]>::try_from(child_root.as_slice()) else {
+                return fail!(CallError::DecodeError, "invalid child root");
+            };
+            Ok(H256(child_root))
+        },
+        None => Ok(hashed_null_node::<L>()),
+    }
+}
+
+/// Applies `change` to the child trie `storage_key`, then stores its new root in the parent
+/// trie, or removes it once the child trie is empty.
+fn update<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &mut H256,
+    storage_key: &[u8],
+    change: impl FnOnce(&mut TrieDBMut<'_, L>) -> CallResult<()>,
+) -> CallResult<()> {
+    let mut child_root = self::root::<L, B>(db, root, storage_key)?;
+    {
+        let mut child_db = db.child(keyspace(storage_key));
+        {
+            let mut trie =
+                TrieDBMutBuilder::<L>::from_existing(&mut child_db, &mut child_root).build();
+            change(&mut trie)?;
+        }
+        child_db.flush();
+    }
+
+    let key = trie_key::<L>(&parent_key(storage_key)).into_owned();
+    let mut trie = TrieDBMutBuilder::<L>::from_existing(db, root).build();
+    let result = if child_root == hashed_null_node::<L>() {
+        trie.remove(&key).map(drop)
+    } else {
+        trie.insert(&key, child_root.as_bytes()).map(drop)
+    };
+    result.map_err(|error| call_error(error.as_ref()))
+}
+
+pub fn insert<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &mut H256,
+    storage_key: &[u8],
+    key: &[u8],
+    value: &[u8],
+) -> CallResult<()> {
+    update::<L, B>(db, root, storage_key, |trie| {
+        let result = trie.insert(&trie_key::<L>(key), value);
+        result.map(drop).map_err(|error| call_error(error.as_ref()))
+    })
+}
+
+pub fn remove<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &mut H256,
+    storage_key: &[u8],
+    key: &[u8],
+) -> CallResult<()> {
+    update::<L, B>(db, root, storage_key, |trie| {
+        let result = trie.remove(&trie_key::<L>(key));
+        result.map(drop).map_err(|error| call_error(error.as_ref()))
+    })
+}
+
+pub fn get<L: TrieLayout, B: TrieBackend>(
+    db: &ExternalDB<B>,
+    root: &H256,
+    storage_key: &[u8],
+    key: &[u8],
+) -> CallResult<Option<Vec<u8>>> {
+    let child_root = self::root::<L, B>(db, root, storage_key)?;
+    trie::read_value::<L>(&db.child(keyspace(storage_key)), &child_root, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::{MemoryBackend, TrieBackend},
+        primitives::{Blake2Hasher, LayoutV1},
+        trie::for_each_node,
+    };
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    #[test]
+    fn child_roots_are_stored_in_the_parent() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        insert::<Layout, _>(&mut db, &mut root, b"first", b"alpha", b"1").unwrap();
+        insert::<Layout, _>(&mut db, &mut root, b"second", b"alpha", b"1").unwrap();
+        db.commit_root(&root);
+
+        let child_root = self::root::<Layout, _>(&db, &root, b"first").unwrap();
+        assert_eq!(child_root, self::root::<Layout, _>(&db, &root, b"second").unwrap());
+        let parent_value =
+            trie::read_value::<Layout>(&db, &root, b":child_storage:default:first").unwrap();
+        assert_eq!(parent_value, Some(child_root.as_bytes().to_vec()));
+        assert_eq!(get::<Layout, _>(&db, &root, b"first", b"alpha").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(get::<Layout, _>(&db, &root, b"first", b"beta").unwrap(), None);
+        assert_eq!(get::<Layout, _>(&db, &root, b"third", b"alpha").unwrap(), None);
+    }
+
+    #[test]
+    fn empty_child_tries_are_removed() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        insert::<Layout, _>(&mut db, &mut root, b"first", b"alpha", b"1").unwrap();
+        insert::<Layout, _>(&mut db, &mut root, b"second", b"alpha", b"1").unwrap();
+        db.commit_root(&root);
+        let second = root;
+        let mut nodes = Vec::new();
+        let first_root = self::root::<Layout, _>(&db, &root, b"first").unwrap();
+        for_each_node::<Layout, _>(&db.child(keyspace(b"first")), &first_root, |node| {
+            nodes.push([&keyspace(b"first")[..], node.as_bytes()].concat());
+        });
+
+        remove::<Layout, _>(&mut db, &mut root, b"first", b"alpha").unwrap();
+        db.commit_root(&root);
+        assert_eq!(
+            self::root::<Layout, _>(&db, &root, b"first").unwrap(),
+            hashed_null_node::<Layout>()
+        );
+        assert!(nodes.iter().all(|node| !db.backend().contains(node)));
+        assert_eq!(
+            trie::read_value::<Layout>(&db, &root, b":child_storage:default:first").unwrap(),
+            None
+        );
+
+        // The identical nodes of the other child trie are kept.
+        let child_root = self::root::<Layout, _>(&db, &root, b"second").unwrap();
+        let child_db = db.child(keyspace(b"second"));
+        for_each_node::<Layout, _>(&child_db, &child_root, |node| {
+            assert_eq!(child_db.counter(node), 1);
+        });
+        assert_eq!(get::<Layout, _>(&db, &root, b"second", b"alpha").unwrap(), Some(b"1".to_vec()));
+        assert_ne!(root, second);
+    }
+}
@@ -3,30 +3,96 @@
 // found in the LICENSE file.
 
 use crate::abort;
-use core::{marker::PhantomData, ptr::NonNull};
+use core::fmt;
 
+/// Index of a node in a [`NodeArena`](crate::trie::NodeArena), given by the arena when the
+/// node is pushed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub(crate) fn new(index: usize) -> Self {
+        match u32::try_from(index) {
+            Ok(index) => Self(index),
+            Err(_) => abort!("too many nodes: {index}"),
+        }
+    }
+
+    pub const fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Clone)]
 pub struct Children {
     mask: u16,
-    children: [usize; 16],
+    /// Children labeled by a nibble.
+    children: [Option<NodeId>; 16],
+    /// Single child of an extension node, which isn't labeled by a nibble.
+    extension: Option<NodeId>,
 }
 
 impl Children {
     pub fn new() -> Self {
-        Self { mask: 0, children: [usize::MAX; 16] }
+        Self { mask: 0, children: [None; 16], extension: None }
     }
 
-    pub fn push(&mut self, val: usize, partial: u8) {
+    pub fn push(&mut self, val: NodeId, partial: u8) {
+        if self.extension.is_some() {
+            abort!("an extension node has a single child");
+        }
         let mask = self.mask;
         let flag = 1u16.wrapping_shl(partial as u32);
         if partial >= 16 || (flag & mask) != 0 {
             abort!("an node can have at maximum 16 children");
         }
-        self.children[partial as usize] = val;
+        self.children[partial as usize] = Some(val);
         self.mask = mask | flag;
     }
 
+    pub fn set_extension(&mut self, val: NodeId) {
+        if self.mask != 0 || self.extension.is_some() {
+            abort!("an extension node has a single child");
+        }
+        self.extension = Some(val);
+    }
+
+    pub fn extension(&self) -> Option<NodeId> {
+        self.extension
+    }
+
+    /// Child labeled by `nibble`.
+    pub fn get(&self, nibble: u8) -> Option<NodeId> {
+        self.children.get(usize::from(nibble)).copied().flatten()
+    }
+
+    /// Number of children labeled by a nibble, the child of an extension node isn't counted.
+    pub fn len(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mask == 0
+    }
+
+    /// Shifts the ids of the children, once their nodes are moved `offset` places.
+    pub fn offset(&mut self, offset: usize) {
+        let slots = self.children.iter_mut().chain(core::iter::once(&mut self.extension));
+        for child in slots.flatten() {
+            *child = NodeId::new(child.index() + offset);
+        }
+    }
+
+    /// Iterates over the children labeled by a nibble, see [`Children::extension`] for the
+    /// child of an extension node.
     pub fn iter(&self) -> ChildrenIter<'_> {
-        ChildrenIter::new(self)
+        ChildrenIter { children: &self.children, mask: self.mask }
     }
 }
 
@@ -37,7 +103,7 @@ impl Default for Children {
 }
 
 impl<'a> IntoIterator for &'a Children {
-    type Item = (usize, u8);
+    type Item = (NodeId, u8);
     type IntoIter = ChildrenIter<'a>;
     #[inline(always)]
     fn into_iter(self) -> Self::IntoIter {
@@ -45,49 +111,95 @@ impl<'a> IntoIterator for &'a Children {
     }
 }
 
+#[derive(Clone)]
 pub struct ChildrenIter<'a> {
-    ptr: NonNull<usize>,
+    children: &'a [Option<NodeId>; 16],
+    /// Nibbles of the children left to visit.
     mask: u16,
-    _marker: PhantomData<&'a Children>,
 }
 
-impl<'a> ChildrenIter<'a> {
+impl ChildrenIter<'_> {
     #[inline]
-    pub(super) const fn new(children: &'a Children) -> Self {
-        let slice = &children.children;
-        let mask = children.mask;
-        let ptr: NonNull<usize> = NonNull::from_ref(slice).cast();
-        Self { ptr, mask, _marker: PhantomData }
+    fn read(&self, nibble: u32) -> (NodeId, u8) {
+        match self.children[nibble as usize] {
+            Some(child) => (child, nibble as u8),
+            None => abort!("[bug] ChildrenIter"),
+        }
     }
 }
-unsafe impl Sync for ChildrenIter<'_> {}
-unsafe impl Send for ChildrenIter<'_> {}
 
-impl Clone for ChildrenIter<'_> {
+impl Iterator for ChildrenIter<'_> {
+    type Item = (NodeId, u8);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mask = self.mask;
+        if mask == 0 {
+            return None;
+        }
+        let nibble = mask.trailing_zeros();
+        self.mask ^= 1 << nibble;
+        Some(self.read(nibble))
+    }
+
     #[inline]
-    fn clone(&self) -> Self {
-        Self { ptr: self.ptr, mask: self.mask, _marker: self._marker }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len();
+        (len, Some(len))
     }
 }
 
-impl<'a> Iterator for ChildrenIter<'a> {
-    type Item = (usize, u8);
-
-    fn next(&mut self) -> Option<Self::Item> {
+impl DoubleEndedIterator for ChildrenIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
         let mask = self.mask;
         if mask == 0 {
             return None;
         }
-        let next = ((!mask) + 1) & mask;
-        let offset = next.trailing_zeros() as u8;
-        self.mask ^= next;
-        unsafe {
-            core::hint::assert_unchecked(offset < 16);
-            let value = self.ptr.add(offset as usize).read();
-            if value == usize::MAX {
-                abort!("[bug] ChildrenIter")
-            }
-            Some((value, offset))
+        let nibble = 15 - mask.leading_zeros();
+        self.mask ^= 1 << nibble;
+        Some(self.read(nibble))
+    }
+}
+
+impl ExactSizeIterator for ChildrenIter<'_> {
+    #[inline]
+    fn len(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(index: usize) -> NodeId {
+        NodeId::new(index)
+    }
+
+    #[test]
+    fn children_are_read_by_nibble() {
+        let mut children = Children::new();
+        assert!(children.is_empty());
+        children.push(id(7), 3);
+        children.push(id(9), 15);
+        assert_eq!(children.len(), 2);
+        assert_eq!(children.get(3), Some(id(7)));
+        assert_eq!(children.get(4), None);
+        assert_eq!(children.get(16), None);
+        children.offset(2);
+        assert_eq!(children.iter().collect::<Vec<_>>(), [(id(9), 3), (id(11), 15)]);
+    }
+
+    #[test]
+    fn children_are_iterated_from_both_ends() {
+        let mut children = Children::new();
+        for (child, nibble) in [(1, 0), (2, 5), (3, 9), (4, 15)] {
+            children.push(id(child), nibble);
         }
+        let mut iter = children.iter();
+        assert_eq!(iter.len(), 4);
+        assert_eq!(iter.next_back(), Some((id(4), 15)));
+        assert_eq!(iter.next(), Some((id(1), 0)));
+        assert_eq!(iter.len(), 2);
+        assert_eq!(iter.rev().collect::<Vec<_>>(), [(id(3), 9), (id(2), 5)]);
     }
 }
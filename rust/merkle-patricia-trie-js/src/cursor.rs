@@ -0,0 +1,207 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Trie iterators kept open across calls.
+//!
+//! A cursor remembers the root it was opened at and the key of the next entry, every read seeks
+//! back to it. Cursors live in the heap, so they need the persistent arena and are closed when
+//! the arena is wiped. Writes committed after a cursor is opened aren't seen by it, and the
+//! nodes they release can't be read anymore.
+use crate::{
+    arena,
+    error::{CallError, CallResult},
+    fail,
+    host::HostFnImpl,
+    primitives::H256,
+    storage::{write_bytes, TrieLayout},
+    trie::call_error,
+};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use hash_db::HashDB;
+use trie_db::{DBValue, Trie, TrieDBBuilder};
+
+struct Cursor {
+    namespace: Vec<u8>,
+    root: H256,
+    /// Key of the next entry, `None` once the end of the trie is reached.
+    next: Option<Vec<u8>>,
+}
+
+struct Cursors(RefCell<Vec<Option<Cursor>>>);
+
+// SAFETY: the module is single threaded.
+#[cfg(target_arch = "wasm32")]
+unsafe impl Sync for Cursors {}
+
+#[cfg(target_arch = "wasm32")]
+static CURSORS: Cursors = Cursors(RefCell::new(Vec::new()));
+
+// The native tests run on several threads, each keeps its own cursors.
+#[cfg(not(target_arch = "wasm32"))]
+std::thread_local! {
+    static CURSORS: Cursors = const { Cursors(RefCell::new(Vec::new())) };
+}
+
+fn with_cursors<R>(f: impl FnOnce(&mut Vec<Option<Cursor>>) -> R) -> R {
+    #[cfg(target_arch = "wasm32")]
+    return f(&mut CURSORS.0.borrow_mut());
+    #[cfg(not(target_arch = "wasm32"))]
+    CURSORS.with(|cursors| f(&mut cursors.0.borrow_mut()))
+}
+
+/// Opens a cursor over the trie at `root` from the first key not below `start`, returning its
+/// handle.
+pub fn open(root: &H256, start: &[u8]) -> CallResult<u32> {
+    if !arena::is_persistent() {
+        return fail!(CallError::InvalidCall, "cursors need a persistent arena");
+    }
+    let cursor = Cursor {
+        namespace: HostFnImpl::with_namespace(<[u8]>::to_vec),
+        root: *root,
+        next: Some(start.to_vec()),
+    };
+    let handle = with_cursors(|cursors| match cursors.iter().position(Option::is_none) {
+        Some(handle) => {
+            cursors[handle] = Some(cursor);
+            handle
+        },
+        None => {
+            cursors.push(Some(cursor));
+            cursors.len() - 1
+        },
+    });
+    Ok(handle as u32)
+}
+
+/// Reads up to `count` entries of the cursor `handle` and moves it past them. The output is
+/// `1` once the cursor reached the end of the trie, `0` otherwise, followed by the entries as
+/// length-prefixed keys and values.
+pub fn next<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    handle: u32,
+    count: u32,
+) -> CallResult<Vec<u8>> {
+    with_cursors(|cursors| {
+        let Some(cursor) = find(cursors, handle) else {
+            return fail!(CallError::InvalidInput, "unknown cursor {handle}");
+        };
+        read::<L>(db, cursor, count)
+    })
+}
+
+/// Reads the entries of `cursor`, see [`next`].
+fn read<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    cursor: &mut Cursor,
+    count: u32,
+) -> CallResult<Vec<u8>> {
+    let mut output = Vec::from([1]);
+    let Some(start) = &cursor.next else {
+        return Ok(output);
+    };
+    let trie = TrieDBBuilder::<L>::new(&db, &cursor.root).build();
+    let mut iter = trie.iter().map_err(|error| call_error(error.as_ref()))?;
+    iter.seek(start).map_err(|error| call_error(error.as_ref()))?;
+    let mut next = None;
+    for (index, item) in iter.enumerate() {
+        let (key, value) = item.map_err(|error| call_error(error.as_ref()))?;
+        if index == count as usize {
+            next = Some(key);
+            break;
+        }
+        write_bytes(&mut output, &key);
+        write_bytes(&mut output, &value);
+    }
+    output[0] = u8::from(next.is_none());
+    cursor.next = next;
+    Ok(output)
+}
+
+/// Closes the cursor `handle`, its handle is reused by the following cursors.
+pub fn close(handle: u32) -> CallResult<()> {
+    with_cursors(|cursors| {
+        if find(cursors, handle).is_none() {
+            return fail!(CallError::InvalidInput, "unknown cursor {handle}");
+        }
+        cursors[handle as usize] = None;
+        Ok(())
+    })
+}
+
+/// Closes every cursor, before the arena is wiped.
+pub fn close_all() {
+    let _cursors = with_cursors(core::mem::take);
+    // The heap is reset right after, there is nothing to free.
+    #[cfg(not(feature = "dlmalloc"))]
+    core::mem::forget(_cursors);
+}
+
+/// Cursor `handle`, unless it's closed or was opened in another namespace.
+fn find(cursors: &mut [Option<Cursor>], handle: u32) -> Option<&mut Cursor> {
+    let cursor = cursors.get_mut(handle as usize)?.as_mut()?;
+    HostFnImpl::with_namespace(|namespace| cursor.namespace == namespace).then_some(cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        arena,
+        native::{call, clear_storage, CallError, DEFAULT_HANDLE},
+        storage::{split_bytes, write_bytes},
+    };
+    use std::collections::BTreeMap;
+
+    /// Reads the cursor `handle` by pages of `count` entries, one call each, until its end.
+    fn drain(handle: u32, count: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let input = [handle.to_le_bytes(), count.to_le_bytes()].concat();
+        let mut entries = Vec::new();
+        loop {
+            let output = call(DEFAULT_HANDLE, 49, &input).unwrap().output.unwrap();
+            let mut rest = &output[1..];
+            while !rest.is_empty() {
+                let (key, next) = split_bytes(rest).unwrap();
+                let (value, next) = split_bytes(next).unwrap();
+                entries.push((key.to_vec(), value.to_vec()));
+                rest = next;
+            }
+            if output[0] == 1 {
+                return entries;
+            }
+        }
+    }
+
+    #[test]
+    fn resumed_cursors_follow_the_key_order() {
+        clear_storage();
+        arena::set_persistent(true);
+        // The nodes released by the writes are kept, so the cursors can read them.
+        call(DEFAULT_HANDLE, 11, &[1]).unwrap();
+        let mut expected = BTreeMap::new();
+        for index in 0..40u32 {
+            let key = format!("key-{}", index * 7 % 40).into_bytes();
+            let value = index.to_le_bytes().to_vec();
+            let mut input = Vec::new();
+            write_bytes(&mut input, &key);
+            write_bytes(&mut input, &value);
+            call(DEFAULT_HANDLE, 0, &input).unwrap();
+            expected.insert(key, value);
+        }
+
+        let handle = call(DEFAULT_HANDLE, 48, &[]).unwrap().ret as u32;
+        assert_eq!(drain(handle, 3), expected.clone().into_iter().collect::<Vec<_>>());
+
+        // A cursor opened from a key starts at the first key not below it, and doesn't see the
+        // writes committed after it was opened.
+        let from = call(DEFAULT_HANDLE, 48, b"key-2").unwrap().ret as u32;
+        call(DEFAULT_HANDLE, 1, b"key-30").unwrap();
+        let tail = expected.range(b"key-2".to_vec()..).map(|(k, v)| (k.clone(), v.clone()));
+        assert_eq!(drain(from, 7), tail.collect::<Vec<_>>());
+
+        call(DEFAULT_HANDLE, 50, &handle.to_le_bytes()).unwrap();
+        let input = [handle.to_le_bytes(), 1u32.to_le_bytes()].concat();
+        assert_eq!(call(DEFAULT_HANDLE, 49, &input), Err(CallError::InvalidInput));
+        arena::set_persistent(false);
+    }
+}
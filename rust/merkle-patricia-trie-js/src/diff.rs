@@ -0,0 +1,317 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Key/value level difference between two roots.
+//!
+//! Both tries are walked side by side, subtrees referenced by the same hash at the same path
+//! are identical and skipped without being loaded.
+use crate::{
+    abort,
+    error::CallResult,
+    node::{nibbles_to_key, NodeRef, NodeValue, OwnedNode},
+    primitives::H256,
+    storage::{write_bytes, TrieLayout},
+};
+use alloc::vec::Vec;
+use hash_db::HashDB;
+use trie_db::DBValue;
+
+const ADDED: u8 = 0;
+const REMOVED: u8 = 1;
+const MODIFIED: u8 = 2;
+
+pub enum Change {
+    Added { key: Vec<u8>, value: Vec<u8> },
+    Removed { key: Vec<u8>, value: Vec<u8> },
+    Modified { key: Vec<u8>, old: Vec<u8>, new: Vec<u8> },
+}
+
+impl Change {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Self::Added { key, .. } | Self::Removed { key, .. } | Self::Modified { key, .. } => key,
+        }
+    }
+
+    /// Value of the key once changed, `None` when it was removed.
+    pub fn value(&self) -> Option<&[u8]> {
+        match self {
+            Self::Added { value, .. } | Self::Modified { new: value, .. } => Some(value),
+            Self::Removed { .. } => None,
+        }
+    }
+}
+
+/// A subtree and the nibble path leading to it, excluding the node partial key.
+struct Subtree {
+    prefix: Vec<u8>,
+    node: NodeRef,
+}
+
+struct Differ<'a, L: TrieLayout> {
+    db: &'a dyn HashDB<L::Hash, DBValue>,
+    changes: Vec<Change>,
+}
+
+impl<L: TrieLayout> Differ<'_, L> {
+    fn change(
+        &mut self,
+        kind: u8,
+        path: &[u8],
+        old: Option<&NodeValue>,
+        new: Option<&NodeValue>,
+    ) -> CallResult<()> {
+        let key = nibbles_to_key(path);
+        let old = old.map(|value| value.resolve(self.db)).transpose()?;
+        let new = new.map(|value| value.resolve(self.db)).transpose()?;
+        let change = match (kind, old, new) {
+            (ADDED, None, Some(value)) => Change::Added { key, value },
+            (REMOVED, Some(value), None) => Change::Removed { key, value },
+            (MODIFIED, Some(old), Some(new)) => Change::Modified { key, old, new },
+            _ => abort!("[bug] invalid change"),
+        };
+        self.changes.push(change);
+        Ok(())
+    }
+
+    /// Reports every value in the subtree as added (`kind = ADDED`) or removed.
+    fn all(&mut self, kind: u8, subtree: Subtree) -> CallResult<()> {
+        let node = OwnedNode::load::<L>(self.db, &subtree.node)?;
+        let mut path = subtree.prefix;
+        path.extend_from_slice(&node.partial);
+        if let Some(value) = node.value.as_ref() {
+            let (old, new) = if kind == ADDED { (None, Some(value)) } else { (Some(value), None) };
+            self.change(kind, &path, old, new)?;
+        }
+        for (nibble, child) in node.children.into_iter().enumerate() {
+            let Some(child) = child else {
+                continue;
+            };
+            let mut prefix = path.clone();
+            prefix.push(nibble as u8);
+            self.all(kind, Subtree { prefix, node: child })?;
+        }
+        Ok(())
+    }
+
+    fn diff(&mut self, old: Option<Subtree>, new: Option<Subtree>) -> CallResult<()> {
+        let (old, new) = match (old, new) {
+            (None, None) => return Ok(()),
+            (Some(old), None) => return self.all(REMOVED, old),
+            (None, Some(new)) => return self.all(ADDED, new),
+            (Some(old), Some(new)) => (old, new),
+        };
+        if old.prefix == new.prefix && old.node == new.node {
+            return Ok(());
+        }
+        let old_node = OwnedNode::load::<L>(self.db, &old.node)?;
+        let new_node = OwnedNode::load::<L>(self.db, &new.node)?;
+        let mut old_path = old.prefix.clone();
+        old_path.extend_from_slice(&old_node.partial);
+        let mut new_path = new.prefix.clone();
+        new_path.extend_from_slice(&new_node.partial);
+
+        if old_path == new_path {
+            match (old_node.value.as_ref(), new_node.value.as_ref()) {
+                (Some(a), Some(b)) if a != b => {
+                    self.change(MODIFIED, &old_path, Some(a), Some(b))?
+                },
+                (Some(a), None) => self.change(REMOVED, &old_path, Some(a), None)?,
+                (None, Some(b)) => self.change(ADDED, &new_path, None, Some(b))?,
+                _ => {},
+            }
+            let children = old_node.children.into_iter().zip(new_node.children);
+            for (nibble, (a, b)) in children.enumerate() {
+                let mut prefix = old_path.clone();
+                prefix.push(nibble as u8);
+                let a = a.map(|node| Subtree { prefix: prefix.clone(), node });
+                let b = b.map(|node| Subtree { prefix, node });
+                self.diff(a, b)?;
+            }
+        } else if new_path.starts_with(&old_path) {
+            // The new node is nested under one of the old node children.
+            if let Some(value) = old_node.value.as_ref() {
+                self.change(REMOVED, &old_path, Some(value), None)?;
+            }
+            let mut new = Some(new);
+            for (nibble, child) in old_node.children.into_iter().enumerate() {
+                let mut prefix = old_path.clone();
+                prefix.push(nibble as u8);
+                let other = new.take_if(|_| new_path[old_path.len()] == nibble as u8);
+                self.diff(child.map(|node| Subtree { prefix, node }), other)?;
+            }
+            if let Some(new) = new {
+                self.all(ADDED, new)?;
+            }
+        } else if old_path.starts_with(&new_path) {
+            // The old node is nested under one of the new node children.
+            if let Some(value) = new_node.value.as_ref() {
+                self.change(ADDED, &new_path, None, Some(value))?;
+            }
+            let mut old = Some(old);
+            for (nibble, child) in new_node.children.into_iter().enumerate() {
+                let mut prefix = new_path.clone();
+                prefix.push(nibble as u8);
+                let other = old.take_if(|_| old_path[new_path.len()] == nibble as u8);
+                self.diff(other, child.map(|node| Subtree { prefix, node }))?;
+            }
+            if let Some(old) = old {
+                self.all(REMOVED, old)?;
+            }
+        } else {
+            self.all(REMOVED, old)?;
+            self.all(ADDED, new)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the changes needed to go from `old` to `new`, fails with `KeyNotFound` when a node of
+/// either trie isn't stored.
+pub fn changes<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    old: &H256,
+    new: &H256,
+) -> CallResult<Vec<Change>> {
+    let mut differ = Differ::<L> { db, changes: Vec::new() };
+    let old = Subtree { prefix: Vec::new(), node: NodeRef::root(old) };
+    let new = Subtree { prefix: Vec::new(), node: NodeRef::root(new) };
+    differ.diff(Some(old), Some(new))?;
+    Ok(differ.changes)
+}
+
+/// Same as [`changes`], each change encoded as its kind (`0` added, `1` removed, `2` modified)
+/// followed by the length-prefixed key, the old value when removed or modified, and the new
+/// value when added or modified.
+pub fn diff<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    old: &H256,
+    new: &H256,
+) -> CallResult<Vec<u8>> {
+    let mut output = Vec::new();
+    for change in changes::<L>(db, old, new)? {
+        match change {
+            Change::Added { key, value } => {
+                output.push(ADDED);
+                write_bytes(&mut output, &key);
+                write_bytes(&mut output, &value);
+            },
+            Change::Removed { key, value } => {
+                output.push(REMOVED);
+                write_bytes(&mut output, &key);
+                write_bytes(&mut output, &value);
+            },
+            Change::Modified { key, old, new } => {
+                output.push(MODIFIED);
+                write_bytes(&mut output, &key);
+                write_bytes(&mut output, &old);
+                write_bytes(&mut output, &new);
+            },
+        }
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::CallError,
+        ethereum::EthereumLayout,
+        primitives::{Blake2Hasher, LayoutV1, MemoryDB},
+    };
+    use hash_db::EMPTY_PREFIX;
+    use trie_db::{NodeCodec, TrieDBMutBuilder, TrieMut};
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    fn build<L: TrieLayout>(db: &mut MemoryDB<L::Hash>, entries: &[(&[u8], &[u8])]) -> H256 {
+        let mut root = H256::zero();
+        let mut trie = TrieDBMutBuilder::<L>::new(db, &mut root).build();
+        for (key, value) in entries {
+            trie.insert(key, value).unwrap();
+        }
+        drop(trie);
+        root
+    }
+
+    fn decode(mut output: &[u8]) -> Vec<(u8, Vec<u8>, Vec<Vec<u8>>)> {
+        fn read<'a>(input: &mut &'a [u8]) -> &'a [u8] {
+            let (len, rest) = input.split_first_chunk::<4>().unwrap();
+            let (bytes, rest) = rest.split_at(u32::from_le_bytes(*len) as usize);
+            *input = rest;
+            bytes
+        }
+        let mut changes = Vec::new();
+        while let Some((&kind, rest)) = output.split_first() {
+            output = rest;
+            let key = read(&mut output).to_vec();
+            let values = if kind == MODIFIED { 2 } else { 1 };
+            let values = (0..values).map(|_| read(&mut output).to_vec()).collect();
+            changes.push((kind, key, values));
+        }
+        changes.sort();
+        changes
+    }
+
+    fn check_diff<L: TrieLayout>() {
+        let mut db = MemoryDB::<L::Hash>::new(L::Codec::empty_node());
+        let large = [7u8; 64];
+        let old = build::<L>(
+            &mut db,
+            &[(b"alpha", b"1"), (b"alphabet", b"2"), (b"beta", &large), (b"gamma", b"3")],
+        );
+        let new = build::<L>(
+            &mut db,
+            &[(b"alpha", b"1"), (b"alphanumeric", b"4"), (b"beta", b"5"), (b"gamma", b"3")],
+        );
+
+        let changes = decode(&diff::<L>(&db, &old, &new).unwrap());
+        assert_eq!(
+            changes,
+            vec![
+                (ADDED, b"alphanumeric".to_vec(), vec![b"4".to_vec()]),
+                (REMOVED, b"alphabet".to_vec(), vec![b"2".to_vec()]),
+                (MODIFIED, b"beta".to_vec(), vec![large.to_vec(), b"5".to_vec()]),
+            ]
+        );
+        assert!(diff::<L>(&db, &old, &old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_modified_keys() {
+        check_diff::<Layout>();
+        check_diff::<EthereumLayout>();
+    }
+
+    #[test]
+    fn diff_against_empty_root() {
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        let empty = build::<Layout>(&mut db, &[]);
+        let root = build::<Layout>(&mut db, &[(b"a", b"1"), (b"ab", b"2")]);
+
+        let added = decode(&diff::<Layout>(&db, &empty, &root).unwrap());
+        assert_eq!(
+            added,
+            vec![
+                (ADDED, b"a".to_vec(), vec![b"1".to_vec()]),
+                (ADDED, b"ab".to_vec(), vec![b"2".to_vec()])
+            ]
+        );
+        let removed = decode(&diff::<Layout>(&db, &root, &empty).unwrap());
+        assert!(removed.iter().all(|(kind, ..)| *kind == REMOVED));
+        assert_eq!(removed.len(), 2);
+    }
+
+    #[test]
+    fn diff_against_a_removed_root_fails() {
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        let root = build::<Layout>(&mut db, &[(b"a", b"1"), (b"b", b"2")]);
+        let removed = build::<Layout>(&mut db, &[(b"a", b"1"), (b"c", b"3")]);
+        HashDB::remove(&mut db, &removed, EMPTY_PREFIX);
+
+        assert_eq!(changes::<Layout>(&db, &root, &removed).err(), Some(CallError::KeyNotFound));
+        assert_eq!(diff::<Layout>(&db, &removed, &root).err(), Some(CallError::KeyNotFound));
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Graphviz DOT rendering of the decoded node graph.
+//!
+//! Each node is labelled with the first bytes of its hash, `inline` for the nodes embedded in
+//! their parent, and its partial key. The edges to the children of a branch are labelled with
+//! their nibble, nodes shared by several parents are drawn once.
+use crate::{children::NodeId, trie::NodeArena};
+use alloc::string::String;
+use core::fmt::Write;
+
+/// Bytes of the hash shown in the label of a node.
+const HASH_PREFIX: usize = 4;
+
+const ALPHABET: &[u8; 16] = b"0123456789abcdef";
+
+/// Renders the nodes, `root` is the index of the root node.
+pub fn encode(root: NodeId, nodes: &NodeArena) -> String {
+    let mut output = String::with_capacity(nodes.len() * 96);
+    output.push_str("digraph trie {\n  node [shape=box, fontname=\"monospace\"];\n");
+    let _ = writeln!(output, "  root [shape=point];\n  root -> n{root};");
+    for index in nodes.ids() {
+        let node = &nodes[index];
+        let _ = write!(output, "  n{index} [label=\"");
+        match node.id.as_ref() {
+            Some(id) => {
+                output.push_str("0x");
+                write_digits(&mut output, id[..HASH_PREFIX].iter().flat_map(|b| [b >> 4, b & 15]));
+                output.push_str("...");
+            },
+            None => output.push_str("inline"),
+        }
+        if let Some(nibbles) = node.nibbles().filter(|slice| !slice.is_empty()) {
+            output.push_str("\\n");
+            write_digits(&mut output, nibbles.iter());
+        }
+        if node.value.is_some() {
+            output.push_str("\\n(value)");
+        }
+        output.push('"');
+        if node.highlighted {
+            output.push_str(", style=filled");
+        }
+        output.push_str("];\n");
+        for (child, nibble) in node.children.iter() {
+            let _ = writeln!(output, "  n{index} -> n{child} [label=\"{nibble:x}\"];");
+        }
+        if let Some(child) = node.children.extension() {
+            let _ = writeln!(output, "  n{index} -> n{child};");
+        }
+    }
+    output.push_str("}\n");
+    output
+}
+
+fn write_digits<I: Iterator<Item = u8>>(output: &mut String, digits: I) {
+    for digit in digits {
+        output.push(char::from(ALPHABET[usize::from(digit & 15)]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        children::Children,
+        primitives::H256,
+        trie::{NodeKind, TrieNode},
+    };
+    use trie_db::node::NibbleSlicePlan;
+
+    #[test]
+    fn labels_nodes_and_edges() {
+        let leaf = TrieNode {
+            id: None,
+            kind: NodeKind::Leaf,
+            depth: 1,
+            partial: Some(NibbleSlicePlan::new(0..1, 1)),
+            value: Some(vec![7]),
+            children: Children::new(),
+            raw_bytes: vec![0xab],
+            highlighted: true,
+            truncated: false,
+            status: None,
+            references: 0,
+            matched_nibbles: None,
+            collapsed: Vec::new(),
+        };
+        let mut nodes = NodeArena::default();
+        let mut children = Children::new();
+        children.push(nodes.push(leaf), 10);
+        let root = TrieNode {
+            id: Some(H256::repeat_byte(0x1f)),
+            kind: NodeKind::Branch,
+            depth: 0,
+            partial: None,
+            value: None,
+            children,
+            raw_bytes: Vec::new(),
+            highlighted: false,
+            truncated: false,
+            status: None,
+            references: 0,
+            matched_nibbles: None,
+            collapsed: Vec::new(),
+        };
+        let root = nodes.push(root);
+        assert_eq!(
+            encode(root, &nodes),
+            "digraph trie {\n  node [shape=box, fontname=\"monospace\"];\n  root [shape=point];\n  \
+             root -> n1;\n  n0 [label=\"inline\\nb\\n(value)\", style=filled];\n  \
+             n1 [label=\"0x1f1f1f1f...\"];\n  n1 -> n0 [label=\"a\"];\n}\n"
+        );
+    }
+}
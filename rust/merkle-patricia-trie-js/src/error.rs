@@ -0,0 +1,213 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Result codes of `__ext_call`.
+//!
+//! Recoverable failures are returned to the host instead of trapping: the writes of the call
+//! are rolled back and the instance stays usable. The host tells them apart from the regular
+//! results by [`ERROR_FLAG`], the low bits hold the [`CallError`] code.
+//!
+//! Every failure, recoverable or not, also records its details in a static buffer the host
+//! reads with `__ext_last_error`, even after a trap.
+#[cfg(feature = "enable-debug-log")]
+use crate::log::{Category, Level};
+use crate::{host::HostFnImpl, storage::write_bytes, trie::TrieErrorKind};
+use alloc::vec::Vec;
+use core::{
+    cell::Cell,
+    fmt::{self, Write},
+};
+use wasm_bindgen::prelude::*;
+
+/// Bit set in the result of a failed call.
+pub const ERROR_FLAG: u64 = 1 << 63;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CallError {
+    /// The call code is unknown.
+    InvalidCall = 1,
+    /// The input is truncated, out of bounds or holds an unknown flag.
+    InvalidInput = 2,
+    /// A node, root or checkpoint referenced by the call isn't stored.
+    KeyNotFound = 3,
+    /// The host storage can't hold a value written by the call.
+    ValueTooLarge = 4,
+    /// A stored node or a proof can't be decoded.
+    DecodeError = 5,
+}
+
+impl CallError {
+    /// Result of `__ext_call` reporting this error.
+    pub fn encode(self) -> u64 {
+        ERROR_FLAG | self as u64
+    }
+}
+
+pub type CallResult<T> = Result<T, CallError>;
+
+/// Error of a storage write, the call fails once it returns.
+struct Deferred(Cell<Option<CallError>>);
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for Deferred {}
+
+static DEFERRED: Deferred = Deferred(Cell::new(None));
+
+/// Fails the current call when it returns, for errors raised where they can't be returned.
+/// The first error is kept.
+pub fn defer(error: CallError) {
+    if DEFERRED.0.get().is_none() {
+        DEFERRED.0.set(Some(error));
+    }
+}
+
+/// Takes the error deferred by the current call.
+pub fn take_deferred() -> Option<CallError> {
+    DEFERRED.0.take()
+}
+
+/// Code recorded by the failures trapping the instance.
+pub const ABORTED: u8 = 0;
+
+/// Bytes of the message kept, longer messages are cut.
+const MESSAGE_LEN: usize = 192;
+
+/// Bytes of the offending key or hash kept.
+const SUBJECT_LEN: usize = 64;
+
+#[derive(Clone, Copy)]
+struct Detail {
+    code: u8,
+    subject: [u8; SUBJECT_LEN],
+    subject_len: usize,
+    message: [u8; MESSAGE_LEN],
+    message_len: usize,
+    /// Kind of the trie error behind the failure, if any.
+    trie_error: Option<TrieErrorKind>,
+}
+
+impl Write for Detail {
+    /// Appends `text`, cut at a character boundary once the buffer is full.
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let mut end = text.len().min(MESSAGE_LEN - self.message_len);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.message[self.message_len..self.message_len + end]
+            .copy_from_slice(&text.as_bytes()[..end]);
+        self.message_len += end;
+        Ok(())
+    }
+}
+
+/// Details of the last failure, kept out of the heap so they outlive the call.
+struct LastError(Cell<Option<Detail>>);
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for LastError {}
+
+static LAST_ERROR: LastError = LastError(Cell::new(None));
+
+/// Records a failure with its code, [`ABORTED`] or a [`CallError`], the offending key or hash,
+/// empty when there is none, and the message. Debug builds log the whole message.
+pub fn record(code: u8, subject: &[u8], message: fmt::Arguments) {
+    #[cfg(feature = "enable-debug-log")]
+    {
+        let level = if code == ABORTED { Level::Error } else { Level::Warn };
+        if crate::log::enabled(level, Category::Call) {
+            HostFnImpl::log(level, Category::Call, alloc::format!("{message}").as_str());
+        }
+    }
+
+    let subject_len = subject.len().min(SUBJECT_LEN);
+    let mut detail = Detail {
+        code,
+        subject: [0; SUBJECT_LEN],
+        subject_len,
+        message: [0; MESSAGE_LEN],
+        message_len: 0,
+        trie_error: None,
+    };
+    detail.subject[..subject_len].copy_from_slice(&subject[..subject_len]);
+    let _ = detail.write_fmt(message);
+    LAST_ERROR.0.set(Some(detail));
+}
+
+/// Tags the last failure as caused by a trie error of kind `kind`.
+pub fn set_trie_error(kind: TrieErrorKind) {
+    if let Some(mut detail) = LAST_ERROR.0.get() {
+        detail.trie_error = Some(kind);
+        LAST_ERROR.0.set(Some(detail));
+    }
+}
+
+/// Forgets the failure of the previous call.
+pub fn clear() {
+    LAST_ERROR.0.set(None);
+}
+
+/// Encodes the last failure as its code, the length-prefixed key or hash and the
+/// length-prefixed UTF-8 message, empty when the last call succeeded.
+fn encode_last() -> Vec<u8> {
+    let Some(detail) = LAST_ERROR.0.get() else {
+        return Vec::new();
+    };
+    let mut output = Vec::with_capacity(9 + detail.subject_len + detail.message_len);
+    output.push(detail.code);
+    write_bytes(&mut output, &detail.subject[..detail.subject_len]);
+    write_bytes(&mut output, &detail.message[..detail.message_len]);
+    output
+}
+
+/// Returns the details of the last failure through `__ext_return`, see [`encode_last`].
+#[export_name = "__ext_last_error"]
+pub extern "C" fn last_error() {
+    HostFnImpl::return_value(&encode_last());
+}
+
+/// Kind of the trie error behind the last failure, `undefined` for the other failures.
+#[wasm_bindgen(js_name = "__ext_trie_error_kind")]
+pub fn trie_error_kind() -> Option<TrieErrorKind> {
+    LAST_ERROR.0.get()?.trie_error
+}
+
+/// Hash of the node named by the trie error behind the last failure, `undefined` when the
+/// failure isn't a trie error or names a key instead.
+#[wasm_bindgen(js_name = "__ext_trie_error_hash")]
+pub fn trie_error_hash() -> Option<Vec<u8>> {
+    let detail = LAST_ERROR.0.get()?;
+    detail.trie_error.filter(|kind| kind.has_hash())?;
+    Some(detail.subject[..detail.subject_len].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_flagged() {
+        assert_eq!(CallError::InvalidInput.encode(), (1 << 63) | 2);
+        defer(CallError::ValueTooLarge);
+        defer(CallError::InvalidCall);
+        assert_eq!(take_deferred(), Some(CallError::ValueTooLarge));
+        assert_eq!(take_deferred(), None);
+    }
+
+    #[test]
+    fn long_messages_are_cut_at_a_character_boundary() {
+        let mut detail = Detail {
+            code: ABORTED,
+            subject: [0; SUBJECT_LEN],
+            subject_len: 0,
+            message: [0; MESSAGE_LEN],
+            message_len: MESSAGE_LEN - 3,
+            trie_error: None,
+        };
+        detail.write_str("ab").unwrap();
+        detail.write_str("é").unwrap();
+        assert_eq!(detail.message_len, MESSAGE_LEN - 1);
+        assert_eq!(&detail.message[MESSAGE_LEN - 3..MESSAGE_LEN - 1], b"ab");
+    }
+}
@@ -0,0 +1,482 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Ethereum flavoured trie, producing the same roots as geth.
+//!
+//! Nodes are RLP lists: leaves and extensions hold the hex-prefix encoded partial key followed
+//! by the value or the child reference, branches hold the 16 child references followed by the
+//! value. Children shorter than 32 bytes are embedded in the parent, the others are referenced
+//! by their Keccak-256 hash.
+//!
+//! Proofs omit the children and values the verifier recomputes. An omitted child is encoded
+//! as an empty list, which no real node is, and an omitted leaf value as an empty string.
+//!
+//! The state trie stores the accounts under the Keccak-256 hash of their address, each the RLP
+//! list of its nonce, balance, storage root and code hash.
+use crate::{
+    abort,
+    error::{CallError, CallResult},
+    fail,
+    primitives::KeccakHasher,
+    storage::{split_bytes, write_bytes, Config, HasherKind, LayoutKind},
+};
+use alloc::vec::Vec;
+use core::{borrow::Borrow, marker::PhantomData, ops::Range};
+use hash_db::Hasher;
+use trie_db::{
+    node::{NibbleSlicePlan, NodeHandlePlan, NodePlan, Value, ValuePlan},
+    ChildReference, NodeCodec, TrieConfiguration, TrieLayout,
+};
+
+/// RLP encoding of the empty string, used for missing children and values, and as the empty
+/// trie.
+const EMPTY_STRING: u8 = 0x80;
+const EMPTY_NODE: &[u8] = &[EMPTY_STRING];
+
+/// Number of items in a branch node, the 16 children and the value.
+const BRANCH_ITEMS: usize = 17;
+
+/// Hex-prefix flag telling the partial key belongs to a leaf.
+const LEAF_FLAG: u8 = 0b10;
+/// Hex-prefix flag telling the partial key has an odd number of nibbles.
+const ODD_FLAG: u8 = 0b01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RlpError {
+    /// The input ends before the item does.
+    UnexpectedEnd,
+    /// A list was found where a string is expected, or the other way around.
+    UnexpectedType,
+    /// The node is neither a leaf or extension (2 items) nor a branch (17 items).
+    InvalidItemCount,
+    /// The node is followed by more bytes.
+    TrailingBytes,
+    /// The hex-prefix flag of a partial key is invalid.
+    InvalidPrefix,
+    /// A child reference is neither empty, a 32 bytes hash nor an embedded node.
+    InvalidChild,
+}
+
+/// A decoded RLP item header.
+struct Item {
+    list: bool,
+    /// Range of the item payload in the input.
+    payload: Range<usize>,
+}
+
+fn read_len(data: &[u8], offset: usize, len_of_len: usize) -> Result<usize, RlpError> {
+    if len_of_len > core::mem::size_of::<usize>() {
+        return Err(RlpError::UnexpectedEnd);
+    }
+    let Some(bytes) = data.get(offset..offset + len_of_len) else {
+        return Err(RlpError::UnexpectedEnd);
+    };
+    Ok(bytes.iter().fold(0, |len, byte| (len << 8) | *byte as usize))
+}
+
+/// Decodes the header of the item starting at `offset`.
+fn decode_item(data: &[u8], offset: usize) -> Result<Item, RlpError> {
+    let Some(&prefix) = data.get(offset) else {
+        return Err(RlpError::UnexpectedEnd);
+    };
+    let (list, start, len) = match prefix {
+        0x00..=0x7f => (false, offset, 1),
+        0x80..=0xb7 => (false, offset + 1, usize::from(prefix - 0x80)),
+        0xb8..=0xbf => {
+            let len_of_len = usize::from(prefix - 0xb7);
+            (false, offset + 1 + len_of_len, read_len(data, offset + 1, len_of_len)?)
+        },
+        0xc0..=0xf7 => (true, offset + 1, usize::from(prefix - 0xc0)),
+        0xf8..=0xff => {
+            let len_of_len = usize::from(prefix - 0xf7);
+            (true, offset + 1 + len_of_len, read_len(data, offset + 1, len_of_len)?)
+        },
+    };
+    match start.checked_add(len) {
+        Some(end) if end <= data.len() => Ok(Item { list, payload: start..end }),
+        _ => Err(RlpError::UnexpectedEnd),
+    }
+}
+
+fn encode_header(output: &mut Vec<u8>, len: usize, offset: u8) {
+    if len < 56 {
+        output.push(offset + len as u8);
+    } else {
+        let bytes = (len as u64).to_be_bytes();
+        let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+        output.push(offset + 55 + (bytes.len() - skip) as u8);
+        output.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+fn encode_string(output: &mut Vec<u8>, bytes: &[u8]) {
+    if let [byte @ 0x00..=0x7f] = bytes {
+        output.push(*byte);
+    } else {
+        encode_header(output, bytes.len(), EMPTY_STRING);
+        output.extend_from_slice(bytes);
+    }
+}
+
+/// Encodes a big endian integer, without its leading zeros.
+fn encode_integer(output: &mut Vec<u8>, bytes: &[u8]) {
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+    encode_string(output, &bytes[skip..]);
+}
+
+fn encode_list_header(output: &mut Vec<u8>, len: usize) {
+    encode_header(output, len, 0xc0);
+}
+
+fn encode_list(payload: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(payload.len() + 9);
+    encode_list_header(&mut output, payload.len());
+    output.extend_from_slice(payload);
+    output
+}
+
+/// Hex-prefix encodes a partial key, given as packed bytes where the first byte only holds the
+/// low nibble when the number of nibbles is odd.
+fn encode_partial(
+    output: &mut Vec<u8>,
+    mut partial: impl Iterator<Item = u8>,
+    number_nibble: usize,
+    flag: u8,
+) {
+    let mut path = Vec::with_capacity(number_nibble / 2 + 1);
+    if number_nibble % 2 == 1 {
+        let first = partial.next().unwrap_or_default() & 0x0f;
+        path.push(((flag | ODD_FLAG) << 4) | first);
+    } else {
+        path.push(flag << 4);
+    }
+    path.extend(partial);
+    encode_string(output, &path);
+}
+
+/// Decodes a hex-prefix encoded partial key, returning the nibbles and the flag.
+fn decode_partial(data: &[u8], item: &Item) -> Result<(NibbleSlicePlan, u8), RlpError> {
+    if item.list {
+        return Err(RlpError::UnexpectedType);
+    }
+    let Some(&first) = data.get(item.payload.start).filter(|_| !item.payload.is_empty()) else {
+        return Err(RlpError::InvalidPrefix);
+    };
+    let flag = first >> 4;
+    if flag > (LEAF_FLAG | ODD_FLAG) {
+        return Err(RlpError::InvalidPrefix);
+    }
+    let partial = if flag & ODD_FLAG != 0 {
+        NibbleSlicePlan::new(item.payload.clone(), 1)
+    } else {
+        NibbleSlicePlan::new(item.payload.start + 1..item.payload.end, 0)
+    };
+    Ok((partial, flag & LEAF_FLAG))
+}
+
+fn encode_child<H: AsRef<[u8]>>(output: &mut Vec<u8>, child: Option<&ChildReference<H>>) {
+    match child {
+        Some(ChildReference::Hash(hash)) => encode_string(output, hash.as_ref()),
+        Some(ChildReference::Inline(_, 0)) => encode_list_header(output, 0),
+        Some(ChildReference::Inline(node, len)) => output.extend_from_slice(&node.as_ref()[..*len]),
+        None => output.push(EMPTY_STRING),
+    }
+}
+
+/// Decodes the child reference at `offset`, embedded nodes are lists.
+fn decode_child(data: &[u8], offset: usize) -> Result<Option<NodeHandlePlan>, RlpError> {
+    let item = decode_item(data, offset)?;
+    if item.list && item.payload.is_empty() {
+        return Ok(Some(NodeHandlePlan::Inline(item.payload)));
+    }
+    if item.list {
+        return Ok(Some(NodeHandlePlan::Inline(offset..item.payload.end)));
+    }
+    match item.payload.len() {
+        0 => Ok(None),
+        32 => Ok(Some(NodeHandlePlan::Hash(item.payload))),
+        _ => Err(RlpError::InvalidChild),
+    }
+}
+
+fn encode_value(output: &mut Vec<u8>, value: Option<Value>) {
+    match value {
+        Some(Value::Inline(value)) => encode_string(output, value),
+        Some(Value::Node(_)) => abort!("values are never stored in separated nodes"),
+        None => output.push(EMPTY_STRING),
+    }
+}
+
+fn decode_value(data: &[u8], offset: usize) -> Result<Option<ValuePlan>, RlpError> {
+    let item = decode_item(data, offset)?;
+    if item.list {
+        return Err(RlpError::UnexpectedType);
+    }
+    if item.payload.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ValuePlan::Inline(item.payload)))
+}
+
+/// Node codec producing the RLP encoded nodes of the Ethereum trie.
+pub struct RlpNodeCodec<H>(PhantomData<H>);
+
+impl<H: Hasher> NodeCodec for RlpNodeCodec<H> {
+    type Error = RlpError;
+    type HashOut = H::Out;
+
+    fn hashed_null_node() -> Self::HashOut {
+        H::hash(EMPTY_NODE)
+    }
+
+    fn decode_plan(data: &[u8]) -> Result<NodePlan, Self::Error> {
+        if Self::is_empty_node(data) {
+            return Ok(NodePlan::Empty);
+        }
+        let node = decode_item(data, 0)?;
+        if !node.list {
+            return Err(RlpError::UnexpectedType);
+        }
+        if node.payload.end != data.len() {
+            return Err(RlpError::TrailingBytes);
+        }
+        let mut offsets = [0usize; BRANCH_ITEMS];
+        let mut count = 0;
+        let mut offset = node.payload.start;
+        while offset < node.payload.end {
+            if count == BRANCH_ITEMS {
+                return Err(RlpError::InvalidItemCount);
+            }
+            offsets[count] = offset;
+            count += 1;
+            offset = decode_item(data, offset)?.payload.end;
+        }
+        match count {
+            2 => {
+                let (partial, flag) = decode_partial(data, &decode_item(data, offsets[0])?)?;
+                if flag == LEAF_FLAG {
+                    let value = decode_value(data, offsets[1])?
+                        .unwrap_or(ValuePlan::Inline(offsets[1] + 1..offsets[1] + 1));
+                    return Ok(NodePlan::Leaf { partial, value });
+                }
+                let Some(child) = decode_child(data, offsets[1])? else {
+                    return Err(RlpError::InvalidChild);
+                };
+                Ok(NodePlan::Extension { partial, child })
+            },
+            BRANCH_ITEMS => {
+                let mut children = [const { None }; 16];
+                for (child, offset) in children.iter_mut().zip(offsets) {
+                    *child = decode_child(data, offset)?;
+                }
+                let value = decode_value(data, offsets[16])?;
+                Ok(NodePlan::Branch { value, children })
+            },
+            _ => Err(RlpError::InvalidItemCount),
+        }
+    }
+
+    fn is_empty_node(data: &[u8]) -> bool {
+        data == EMPTY_NODE
+    }
+
+    fn empty_node() -> &'static [u8] {
+        EMPTY_NODE
+    }
+
+    fn leaf_node(partial: impl Iterator<Item = u8>, number_nibble: usize, value: Value) -> Vec<u8> {
+        let mut payload = Vec::new();
+        encode_partial(&mut payload, partial, number_nibble, LEAF_FLAG);
+        encode_value(&mut payload, Some(value));
+        encode_list(&payload)
+    }
+
+    fn extension_node(
+        partial: impl Iterator<Item = u8>,
+        number_nibble: usize,
+        child_ref: ChildReference<Self::HashOut>,
+    ) -> Vec<u8> {
+        let mut payload = Vec::new();
+        encode_partial(&mut payload, partial, number_nibble, 0);
+        encode_child(&mut payload, Some(&child_ref));
+        encode_list(&payload)
+    }
+
+    fn branch_node(
+        children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+        value: Option<Value>,
+    ) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for child in children {
+            encode_child(&mut payload, child.borrow().as_ref());
+        }
+        encode_value(&mut payload, value);
+        encode_list(&payload)
+    }
+
+    fn branch_node_nibbled(
+        _partial: impl Iterator<Item = u8>,
+        _number_nibble: usize,
+        _children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+        _value: Option<Value>,
+    ) -> Vec<u8> {
+        abort!("[bug] branches never have a partial key when using extension nodes");
+    }
+}
+
+/// Layout of the Ethereum trie, keys are used as is, hashing them is left to the caller. Other
+/// hashers than Keccak-256 give the same structure with different roots.
+pub struct EthereumLayout<H = KeccakHasher>(PhantomData<H>);
+
+impl<H: Hasher> TrieLayout for EthereumLayout<H> {
+    const USE_EXTENSION: bool = true;
+    const ALLOW_EMPTY: bool = false;
+    const MAX_INLINE_VALUE: Option<u32> = None;
+
+    type Hash = H;
+    type Codec = RlpNodeCodec<H>;
+}
+
+impl<H: Hasher> TrieConfiguration for EthereumLayout<H> {
+    /// Ordered tries, as the transactions and receipts of a block, are keyed by the RLP
+    /// encoding of the index.
+    fn encode_index(index: u32) -> Vec<u8> {
+        let mut output = Vec::with_capacity(5);
+        encode_integer(&mut output, &index.to_be_bytes());
+        output
+    }
+}
+
+/// Decodes an account of the state trie, the RLP list of its nonce, balance, storage root and
+/// code hash. The integers are the big endian bytes without leading zeros.
+pub fn decode_account(value: &[u8]) -> Option<[&[u8]; 4]> {
+    let list = decode_item(value, 0).ok().filter(|item| item.list)?;
+    if list.payload.end != value.len() {
+        return None;
+    }
+    let mut fields = [&[][..]; 4];
+    let mut offset = list.payload.start;
+    for field in &mut fields {
+        let item = decode_item(value, offset).ok();
+        let item = item.filter(|item| !item.list && item.payload.end <= list.payload.end)?;
+        *field = &value[item.payload.clone()];
+        offset = item.payload.end;
+    }
+    (offset == list.payload.end).then_some(fields)
+}
+
+/// Entry of an account in the input format of an insert. The input holds the length-prefixed
+/// address, the nonce as a little endian `u64`, then the balance as 32 big endian bytes, the
+/// storage root and the code hash. The storage must be configured as the Ethereum state trie,
+/// which stores the accounts under the Keccak-256 hash of their address.
+pub fn account_entry(input: &[u8]) -> CallResult<Vec<u8>> {
+    let config = Config::load();
+    if config.layout != LayoutKind::Ethereum ||
+        config.hasher != HasherKind::Keccak ||
+        !config.secure_keys
+    {
+        return fail!(CallError::InvalidCall, "accounts are stored in the Ethereum secure trie");
+    }
+    let (address, fields) = split_bytes(input)?;
+    let Some((nonce, fields)) = fields.split_first_chunk::<8>() else {
+        return fail!(CallError::InvalidInput, "expected the nonce of the account");
+    };
+    let ([balance, storage_root, code_hash], []) = fields.as_chunks::<32>() else {
+        return fail!(CallError::InvalidInput, "expected the balance, storage root and code hash");
+    };
+    let mut payload = Vec::with_capacity(112);
+    encode_integer(&mut payload, &u64::from_le_bytes(*nonce).to_be_bytes());
+    encode_integer(&mut payload, balance);
+    encode_string(&mut payload, storage_root);
+    encode_string(&mut payload, code_hash);
+    let mut entry = Vec::with_capacity(address.len() + payload.len() + 11);
+    write_bytes(&mut entry, address);
+    write_bytes(&mut entry, &encode_list(&payload));
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{MemoryDB, H256};
+    use trie_db::{Trie, TrieDBBuilder, TrieDBMutBuilder, TrieMut};
+
+    fn build(db: &mut MemoryDB<KeccakHasher>, entries: &[(&[u8], &[u8])]) -> H256 {
+        let mut root = H256::zero();
+        let mut trie = TrieDBMutBuilder::<EthereumLayout>::new(db, &mut root).build();
+        for (key, value) in entries {
+            trie.insert(key, value).unwrap();
+        }
+        drop(trie);
+        root
+    }
+
+    #[test]
+    fn roots_match_geth() {
+        let mut db = MemoryDB::<KeccakHasher>::new(EMPTY_NODE);
+        assert_eq!(
+            build(&mut db, &[]),
+            H256(hex_literal::hex!(
+                "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+            ))
+        );
+        let root =
+            build(&mut db, &[(b"doe", b"reindeer"), (b"dog", b"puppy"), (b"dogglesworth", b"cat")]);
+        assert_eq!(
+            root,
+            H256(hex_literal::hex!(
+                "8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"
+            ))
+        );
+        let root = build(&mut db, &[(b"A", &[b'a'; 50])]);
+        assert_eq!(
+            root,
+            H256(hex_literal::hex!(
+                "d23786fb4a010da3ce639d66d5e904a11dbc02746d1ce25029e53290cabf28ab"
+            ))
+        );
+    }
+
+    #[test]
+    fn stored_nodes_decode() {
+        let mut db = MemoryDB::<KeccakHasher>::new(EMPTY_NODE);
+        let entries: [(&[u8], &[u8]); 4] =
+            [(b"do", b"verb"), (b"dog", b"puppy"), (b"doge", b"coin"), (b"horse", b"stallion")];
+        let root = build(&mut db, &entries);
+        let trie = TrieDBBuilder::<EthereumLayout>::new(&db, &root).build();
+        for (key, value) in entries {
+            assert_eq!(trie.get(key).unwrap().as_deref(), Some(value));
+        }
+        assert_eq!(trie.get(b"d").unwrap(), None);
+        assert_eq!(trie.iter().unwrap().count(), entries.len());
+    }
+
+    #[test]
+    fn accounts_are_stored_by_address_hash() {
+        use crate::{
+            error::CallError,
+            native::{call, clear_storage, DEFAULT_HANDLE},
+        };
+
+        let address = [0x11; 20];
+        let mut input = Vec::new();
+        write_bytes(&mut input, &address);
+        input.extend_from_slice(&5u64.to_le_bytes());
+        input.extend_from_slice(&[&[0; 30][..], &[0x03, 0xe8]].concat());
+        input.extend_from_slice(&[0xaa; 32]);
+        input.extend_from_slice(&[0xbb; 32]);
+        clear_storage();
+        assert_eq!(call(DEFAULT_HANDLE, 71, &input).err(), Some(CallError::InvalidCall));
+
+        call(DEFAULT_HANDLE, 26, &[1, 2, 1]).unwrap();
+        call(DEFAULT_HANDLE, 71, &input).unwrap();
+        let account = call(DEFAULT_HANDLE, 3, &address).unwrap().output.unwrap();
+        let fields = decode_account(&account).unwrap();
+        assert_eq!(fields, [&[5][..], &[0x03, 0xe8], &[0xaa; 32], &[0xbb; 32]]);
+
+        let mut db = MemoryDB::<KeccakHasher>::new(EMPTY_NODE);
+        let root = build(&mut db, &[(&KeccakHasher::hash(&address).0, &account)]);
+        assert_eq!(call(DEFAULT_HANDLE, 4, &[]).unwrap().output.as_deref(), Some(&root.0[..]));
+    }
+}
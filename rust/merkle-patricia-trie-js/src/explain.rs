@@ -0,0 +1,200 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Explanation of an insert or a removal, the nodes it changed on the way to the new root.
+//!
+//! The nodes of the former and the resulting trie are paired by their path, the nibbles leading
+//! to them from the root. A node found at the same path with the same kind and partial key was
+//! rehashed, its value or one of its children changed. Otherwise the former node was deleted
+//! and the resulting one created. The unchanged nodes aren't listed.
+use crate::{
+    children::NodeId,
+    error::CallResult,
+    primitives::H256,
+    steps::split_operation,
+    storage::{trie_key, write_bytes, ExternalDB, OverlayDB, TrieLayout},
+    trie::{decode_nodes, mark_lookup, MerklePatriciaTrie, NodeArena, TrieNode},
+};
+use alloc::{collections::BTreeMap, vec::Vec};
+
+const CREATED: u8 = 0;
+const DELETED: u8 = 1;
+const REHASHED: u8 = 2;
+
+/// Lists the path of the node at `index` and of its descendants, with their index.
+fn collect_paths(
+    nodes: &NodeArena,
+    index: NodeId,
+    path: Vec<u8>,
+    paths: &mut Vec<(Vec<u8>, NodeId)>,
+) {
+    let node = &nodes[index];
+    let mut inner = path.clone();
+    inner.extend(node.nibbles().iter().flat_map(|slice| slice.iter()));
+    paths.push((path, index));
+    if let Some(child) = node.children.extension() {
+        return collect_paths(nodes, child, inner, paths);
+    }
+    for (child, nibble) in node.children.iter() {
+        let mut path = inner.clone();
+        path.push(nibble);
+        collect_paths(nodes, child, path, paths);
+    }
+}
+
+fn paths(nodes: &NodeArena, index: NodeId) -> BTreeMap<Vec<u8>, NodeId> {
+    let mut paths = Vec::with_capacity(nodes.len());
+    collect_paths(nodes, index, Vec::new(), &mut paths);
+    paths.into_iter().collect()
+}
+
+/// Writes a changed node, the hashes are empty for the nodes inlined in their parent.
+fn write_change(
+    output: &mut Vec<u8>,
+    change: u8,
+    path: &[u8],
+    old: Option<&TrieNode>,
+    new: Option<&TrieNode>,
+) {
+    let Some(node) = new.or(old) else {
+        return;
+    };
+    output.push(change);
+    output.push(node.kind as u8);
+    write_bytes(output, path);
+    for node in [old, new] {
+        let id = node.and_then(|node| node.id);
+        write_bytes(output, id.as_ref().map_or(&[][..], H256::as_bytes));
+    }
+}
+
+/// Key nibble where an insert splits the partial key of a node, its keys and the inserted one
+/// sharing the nibbles before it.
+fn split_at(nodes: &NodeArena, paths: &BTreeMap<Vec<u8>, NodeId>) -> Option<u32> {
+    let (path, node) = paths
+        .iter()
+        .map(|(path, &index)| (path, &nodes[index]))
+        .filter(|(_, node)| node.matched_nibbles.is_some())
+        .max_by_key(|(path, _)| path.len())?;
+    let matched = node.matched_nibbles?;
+    let end = path.len() + node.nibbles().map_or(0, |slice| slice.len());
+    (matched < end as u32).then_some(matched)
+}
+
+/// Explains the operation in `input`, in the format of `apply_batch`, on the trie at `root`
+/// without applying it.
+///
+/// The output starts with the number of hashes computed up to the root, followed by `1` and
+/// the key nibble where a partial key was split, or `0`. Then each changed node: the change,
+/// its kind, the length-prefixed path and the length-prefixed former and new hashes.
+pub fn explain<L: TrieLayout>(db: &ExternalDB, root: &H256, input: &[u8]) -> CallResult<Vec<u8>> {
+    let (op, key) = split_operation(input)?;
+    let (index, mut old) = decode_nodes::<L>(db, root, &[], u32::MAX);
+    mark_lookup(&mut old, index, &trie_key::<L>(key));
+    let old_paths = paths(&old, index);
+
+    let mut overlay = OverlayDB::<L::Hash>::new(db);
+    let mut new_root = *root;
+    MerklePatriciaTrie::<L>::open(&mut overlay, &mut new_root).apply_batch(input)?;
+    let (index, new) = decode_nodes::<L>(&overlay, &new_root, &[], u32::MAX);
+    let new_paths = paths(&new, index);
+
+    let mut changes = Vec::new();
+    let mut hashes = 0u32;
+    for (path, &index) in &new_paths {
+        let node = &new[index];
+        let former = old_paths.get(path).map(|&index| &old[index]);
+        match former {
+            Some(former) if former.raw_bytes == node.raw_bytes => continue,
+            Some(former) if former.kind == node.kind && former.nibbles() == node.nibbles() => {
+                write_change(&mut changes, REHASHED, path, Some(former), Some(node));
+            },
+            Some(former) => {
+                write_change(&mut changes, DELETED, path, Some(former), None);
+                write_change(&mut changes, CREATED, path, None, Some(node));
+            },
+            None => write_change(&mut changes, CREATED, path, None, Some(node)),
+        }
+        // Inline nodes are encoded in their parent, only the stored ones are hashed.
+        if node.id.is_some() {
+            hashes += 1;
+        }
+    }
+    for (path, &index) in &old_paths {
+        if !new_paths.contains_key(path) {
+            write_change(&mut changes, DELETED, path, Some(&old[index]), None);
+        }
+    }
+
+    let mut output = Vec::with_capacity(changes.len() + 9);
+    output.extend_from_slice(&hashes.to_le_bytes());
+    match split_at(&old, &old_paths).filter(|_| op == 0) {
+        Some(nibble) => {
+            output.push(1);
+            output.extend_from_slice(&nibble.to_le_bytes());
+        },
+        None => output.push(0),
+    }
+    output.extend_from_slice(&changes);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        native::{call, clear_storage, DEFAULT_HANDLE},
+        storage::split_bytes,
+    };
+
+    fn operation(op: u8, key: &[u8], value: Option<&[u8]>) -> Vec<u8> {
+        let mut input = vec![op];
+        write_bytes(&mut input, key);
+        if let Some(value) = value {
+            write_bytes(&mut input, value);
+        }
+        input
+    }
+
+    /// Splits the output of `explain` into the number of hashes, the split nibble and the
+    /// change and kind of each node.
+    fn parse(output: &[u8]) -> (u32, Option<u32>, Vec<(u8, u8)>) {
+        let hashes = u32::from_le_bytes(output[..4].try_into().unwrap());
+        let (split, mut rest) = match output[4] {
+            1 => (Some(u32::from_le_bytes(output[5..9].try_into().unwrap())), &output[9..]),
+            _ => (None, &output[5..]),
+        };
+        let mut changes = Vec::new();
+        while let [change, kind, next @ ..] = rest {
+            let (_, next) = split_bytes(next).unwrap();
+            let (_, next) = split_bytes(next).unwrap();
+            let (_, next) = split_bytes(next).unwrap();
+            changes.push((*change, *kind));
+            rest = next;
+        }
+        (hashes, split, changes)
+    }
+
+    #[test]
+    fn explains_a_split_and_its_undo() {
+        clear_storage();
+        call(DEFAULT_HANDLE, 0, &operation(0, b"alpha", Some(b"1"))[1..]).unwrap();
+        let root = call(DEFAULT_HANDLE, 4, &[]).unwrap().output;
+
+        // `alps` leaves `alpha` at the 7th nibble, the leaf becomes a branch holding two leaves
+        // inlined in it, only the branch is hashed.
+        let insert = operation(0, b"alps", Some(b"2"));
+        let reply = call(DEFAULT_HANDLE, 65, &insert).unwrap();
+        let (hashes, split, mut changes) = parse(&reply.output.unwrap());
+        assert_eq!((hashes, split), (1, Some(6)));
+        changes.sort_unstable();
+        assert_eq!(changes, [(CREATED, 1), (CREATED, 1), (CREATED, 4), (DELETED, 1)]);
+        assert_eq!(call(DEFAULT_HANDLE, 3, b"alps").unwrap().output.as_deref(), Some(&b"2"[..]));
+
+        let reply = call(DEFAULT_HANDLE, 65, &operation(1, b"alps", None)).unwrap();
+        let (hashes, split, _) = parse(&reply.output.unwrap());
+        assert_eq!((hashes, split), (1, None));
+        assert_eq!(call(DEFAULT_HANDLE, 4, &[]).unwrap().output, root);
+    }
+}
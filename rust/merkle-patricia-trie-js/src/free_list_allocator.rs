@@ -0,0 +1,191 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A size-classed free-list allocator.
+//!
+//! Allocations are rounded up to a power of two and carved from the pages of the bump
+//! allocator. Freed blocks are kept in a list per size class and handed out again, so a long
+//! call such as listing the nodes of a big trie doesn't exhaust the heap. Like the bump
+//! allocator, the heap is wiped between calls.
+use crate::bump_allocator::{out_of_memory, pre_allocate_in, InnerAlloc, MemoryStats};
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Smallest block, large enough for the link to the next free block. Blocks are aligned to
+/// this size, allocations with a larger alignment are served by the bump allocator and
+/// never reused.
+const MIN_BLOCK_SIZE: usize = 16;
+
+/// One size class per power of two from [`MIN_BLOCK_SIZE`] up to the address space.
+const CLASSES: usize = (usize::BITS - MIN_BLOCK_SIZE.trailing_zeros()) as usize;
+
+static mut INNER: Option<FreeLists> = None;
+
+/// A free-list allocator suitable for use in a Wasm environment.
+pub struct FreeListAllocator;
+
+impl FreeListAllocator {
+    pub fn stats() -> MemoryStats {
+        #[allow(static_mut_refs)]
+        unsafe { INNER.as_ref() }.map(|inner| inner.heap.stats()).unwrap_or_default()
+    }
+
+    /// Empties the heap, the allocations of the previous exports must no longer be
+    /// referenced, see [`crate::arena::begin`].
+    pub fn reset() {
+        unsafe { INNER = Some(FreeLists::new()) };
+    }
+
+    /// Initializes the heap unless it already is, keeping the allocations of the previous
+    /// calls.
+    pub fn init() {
+        #[allow(static_mut_refs)]
+        unsafe {
+            INNER.get_or_insert_with(FreeLists::new)
+        };
+    }
+
+    /// Drops the heap, the memory is reused from the start by the next call.
+    pub fn wipe() {
+        unsafe { INNER = None };
+    }
+
+    pub fn pre_allocate_buffer<R>(size: u32, callback: fn(&'static mut [u8], &mut u32) -> R) -> R {
+        unsafe { pre_allocate_in(&raw mut INNER, size, callback) }
+    }
+}
+
+unsafe impl GlobalAlloc for FreeListAllocator {
+    #[inline]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        #[allow(static_mut_refs)]
+        let inner = INNER.get_or_insert_with(FreeLists::new);
+        match inner.alloc(layout) {
+            Some(start) => start as *mut u8,
+            None => out_of_memory(layout.size()),
+        }
+    }
+
+    #[inline]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[allow(static_mut_refs)]
+        if let Some(inner) = INNER.as_mut() {
+            inner.dealloc(ptr as usize, layout);
+        }
+    }
+
+    #[inline]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        // The block already fits the new size.
+        if class(&layout).is_some() && class(&layout) == class(&new_layout) {
+            return ptr;
+        }
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+struct FreeLists {
+    heap: InnerAlloc,
+    /// First free block of each size class, `0` when the list is empty. Each free block
+    /// starts with the address of the next one.
+    heads: [usize; CLASSES],
+}
+
+impl FreeLists {
+    fn new() -> Self {
+        Self { heap: InnerAlloc::new(), heads: [0; CLASSES] }
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Option<usize> {
+        let Some(class) = class(&layout) else {
+            return self.heap.alloc(layout);
+        };
+        let head = self.heads[class];
+        if head != 0 {
+            // SAFETY: free blocks are at least `MIN_BLOCK_SIZE` bytes and hold the link.
+            self.heads[class] = unsafe { *(head as *const usize) };
+            return Some(head);
+        }
+        let layout = Layout::from_size_align(block_size(class), MIN_BLOCK_SIZE).ok()?;
+        self.heap.alloc(layout)
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by [`Self::alloc`] with the same `layout`.
+    unsafe fn dealloc(&mut self, ptr: usize, layout: Layout) {
+        let Some(class) = class(&layout) else {
+            return;
+        };
+        *(ptr as *mut usize) = self.heads[class];
+        self.heads[class] = ptr;
+    }
+}
+
+impl AsMut<InnerAlloc> for FreeLists {
+    fn as_mut(&mut self) -> &mut InnerAlloc {
+        &mut self.heap
+    }
+}
+
+/// Size class of an allocation, `None` when its alignment exceeds the blocks alignment.
+fn class(layout: &Layout) -> Option<usize> {
+    if layout.align() > MIN_BLOCK_SIZE {
+        return None;
+    }
+    let size = layout.size().max(MIN_BLOCK_SIZE).checked_next_power_of_two()?;
+    Some((size.trailing_zeros() - MIN_BLOCK_SIZE.trailing_zeros()) as usize)
+}
+
+fn block_size(class: usize) -> usize {
+    MIN_BLOCK_SIZE << class
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_rounded_to_their_class() {
+        let layout = |size, align| Layout::from_size_align(size, align).unwrap();
+        assert_eq!(class(&layout(0, 1)), Some(0));
+        assert_eq!(class(&layout(16, 8)), Some(0));
+        assert_eq!(class(&layout(17, 1)), Some(1));
+        assert_eq!(class(&layout(1024, 4)), Some(6));
+        assert_eq!(block_size(6), 1024);
+        assert_eq!(class(&layout(1025, 16)), Some(7));
+        assert_eq!(class(&layout(8, 32)), None);
+    }
+
+    #[test]
+    fn freed_blocks_are_reused_by_their_class() {
+        // Blocks of 16 bytes up to 64KiB, each class and the next one carved once from the
+        // heap, which must hold them all.
+        const LAST_CLASS: usize = 12;
+        let mut memory = vec![0u128; 8 * block_size(LAST_CLASS) / MIN_BLOCK_SIZE];
+        let start = memory.as_mut_ptr() as usize;
+        let heap = InnerAlloc::with_region(start, memory.len() * MIN_BLOCK_SIZE);
+        let mut lists = FreeLists { heap, heads: [0; CLASSES] };
+        for class in 0..=LAST_CLASS {
+            let layout = Layout::from_size_align(block_size(class), 8).unwrap();
+            let block = lists.alloc(layout).unwrap();
+            assert_eq!(block % MIN_BLOCK_SIZE, 0);
+            unsafe { lists.dealloc(block, layout) };
+
+            // Another class doesn't take the block, an allocation of its class does.
+            let larger = Layout::from_size_align(block_size(class + 1), 8).unwrap();
+            assert_ne!(lists.alloc(larger), Some(block));
+            let smaller = Layout::from_size_align(block_size(class) / 2 + 1, 1).unwrap();
+            assert_eq!(lists.alloc(smaller), Some(block));
+            unsafe { lists.dealloc(block, smaller) };
+            assert_eq!(lists.alloc(layout), Some(block));
+        }
+        assert!(lists.heap.next <= start + memory.len() * MIN_BLOCK_SIZE);
+    }
+}
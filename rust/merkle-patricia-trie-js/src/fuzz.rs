@@ -0,0 +1,101 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Entry points of the fuzz targets, see the `fuzz` directory.
+//!
+//! Only built by `cargo fuzz`, which sets `cfg(fuzzing)`. The calls run on the native host,
+//! see [`crate::native`], which keeps the storage in memory, so a buffer freed twice or read
+//! after being freed is caught by the system allocator. The listings handed to JavaScript, see
+//! [`crate::trie_builder`], can't be built natively.
+//!
+//! The module leaks the memory it expects the next call to reset, the targets run with
+//! `-detect_leaks=0`.
+use crate::{
+    native,
+    primitives::{Blake2Hasher, LayoutV1, MemoryDB, H256},
+    storage,
+    trie::{self, MerklePatriciaTrie, NodeSource},
+};
+use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+use std::collections::BTreeMap;
+use trie_db::{TrieDBMutBuilder, TrieMut};
+
+type Layout = LayoutV1<Blake2Hasher>;
+
+/// Calls left out of [`dispatch`]: merges and node pages abort on the roots they aren't given
+/// by the module, and the benchmark runs as large a workload as asked.
+const SKIPPED_CALLS: [u8; 3] = [13, 42, 52];
+
+impl<H: Hasher<Out = H256>> NodeSource<H> for MemoryDB<H> {
+    fn prefetch(&self, _keys: &[H256]) {}
+
+    fn counters(&self, keys: &[H256]) -> Vec<i32> {
+        keys.iter()
+            .map(|key| i32::from(HashDB::contains(self, key, EMPTY_PREFIX)))
+            .collect()
+    }
+}
+
+/// Splits the length-prefixed keys and values of `data` until it runs out.
+pub fn extract_input(data: &[u8]) {
+    let mut input = data;
+    while let Ok((key, rest)) = MerklePatriciaTrie::<Layout>::extract_input(input) {
+        assert_eq!(4 + key.len() + rest.len(), input.len());
+        input = rest;
+    }
+}
+
+/// Runs the calls of `data` against an empty storage, each one encoded as the call code as
+/// `u8` followed by its length-prefixed input, see [`SKIPPED_CALLS`]. Failed calls are rolled
+/// back, as `__ext_call` does.
+pub fn dispatch(data: &[u8]) {
+    native::clear_storage();
+    let mut data = data;
+    while let Some((&code, rest)) = data.split_first() {
+        let Ok((input, rest)) = storage::split_bytes(rest) else {
+            return;
+        };
+        data = rest;
+        if !SKIPPED_CALLS.contains(&code) {
+            let _ = native::call(native::DEFAULT_HANDLE, code.into(), input);
+        }
+    }
+}
+
+/// Decodes the trie built from the keys and values of `data`, each one encoded as its `u8`
+/// length followed by its bytes, down to the depth given by the first byte.
+pub fn decode_nodes(data: &[u8]) {
+    let Some((&depth, mut data)) = data.split_first() else {
+        return;
+    };
+    let mut entries = BTreeMap::new();
+    while let Some((key, rest)) = split_short(data) {
+        let Some((value, rest)) = split_short(rest) else {
+            break;
+        };
+        entries.insert(key, value);
+        data = rest;
+    }
+    let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+    let mut root = H256::zero();
+    {
+        let mut trie = TrieDBMutBuilder::<Layout>::new(&mut db, &mut root).build();
+        for (key, value) in &entries {
+            trie.insert(key, value).unwrap();
+        }
+    }
+    let (index, nodes) = trie::decode_nodes::<Layout>(&db, &root, &[], depth.into());
+    assert_eq!(nodes[index].id, Some(root));
+    for node in &nodes {
+        let children = node.children.iter().map(|(child, _)| child);
+        for child in children.chain(node.children.extension()) {
+            assert!(nodes.get(child).is_some());
+        }
+    }
+}
+
+fn split_short(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&len, rest) = data.split_first()?;
+    rest.split_at_checked(len.into())
+}
@@ -1,3 +1,7 @@
+//! Non-cryptographic hashes of the xxHash family, for checksums and keys of internal maps.
+use core::hash::{BuildHasherDefault, Hasher};
+use wasm_bindgen::prelude::*;
+
 const PRIME32_1: u32 = 0x9E3779B1;
 const PRIME32_2: u32 = 0x85EBCA77;
 const PRIME32_3: u32 = 0xC2B2AE3D;
@@ -16,77 +20,538 @@ const fn round(mut acc: u32, lane: u32) -> u32 {
   acc.wrapping_mul(PRIME32_1)
 }
 
-#[unsafe(no_mangle)]
-pub fn xxhash32(seed: u32, mut data: &[u8]) -> u32 {
-  let len = data.len() as u64;
+/// One round of the four XXH32 lanes.
+#[cfg(not(all(feature = "simd128", target_arch = "wasm32")))]
+#[inline(always)]
+fn round_lanes(accumulators: Lanes, lanes: Lanes) -> Lanes {
+  let [acc1, acc2, acc3, acc4] = accumulators;
+  let [lane1, lane2, lane3, lane4] = lanes;
+  [round(acc1, lane1), round(acc2, lane2), round(acc3, lane3), round(acc4, lane4)]
+}
+
+/// Incremental XXH32, for data arriving in chunks.
+pub struct XxHash32 {
+  seed: u32,
+  accumulators: Lanes,
+  /// Bytes of an incomplete stripe, kept until the next update fills it.
+  buffer: Bytes,
+  buffered: usize,
+  len: u64,
+}
+
+impl XxHash32 {
+  pub const fn new(seed: u32) -> Self {
+    Self {
+      seed,
+      accumulators: [
+        seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2),
+        seed.wrapping_add(PRIME32_2),
+        seed,
+        seed.wrapping_sub(PRIME32_1),
+      ],
+      buffer: [0; BYTES_IN_LANE],
+      buffered: 0,
+      len: 0,
+    }
+  }
+
+  fn process(&mut self, chunk: &Bytes) {
+    // SAFETY: We have the right number of bytes and are
+    // handling the unaligned case.
+    let lanes = unsafe { chunk.as_ptr().cast::<Lanes>().read_unaligned() };
+    self.accumulators = round_lanes(self.accumulators, lanes.map(u32::to_le));
+  }
+
+  pub fn update(&mut self, mut data: &[u8]) {
+    self.len += data.len() as u64;
+
+    if self.buffered > 0 {
+      let take = data.len().min(BYTES_IN_LANE - self.buffered);
+      let (head, rest) = data.split_at(take);
+      self.buffer[self.buffered..self.buffered + take].copy_from_slice(head);
+      self.buffered += take;
+      data = rest;
+      if self.buffered < BYTES_IN_LANE {
+        return;
+      }
+      let buffer = self.buffer;
+      self.process(&buffer);
+      self.buffered = 0;
+    }
+
+    while let Some((chunk, rest)) = data.split_first_chunk::<BYTES_IN_LANE>() {
+      self.process(chunk);
+      data = rest;
+    }
+    self.buffer[..data.len()].copy_from_slice(data);
+    self.buffered = data.len();
+  }
+
+  pub fn finish(&self) -> u32 {
+    let mut acc = if self.len < (BYTES_IN_LANE as u64) {
+      self.seed.wrapping_add(PRIME32_5)
+    } else {
+      let [acc1, acc2, acc3, acc4] = self.accumulators;
+      let acc1 = acc1.rotate_left(1);
+      let acc2 = acc2.rotate_left(7);
+      let acc3 = acc3.rotate_left(12);
+      let acc4 = acc4.rotate_left(18);
+      acc1
+        .wrapping_add(acc2)
+        .wrapping_add(acc3)
+        .wrapping_add(acc4)
+    };
+    acc = acc.wrapping_add(self.len as u32);
+
+    let mut data = &self.buffer[..self.buffered];
+    while let Some((chunk, rest)) = data.split_first_chunk() {
+      let lane = u32::from_ne_bytes(*chunk).to_le();
+
+      acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_3));
+      acc = acc.rotate_left(17).wrapping_mul(PRIME32_4);
+
+      data = rest;
+    }
+    for &byte in data {
+      let lane: u32 = byte.into();
+      acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_5));
+      acc = acc.rotate_left(11).wrapping_mul(PRIME32_1);
+    }
+    // Step 6. Final mix (avalanche)
+    acc ^= acc >> 15;
+    acc = acc.wrapping_mul(PRIME32_2);
+    acc ^= acc >> 13;
+    acc = acc.wrapping_mul(PRIME32_3);
+    acc ^= acc >> 16;
+
+    acc
+  }
+}
+
+/// XXH32 of `data`, exported so the host checksums with the same implementation.
+#[wasm_bindgen]
+pub fn xxhash32(seed: u32, data: &[u8]) -> u32 {
+  let mut hasher = XxHash32::new(seed);
+  hasher.update(data);
+  hasher.finish()
+}
+
+const PRIME64_1: u64 = 0x9E3779B185EBCA87;
+const PRIME64_2: u64 = 0xC2B2AE3D27D4EB4F;
+const PRIME64_3: u64 = 0x165667B19E3779F9;
+const PRIME64_4: u64 = 0x85EBCA77C2B2AE63;
+const PRIME64_5: u64 = 0x27D4EB2F165667C5;
+
+type Lanes64 = [u64; 4];
+type Bytes64 = [u8; 32];
+const BYTES_IN_LANE64: usize = core::mem::size_of::<Bytes64>();
+
+#[inline(always)]
+const fn round64(mut acc: u64, lane: u64) -> u64 {
+  acc = acc.wrapping_add(lane.wrapping_mul(PRIME64_2));
+  acc = acc.rotate_left(31);
+  acc.wrapping_mul(PRIME64_1)
+}
+
+#[inline(always)]
+const fn merge_round64(mut acc: u64, acc_n: u64) -> u64 {
+  acc ^= round64(0, acc_n);
+  acc = acc.wrapping_mul(PRIME64_1);
+  acc.wrapping_add(PRIME64_4)
+}
 
-  // Since we know that there's no more data coming, we don't
-  // need to construct the intermediate buffers or copy data to
-  // or from the buffers.
+/// One round of the four XXH64 lanes.
+#[cfg(not(all(feature = "simd128", target_arch = "wasm32")))]
+#[inline(always)]
+fn round_lanes64(accumulators: Lanes64, lanes: Lanes64) -> Lanes64 {
+  let [acc1, acc2, acc3, acc4] = accumulators;
+  let [lane1, lane2, lane3, lane4] = lanes;
+  [round64(acc1, lane1), round64(acc2, lane2), round64(acc3, lane3), round64(acc4, lane4)]
+}
+
+/// Lane rounds on wasm SIMD, each round runs on every lane at once.
+#[cfg(all(feature = "simd128", target_arch = "wasm32"))]
+mod simd {
+  use super::{Lanes, Lanes64, PRIME32_1, PRIME32_2, PRIME64_1, PRIME64_2};
+  use core::arch::wasm32::*;
+
+  #[cfg(not(target_feature = "simd128"))]
+  compile_error!("the `simd128` feature needs the `+simd128` target feature");
+
+  #[inline(always)]
+  pub fn round_lanes(accumulators: Lanes, lanes: Lanes) -> Lanes {
+    let [acc1, acc2, acc3, acc4] = accumulators;
+    let [lane1, lane2, lane3, lane4] = lanes;
+    let mut acc = u32x4(acc1, acc2, acc3, acc4);
+    acc = i32x4_add(acc, i32x4_mul(u32x4(lane1, lane2, lane3, lane4), u32x4_splat(PRIME32_2)));
+    acc = v128_or(i32x4_shl(acc, 13), u32x4_shr(acc, 19));
+    acc = i32x4_mul(acc, u32x4_splat(PRIME32_1));
+    [
+      u32x4_extract_lane::<0>(acc),
+      u32x4_extract_lane::<1>(acc),
+      u32x4_extract_lane::<2>(acc),
+      u32x4_extract_lane::<3>(acc),
+    ]
+  }
+
+  #[inline(always)]
+  fn round64x2(acc: v128, lanes: v128) -> v128 {
+    let acc = i64x2_add(acc, i64x2_mul(lanes, u64x2_splat(PRIME64_2)));
+    let acc = v128_or(i64x2_shl(acc, 31), u64x2_shr(acc, 33));
+    i64x2_mul(acc, u64x2_splat(PRIME64_1))
+  }
+
+  #[inline(always)]
+  pub fn round_lanes64(accumulators: Lanes64, lanes: Lanes64) -> Lanes64 {
+    let [acc1, acc2, acc3, acc4] = accumulators;
+    let [lane1, lane2, lane3, lane4] = lanes;
+    let low = round64x2(u64x2(acc1, acc2), u64x2(lane1, lane2));
+    let high = round64x2(u64x2(acc3, acc4), u64x2(lane3, lane4));
+    [
+      u64x2_extract_lane::<0>(low),
+      u64x2_extract_lane::<1>(low),
+      u64x2_extract_lane::<0>(high),
+      u64x2_extract_lane::<1>(high),
+    ]
+  }
+}
+
+#[cfg(all(feature = "simd128", target_arch = "wasm32"))]
+use simd::{round_lanes, round_lanes64};
+
+/// [`Hasher`] of the internal maps, the XXH64 of the bytes written to it.
+#[derive(Clone, Copy, Default)]
+pub struct XxHasher64(u64);
+
+impl Hasher for XxHasher64 {
+  fn write(&mut self, bytes: &[u8]) {
+    self.0 = xxhash64(self.0, bytes);
+  }
+
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
+
+pub type BuildXxHasher64 = BuildHasherDefault<XxHasher64>;
+
+/// XXH64 of `data`, the 32-bit variant collides too readily to key maps of large tries.
+#[wasm_bindgen]
+pub fn xxhash64(seed: u64, mut data: &[u8]) -> u64 {
+  let len = data.len() as u64;
 
-  // let mut accumulators = Accumulators::new(seed);
   let mut accumulators = [
-    seed.wrapping_add(PRIME32_1).wrapping_add(PRIME32_2),
-    seed.wrapping_add(PRIME32_2),
+    seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+    seed.wrapping_add(PRIME64_2),
     seed,
-    seed.wrapping_sub(PRIME32_1),
+    seed.wrapping_sub(PRIME64_1),
   ];
-  // finish_with(seed, len.into_u64(), &accumulators, data)
 
-  while let Some((chunk, rest)) = data.split_first_chunk::<BYTES_IN_LANE>() {
+  while let Some((chunk, rest)) = data.split_first_chunk::<BYTES_IN_LANE64>() {
     // SAFETY: We have the right number of bytes and are
     // handling the unaligned case.
-    let lanes = unsafe { chunk.as_ptr().cast::<Lanes>().read_unaligned() };
-    {
-      let [acc1, acc2, acc3, acc4] = &mut accumulators;
-      let [lane1, lane2, lane3, lane4] = lanes;
-
-      *acc1 = round(*acc1, lane1.to_le());
-      *acc2 = round(*acc2, lane2.to_le());
-      *acc3 = round(*acc3, lane3.to_le());
-      *acc4 = round(*acc4, lane4.to_le());
-    }
+    let lanes = unsafe { chunk.as_ptr().cast::<Lanes64>().read_unaligned() };
+    accumulators = round_lanes64(accumulators, lanes.map(u64::to_le));
     data = rest;
   }
 
-  let mut acc = if len < (BYTES_IN_LANE as u64) {
-    seed.wrapping_add(PRIME32_5)
+  let mut acc = if len < (BYTES_IN_LANE64 as u64) {
+    seed.wrapping_add(PRIME64_5)
   } else {
     let [acc1, acc2, acc3, acc4] = accumulators;
-    let acc1 = acc1.rotate_left(1);
-    let acc2 = acc2.rotate_left(7);
-    let acc3 = acc3.rotate_left(12);
-    let acc4 = acc4.rotate_left(18);
-    acc1
-      .wrapping_add(acc2)
-      .wrapping_add(acc3)
-      .wrapping_add(acc4)
+    let mut acc = acc1
+      .rotate_left(1)
+      .wrapping_add(acc2.rotate_left(7))
+      .wrapping_add(acc3.rotate_left(12))
+      .wrapping_add(acc4.rotate_left(18));
+    acc = merge_round64(acc, acc1);
+    acc = merge_round64(acc, acc2);
+    acc = merge_round64(acc, acc3);
+    merge_round64(acc, acc4)
   };
-  acc += len as u32;
+  acc = acc.wrapping_add(len);
 
   while let Some((chunk, rest)) = data.split_first_chunk() {
-    let lane = u32::from_ne_bytes(*chunk).to_le();
+    let lane = u64::from_le_bytes(*chunk);
 
-    acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_3));
-    acc = acc.rotate_left(17).wrapping_mul(PRIME32_4);
+    acc ^= round64(0, lane);
+    acc = acc.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+
+    data = rest;
+  }
+  if let Some((chunk, rest)) = data.split_first_chunk() {
+    let lane: u64 = u32::from_le_bytes(*chunk).into();
+
+    acc ^= lane.wrapping_mul(PRIME64_1);
+    acc = acc.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
 
     data = rest;
   }
   for &byte in data {
-    let lane: u32 = byte.into();
-    acc = acc.wrapping_add(lane.wrapping_mul(PRIME32_5));
-    acc = acc.rotate_left(11).wrapping_mul(PRIME32_1);
+    let lane: u64 = byte.into();
+    acc ^= lane.wrapping_mul(PRIME64_5);
+    acc = acc.rotate_left(11).wrapping_mul(PRIME64_1);
   }
-  // Step 6. Final mix (avalanche)
-  acc ^= acc >> 15;
-  acc = acc.wrapping_mul(PRIME32_2);
-  acc ^= acc >> 13;
-  acc = acc.wrapping_mul(PRIME32_3);
-  acc ^= acc >> 16;
+  // Final mix (avalanche)
+  acc ^= acc >> 33;
+  acc = acc.wrapping_mul(PRIME64_2);
+  acc ^= acc >> 29;
+  acc = acc.wrapping_mul(PRIME64_3);
+  acc ^= acc >> 32;
 
   acc
 }
 
+const PRIME_MX1: u64 = 0x165667919E3779F9;
+const PRIME_MX2: u64 = 0x9FB21C651E98DF25;
+
+const STRIPE_LEN: usize = 64;
+const SECRET_CONSUME_RATE: usize = 8;
+const SECRET_SIZE: usize = 192;
+const MIDSIZE_MAX: usize = 240;
+
+/// Default secret of XXH3, the seeded hashes of long inputs derive theirs from it.
+const SECRET: [u8; SECRET_SIZE] = [
+  0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+  0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+  0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+  0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+  0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+  0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+  0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+  0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+  0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+  0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+  0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+  0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+#[inline(always)]
+fn read32(data: &[u8], offset: usize) -> u32 {
+  u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[inline(always)]
+fn read64(data: &[u8], offset: usize) -> u64 {
+  u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[inline(always)]
+const fn mul128_fold64(lhs: u64, rhs: u64) -> u64 {
+  let product = (lhs as u128).wrapping_mul(rhs as u128);
+  (product as u64) ^ ((product >> 64) as u64)
+}
+
+#[inline(always)]
+const fn xxh64_avalanche(mut acc: u64) -> u64 {
+  acc ^= acc >> 33;
+  acc = acc.wrapping_mul(PRIME64_2);
+  acc ^= acc >> 29;
+  acc = acc.wrapping_mul(PRIME64_3);
+  acc ^ (acc >> 32)
+}
+
+#[inline(always)]
+const fn xxh3_avalanche(mut acc: u64) -> u64 {
+  acc ^= acc >> 37;
+  acc = acc.wrapping_mul(PRIME_MX1);
+  acc ^ (acc >> 32)
+}
+
+/// Packs the two halves of an XXH3-128 hash, the high half first as in its canonical form.
+#[inline(always)]
+const fn join(low: u64, high: u64) -> u128 {
+  ((high as u128) << 64) | low as u128
+}
+
+fn mix16(data: &[u8], offset: usize, secret: &[u8], secret_offset: usize, seed: u64) -> u64 {
+  let lo = read64(data, offset) ^ read64(secret, secret_offset).wrapping_add(seed);
+  let hi = read64(data, offset + 8) ^ read64(secret, secret_offset + 8).wrapping_sub(seed);
+  mul128_fold64(lo, hi)
+}
+
+fn mix32(
+  [low, high]: [u64; 2],
+  data: &[u8],
+  (first, second): (usize, usize),
+  secret: &[u8],
+  secret_offset: usize,
+  seed: u64,
+) -> [u64; 2] {
+  let mut low = low.wrapping_add(mix16(data, first, secret, secret_offset, seed));
+  low ^= read64(data, second).wrapping_add(read64(data, second + 8));
+  let mut high = high.wrapping_add(mix16(data, second, secret, secret_offset + 16, seed));
+  high ^= read64(data, first).wrapping_add(read64(data, first + 8));
+  [low, high]
+}
+
+fn xxh3_128_len_1to3(data: &[u8], seed: u64) -> u128 {
+  let len = data.len();
+  let c1 = u32::from(data[0]);
+  let c2 = u32::from(data[len >> 1]);
+  let c3 = u32::from(data[len - 1]);
+  let combined_low = (c1 << 16) | (c2 << 24) | c3 | ((len as u32) << 8);
+  let combined_high = combined_low.swap_bytes().rotate_left(13);
+  let flip_low = u64::from(read32(&SECRET, 0) ^ read32(&SECRET, 4)).wrapping_add(seed);
+  let flip_high = u64::from(read32(&SECRET, 8) ^ read32(&SECRET, 12)).wrapping_sub(seed);
+  join(
+    xxh64_avalanche(u64::from(combined_low) ^ flip_low),
+    xxh64_avalanche(u64::from(combined_high) ^ flip_high),
+  )
+}
+
+fn xxh3_128_len_4to8(data: &[u8], mut seed: u64) -> u128 {
+  let len = data.len();
+  seed ^= u64::from((seed as u32).swap_bytes()) << 32;
+  let input = u64::from(read32(data, 0)).wrapping_add(u64::from(read32(data, len - 4)) << 32);
+  let flip = (read64(&SECRET, 16) ^ read64(&SECRET, 24)).wrapping_add(seed);
+  let multiplier = PRIME64_1.wrapping_add((len as u64) << 2);
+  let product = u128::from(input ^ flip).wrapping_mul(u128::from(multiplier));
+  let mut low = product as u64;
+  let mut high = (product >> 64) as u64;
+  high = high.wrapping_add(low << 1);
+  low ^= high >> 3;
+  low ^= low >> 35;
+  low = low.wrapping_mul(PRIME_MX2);
+  low ^= low >> 28;
+  join(low, xxh3_avalanche(high))
+}
+
+fn xxh3_128_len_9to16(data: &[u8], seed: u64) -> u128 {
+  let len = data.len();
+  let flip_low = (read64(&SECRET, 32) ^ read64(&SECRET, 40)).wrapping_sub(seed);
+  let flip_high = (read64(&SECRET, 48) ^ read64(&SECRET, 56)).wrapping_add(seed);
+  let input_low = read64(data, 0);
+  let mut input_high = read64(data, len - 8);
+  let product = u128::from(input_low ^ input_high ^ flip_low).wrapping_mul(u128::from(PRIME64_1));
+  let mut low = (product as u64).wrapping_add(((len - 1) as u64) << 54);
+  input_high ^= flip_high;
+  let mut high = ((product >> 64) as u64)
+    .wrapping_add(input_high)
+    .wrapping_add(u64::from(input_high as u32).wrapping_mul(u64::from(PRIME32_2 - 1)));
+  low ^= high.swap_bytes();
+  let product = u128::from(low).wrapping_mul(u128::from(PRIME64_2));
+  high = ((product >> 64) as u64).wrapping_add(high.wrapping_mul(PRIME64_2));
+  join(xxh3_avalanche(product as u64), xxh3_avalanche(high))
+}
+
+fn xxh3_128_len_17to240(data: &[u8], seed: u64) -> u128 {
+  let len = data.len();
+  let mut acc = [(len as u64).wrapping_mul(PRIME64_1), 0];
+  if len <= 128 {
+    let mut offset = (len - 1) / 32 * 16;
+    loop {
+      acc = mix32(acc, data, (offset, len - offset - 16), &SECRET, offset * 2, seed);
+      if offset == 0 {
+        break;
+      }
+      offset -= 16;
+    }
+  } else {
+    for round in 0..4 {
+      acc = mix32(acc, data, (32 * round, 32 * round + 16), &SECRET, 32 * round, seed);
+    }
+    acc = acc.map(xxh3_avalanche);
+    for round in 4..len / 32 {
+      let secret_offset = 3 + 32 * (round - 4);
+      acc = mix32(acc, data, (32 * round, 32 * round + 16), &SECRET, secret_offset, seed);
+    }
+    let last = (len - 16, len - 32);
+    acc = mix32(acc, data, last, &SECRET, 136 - 17 - 16, 0u64.wrapping_sub(seed));
+  }
+  let [acc_low, acc_high] = acc;
+  let low = acc_low.wrapping_add(acc_high);
+  let high = acc_low
+    .wrapping_mul(PRIME64_1)
+    .wrapping_add(acc_high.wrapping_mul(PRIME64_4))
+    .wrapping_add((len as u64).wrapping_sub(seed).wrapping_mul(PRIME64_2));
+  join(xxh3_avalanche(low), 0u64.wrapping_sub(xxh3_avalanche(high)))
+}
+
+fn accumulate_512(acc: &mut [u64; 8], stripe: &[u8], secret: &[u8]) {
+  for i in 0..8 {
+    let value = read64(stripe, 8 * i);
+    let key = value ^ read64(secret, 8 * i);
+    acc[i ^ 1] = acc[i ^ 1].wrapping_add(value);
+    acc[i] = acc[i].wrapping_add((key & 0xffff_ffff).wrapping_mul(key >> 32));
+  }
+}
+
+fn scramble(acc: &mut [u64; 8], secret: &[u8]) {
+  for (i, acc) in acc.iter_mut().enumerate() {
+    *acc ^= *acc >> 47;
+    *acc ^= read64(secret, 8 * i);
+    *acc = acc.wrapping_mul(u64::from(PRIME32_1));
+  }
+}
+
+fn merge_accs(acc: &[u64; 8], secret: &[u8], start: u64) -> u64 {
+  let mut result = start;
+  for i in 0..4 {
+    let lo = acc[2 * i] ^ read64(secret, 16 * i);
+    let hi = acc[2 * i + 1] ^ read64(secret, 16 * i + 8);
+    result = result.wrapping_add(mul128_fold64(lo, hi));
+  }
+  xxh3_avalanche(result)
+}
+
+fn xxh3_128_long(data: &[u8], secret: &[u8; SECRET_SIZE]) -> u128 {
+  const STRIPES_PER_BLOCK: usize = (SECRET_SIZE - STRIPE_LEN) / SECRET_CONSUME_RATE;
+  const BLOCK_LEN: usize = STRIPE_LEN * STRIPES_PER_BLOCK;
+  let len = data.len();
+  let mut acc = [
+    u64::from(PRIME32_3),
+    PRIME64_1,
+    PRIME64_2,
+    PRIME64_3,
+    PRIME64_4,
+    u64::from(PRIME32_2),
+    PRIME64_5,
+    u64::from(PRIME32_1),
+  ];
+  let blocks = (len - 1) / BLOCK_LEN;
+  for block in data.chunks_exact(BLOCK_LEN).take(blocks) {
+    for (stripe, chunk) in block.chunks_exact(STRIPE_LEN).enumerate() {
+      accumulate_512(&mut acc, chunk, &secret[stripe * SECRET_CONSUME_RATE..]);
+    }
+    scramble(&mut acc, &secret[SECRET_SIZE - STRIPE_LEN..]);
+  }
+  let tail = &data[blocks * BLOCK_LEN..];
+  for (stripe, chunk) in tail[..tail.len() - 1].chunks_exact(STRIPE_LEN).enumerate() {
+    accumulate_512(&mut acc, chunk, &secret[stripe * SECRET_CONSUME_RATE..]);
+  }
+  accumulate_512(&mut acc, &data[len - STRIPE_LEN..], &secret[SECRET_SIZE - STRIPE_LEN - 7..]);
+  let low = merge_accs(&acc, &secret[11..], (len as u64).wrapping_mul(PRIME64_1));
+  let high_secret = &secret[SECRET_SIZE - STRIPE_LEN - 11..];
+  let high = merge_accs(&acc, high_secret, !(len as u64).wrapping_mul(PRIME64_2));
+  join(low, high)
+}
+
+/// XXH3 128-bit hash of `data`, with the high half in the upper bits.
+pub fn xxh3_128(seed: u64, data: &[u8]) -> u128 {
+  match data.len() {
+    0 => {
+      let flip_low = read64(&SECRET, 64) ^ read64(&SECRET, 72);
+      let flip_high = read64(&SECRET, 80) ^ read64(&SECRET, 88);
+      join(xxh64_avalanche(seed ^ flip_low), xxh64_avalanche(seed ^ flip_high))
+    },
+    1..=3 => xxh3_128_len_1to3(data, seed),
+    4..=8 => xxh3_128_len_4to8(data, seed),
+    9..=16 => xxh3_128_len_9to16(data, seed),
+    17..=MIDSIZE_MAX => xxh3_128_len_17to240(data, seed),
+    _ if seed == 0 => xxh3_128_long(data, &SECRET),
+    _ => {
+      let mut secret = [0u8; SECRET_SIZE];
+      for (i, chunk) in secret.chunks_exact_mut(16).enumerate() {
+        let low = read64(&SECRET, 16 * i).wrapping_add(seed);
+        let high = read64(&SECRET, 16 * i + 8).wrapping_sub(seed);
+        chunk[..8].copy_from_slice(&low.to_le_bytes());
+        chunk[8..].copy_from_slice(&high.to_le_bytes());
+      }
+      xxh3_128_long(data, &secret)
+    },
+  }
+}
 
 #[cfg(test)]
 mod tests {
@@ -141,4 +606,149 @@ mod tests {
       assert_eq!(xxhash32(seed, data), expected);
     }
   }
+
+  #[test]
+  fn chunked_xxhash32_matches_the_one_shot_hash() {
+    let mut buffer = vec![0u8; SANITY_BUFFER_SIZE];
+    byte_gen(&mut buffer);
+    for chunk_size in [1, 3, 16, 17, 100] {
+      for seed in [0, PRIME32_1] {
+        let mut hasher = XxHash32::new(seed);
+        for chunk in buffer.chunks(chunk_size) {
+          hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(), xxhash32(seed, &buffer));
+      }
+    }
+    let mut hasher = XxHash32::new(0);
+    hasher.update(&buffer[..7]);
+    hasher.update(&[]);
+    assert_eq!(hasher.finish(), xxhash32(0, &buffer[..7]));
+  }
+
+  #[test]
+  fn test_xxhash64() {
+    let test_cases = [
+      [0, 0x00000000u64, 0xEF46DB3751D8E999u64],
+      [0, 0x9E3779B1u64, 0xAC75FDA2929B17EFu64],
+      [1, 0x00000000u64, 0xE934A84ADB052768u64],
+      [1, 0x9E3779B1u64, 0x5014607643A9B4C3u64],
+      [2, 0x00000000u64, 0x5D48CD60A77E23FFu64],
+      [2, 0x9E3779B1u64, 0x9E93152232D54A39u64],
+      [3, 0x00000000u64, 0xFF7E1959CB50794Au64],
+      [3, 0x9E3779B1u64, 0xAA8584E83660F7D1u64],
+      [4, 0x00000000u64, 0x9136A0DCA57457EEu64],
+      [4, 0x9E3779B1u64, 0xCAAB286BD8E9FDB5u64],
+      [5, 0x00000000u64, 0x9B046FB1397F09A5u64],
+      [5, 0x9E3779B1u64, 0x2AF5249930F984ECu64],
+      [6, 0x00000000u64, 0xC72565B7154268A8u64],
+      [6, 0x9E3779B1u64, 0xCA4C6723580E8EF6u64],
+      [7, 0x00000000u64, 0x6C83909A9F01ED25u64],
+      [7, 0x9E3779B1u64, 0xF98D03B1AD6F9293u64],
+      [8, 0x00000000u64, 0xCDBCF538E71D1348u64],
+      [8, 0x9E3779B1u64, 0xFE0C047A5353CDACu64],
+      [9, 0x00000000u64, 0x554B1AE991EDA6B6u64],
+      [9, 0x9E3779B1u64, 0x7908265248F6D73Fu64],
+      [10, 0x00000000u64, 0x5D00E7351392EA84u64],
+      [10, 0x9E3779B1u64, 0x2A8AE16B86CD2F12u64],
+      [11, 0x00000000u64, 0x6345D5746F35DA70u64],
+      [11, 0x9E3779B1u64, 0xEAA08A8C8BE3CCCFu64],
+      [12, 0x00000000u64, 0x0723BF50086EAD9Au64],
+      [12, 0x9E3779B1u64, 0x8252819F4E506951u64],
+      [16, 0x00000000u64, 0x98C90B57FDFCB55Cu64],
+      [16, 0x9E3779B1u64, 0xC900AD2D536B607Eu64],
+      [31, 0x00000000u64, 0x299B39A290E6D783u64],
+      [31, 0x9E3779B1u64, 0xDA673D5FEB5C1D79u64],
+      [32, 0x00000000u64, 0x18B216492BB44B70u64],
+      [32, 0x9E3779B1u64, 0xB3F33BDF93ADE409u64],
+      [33, 0x00000000u64, 0x55C8DC3E578F5B59u64],
+      [33, 0x9E3779B1u64, 0xE92C292F64BC3071u64],
+      [63, 0x00000000u64, 0xA9EFBE0FA0F3F4E7u64],
+      [63, 0x9E3779B1u64, 0x6C911FADB05B6FC2u64],
+      [64, 0x00000000u64, 0xEF558F8ACAC2B5CDu64],
+      [64, 0x9E3779B1u64, 0xB5EEBA99264CC44Fu64],
+      [222, 0x00000000u64, 0xB641AE8CB691C174u64],
+      [222, 0x9E3779B1u64, 0x20CB8AB7AE10C14Au64],
+      [4096, 0x00000000u64, 0xAB77F4AF85F4E70Bu64],
+      [4096, 0x9E3779B1u64, 0xCB8B60CBA513125Du64],
+      [4161, 0x00000000u64, 0xCD3D6DF2DB509A75u64],
+      [4161, 0x9E3779B1u64, 0x2394CB79E97368D1u64],
+    ];
+
+    let mut buffer = vec![0u8; SANITY_BUFFER_SIZE];
+    byte_gen(&mut buffer);
+    for [len, seed, expected] in test_cases {
+      let len = len as usize;
+      let data = &buffer[0..len];
+      assert_eq!(xxhash64(seed, data), expected);
+    }
+  }
+  #[test]
+  fn test_xxh3_128() {
+    let test_cases = [
+      (0, 0x00000000, 0x99AA06D3014798D8_6001C324468D497Fu128),
+      (0, 0x9E3779B1, 0x92220AE55E14AB50_5444F7869C671AB0u128),
+      (1, 0x00000000, 0xA6CD5E9392000F6A_C44BDFF4074EECDBu128),
+      (1, 0x9E3779B1, 0x89B99554BA22467C_B53D5557E7F76F8Du128),
+      (2, 0x00000000, 0x76750C3C7BF95668_7A9978044CB8A8BBu128),
+      (2, 0x9E3779B1, 0x8B75A791EC034873_8295910C7638B180u128),
+      (3, 0x00000000, 0x20EFC49FF02422EA_54247382A8D6B94Du128),
+      (3, 0x9E3779B1, 0x48F82C2FE0ABD468_F173D14DAD53A5DCu128),
+      (4, 0x00000000, 0x970D585AC632BF8E_2E7D8D6876A39FE9u128),
+      (4, 0x9E3779B1, 0x7170492A2AA08992_EF78D5C489CFE10Bu128),
+      (8, 0x00000000, 0x47A7F080D82BB456_64C69CAB4BB21DC5u128),
+      (8, 0x9E3779B1, 0xF959013232655FF1_5F462F3DE2E8B940u128),
+      (9, 0x00000000, 0x564EF6078950D457_ED7CCBC501EB7501u128),
+      (9, 0x9E3779B1, 0x75FB6D1BD353B45C_07DE00B45EEE033Au128),
+      (16, 0x00000000, 0xC68C368ECF8A9C05_562980258A998629u128),
+      (16, 0x9E3779B1, 0x3767C90D0CDBB93D_B07EEEAB4C56392Bu128),
+      (17, 0x00000000, 0x955FA78643ED3669_ABBC12D11973D7DBu128),
+      (17, 0x9E3779B1, 0x99E7C628E75D6431_3CC9FF6CAE79ACCBu128),
+      (32, 0x00000000, 0x98FC6458710DC2E8_278410A17595E3F9u128),
+      (32, 0x9E3779B1, 0x326D21E5BCD395DE_3589C5CD99CD6267u128),
+      (64, 0x00000000, 0x6D90E81A9B0FD622_EFDB6A44690721A9u128),
+      (64, 0x9E3779B1, 0x5F29E4EDBA49A7AE_592B9762BBEBCEBBu128),
+      (96, 0x00000000, 0xD9D0B885F56C93F1_E9324473EA9AFEBEu128),
+      (96, 0x9E3779B1, 0x326705F7850BAF1A_4F7CE88D5FF06796u128),
+      (128, 0x00000000, 0x39992220E045260A_EBB15E34A7FB5AB1u128),
+      (128, 0x9E3779B1, 0x98801187DF8D614D_1453819941D93C1Du128),
+      (129, 0x00000000, 0x03815FC91F1B30B6_86C9E3BC8F0A3B5Cu128),
+      (129, 0x9E3779B1, 0xB7F7349A47B39E56_B37B716F66B40F02u128),
+      (240, 0x00000000, 0xAA4202DAA2769DC8_5C9AAE94C8EBE5A0u128),
+      (240, 0x9E3779B1, 0xDA888104BEAE5AE0_CA19087F1D335DAEu128),
+      (241, 0x00000000, 0x99A80ECF0ECFC647_C5A639ECD2030E5Eu128),
+      (241, 0x9E3779B1, 0x4BF2229C3A8FC3C3_5927E3637BAC8149u128),
+      (1024, 0x00000000, 0x0D30D24071C64C57_DD85C9B5C1109C5Cu128),
+      (1024, 0x9E3779B1, 0x885B0B4DEBE3D2FF_B8B95C07CD4A75FAu128),
+      (2048, 0x00000000, 0xF736557FD47073A5_DD59E2C3A5F038E0u128),
+      (2048, 0x9E3779B1, 0x7FB03F7E7186C3EA_230D43F30206260Bu128),
+      (2240, 0x00000000, 0xCCB134FBFA7CE49D_6E73A90539CF2948u128),
+      (2240, 0x9E3779B1, 0x50A1FE17B338995F_ED385111126FBA6Fu128),
+      (2367, 0x00000000, 0xE89C0F6FF369B427_CB37AEB9E5D361EDu128),
+      (2367, 0x9E3779B1, 0xD23AAE4B76C31ECB_6F5360AE69C2F406u128),
+      (4096, 0x00000000, 0xB9CFAEA2CA5626A4_E91206429D1F48F9u128),
+      (4096, 0x9E3779B1, 0x61F9525DA2DCBE15_2C32262E6834F8B9u128),
+      (4161, 0x00000000, 0x06E30DA044FBC01E_EFB6CCB06C0B206Au128),
+      (4161, 0x9E3779B1, 0x94C8205CDB2D2202_53755720C67F7BDBu128),
+    ];
+
+    let mut buffer = vec![0u8; SANITY_BUFFER_SIZE];
+    byte_gen(&mut buffer);
+    for (len, seed, expected) in test_cases {
+      let data = &buffer[0..len];
+      assert_eq!(xxh3_128(seed, data), expected);
+    }
+  }
+
+  #[test]
+  fn xxh3_128_is_returned_by_call() {
+    use crate::native::{call, clear_storage, DEFAULT_HANDLE};
+
+    clear_storage();
+    let mut buffer = vec![0u8; 17];
+    byte_gen(&mut buffer);
+    let input = [&0x9E3779B1u64.to_le_bytes()[..], &buffer].concat();
+    let output = call(DEFAULT_HANDLE, 73, &input).unwrap().output.unwrap();
+    assert_eq!(output, 0x99E7C628E75D6431_3CC9FF6CAE79ACCBu128.to_le_bytes());
+  }
 }
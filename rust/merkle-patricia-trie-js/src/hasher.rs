@@ -0,0 +1,104 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Hashers not provided by `sp_core`, usable by any of the trie layouts. The slim build also
+//! defines the ones of `sp_core` here, see [`crate::primitives`].
+use crate::primitives::H256;
+use hash256_std_hasher::Hash256StdHasher;
+use hash_db::Hasher;
+use sha2::Digest;
+
+/// Blake2-256, the hasher of the Substrate tries.
+#[cfg(not(feature = "substrate"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Blake2Hasher;
+
+#[cfg(not(feature = "substrate"))]
+impl Hasher for Blake2Hasher {
+    type Out = H256;
+    type StdHasher = Hash256StdHasher;
+    const LENGTH: usize = 32;
+
+    fn hash(data: &[u8]) -> Self::Out {
+        H256(sp_crypto_hashing::blake2_256(data))
+    }
+}
+
+/// Keccak-256, the hasher of the Ethereum tries.
+#[cfg(not(feature = "substrate"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeccakHasher;
+
+#[cfg(not(feature = "substrate"))]
+impl Hasher for KeccakHasher {
+    type Out = H256;
+    type StdHasher = Hash256StdHasher;
+    const LENGTH: usize = 32;
+
+    fn hash(data: &[u8]) -> Self::Out {
+        H256(sp_crypto_hashing::keccak_256(data))
+    }
+}
+
+/// BLAKE3 with the default 256-bit output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Blake3Hasher;
+
+impl Hasher for Blake3Hasher {
+    type Out = H256;
+    type StdHasher = Hash256StdHasher;
+    const LENGTH: usize = 32;
+
+    fn hash(data: &[u8]) -> Self::Out {
+        H256(*blake3::hash(data).as_bytes())
+    }
+}
+
+/// SHA-256, the hash function used by Bitcoin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sha256Hasher;
+
+impl Hasher for Sha256Hasher {
+    type Out = H256;
+    type StdHasher = Hash256StdHasher;
+    const LENGTH: usize = 32;
+
+    fn hash(data: &[u8]) -> Self::Out {
+        H256(sha2::Sha256::digest(data).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{primitives::LayoutV1, storage::hashed_null_node};
+
+    #[test]
+    fn blake3_matches_the_reference_output() {
+        assert_eq!(
+            Blake3Hasher::hash(b""),
+            H256(hex_literal::hex!(
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+            ))
+        );
+    }
+
+    #[test]
+    fn sha256_matches_the_reference_output() {
+        assert_eq!(
+            Sha256Hasher::hash(b""),
+            H256(hex_literal::hex!(
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+            ))
+        );
+    }
+
+    #[test]
+    fn empty_root_is_derived_from_the_hasher() {
+        let root = hashed_null_node::<LayoutV1<Blake3Hasher>>();
+        assert_eq!(root, Blake3Hasher::hash(&[0]));
+        let root = hashed_null_node::<LayoutV1<Sha256Hasher>>();
+        assert_eq!(root, Sha256Hasher::hash(&[0]));
+    }
+}
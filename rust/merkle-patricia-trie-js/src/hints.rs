@@ -0,0 +1,163 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Decoding hints of the listed values.
+//!
+//! The host registers a hint per key prefix, the listings then show the values whose key
+//! starts with the longest matching prefix in a readable form next to their bytes. Keys are
+//! matched as stored in the trie, hashed by the layouts hashing their keys.
+use crate::{
+    error::{CallError, CallResult},
+    ethereum::decode_account,
+    fail,
+    storage::split_bytes,
+};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueHint {
+    Utf8,
+    /// SCALE encoded `u32`, 4 little endian bytes.
+    U32,
+    /// SCALE encoded `u128`, 16 little endian bytes.
+    U128,
+    H256,
+    /// Account of the Ethereum state trie, the RLP list of its nonce, balance, storage root and
+    /// code hash.
+    Account,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len() * 2 + 2);
+    output.push_str("0x");
+    for byte in bytes {
+        let _ = write!(output, "{byte:02x}");
+    }
+    output
+}
+
+/// Big endian integer in decimal, or in hex when it doesn't fit in a `u128`.
+fn integer(bytes: &[u8]) -> String {
+    if bytes.len() > 16 {
+        return hex(bytes);
+    }
+    bytes
+        .iter()
+        .fold(0u128, |value, byte| (value << 8) | u128::from(*byte))
+        .to_string()
+}
+
+impl ValueHint {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Utf8),
+            1 => Some(Self::U32),
+            2 => Some(Self::U128),
+            3 => Some(Self::H256),
+            4 => Some(Self::Account),
+            _ => None,
+        }
+    }
+
+    /// Readable form of `value`, `None` when the value doesn't fit the hint.
+    pub fn decode(self, value: &[u8]) -> Option<String> {
+        match self {
+            Self::Utf8 => core::str::from_utf8(value).ok().map(ToString::to_string),
+            Self::U32 => Some(u32::from_le_bytes(value.try_into().ok()?).to_string()),
+            Self::U128 => Some(u128::from_le_bytes(value.try_into().ok()?).to_string()),
+            Self::H256 => Some(hex(<&[u8; 32]>::try_from(value).ok()?)),
+            Self::Account => {
+                let [nonce, balance, storage_root, code_hash] = decode_account(value)?;
+                Some(format!(
+                    "nonce {}, balance {}, storage root {}, code hash {}",
+                    integer(nonce),
+                    integer(balance),
+                    hex(storage_root),
+                    hex(code_hash)
+                ))
+            },
+        }
+    }
+}
+
+/// Hints of the key prefixes, as registered by the host.
+#[derive(Default)]
+pub struct ValueHints(Vec<(Vec<u8>, ValueHint)>);
+
+impl ValueHints {
+    /// Decodes the hints encoded as the hint kind, `0` UTF-8, `1` `u32`, `2` `u128`, `3`
+    /// `H256` or `4` Ethereum account, followed by the length-prefixed key prefix, one after the
+    /// other.
+    pub fn decode(mut input: &[u8]) -> CallResult<Self> {
+        let mut hints = Vec::new();
+        while let Some((&code, rest)) = input.split_first() {
+            let Some(hint) = ValueHint::from_code(code) else {
+                return fail!(CallError::InvalidInput, "unknown value hint {code}");
+            };
+            let (prefix, rest) = split_bytes(rest)?;
+            hints.push((prefix.to_vec(), hint));
+            input = rest;
+        }
+        Ok(Self(hints))
+    }
+
+    /// Hint of the longest registered prefix of `key`.
+    pub fn find(&self, key: &[u8]) -> Option<ValueHint> {
+        self.0
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, hint)| *hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::write_bytes;
+
+    #[test]
+    fn values_are_decoded_by_their_hint() {
+        assert_eq!(ValueHint::Utf8.decode(b"alpha").as_deref(), Some("alpha"));
+        assert_eq!(ValueHint::Utf8.decode(&[0xff]), None);
+        assert_eq!(ValueHint::U32.decode(&[1, 1, 0, 0]).as_deref(), Some("257"));
+        assert_eq!(ValueHint::U32.decode(&[1, 1, 0]), None);
+        assert_eq!(ValueHint::U128.decode(&u128::MAX.to_le_bytes()), Some(u128::MAX.to_string()));
+        let hash = ValueHint::H256.decode(&[0xab; 32]).unwrap();
+        assert_eq!(hash, format!("0x{}", "ab".repeat(32)));
+        let account =
+            [&[0xf8, 0x46, 0x01, 0x82, 0x03, 0xe8, 0xa0][..], &[0xaa; 32], &[0xa0], &[0xbb; 32]];
+        let account = ValueHint::Account.decode(&account.concat()).unwrap();
+        assert_eq!(
+            account,
+            format!(
+                "nonce 1, balance 1000, storage root 0x{}, code hash 0x{}",
+                "aa".repeat(32),
+                "bb".repeat(32)
+            )
+        );
+        assert_eq!(ValueHint::Account.decode(&[0xc0]), None);
+    }
+
+    #[test]
+    fn the_longest_prefix_wins() {
+        let mut input = Vec::new();
+        input.push(0);
+        write_bytes(&mut input, b"");
+        input.push(1);
+        write_bytes(&mut input, b"balance:");
+        input.push(3);
+        write_bytes(&mut input, b"balance:root");
+        let hints = ValueHints::decode(&input).unwrap();
+        assert_eq!(hints.find(b"name"), Some(ValueHint::Utf8));
+        assert_eq!(hints.find(b"balance:alice"), Some(ValueHint::U32));
+        assert_eq!(hints.find(b"balance:root"), Some(ValueHint::H256));
+        assert_eq!(ValueHints::default().find(b"name"), None);
+    }
+}
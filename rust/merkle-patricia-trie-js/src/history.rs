@@ -0,0 +1,41 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Every committed root is appended to the history under a reserved key, indexed by a
+//! monotonically increasing sequence number.
+use crate::{backend::TrieBackend, primitives::H256, storage::ExternalDB};
+use alloc::vec::Vec;
+
+const HISTORY_LEN_KEY: &[u8] = b":root_history";
+const HISTORY_PREFIX: &[u8] = b":root_history:";
+
+fn history_key(seq: u64) -> [u8; HISTORY_PREFIX.len() + 8] {
+    let mut key = [0u8; HISTORY_PREFIX.len() + 8];
+    key[..HISTORY_PREFIX.len()].copy_from_slice(HISTORY_PREFIX);
+    key[HISTORY_PREFIX.len()..].copy_from_slice(&seq.to_be_bytes());
+    key
+}
+
+/// Number of roots recorded so far.
+pub fn len<B: TrieBackend>(db: &ExternalDB<B>) -> u64 {
+    db.get_bytes::<8>(HISTORY_LEN_KEY).map(u64::from_le_bytes).unwrap_or_default()
+}
+
+pub fn record<B: TrieBackend>(db: &ExternalDB<B>, root: &H256) {
+    let seq = len(db);
+    db.set_hash(&history_key(seq), root);
+    db.set_bytes(HISTORY_LEN_KEY, &(seq + 1).to_le_bytes());
+}
+
+/// Returns the concatenation of all recorded roots, oldest first.
+pub fn list<B: TrieBackend>(db: &ExternalDB<B>) -> Vec<u8> {
+    let len = len(db);
+    let mut roots = Vec::with_capacity(len as usize * 32);
+    for seq in 0..len {
+        if let Some(root) = db.get_hash(&history_key(seq)) {
+            roots.extend_from_slice(root.as_bytes());
+        }
+    }
+    roots
+}
@@ -2,17 +2,185 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::storage::EMPTY_PTR;
-use core::num::NonZeroU32;
+#[cfg(feature = "enable-debug-log")]
+use crate::log::{Category, Level};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::HostFn;
+use crate::{
+    abort, journal,
+    metrics::{self, HostCall},
+    scratch,
+    storage::EMPTY_PTR,
+};
+use alloc::vec::Vec;
+use core::{
+    cell::{Cell, RefCell},
+    fmt,
+};
+#[cfg(target_arch = "wasm32")]
 use ext::HostFn;
 
+/// Size of the first read of [`HostFnImpl::get_storage_vec`] on hosts without
+/// `__ext_value_size`, enough for any branch node.
+const INITIAL_READ_SIZE: usize = 1024;
+
+/// Version of the host ABI implemented by this module.
+///
+/// - `1`: `__ext_log`, `__ext_input`, `__ext_return` and the single key storage functions.
+/// - `2`: `__ext_get_storage_batch` and `__ext_contains_storage`.
+/// - `3`: `__ext_value_size`.
+/// - `4`: `__ext_take_storage`.
+/// - `5`: `__ext_out_of_memory`.
+/// - `6`: recoverable errors returned by `__ext_call`, see [`crate::error`].
+/// - `7`: `__ext_log_entry`, the log level and category along with the message.
+/// - `8`: `__ext_notify`, the watched keys changed by a call, see [`crate::watch`].
+/// - `9`: `__ext_now`, the host clock timing [`crate::bench`].
+/// - `10`: calls address a trie by handle instead of a namespace, see [`crate::tries`].
+pub const ABI_VERSION: u32 = 10;
+
+/// Oldest host this module runs on, newer functions are skipped on older hosts. The same
+/// baseline as `MIN_MODULE_ABI_VERSION` in `trie-wasm-bindings/src/lib.exports.ts`.
+pub const MIN_HOST_VERSION: u32 = 1;
+
+/// Host ABI version negotiated by [`version`], `0` until the host calls it.
+struct HostVersion(Cell<u32>);
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for HostVersion {}
+
+static HOST_VERSION: HostVersion = HostVersion(Cell::new(0));
+
+/// Handshake called by the host before any other export, with the ABI version it implements.
+/// Returns the version of this module, the host refuses to run modules it doesn't support.
+#[export_name = "__ext_version"]
+pub extern "C" fn version(host_version: u32) -> u32 {
+    HOST_VERSION.0.set(host_version.min(ABI_VERSION));
+    ABI_VERSION
+}
+
+/// Length reported by [`HostFnImpl::get_storage_batch`] for a missing key.
+const BATCH_MISSING: u32 = u32::MAX;
+
+/// Namespace of the current call, see [`HostFnImpl::set_namespace`]. The bytes are kept
+/// inline, so the namespace outlives the heap of the call selecting it.
+struct Namespace {
+    bytes: RefCell<[u8; u8::MAX as usize]>,
+    len: Cell<u8>,
+}
+
+impl Namespace {
+    const fn new() -> Self {
+        Self { bytes: RefCell::new([0; u8::MAX as usize]), len: Cell::new(0) }
+    }
+}
+
+// SAFETY: the module is single threaded.
+#[cfg(target_arch = "wasm32")]
+unsafe impl Sync for Namespace {}
+
+#[cfg(target_arch = "wasm32")]
+static NAMESPACE: Namespace = Namespace::new();
+
+// The native tests run on several threads, each selects its own namespace.
+#[cfg(not(target_arch = "wasm32"))]
+std::thread_local! {
+    static NAMESPACE: Namespace = const { Namespace::new() };
+}
+
+fn with_namespace_cell<R>(f: impl FnOnce(&Namespace) -> R) -> R {
+    #[cfg(target_arch = "wasm32")]
+    return f(&NAMESPACE);
+    #[cfg(not(target_arch = "wasm32"))]
+    NAMESPACE.with(f)
+}
+
+/// Prefixes `key` with the namespace of the current call and its length, so namespaces that
+/// are prefixes of each other don't share keys. The empty namespace leaves keys unchanged,
+/// otherwise the prefixed key is written to `buffer`.
+fn storage_key<'a>(key: &'a [u8], buffer: &'a mut Vec<u8>) -> &'a [u8] {
+    let prefixed = HostFnImpl::with_namespace(|namespace| {
+        if namespace.is_empty() {
+            return false;
+        }
+        buffer.clear();
+        buffer.push(namespace.len() as u8);
+        buffer.extend_from_slice(namespace);
+        buffer.extend_from_slice(key);
+        true
+    });
+    if prefixed {
+        buffer
+    } else {
+        key
+    }
+}
+
+/// Errors of the host functions, decoded from their return code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostError {
+    /// The host failed while running the function.
+    Trapped,
+    /// A pointer or a length given to the host is out of bounds.
+    Reverted,
+    /// No value is stored under the key.
+    KeyNotFound,
+    /// The output buffer is too small for the value.
+    BufferTooSmall,
+    /// The host storage can't hold the value.
+    StorageFull,
+    /// A return code this module doesn't know.
+    Unknown(u32),
+}
+
+impl HostError {
+    /// Decodes the return code of a host function, `0` means success.
+    fn check(code: u32) -> Result<(), Self> {
+        match code {
+            0 => Ok(()),
+            1 => Err(Self::Trapped),
+            2 => Err(Self::Reverted),
+            3 => Err(Self::KeyNotFound),
+            4 => Err(Self::BufferTooSmall),
+            5 => Err(Self::StorageFull),
+            code => Err(Self::Unknown(code)),
+        }
+    }
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(code) => write!(f, "unknown return code {code}"),
+            error => fmt::Debug::fmt(error, f),
+        }
+    }
+}
+
 pub enum HostFnImpl {}
 
 impl HostFnImpl {
+    /// ABI version shared with the host, aborts when the host is too old or skipped the
+    /// handshake.
+    pub fn version() -> u32 {
+        let version = HOST_VERSION.0.get();
+        if version < MIN_HOST_VERSION {
+            abort!("unsupported host ABI version {version}, expected at least {MIN_HOST_VERSION}");
+        }
+        version
+    }
+
+    /// Logs `str`, older hosts receive the message alone. Doesn't check the handshake, a
+    /// message may be logged before it.
+    #[cfg(feature = "enable-debug-log")]
     #[inline(always)]
-    pub fn log(str: &str) {
+    pub fn log(level: Level, category: Category, str: &str) {
+        metrics::record(HostCall::Log, str.len());
         unsafe {
-            HostFn::log(str.as_ptr(), str.len() as u32);
+            if HOST_VERSION.0.get() >= 7 {
+                HostFn::log_entry(level as u32, category as u32, str.as_ptr(), str.len() as u32);
+            } else {
+                HostFn::log(str.as_ptr(), str.len() as u32);
+            }
         }
     }
 
@@ -22,11 +190,65 @@ impl HostFnImpl {
         {
             unsafe { HostFn::input(output.as_mut_ptr(), &mut output_len) };
         }
+        metrics::record(HostCall::Input, output_len as usize);
         extract_from_slice(output, output_len as usize);
     }
 
+    /// Hands `value` to the host, which copies it before the call returns. A call can return
+    /// several buffers, in order.
+    pub fn return_value(value: &[u8]) {
+        metrics::record(HostCall::Return, value.len());
+        let ret_code = unsafe { HostFn::return_value(value.as_ptr(), value.len() as u32) };
+        if let Err(error) = HostError::check(ret_code) {
+            abort!("return_value failed: {error}");
+        }
+    }
+
+    /// Tells the host the call is about to abort because an allocation of `requested` bytes
+    /// failed, `max_pages` is the configured heap limit. Doesn't allocate.
+    pub fn out_of_memory(requested: u32, max_pages: u32) {
+        if HOST_VERSION.0.get() >= 5 {
+            unsafe { HostFn::out_of_memory(requested, max_pages) };
+        }
+    }
+
+    /// Hands the watched keys changed by the call to the host, along with the new root. Older
+    /// hosts aren't notified.
+    pub fn notify(changes: &[u8]) {
+        if HOST_VERSION.0.get() >= 8 {
+            unsafe { HostFn::notify(changes.as_ptr(), changes.len() as u32) };
+        }
+    }
+
+    /// Milliseconds elapsed on the host clock since an arbitrary origin, `None` on older
+    /// hosts.
+    pub fn now() -> Option<f64> {
+        (HOST_VERSION.0.get() >= 9).then(|| unsafe { HostFn::now() })
+    }
+
+    /// Selects the namespace prefixed to the storage keys of the current call, several tries
+    /// can share the host storage using distinct namespaces.
+    /// The namespace is copied, the caller keeps its buffer.
+    pub fn set_namespace(namespace: &[u8]) {
+        let Ok(len) = u8::try_from(namespace.len()) else {
+            abort!("namespace longer than 255 bytes");
+        };
+        with_namespace_cell(|cell| {
+            cell.bytes.borrow_mut()[..namespace.len()].copy_from_slice(namespace);
+            cell.len.set(len);
+        });
+    }
+
+    /// Runs `f` with the namespace of the current call, see [`Self::set_namespace`].
+    pub fn with_namespace<R>(f: impl FnOnce(&[u8]) -> R) -> R {
+        with_namespace_cell(|cell| f(&cell.bytes.borrow()[..usize::from(cell.len.get())]))
+    }
+
     #[inline(always)]
-    pub fn set_storage(key: &[u8], encoded_value: &[u8]) -> Option<NonZeroU32> {
+    pub fn set_storage(key: &[u8], encoded_value: &[u8]) -> Result<(), HostError> {
+        let mut buffer = scratch::bytes();
+        let key = storage_key(key, &mut buffer);
+        metrics::record(HostCall::SetStorage, key.len() + encoded_value.len());
         let ret_code = unsafe {
             HostFn::set_storage(
                 key.as_ptr(),
@@ -35,11 +257,13 @@ impl HostFnImpl {
                 encoded_value.len() as u32,
             )
         };
-        NonZeroU32::new(ret_code)
+        HostError::check(ret_code)
     }
 
     #[inline(always)]
-    pub fn get_storage(mut key: &[u8], output: &mut &mut [u8]) -> Option<NonZeroU32> {
+    pub fn get_storage(key: &[u8], output: &mut &mut [u8]) -> Result<(), HostError> {
+        let mut buffer = scratch::bytes();
+        let mut key = storage_key(key, &mut buffer);
         let mut output_len = output.len() as u32;
         let ret_code = {
             unsafe {
@@ -54,14 +278,203 @@ impl HostFnImpl {
                 )
             }
         };
+        metrics::record(HostCall::GetStorage, key.len() + output.len().min(output_len as usize));
         extract_from_slice(output, output_len as usize);
-        NonZeroU32::new(ret_code)
+        HostError::check(ret_code)
+    }
+
+    /// Reads the value stored under `key` whatever its size.
+    pub fn get_storage_vec(key: &[u8]) -> Result<Vec<u8>, HostError> {
+        let mut value = Vec::new();
+        Self::get_storage_into(key, &mut value)?;
+        Ok(value)
+    }
+
+    /// Same as [`HostFnImpl::get_storage_vec`], reading the value into `value`.
+    pub fn get_storage_into(key: &[u8], value: &mut Vec<u8>) -> Result<(), HostError> {
+        read_storage(storage_key(key, &mut scratch::bytes()), value)
+    }
+
+    /// Reads the values stored under `keys` in a single host call, `None` for the missing ones.
+    pub fn get_storage_batch(keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>, HostError> {
+        let (mut request, mut buffer) = (scratch::bytes(), scratch::bytes());
+        for key in keys {
+            let key = storage_key(key, &mut buffer);
+            request.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            request.extend_from_slice(key);
+        }
+        let mut output = scratch::bytes();
+        output.resize(INITIAL_READ_SIZE * keys.len().max(1), 0);
+        loop {
+            let mut output_len = output.len() as u32;
+            let ret_code = unsafe {
+                HostFn::get_storage_batch(
+                    request.as_ptr(),
+                    request.len() as u32,
+                    output.as_mut_ptr(),
+                    &mut output_len,
+                )
+            };
+            HostError::check(ret_code)?;
+            let output_len = output_len as usize;
+            metrics::record(
+                HostCall::GetStorageBatch,
+                request.len() + output.len().min(output_len),
+            );
+            if output_len <= output.len() {
+                output.truncate(output_len);
+                break;
+            }
+            output.resize(output_len, 0);
+        }
+        let mut values = Vec::with_capacity(keys.len());
+        let mut output = output.as_slice();
+        for _ in keys {
+            let Some((len, rest)) = output.split_first_chunk::<4>() else {
+                abort!("get_storage_batch: truncated output");
+            };
+            let len = u32::from_le_bytes(*len);
+            if len == BATCH_MISSING {
+                values.push(None);
+                output = rest;
+                continue;
+            }
+            let Some((value, rest)) = rest.split_at_checked(len as usize) else {
+                abort!("get_storage_batch: truncated output");
+            };
+            values.push(Some(value.to_vec()));
+            output = rest;
+        }
+        Ok(values)
+    }
+
+    /// Whether a value is stored under `key`, the host doesn't copy the value.
+    pub fn contains_storage(key: &[u8]) -> Result<bool, HostError> {
+        let mut buffer = scratch::bytes();
+        let mut key = storage_key(key, &mut buffer);
+        if key.is_empty() {
+            key = EMPTY_PTR;
+        }
+        metrics::record(HostCall::ContainsStorage, key.len());
+        let ret_code = unsafe { HostFn::contains_storage(key.as_ptr(), key.len() as u32) };
+        match HostError::check(ret_code) {
+            Ok(()) => Ok(true),
+            Err(HostError::KeyNotFound) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Byte length of the value stored under `key`, the host doesn't copy the value.
+    pub fn value_size(key: &[u8]) -> Result<u32, HostError> {
+        value_size(storage_key(key, &mut scratch::bytes()))
+    }
+
+    /// Reads the value stored under `key` and clears it in a single host call. The value
+    /// read is the one the journal restores.
+    pub fn take_storage(key: &[u8]) -> Result<Vec<u8>, HostError> {
+        let mut buffer = scratch::bytes();
+        let mut key = storage_key(key, &mut buffer);
+        if key.is_empty() {
+            key = EMPTY_PTR;
+        }
+        let mut value = vec![0; INITIAL_READ_SIZE];
+        loop {
+            let mut output_len = value.len() as u32;
+            let ret_code = unsafe {
+                HostFn::take_storage(
+                    key.as_ptr(),
+                    key.len() as u32,
+                    value.as_mut_ptr(),
+                    &mut output_len,
+                )
+            };
+            HostError::check(ret_code)?;
+            // The host keeps a value larger than the buffer, it is taken again.
+            let output_len = output_len as usize;
+            metrics::record(HostCall::TakeStorage, key.len() + value.len().min(output_len));
+            if output_len <= value.len() {
+                value.truncate(output_len);
+                journal::record(key, Some(value.clone()));
+                return Ok(value);
+            }
+            value.resize(output_len, 0);
+        }
+    }
+
+    /// Records the value `key` held before a write, restored when the call fails, see
+    /// [`journal`]. The writes of the host functions aren't journaled on their own.
+    pub fn journal_write(key: &[u8], previous: Option<Vec<u8>>) {
+        journal::record(storage_key(key, &mut scratch::bytes()), previous);
     }
 
     #[inline(always)]
-    pub fn clear_storage(key: &[u8]) -> Option<NonZeroU32> {
+    pub fn clear_storage(key: &[u8]) -> Result<(), HostError> {
+        let mut buffer = scratch::bytes();
+        let key = storage_key(key, &mut buffer);
+        metrics::record(HostCall::ClearStorage, key.len());
         let ret_code = { unsafe { HostFn::clear_storage(key.as_ptr(), key.len() as u32) } };
-        NonZeroU32::new(ret_code)
+        HostError::check(ret_code)
+    }
+
+    /// Writes back a value recorded by the journal, `key` already includes the namespace.
+    /// Errors are ignored, the call traps right after the rollback.
+    pub(crate) fn restore_storage(key: &[u8], value: Option<&[u8]>) {
+        match value {
+            Some(value) => metrics::record(HostCall::SetStorage, key.len() + value.len()),
+            None => metrics::record(HostCall::ClearStorage, key.len()),
+        }
+        unsafe {
+            match value {
+                Some(value) => HostFn::set_storage(
+                    key.as_ptr(),
+                    key.len() as u32,
+                    value.as_ptr(),
+                    value.len() as u32,
+                ),
+                None => HostFn::clear_storage(key.as_ptr(), key.len() as u32),
+            };
+        }
+    }
+}
+
+/// Byte length of the value stored under `key`, which includes the namespace.
+fn value_size(mut key: &[u8]) -> Result<u32, HostError> {
+    if key.is_empty() {
+        key = EMPTY_PTR;
+    }
+    let mut size = 0;
+    metrics::record(HostCall::ValueSize, key.len() + 4);
+    let ret_code = unsafe { HostFn::value_size(key.as_ptr(), key.len() as u32, &mut size) };
+    HostError::check(ret_code)?;
+    Ok(size)
+}
+
+/// Reads the value stored under `key`, which includes the namespace, whatever its size into
+/// `value`. The buffer is sized by `__ext_value_size`, older hosts report the full length of a
+/// value larger than the buffer, which is then read again into a buffer large enough.
+fn read_storage(mut key: &[u8], value: &mut Vec<u8>) -> Result<(), HostError> {
+    if key.is_empty() {
+        key = EMPTY_PTR;
+    }
+    let size = match HostFnImpl::version() {
+        3.. => value_size(key)? as usize,
+        _ => INITIAL_READ_SIZE,
+    };
+    value.clear();
+    value.resize(size, 0);
+    loop {
+        let mut output_len = value.len() as u32;
+        let ret_code = unsafe {
+            HostFn::get_storage(key.as_ptr(), key.len() as u32, value.as_mut_ptr(), &mut output_len)
+        };
+        HostError::check(ret_code)?;
+        let output_len = output_len as usize;
+        metrics::record(HostCall::GetStorage, key.len() + value.len().min(output_len));
+        if output_len <= value.len() {
+            value.truncate(output_len);
+            return Ok(());
+        }
+        value.resize(output_len, 0);
     }
 }
 
@@ -74,19 +487,43 @@ fn extract_from_slice(output: &mut &mut [u8], new_len: usize) {
 
 // external methods defined outside webassembly.
 // https://github.com/paritytech/polkadot-sdk/blob/polkadot-stable2509/substrate/frame/contracts/uapi/src/host/wasm32.rs
+//
+// The imports come from `lib.exports.ts`, as laid out by the bundler. With the `node-host`
+// feature they're read from the `trieHost` global instead, see `installNodeHost`, so the
+// module runs from scripts without a bundler. `trie_builder` binds its imports the same way.
+#[cfg(target_arch = "wasm32")]
 pub(crate) mod ext {
     use wasm_bindgen::prelude::*;
 
-    #[wasm_bindgen(raw_module = "../src/lib.exports.ts")]
+    #[cfg_attr(not(feature = "node-host"), wasm_bindgen(raw_module = "../src/lib.exports.ts"))]
+    #[cfg_attr(feature = "node-host", wasm_bindgen(js_namespace = trieHost))]
     extern "C" {
         pub(crate) type HostFn;
 
+        #[cfg(feature = "enable-debug-log")]
         #[wasm_bindgen(js_name = "__ext_log", static_method_of = HostFn)]
         pub unsafe fn log(ptr: *const u8, len: u32);
 
+        #[cfg(feature = "enable-debug-log")]
+        #[wasm_bindgen(js_name = "__ext_log_entry", static_method_of = HostFn)]
+        pub unsafe fn log_entry(level: u32, category: u32, ptr: *const u8, len: u32);
+
         #[wasm_bindgen(js_name = "__ext_input", static_method_of = HostFn)]
         pub unsafe fn input(buf_ptr: *mut u8, buf_len_ptr: *mut u32);
 
+        #[wasm_bindgen(js_name = "__ext_return", static_method_of = HostFn)]
+        pub unsafe fn return_value(ptr: *const u8, len: u32) -> u32;
+
+        #[wasm_bindgen(js_name = "__ext_out_of_memory", static_method_of = HostFn)]
+        pub unsafe fn out_of_memory(requested: u32, max_pages: u32);
+
+        /// `changes` holds the new root followed by the changed keys as `[u32 len][key]`.
+        #[wasm_bindgen(js_name = "__ext_notify", static_method_of = HostFn)]
+        pub unsafe fn notify(changes_ptr: *const u8, changes_len: u32);
+
+        #[wasm_bindgen(js_name = "__ext_now", static_method_of = HostFn)]
+        pub unsafe fn now() -> f64;
+
         #[wasm_bindgen(js_name = "__ext_set_storage", static_method_of = HostFn)]
         pub unsafe fn set_storage(
             key_ptr: *const u8,
@@ -103,6 +540,34 @@ pub(crate) mod ext {
             out_len_ptr: *mut u32,
         ) -> u32;
 
+        /// `keys` holds `[u32 len][key]` entries, the output `[u32 len][value]` entries where
+        /// missing values have the length `u32::MAX`.
+        #[wasm_bindgen(js_name = "__ext_get_storage_batch", static_method_of = HostFn)]
+        pub unsafe fn get_storage_batch(
+            keys_ptr: *const u8,
+            keys_len: u32,
+            out_ptr: *mut u8,
+            out_len_ptr: *mut u32,
+        ) -> u32;
+
+        /// Returns `0` when a value is stored under the key, `3` otherwise.
+        #[wasm_bindgen(js_name = "__ext_contains_storage", static_method_of = HostFn)]
+        pub unsafe fn contains_storage(key_ptr: *const u8, key_len: u32) -> u32;
+
+        /// Writes the length of the value to `out_len_ptr`, returns `3` when the key is missing.
+        #[wasm_bindgen(js_name = "__ext_value_size", static_method_of = HostFn)]
+        pub unsafe fn value_size(key_ptr: *const u8, key_len: u32, out_len_ptr: *mut u32) -> u32;
+
+        /// Same as `__ext_get_storage`, also clearing the key. A value larger than the output
+        /// buffer is kept, only its length is written.
+        #[wasm_bindgen(js_name = "__ext_take_storage", static_method_of = HostFn)]
+        pub unsafe fn take_storage(
+            key_ptr: *const u8,
+            key_len: u32,
+            out_ptr: *mut u8,
+            out_len_ptr: *mut u32,
+        ) -> u32;
+
         #[wasm_bindgen(js_name = "__ext_clear_storage", static_method_of = HostFn)]
         pub unsafe fn clear_storage(key_ptr: *const u8, key_len: u32) -> u32;
     }
@@ -0,0 +1,73 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Journal of the storage mutations made by the current call.
+//!
+//! Every write records the value it replaces, when the call aborts the journal is replayed in
+//! reverse before trapping, so a failed operation doesn't leave orphaned nodes or stale
+//! counters behind. The writes don't read the value they replace: the nodes are written by
+//! [`ExternalDB::flush`](crate::storage::ExternalDB::flush), which knows the entries it
+//! replaces, and a take already returns it. Only the writes of the reserved keys read it
+//! first, see [`TrieBackend::write`](crate::backend::TrieBackend::write).
+use crate::host::HostFnImpl;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Storage key and the value it held before the write.
+type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+/// Former values of the keys written, in the order of the writes.
+#[derive(Default)]
+pub struct Journal(RefCell<Vec<Entry>>);
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for Journal {}
+
+impl Journal {
+    pub const fn new() -> Self {
+        Self(RefCell::new(Vec::new()))
+    }
+
+    /// Records that `key` held `previous` before being written.
+    pub fn record(&self, key: &[u8], previous: Option<Vec<u8>>) {
+        self.0.borrow_mut().push((key.to_vec(), previous));
+    }
+
+    /// Restores the value of every key recorded with `restore`, the most recent write first.
+    /// The entries are taken before replaying them, so an abort while restoring doesn't
+    /// recurse.
+    pub fn rollback(&self, mut restore: impl FnMut(&[u8], Option<&[u8]>)) {
+        let entries = self.0.take();
+        for (key, previous) in entries.into_iter().rev() {
+            restore(&key, previous.as_deref());
+        }
+    }
+}
+
+/// Journal of the host storage, keys include the namespace prefix.
+static JOURNAL: Journal = Journal::new();
+
+/// Starts the journal of a new call, dropping the entries of the previous one. The heap they
+/// point to is only reset after, see [`crate::arena::begin`].
+pub fn begin() {
+    JOURNAL.0.take();
+}
+
+/// Empties the journal before the heap is wiped.
+pub fn wipe() {
+    let _entries = JOURNAL.0.take();
+    // The heap is reset by the caller, there is nothing to free.
+    #[cfg(not(feature = "dlmalloc"))]
+    core::mem::forget(_entries);
+}
+
+/// Records that `key` held `previous` before being written.
+pub fn record(key: &[u8], previous: Option<Vec<u8>>) {
+    JOURNAL.record(key, previous);
+}
+
+/// Restores the values of every key written by the current call, the most recent write first.
+pub fn rollback() {
+    JOURNAL.rollback(HostFnImpl::restore_storage);
+}
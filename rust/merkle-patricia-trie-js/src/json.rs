@@ -0,0 +1,139 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! JSON encoding of the decoded node graph, for hosts not using `JSTrieBuilder`.
+//!
+//! Each node is an object with the fields set by the builder: `id`, `nibbles`, `value` and
+//! `rawBytes` as `0x` prefixed hex strings or `null`, `kind`, `depth`, `encodedSize`,
+//! `highlighted`, `children` as a list of `{"nibble": n, "node": {..}}` and `extension`, the
+//! single child of an extension node.
+use crate::{children::NodeId, trie::NodeArena};
+use alloc::string::String;
+use core::fmt::Write;
+
+const ALPHABET: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Encodes the node at `index` and its descendants.
+pub fn encode(index: NodeId, nodes: &NodeArena) -> String {
+    let mut output = String::with_capacity(nodes.len() * 256);
+    write_node(&mut output, index, nodes);
+    output
+}
+
+fn write_node(output: &mut String, index: NodeId, nodes: &NodeArena) {
+    let node = &nodes[index];
+    output.push_str("{\"id\":");
+    write_hex(output, node.id.as_ref().map(|id| id.as_bytes().iter().copied()));
+    let _ = write!(
+        output,
+        ",\"kind\":\"{}\",\"depth\":{},\"encodedSize\":{}",
+        node.kind.name(),
+        node.depth,
+        node.raw_bytes.len()
+    );
+    output.push_str(",\"nibbles\":");
+    let nibbles = node.nibbles().filter(|slice| !slice.is_empty());
+    write_hex_digits(output, nibbles.as_ref().map(|slice| slice.iter()));
+    output.push_str(",\"value\":");
+    write_hex(output, node.value.as_ref().map(|value| value.iter().copied()));
+    output.push_str(",\"rawBytes\":");
+    write_hex(output, Some(node.raw_bytes.iter().copied()));
+    output.push_str(",\"highlighted\":");
+    output.push_str(if node.highlighted { "true" } else { "false" });
+    output.push_str(",\"children\":[");
+    for (position, (child, nibble)) in node.children.iter().enumerate() {
+        if position > 0 {
+            output.push(',');
+        }
+        let _ = write!(output, "{{\"nibble\":{nibble},\"node\":");
+        write_node(output, child, nodes);
+        output.push('}');
+    }
+    output.push_str("],\"extension\":");
+    match node.children.extension() {
+        Some(child) => write_node(output, child, nodes),
+        None => output.push_str("null"),
+    }
+    output.push('}');
+}
+
+fn write_hex<I: Iterator<Item = u8>>(output: &mut String, bytes: Option<I>) {
+    write_hex_digits(output, bytes.map(|bytes| bytes.flat_map(|byte| [byte >> 4, byte & 15])));
+}
+
+/// Writes the digits as a `0x` prefixed string, or `null`.
+fn write_hex_digits<I: Iterator<Item = u8>>(output: &mut String, digits: Option<I>) {
+    let Some(digits) = digits else {
+        output.push_str("null");
+        return;
+    };
+    output.push_str("\"0x");
+    for digit in digits {
+        output.push(char::from(ALPHABET[usize::from(digit & 15)]));
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        children::Children,
+        primitives::H256,
+        trie::{NodeKind, TrieNode},
+    };
+    use trie_db::node::NibbleSlicePlan;
+
+    fn leaf(value: u8) -> TrieNode {
+        TrieNode {
+            id: None,
+            kind: NodeKind::Leaf,
+            depth: 1,
+            partial: Some(NibbleSlicePlan::new(0..1, 1)),
+            value: Some(vec![value]),
+            children: Children::new(),
+            raw_bytes: vec![0xab],
+            highlighted: false,
+            truncated: false,
+            status: None,
+            references: 0,
+            matched_nibbles: None,
+            collapsed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn encodes_the_nodes_from_the_root() {
+        let mut nodes = NodeArena::default();
+        let mut children = Children::new();
+        children.push(nodes.push(leaf(7)), 10);
+        let root = TrieNode {
+            id: Some(H256::repeat_byte(0x1f)),
+            kind: NodeKind::Branch,
+            depth: 0,
+            partial: None,
+            value: None,
+            children,
+            raw_bytes: vec![0x80, 0x00],
+            highlighted: true,
+            truncated: false,
+            status: None,
+            references: 0,
+            matched_nibbles: None,
+            collapsed: Vec::new(),
+        };
+        let root = nodes.push(root);
+        let json = encode(root, &nodes);
+        let id = format!("0x{}", "1F".repeat(32));
+        assert_eq!(
+            json,
+            format!(
+                "{{\"id\":\"{id}\",\"kind\":\"branch\",\"depth\":0,\"encodedSize\":2,\"nibbles\":null,\"value\":null,\"rawBytes\":\"0x8000\",\
+                 \"highlighted\":true,\"children\":[{{\"nibble\":10,\"node\":{{\"id\":null,\
+                 \"kind\":\"leaf\",\"depth\":1,\"encodedSize\":1,\"nibbles\":\"0xB\",\"value\":\"0x07\",\"rawBytes\":\"0xAB\",\
+                 \"highlighted\":false,\"children\":[],\"extension\":null}}}}],\"extension\":null}}"
+            )
+        );
+    }
+}
@@ -0,0 +1,580 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Substrate layouts of the slim build, the node codec and proofs of `sp_trie` without the rest
+//! of the Substrate stack.
+//!
+//! A node starts with a header holding its kind and the number of nibbles of its partial key,
+//! followed by the partial key packed two nibbles per byte. Branches then hold the bitmap of
+//! their children as a little endian `u16`. Values and children are SCALE encoded byte strings,
+//! children of 32 bytes are hashes and the shorter ones are inlined. `LayoutV1` stores the
+//! values longer than 32 bytes in their own node, referenced by hash.
+//!
+//! Compact proofs encode the top trie first, then the child tries in the order of their root
+//! in the top trie.
+use crate::{abort, child::CHILD_STORAGE_PREFIX};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use codec::{Compact, Decode, Encode, Input, Output};
+use core::{borrow::Borrow, marker::PhantomData, ops::Range};
+use hash_db::{HashDB, HashDBRef, Hasher, EMPTY_PREFIX};
+use trie_db::{
+    node::{NibbleSlicePlan, NodeHandlePlan, NodePlan, Value, ValuePlan},
+    trie_visit, ChildReference, DBValue, NodeCodec, Trie, TrieConfiguration, TrieDBBuilder,
+    TrieError, TrieHash, TrieLayout, TrieRoot,
+};
+
+const EMPTY_TRIE: u8 = 0;
+/// Header of the compact proofs, telling an omitted value or child.
+const ESCAPE_COMPACT_HEADER: u8 = EMPTY_TRIE | 0b01;
+const LEAF: u8 = 0b01 << 6;
+const BRANCH_WITHOUT_VALUE: u8 = 0b10 << 6;
+const BRANCH_WITH_VALUE: u8 = 0b11 << 6;
+const HASHED_VALUE_LEAF: u8 = 0b001 << 5;
+const HASHED_VALUE_BRANCH: u8 = 0b0001 << 4;
+
+/// Values longer than this are stored in their own node by `LayoutV1`.
+const VALUE_NODE_THRESHOLD: u32 = 33;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecError {
+    /// The input ends before the node does.
+    UnexpectedEnd,
+    /// The header, the padding of the partial key or the bitmap is invalid.
+    BadFormat,
+    /// A length isn't a valid SCALE compact integer.
+    InvalidLength,
+}
+
+/// Reads the node bytes, tracking the offset so the plan can point into them.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<Range<usize>, CodecError> {
+        let end = self.offset.checked_add(count).filter(|end| *end <= self.data.len());
+        let Some(end) = end else {
+            return Err(CodecError::UnexpectedEnd);
+        };
+        let range = self.offset..end;
+        self.offset = end;
+        Ok(range)
+    }
+
+    fn byte(&mut self) -> Result<u8, CodecError> {
+        let range = self.take(1)?;
+        Ok(self.data[range.start])
+    }
+
+    /// Reads a SCALE encoded byte string, returning the range of its bytes.
+    fn bytes(&mut self) -> Result<Range<usize>, CodecError> {
+        let mut input = &self.data[self.offset..];
+        let Ok(Compact(len)) = Compact::<u32>::decode(&mut input) else {
+            return Err(CodecError::InvalidLength);
+        };
+        self.offset = self.data.len() - input.len();
+        self.take(len as usize)
+    }
+
+    /// Reads the number of nibbles following the header bits, extended by the next bytes while
+    /// they are all ones.
+    fn nibble_count(&mut self, first: u8, prefix_bits: u32) -> Result<usize, CodecError> {
+        let max = usize::from(u8::MAX >> prefix_bits);
+        let mut count = usize::from(first) & max;
+        if count < max {
+            return Ok(count);
+        }
+        loop {
+            let byte = self.byte()?;
+            count += usize::from(byte);
+            if byte < u8::MAX {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+/// Writes the header of a node, the kind `prefix` in its `prefix_bits` high bits and the
+/// number of nibbles in the others.
+fn encode_header(output: &mut Vec<u8>, prefix: u8, prefix_bits: u32, nibble_count: usize) {
+    let max = u8::MAX >> prefix_bits;
+    if nibble_count < usize::from(max) {
+        output.push(prefix | nibble_count as u8);
+        return;
+    }
+    output.push(prefix | max);
+    let mut rest = nibble_count - usize::from(max);
+    while rest >= usize::from(u8::MAX) {
+        output.push(u8::MAX);
+        rest -= usize::from(u8::MAX);
+    }
+    output.push(rest as u8);
+}
+
+fn encode_partial(
+    prefix: u8,
+    prefix_bits: u32,
+    partial: impl Iterator<Item = u8>,
+    number_nibble: usize,
+) -> Vec<u8> {
+    let mut output = Vec::with_capacity(number_nibble / 2 + 4);
+    encode_header(&mut output, prefix, prefix_bits, number_nibble);
+    output.extend(partial);
+    output
+}
+
+fn encode_value(output: &mut Vec<u8>, value: Value) {
+    match value {
+        Value::Inline(value) => {
+            Compact(value.len() as u32).encode_to(output);
+            output.extend_from_slice(value);
+        },
+        Value::Node(hash) => output.extend_from_slice(hash),
+    }
+}
+
+/// Plans the partial key of `nibble_count` nibbles at the offset of `reader`, the padding
+/// nibble of an odd count must be zero.
+fn decode_partial(reader: &mut Reader, nibble_count: usize) -> Result<NibbleSlicePlan, CodecError> {
+    let Some(&first) = reader.data.get(reader.offset) else {
+        return Err(CodecError::BadFormat);
+    };
+    let padding = nibble_count % 2;
+    if padding == 1 && first & 0xf0 != 0 {
+        return Err(CodecError::BadFormat);
+    }
+    let range = reader.take(nibble_count.div_ceil(2))?;
+    Ok(NibbleSlicePlan::new(range, padding))
+}
+
+/// Node codec of the Substrate tries, without extension nodes.
+pub struct SubstrateNodeCodec<H>(PhantomData<H>);
+
+impl<H: Hasher> NodeCodec for SubstrateNodeCodec<H> {
+    const ESCAPE_HEADER: Option<u8> = Some(ESCAPE_COMPACT_HEADER);
+    type Error = CodecError;
+    type HashOut = H::Out;
+
+    fn hashed_null_node() -> Self::HashOut {
+        H::hash(Self::empty_node())
+    }
+
+    fn decode_plan(data: &[u8]) -> Result<NodePlan, Self::Error> {
+        let mut reader = Reader { data, offset: 0 };
+        let first = reader.byte()?;
+        // The kind of the node, the bits of its header and whether its value is hashed.
+        let (kind, prefix_bits, hashed) = match first >> 6 {
+            _ if first == EMPTY_TRIE => return Ok(NodePlan::Empty),
+            0b01 => (LEAF, 2, false),
+            0b10 => (BRANCH_WITHOUT_VALUE, 2, false),
+            0b11 => (BRANCH_WITH_VALUE, 2, false),
+            _ if first & 0b1110_0000 == HASHED_VALUE_LEAF => (LEAF, 3, true),
+            _ if first & 0b1111_0000 == HASHED_VALUE_BRANCH => (BRANCH_WITH_VALUE, 4, true),
+            _ => return Err(CodecError::BadFormat),
+        };
+        let nibble_count = reader.nibble_count(first, prefix_bits)?;
+        let partial = decode_partial(&mut reader, nibble_count)?;
+        let value = |reader: &mut Reader| match hashed {
+            true => reader.take(H::LENGTH).map(ValuePlan::Node),
+            false => reader.bytes().map(ValuePlan::Inline),
+        };
+        if kind == LEAF {
+            return Ok(NodePlan::Leaf { partial, value: value(&mut reader)? });
+        }
+
+        let bitmap = reader.take(2)?;
+        let bitmap = u16::from_le_bytes([data[bitmap.start], data[bitmap.start + 1]]);
+        if bitmap == 0 {
+            return Err(CodecError::BadFormat);
+        }
+        let value = match kind {
+            BRANCH_WITH_VALUE => Some(value(&mut reader)?),
+            _ => None,
+        };
+        let mut children = [const { None }; 16];
+        for (nibble, child) in children.iter_mut().enumerate() {
+            if bitmap & (1 << nibble) == 0 {
+                continue;
+            }
+            let range = reader.bytes()?;
+            *child = Some(match range.len() == H::LENGTH {
+                true => NodeHandlePlan::Hash(range),
+                false => NodeHandlePlan::Inline(range),
+            });
+        }
+        Ok(NodePlan::NibbledBranch { partial, value, children })
+    }
+
+    fn is_empty_node(data: &[u8]) -> bool {
+        data == Self::empty_node()
+    }
+
+    fn empty_node() -> &'static [u8] {
+        &[EMPTY_TRIE]
+    }
+
+    fn leaf_node(partial: impl Iterator<Item = u8>, number_nibble: usize, value: Value) -> Vec<u8> {
+        let mut output = match value {
+            Value::Inline(_) => encode_partial(LEAF, 2, partial, number_nibble),
+            Value::Node(_) => encode_partial(HASHED_VALUE_LEAF, 3, partial, number_nibble),
+        };
+        encode_value(&mut output, value);
+        output
+    }
+
+    fn extension_node(
+        _partial: impl Iterator<Item = u8>,
+        _number_nibble: usize,
+        _child_ref: ChildReference<Self::HashOut>,
+    ) -> Vec<u8> {
+        abort!("[bug] the Substrate layouts have no extension nodes");
+    }
+
+    fn branch_node(
+        _children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+        _value: Option<Value>,
+    ) -> Vec<u8> {
+        abort!("[bug] the Substrate layouts have no extension nodes");
+    }
+
+    fn branch_node_nibbled(
+        partial: impl Iterator<Item = u8>,
+        number_nibble: usize,
+        children: impl Iterator<Item = impl Borrow<Option<ChildReference<Self::HashOut>>>>,
+        value: Option<Value>,
+    ) -> Vec<u8> {
+        let mut output = match value {
+            None => encode_partial(BRANCH_WITHOUT_VALUE, 2, partial, number_nibble),
+            Some(Value::Inline(_)) => encode_partial(BRANCH_WITH_VALUE, 2, partial, number_nibble),
+            Some(Value::Node(_)) => encode_partial(HASHED_VALUE_BRANCH, 4, partial, number_nibble),
+        };
+        let bitmap_at = output.len();
+        output.extend_from_slice(&[0, 0]);
+        if let Some(value) = value {
+            encode_value(&mut output, value);
+        }
+        let mut bitmap = 0u16;
+        for (nibble, child) in children.enumerate() {
+            let child = match child.borrow() {
+                Some(ChildReference::Hash(hash)) => hash.as_ref(),
+                Some(ChildReference::Inline(node, len)) => &node.as_ref()[..*len],
+                None => continue,
+            };
+            child.encode_to(&mut output);
+            bitmap |= 1 << nibble;
+        }
+        output[bitmap_at..bitmap_at + 2].copy_from_slice(&bitmap.to_le_bytes());
+        output
+    }
+}
+
+/// Root of the trie of `input`. `trie_visit` wants the keys sorted and unique, the last value of
+/// a key wins, as in the `sp_trie` layouts.
+fn sorted_root<L: TrieLayout, I, A, B>(input: I) -> TrieHash<L>
+where
+    I: IntoIterator<Item = (A, B)>,
+    A: AsRef<[u8]> + Ord,
+    B: AsRef<[u8]>,
+{
+    let input: BTreeMap<A, B> = input.into_iter().collect();
+    let mut root = TrieRoot::<L>::default();
+    trie_visit::<L, _, _, _, _>(input, &mut root);
+    root.root.unwrap_or_default()
+}
+
+/// Layout of the tries of state version `0`, values are always inlined in their node.
+pub struct LayoutV0<H>(PhantomData<H>);
+
+impl<H: Hasher> TrieLayout for LayoutV0<H> {
+    const USE_EXTENSION: bool = false;
+    const ALLOW_EMPTY: bool = true;
+    const MAX_INLINE_VALUE: Option<u32> = None;
+
+    type Hash = H;
+    type Codec = SubstrateNodeCodec<H>;
+}
+
+impl<H: Hasher> TrieConfiguration for LayoutV0<H> {
+    fn trie_root<I, A, B>(input: I) -> H::Out
+    where
+        I: IntoIterator<Item = (A, B)>,
+        A: AsRef<[u8]> + Ord,
+        B: AsRef<[u8]>,
+    {
+        sorted_root::<Self, _, _, _>(input)
+    }
+
+    fn encode_index(index: u32) -> Vec<u8> {
+        Compact(index).encode()
+    }
+}
+
+/// Layout of the tries of state version `1`, values longer than 32 bytes are hashed.
+pub struct LayoutV1<H>(PhantomData<H>);
+
+impl<H: Hasher> TrieLayout for LayoutV1<H> {
+    const USE_EXTENSION: bool = false;
+    const ALLOW_EMPTY: bool = true;
+    const MAX_INLINE_VALUE: Option<u32> = Some(VALUE_NODE_THRESHOLD);
+
+    type Hash = H;
+    type Codec = SubstrateNodeCodec<H>;
+}
+
+impl<H: Hasher> TrieConfiguration for LayoutV1<H> {
+    fn trie_root<I, A, B>(input: I) -> H::Out
+    where
+        I: IntoIterator<Item = (A, B)>,
+        A: AsRef<[u8]> + Ord,
+        B: AsRef<[u8]>,
+    {
+        sorted_root::<Self, _, _, _>(input)
+    }
+
+    fn encode_index(index: u32) -> Vec<u8> {
+        Compact(index).encode()
+    }
+}
+
+/// Nodes in memory, keyed by their hash.
+pub type MemoryDB<H> = memory_db::MemoryDB<H, memory_db::HashKey<H>, DBValue>;
+
+/// Failure to encode or decode a compact proof.
+#[derive(Debug)]
+pub enum CompactProofError {
+    RootMismatch,
+    IncompleteProof,
+    /// The proof holds nodes after the last child trie.
+    ExtraneousChildNode,
+    /// A child trie of the proof has no root in the top trie.
+    ExtraneousChildProof,
+    InvalidChildRoot,
+    Trie,
+}
+
+impl<H, E> From<Box<TrieError<H, E>>> for CompactProofError {
+    fn from(_: Box<TrieError<H, E>>) -> Self {
+        Self::Trie
+    }
+}
+
+/// Roots of the child tries found in the top trie, tolerating the nodes missing from a proof.
+fn child_roots<L: TrieConfiguration>(
+    trie: &impl Trie<L>,
+) -> Result<Vec<TrieHash<L>>, CompactProofError> {
+    let mut roots = Vec::new();
+    let mut iter = trie.iter()?;
+    if iter.seek(CHILD_STORAGE_PREFIX).is_err() {
+        return Ok(roots);
+    }
+    loop {
+        match iter.next() {
+            Some(Ok((key, value))) if key.starts_with(CHILD_STORAGE_PREFIX) => {
+                let mut root = TrieHash::<L>::default();
+                if root.as_ref().len() != value.len() {
+                    return Err(CompactProofError::InvalidChildRoot);
+                }
+                root.as_mut().copy_from_slice(&value);
+                roots.push(root);
+            },
+            Some(Err(error)) if matches!(*error, TrieError::IncompleteDatabase(_)) => {},
+            Some(Err(error)) => return Err(error.into()),
+            _ => return Ok(roots),
+        }
+    }
+}
+
+/// Decodes the nodes of a compact proof into `db`, returning the root of the top trie.
+pub fn decode_compact<'a, L, DB, I>(
+    db: &mut DB,
+    encoded: I,
+    expected_root: Option<&TrieHash<L>>,
+) -> Result<TrieHash<L>, CompactProofError>
+where
+    L: TrieConfiguration,
+    DB: HashDB<L::Hash, DBValue> + HashDBRef<L::Hash, DBValue>,
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let mut nodes = encoded.into_iter().peekable();
+    let (root, _) = trie_db::decode_compact_from_iter::<L, _, _>(db, &mut nodes)?;
+    if expected_root.is_some_and(|expected| *expected != root) {
+        return Err(CompactProofError::RootMismatch);
+    }
+    let child_roots = child_roots::<L>(&TrieDBBuilder::<L>::new(&*db, &root).build())?;
+    if !HashDB::contains(&*db, &root, EMPTY_PREFIX) {
+        return Err(CompactProofError::IncompleteProof);
+    }
+
+    // The child tries come in the order of their root, a root may have no trie in the proof.
+    let mut pending = None;
+    for child_root in child_roots {
+        if pending.is_none() && nodes.peek().is_some() {
+            pending = Some(trie_db::decode_compact_from_iter::<L, _, _>(db, &mut nodes)?.0);
+        }
+        if pending == Some(child_root) {
+            pending = None;
+        }
+    }
+    if pending.is_some() {
+        return Err(CompactProofError::ExtraneousChildProof);
+    }
+    if nodes.next().is_some() {
+        return Err(CompactProofError::ExtraneousChildNode);
+    }
+    Ok(root)
+}
+
+/// Encodes the nodes of `db` as a compact proof of the trie at `root` and of its child tries.
+pub fn encode_compact<L: TrieConfiguration, DB>(
+    db: &DB,
+    root: &TrieHash<L>,
+) -> Result<CompactProof, CompactProofError>
+where
+    DB: HashDB<L::Hash, DBValue> + HashDBRef<L::Hash, DBValue>,
+{
+    let trie = TrieDBBuilder::<L>::new(db, root).build();
+    let child_roots = child_roots::<L>(&trie)?;
+    let mut encoded_nodes = trie_db::encode_compact::<L>(&trie)?;
+    for child_root in child_roots {
+        // The root of a child trie can be in the proof without its nodes.
+        if !HashDB::contains(db, &child_root, EMPTY_PREFIX) {
+            continue;
+        }
+        let trie = TrieDBBuilder::<L>::new(db, &child_root).build();
+        encoded_nodes.extend(trie_db::encode_compact::<L>(&trie)?);
+    }
+    Ok(CompactProof { encoded_nodes })
+}
+
+/// Nodes of a proof, without duplicates. Encoded as a SCALE list of byte strings.
+pub struct StorageProof {
+    trie_nodes: BTreeSet<Vec<u8>>,
+}
+
+impl StorageProof {
+    pub fn new(trie_nodes: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        Self { trie_nodes: trie_nodes.into_iter().collect() }
+    }
+
+    pub fn encoded_size(&self) -> usize {
+        self.trie_nodes.encoded_size()
+    }
+
+    pub fn into_iter_nodes(self) -> impl Iterator<Item = Vec<u8>> {
+        self.trie_nodes.into_iter()
+    }
+
+    pub fn to_memory_db<H: Hasher>(&self) -> MemoryDB<H> {
+        let mut db = MemoryDB::default();
+        for node in &self.trie_nodes {
+            db.insert(EMPTY_PREFIX, node);
+        }
+        db
+    }
+
+    pub fn into_memory_db<H: Hasher>(self) -> MemoryDB<H> {
+        self.to_memory_db()
+    }
+}
+
+/// Proof where the values and child hashes the verifier recomputes are omitted, see
+/// [`trie_db::encode_compact`].
+pub struct CompactProof {
+    pub encoded_nodes: Vec<Vec<u8>>,
+}
+
+impl Encode for CompactProof {
+    fn size_hint(&self) -> usize {
+        self.encoded_nodes.size_hint()
+    }
+
+    fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        self.encoded_nodes.encode_to(dest);
+    }
+}
+
+impl Decode for CompactProof {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        Ok(Self { encoded_nodes: Decode::decode(input)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::Blake2Hasher;
+    use trie_db::{TrieDBMutBuilder, TrieMut};
+
+    type Layout = LayoutV1<Blake2Hasher>;
+    type Reference = sp_trie::LayoutV1<sp_core::Blake2Hasher>;
+
+    /// Entries with inline and hashed values, under leaves and branches with and without value.
+    fn entries() -> BTreeMap<Vec<u8>, Vec<u8>> {
+        (0..300u32)
+            .map(|index| {
+                let key = sp_crypto_hashing::blake2_128(&index.to_le_bytes());
+                let key = key[..index as usize % 7 + 1].to_vec();
+                (key, index.to_be_bytes().repeat(index as usize % 12))
+            })
+            .collect()
+    }
+
+    fn build<L: TrieLayout<Hash = H>, H: Hasher>(db: &mut dyn HashDB<H, DBValue>) -> H::Out {
+        let mut root = Default::default();
+        let mut trie = TrieDBMutBuilder::<L>::new(db, &mut root).build();
+        for (key, value) in entries() {
+            trie.insert(&key, &value).ok();
+        }
+        drop(trie);
+        root
+    }
+
+    #[test]
+    fn roots_match_sp_trie() {
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0]);
+        let root = build::<Layout, _>(&mut db);
+        let mut reference_db = sp_trie::MemoryDB::<sp_core::Blake2Hasher>::new(&[0]);
+        let reference_root = build::<Reference, _>(&mut reference_db);
+        assert_eq!(root.as_bytes(), reference_root.as_bytes());
+        assert_eq!(Layout::trie_root(entries()), root);
+
+        let values = entries().into_values().collect::<Vec<_>>();
+        let ordered_root = Reference::ordered_trie_root(&values);
+        assert_eq!(Layout::ordered_trie_root(&values).as_bytes(), ordered_root.as_bytes());
+        let v0_root = sp_trie::LayoutV0::<sp_core::Blake2Hasher>::trie_root(entries());
+        assert_eq!(LayoutV0::<Blake2Hasher>::trie_root(entries()).as_bytes(), v0_root.as_bytes());
+
+        let trie = TrieDBBuilder::<Layout>::new(&db, &root).build();
+        for (key, value) in entries() {
+            assert_eq!(trie.get(&key).ok(), Some(Some(value)));
+        }
+    }
+
+    #[test]
+    fn compact_proofs_match_sp_trie() {
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0]);
+        let root = build::<Layout, _>(&mut db);
+        let compact = encode_compact::<Layout, _>(&db, &root).ok().unwrap();
+
+        let mut reference_db = sp_trie::MemoryDB::<sp_core::Blake2Hasher>::new(&[0]);
+        let reference_root = build::<Reference, _>(&mut reference_db);
+        let reference = sp_trie::encode_compact::<Reference, _>(&reference_db, &reference_root);
+        assert_eq!(compact.encode(), reference.ok().unwrap().encode());
+
+        let decoded = CompactProof::decode(&mut &compact.encode()[..]).unwrap();
+        let mut proof_db = MemoryDB::<Blake2Hasher>::new(&[]);
+        let nodes = decoded.encoded_nodes.iter().map(Vec::as_slice);
+        let decoded_root = decode_compact::<Layout, _, _>(&mut proof_db, nodes, Some(&root));
+        assert_eq!(decoded_root.ok(), Some(root));
+        let trie = TrieDBBuilder::<Layout>::new(&proof_db, &root).build();
+        for (key, value) in entries() {
+            assert_eq!(trie.get(&key).ok(), Some(Some(value)));
+        }
+    }
+}
@@ -4,19 +4,70 @@
 
 #![cfg_attr(all(target_arch = "wasm32", not(test)), no_std, no_main)]
 
+// The free-list allocator only uses the heap of the bump allocator.
 #[cfg(not(feature = "dlmalloc"))]
+#[cfg_attr(feature = "free-list", allow(dead_code))]
 mod bump_allocator;
 
+#[cfg(all(feature = "free-list", not(feature = "dlmalloc")))]
+mod free_list_allocator;
+
+#[cfg(fuzzing)]
+pub mod fuzz;
+
+mod arena;
+mod backend;
+mod bench;
+mod cache;
+mod cbor;
+mod checkpoint;
+mod child;
 mod children;
+mod cursor;
+mod diff;
+mod dot;
+mod error;
+mod ethereum;
+mod explain;
 mod hash;
+mod hasher;
+mod hints;
+mod history;
 mod host;
+mod journal;
+mod json;
+#[cfg(not(feature = "substrate"))]
+mod layout;
+mod log;
+mod metrics;
+mod migration;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native;
+mod node;
+mod oplog;
+mod ordered;
+mod primitives;
+#[cfg(all(feature = "alloc-profile", not(feature = "dlmalloc")))]
+mod profile;
+mod proof;
+#[cfg(test)]
+mod reference;
+mod rpc;
+mod scratch;
+mod snapshot;
+mod steps;
 mod storage;
+mod substrate;
+mod sync;
 mod trie;
 mod trie_builder;
+mod tries;
+mod value_index;
+mod watch;
 
 use crate::alloc::string::String;
 
-#[cfg(not(feature = "enable-debug-log"))]
+#[cfg(all(target_arch = "wasm32", not(test), not(feature = "enable-debug-log")))]
 use crate::alloc::string::ToString;
 
 use wasm_bindgen::prelude::*;
@@ -25,14 +76,22 @@ use wasm_bindgen::prelude::*;
 #[cfg_attr(any(test, feature = "enable-debug-log"), macro_use)]
 extern crate alloc;
 
-#[cfg(not(feature = "dlmalloc"))]
+#[cfg(not(any(feature = "dlmalloc", feature = "free-list")))]
 pub type Allocator = bump_allocator::BumpAllocator;
 
-#[cfg(not(feature = "dlmalloc"))]
+#[cfg(not(any(feature = "dlmalloc", feature = "free-list")))]
 const fn allocator() -> Allocator {
     bump_allocator::BumpAllocator
 }
 
+#[cfg(all(feature = "free-list", not(feature = "dlmalloc")))]
+pub type Allocator = free_list_allocator::FreeListAllocator;
+
+#[cfg(all(feature = "free-list", not(feature = "dlmalloc")))]
+const fn allocator() -> Allocator {
+    free_list_allocator::FreeListAllocator
+}
+
 #[cfg(feature = "dlmalloc")]
 pub type Allocator = dlmalloc::GlobalDlmalloc;
 
@@ -41,37 +100,140 @@ const fn allocator() -> Allocator {
     dlmalloc::GlobalDlmalloc
 }
 
-#[global_allocator]
+/// Returns the heap usage of the last call through `__ext_return`, see
+/// [`bump_allocator::MemoryStats`].
+#[cfg(not(feature = "dlmalloc"))]
+#[export_name = "__ext_memory_stats"]
+pub extern "C" fn memory_stats() {
+    host::HostFnImpl::return_value(&Allocator::stats().encode());
+}
+
+/// Caps the Wasm memory the heap may grow to, in pages of `64KiB`. The limit lives in the
+/// module memory, the host sets it again after restoring the memory.
+#[cfg(not(feature = "dlmalloc"))]
+#[export_name = "__ext_set_heap_limit"]
+pub extern "C" fn set_heap_limit(pages: u32) {
+    bump_allocator::set_max_pages(pages as usize);
+}
+
+/// Keeps the heap and the node cache across calls when `persistent` isn't zero, until
+/// `__ext_reset` is called. Disabling it wipes the heap.
+#[export_name = "__ext_set_persistent"]
+pub extern "C" fn set_persistent(persistent: u32) {
+    arena::set_persistent(persistent != 0);
+}
+
+/// Wipes the heap kept across calls by a persistent arena.
+#[export_name = "__ext_reset"]
+pub extern "C" fn reset() {
+    arena::wipe();
+}
+
+// The bump allocator grows the wasm memory, the native builds keep the system allocator.
+#[cfg_attr(all(target_arch = "wasm32", not(test)), global_allocator)]
+#[cfg_attr(not(all(target_arch = "wasm32", not(test))), allow(dead_code))]
 static mut ALLOC: Allocator = allocator();
 
+/// Records the failure, see [`error::record`], and traps. The message may be preceded by
+/// `key = ..`, the offending key or hash.
 #[macro_export]
 macro_rules! abort {
+  (key = $key:expr, $($arg:tt)*) => {{
+    $crate::error::record($crate::error::ABORTED, $key, format_args!($($arg)*));
+    $crate::__abort();
+  }};
   ($($arg:tt)*) => {{
-    {
-        #[cfg(feature = "enable-debug-log")]
-        {
-            let msg = format!($($arg)*);
-            $crate::host::HostFnImpl::log(msg.as_str());
-        }
+    $crate::error::record($crate::error::ABORTED, &[], format_args!($($arg)*));
+    $crate::__abort();
+  }};
+}
 
-        $crate::__abort();
-    }
+/// Records the failure and evaluates to `Err($error)`, the recoverable counterpart of
+/// [`abort!`], see [`error::CallError`].
+#[macro_export]
+macro_rules! fail {
+  ($error:expr, key = $key:expr, $($arg:tt)*) => {{
+    $crate::error::record($error as u8, $key, format_args!($($arg)*));
+    Err($error)
+  }};
+  ($error:expr, $($arg:tt)*) => {{
+    $crate::error::record($error as u8, &[], format_args!($($arg)*));
+    Err($error)
   }};
 }
 
+/// Logs a message in debug builds, unless filtered out, see [`log`]. The message may be
+/// preceded by its level and category, `Debug` and `Call` by default.
 #[macro_export]
 macro_rules! debug_log {
-  ($($arg:tt)*) => {{
+  ($level:ident, $category:ident, $($arg:tt)*) => {{
     #[cfg(feature = "enable-debug-log")]
     {
-        let msg = format!($($arg)*);
-        $crate::host::HostFnImpl::log(msg.as_str());
+        use $crate::log::{Category, Level};
+        if $crate::log::enabled(Level::$level, Category::$category) {
+            let msg = format!($($arg)*);
+            $crate::host::HostFnImpl::log(Level::$level, Category::$category, msg.as_str());
+        }
     }
   }};
+  ($($arg:tt)*) => {{
+    $crate::debug_log!(Debug, Call, $($arg)*)
+  }};
+}
+
+/// Evaluates `$body` with the type `$layout` bound to the trie layout configured for the
+/// storage, see [`storage::Config`].
+#[macro_export]
+macro_rules! with_layout {
+    ($layout:ident => $body:expr) => {{
+        use $crate::storage::{Config, HasherKind};
+        let config = Config::load();
+        match config.hasher {
+            HasherKind::Blake2 => {
+                $crate::with_layout!(@layout config, $crate::primitives::Blake2Hasher, $layout => $body)
+            },
+            HasherKind::Keccak => {
+                $crate::with_layout!(@layout config, $crate::primitives::KeccakHasher, $layout => $body)
+            },
+            HasherKind::Blake3 => {
+                $crate::with_layout!(@layout config, $crate::hasher::Blake3Hasher, $layout => $body)
+            },
+            HasherKind::Sha256 => {
+                $crate::with_layout!(@layout config, $crate::hasher::Sha256Hasher, $layout => $body)
+            },
+        }
+    }};
+    (@layout $config:ident, $hasher:ty, $layout:ident => $body:expr) => {{
+        use $crate::storage::LayoutKind;
+        match $config.layout {
+            LayoutKind::V0 => {
+                $crate::with_layout!(@keys $config, $crate::primitives::LayoutV0<$hasher>, $layout => $body)
+            },
+            LayoutKind::V1 => {
+                $crate::with_layout!(@keys $config, $crate::primitives::LayoutV1<$hasher>, $layout => $body)
+            },
+            LayoutKind::Ethereum => {
+                $crate::with_layout!(
+                    @keys $config, $crate::ethereum::EthereumLayout<$hasher>, $layout => $body
+                )
+            },
+        }
+    }};
+    (@keys $config:ident, $inner:ty, $layout:ident => $body:expr) => {{
+        if $config.secure_keys {
+            type $layout = $crate::storage::SecureKeys<$inner>;
+            $body
+        } else {
+            type $layout = $inner;
+            $body
+        }
+    }};
 }
 
 #[cold]
 pub(crate) fn __abort() -> ! {
+    journal::rollback();
+
     #[cfg(not(all(target_arch = "wasm32", any(target_os = "unknown", target_os = "none"))))]
     std::process::abort();
 
@@ -107,6 +269,8 @@ unsafe fn panic(info: &core::panic::PanicInfo) -> ! {
 
     // Finally, log the panic with `console.error`!
     error(msg);
+    crate::error::record(crate::error::ABORTED, &[], format_args!("{info}"));
+    journal::rollback();
     core::arch::wasm32::unreachable()
 }
 
@@ -0,0 +1,76 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Levels and categories of the debug log.
+//!
+//! Every message carries a [`Level`] and a [`Category`], the messages below the minimum level
+//! or outside the enabled categories are dropped before being formatted. The filter lives in
+//! the module memory, the host sets it again after restoring the memory.
+use core::cell::Cell;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+}
+
+/// Part of the module a message comes from, one bit of the category mask each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Category {
+    /// Entry and result of the exported calls.
+    Call = 0,
+    /// Reads and writes of the host storage.
+    Storage = 1,
+    /// Decoding and updates of the trie nodes.
+    Trie = 2,
+    /// Generation and verification of proofs.
+    Proof = 3,
+    /// Heap allocations.
+    Memory = 4,
+}
+
+struct Filter {
+    level: Cell<u8>,
+    categories: Cell<u32>,
+}
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for Filter {}
+
+static FILTER: Filter =
+    Filter { level: Cell::new(Level::Trace as u8), categories: Cell::new(u32::MAX) };
+
+/// Whether messages of `level` and `category` are logged.
+#[cfg(feature = "enable-debug-log")]
+pub fn enabled(level: Level, category: Category) -> bool {
+    level as u8 >= FILTER.level.get() && FILTER.categories.get() & (1 << category as u8) != 0
+}
+
+/// Logs the messages of at least `level` whose category bit is set in `categories`. Every
+/// message is logged until the host calls it.
+#[export_name = "__ext_set_log_filter"]
+pub extern "C" fn set_filter(level: u32, categories: u32) {
+    FILTER.level.set(level.min(u32::from(u8::MAX)) as u8);
+    FILTER.categories.set(categories);
+}
+
+#[cfg(all(test, feature = "enable-debug-log"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_filtered_by_level_and_category() {
+        assert!(enabled(Level::Trace, Category::Memory));
+        set_filter(Level::Warn as u32, 1 << Category::Storage as u8);
+        assert!(enabled(Level::Error, Category::Storage));
+        assert!(!enabled(Level::Info, Category::Storage));
+        assert!(!enabled(Level::Error, Category::Trie));
+        set_filter(Level::Trace as u32, u32::MAX);
+    }
+}
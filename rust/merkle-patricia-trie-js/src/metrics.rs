@@ -0,0 +1,119 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Counters of the host calls made by the module.
+//!
+//! Every host function counts its calls and the bytes copied across the boundary, keys and
+//! values included. The counters add up across calls until they are taken, so the cost of
+//! several single operations can be compared with a batch.
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Host functions, in the order of the encoded counters. `Log` keeps its counter when the
+/// messages are compiled out, so the encoding doesn't depend on the features.
+#[derive(Clone, Copy)]
+pub enum HostCall {
+    #[cfg(feature = "enable-debug-log")]
+    Log = 0,
+    Input = 1,
+    Return,
+    GetStorage,
+    GetStorageBatch,
+    ContainsStorage,
+    ValueSize,
+    TakeStorage,
+    SetStorage,
+    ClearStorage,
+}
+
+const HOST_CALLS: usize = HostCall::ClearStorage as usize + 1;
+
+#[derive(Clone, Copy, Default)]
+struct Counter {
+    calls: u32,
+    bytes: u64,
+}
+
+struct Metrics(RefCell<[Counter; HOST_CALLS]>);
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for Metrics {}
+
+static METRICS: Metrics = Metrics(RefCell::new([Counter { calls: 0, bytes: 0 }; HOST_CALLS]));
+
+/// Counts a call of `call` which copied `bytes` across the boundary.
+pub fn record(call: HostCall, bytes: usize) {
+    let counter = &mut METRICS.0.borrow_mut()[call as usize];
+    counter.calls = counter.calls.saturating_add(1);
+    counter.bytes = counter.bytes.saturating_add(bytes as u64);
+}
+
+/// Resets the counters, returning them encoded as `calls` u32 and `bytes` u64 per host
+/// function, in the order of [`HostCall`].
+pub fn take() -> Vec<u8> {
+    encode(METRICS.0.take())
+}
+
+fn encode(counters: [Counter; HOST_CALLS]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(HOST_CALLS * 12);
+    for counter in counters {
+        output.extend_from_slice(&counter.calls.to_le_bytes());
+        output.extend_from_slice(&counter.bytes.to_le_bytes());
+    }
+    output
+}
+
+/// Counters at some point of the call, see [`Snapshot::elapsed`].
+pub struct Snapshot([Counter; HOST_CALLS]);
+
+impl Snapshot {
+    pub fn take() -> Self {
+        Self(*METRICS.0.borrow())
+    }
+
+    /// Host calls counted since the snapshot, encoded like [`take`], the counters are kept.
+    pub fn elapsed(&self) -> Vec<u8> {
+        let mut counters = *METRICS.0.borrow();
+        for (counter, before) in counters.iter_mut().zip(&self.0) {
+            counter.calls = counter.calls.saturating_sub(before.calls);
+            counter.bytes = counter.bytes.saturating_sub(before.bytes);
+        }
+        encode(counters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_reset_when_taken() {
+        take();
+        record(HostCall::SetStorage, 40);
+        record(HostCall::SetStorage, 2);
+        record(HostCall::Input, 7);
+
+        let output = take();
+        assert_eq!(output.len(), HOST_CALLS * 12);
+        let counter = |call: HostCall| {
+            let offset = call as usize * 12;
+            let calls = u32::from_le_bytes(output[offset..offset + 4].try_into().unwrap());
+            let bytes = u64::from_le_bytes(output[offset + 4..offset + 12].try_into().unwrap());
+            (calls, bytes)
+        };
+        assert_eq!(counter(HostCall::SetStorage), (2, 42));
+        assert_eq!(counter(HostCall::Input), (1, 7));
+        assert_eq!(counter(HostCall::GetStorage), (0, 0));
+        assert!(take().iter().all(|byte| *byte == 0));
+
+        record(HostCall::Return, 5);
+        let snapshot = Snapshot::take();
+        record(HostCall::Return, 3);
+        let elapsed = snapshot.elapsed();
+        let offset = HostCall::Return as usize * 12;
+        assert_eq!(elapsed[offset..offset + 4], 1u32.to_le_bytes());
+        assert_eq!(elapsed[offset + 4..offset + 12], 3u64.to_le_bytes());
+        assert_eq!(take()[offset..offset + 4], 2u32.to_le_bytes());
+    }
+}
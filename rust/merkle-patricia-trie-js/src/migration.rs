@@ -0,0 +1,96 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Migration of a trie from state version `V0` to `V1`, like Substrate's trie migration.
+//!
+//! Every entry is written again with `LayoutV1`, values longer than 32 bytes move out of their
+//! leaf or branch to a separated node, which changes the nodes on their path. Nodes holding
+//! only small values encode the same in both versions and are kept.
+use crate::{
+    error::CallResult,
+    primitives::{LayoutV0, LayoutV1, H256},
+    trie::{call_error, for_each_node},
+};
+use alloc::{collections::BTreeSet, vec::Vec};
+use hash_db::{HashDB, Hasher};
+use trie_db::{DBValue, Trie, TrieDBBuilder, TrieDBMutBuilder, TrieMut};
+
+/// Migrates the trie at `root` to `LayoutV1`, updating `root`. Returns the number of nodes
+/// the migrated trie holds that the original didn't.
+pub fn migrate<H: Hasher<Out = H256>>(
+    db: &mut dyn HashDB<H, DBValue>,
+    root: &mut H256,
+) -> CallResult<u32> {
+    let mut original = BTreeSet::new();
+    for_each_node::<LayoutV0<H>, _>(db, root, |node| {
+        original.insert(*node);
+    });
+
+    let entries = {
+        let trie = TrieDBBuilder::<LayoutV0<H>>::new(&db, root).build();
+        let iter = trie.iter().map_err(|error| call_error(error.as_ref()))?;
+        iter.collect::<Result<Vec<_>, _>>()
+            .map_err(|error| call_error(error.as_ref()))?
+    };
+
+    {
+        let mut trie = TrieDBMutBuilder::<LayoutV1<H>>::from_existing(db, root).build();
+        for (key, value) in entries {
+            trie.insert(&key, &value).map_err(|error| call_error(error.as_ref()))?;
+        }
+    }
+
+    let mut changed = 0;
+    for_each_node::<LayoutV1<H>, _>(db, root, |node| {
+        if !original.contains(node) {
+            changed += 1;
+        }
+    });
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::{Blake2Hasher, MemoryDB};
+
+    fn build<L: crate::storage::TrieLayout<Hash = Blake2Hasher>>(
+        db: &mut MemoryDB<Blake2Hasher>,
+        entries: &[(&[u8], &[u8])],
+    ) -> H256 {
+        let mut root = H256::zero();
+        let mut trie = TrieDBMutBuilder::<L>::new(db, &mut root).build();
+        for (key, value) in entries {
+            trie.insert(key, value).unwrap();
+        }
+        drop(trie);
+        root
+    }
+
+    #[test]
+    fn large_values_move_to_separated_nodes() {
+        let large = [7u8; 64];
+        let entries: [(&[u8], &[u8]); 3] =
+            [(b"alpha", b"1"), (b"alphabet", &large), (b"beta", b"2")];
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        let mut root = build::<LayoutV0<Blake2Hasher>>(&mut db, &entries);
+        let expected = build::<LayoutV1<Blake2Hasher>>(&mut MemoryDB::new(&[0u8]), &entries);
+        assert_ne!(root, expected);
+
+        // The leaf, its parent branch and the root are rewritten and the value node is added.
+        assert_eq!(migrate(&mut db, &mut root), Ok(4));
+        assert_eq!(root, expected);
+        assert_eq!(migrate(&mut db, &mut root), Ok(0));
+    }
+
+    #[test]
+    fn small_values_are_kept() {
+        let entries: [(&[u8], &[u8]); 2] = [(b"alpha", b"1"), (b"beta", b"2")];
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        let mut root = build::<LayoutV0<Blake2Hasher>>(&mut db, &entries);
+        let original = root;
+        assert_eq!(migrate(&mut db, &mut root), Ok(0));
+        assert_eq!(root, original);
+    }
+}
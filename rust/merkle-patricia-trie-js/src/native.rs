@@ -0,0 +1,250 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Host of the native builds, where the module runs as a library instead of a wasm instance.
+//!
+//! [`HostFn`] replaces the wasm imports, it keeps the storage in a `HashMap` of the current
+//! thread, prints the log messages and hands the notifications to the [`Reply`]. [`call`] runs the
+//! calls of `__ext_call` against it, so the whole stack runs in `cargo test`, the fuzz targets and
+//! server-side tools. The heap is the system allocator, the persistent arena isn't supported:
+//! nothing refers to the input of a call once it returns. The listings built in JavaScript, see
+//! [`crate::trie_builder`], aren't available.
+use crate::{
+    host::{self, ABI_VERSION},
+    storage, tries,
+};
+use core::cell::RefCell;
+use std::{collections::HashMap, sync::OnceLock, time::Instant};
+
+pub use crate::{
+    error::{CallError, CallResult},
+    log::{set_filter as set_log_filter, Category, Level},
+    tries::DEFAULT_HANDLE,
+};
+
+/// Length written by `__ext_get_storage_batch` for a missing key.
+const BATCH_MISSING: u32 = u32::MAX;
+
+thread_local! {
+    static STORAGE: RefCell<HashMap<Vec<u8>, Vec<u8>>> = RefCell::new(HashMap::new());
+    static OUTPUT: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+    static NOTIFIED: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Copies `value` to the output buffer, as much as fits, and writes its full length, as the
+/// JavaScript host does.
+unsafe fn write_output(value: &[u8], out_ptr: *mut u8, out_len_ptr: *mut u32) {
+    let len = value.len().min(*out_len_ptr as usize);
+    core::ptr::copy_nonoverlapping(value.as_ptr(), out_ptr, len);
+    *out_len_ptr = value.len() as u32;
+}
+
+unsafe fn slice<'a>(ptr: *const u8, len: u32) -> &'a [u8] {
+    core::slice::from_raw_parts(ptr, len as usize)
+}
+
+fn stored(key: &[u8]) -> Option<Vec<u8>> {
+    STORAGE.with_borrow(|storage| storage.get(key).cloned())
+}
+
+/// Host functions of the native builds, with the signatures of the wasm imports.
+pub(crate) enum HostFn {}
+
+#[allow(clippy::missing_safety_doc)]
+impl HostFn {
+    /// Messages are printed, so the failure leading to a crash is reported along with it.
+    #[cfg(feature = "enable-debug-log")]
+    pub unsafe fn log(ptr: *const u8, len: u32) {
+        eprintln!("{}", String::from_utf8_lossy(slice(ptr, len)));
+    }
+
+    #[cfg(feature = "enable-debug-log")]
+    pub unsafe fn log_entry(_level: u32, _category: u32, ptr: *const u8, len: u32) {
+        Self::log(ptr, len);
+    }
+
+    /// The input is handed to the dispatcher directly, the host has none.
+    pub unsafe fn input(_buf_ptr: *mut u8, buf_len_ptr: *mut u32) {
+        *buf_len_ptr = 0;
+    }
+
+    pub unsafe fn return_value(ptr: *const u8, len: u32) -> u32 {
+        OUTPUT.set(Some(slice(ptr, len).to_vec()));
+        0
+    }
+
+    pub unsafe fn out_of_memory(_requested: u32, _max_pages: u32) {}
+
+    pub unsafe fn notify(changes_ptr: *const u8, changes_len: u32) {
+        NOTIFIED
+            .with_borrow_mut(|notified| notified.push(slice(changes_ptr, changes_len).to_vec()));
+    }
+
+    /// Milliseconds since the first reading, like `performance.now()`.
+    pub unsafe fn now() -> f64 {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+    }
+
+    pub unsafe fn set_storage(
+        key_ptr: *const u8,
+        key_len: u32,
+        value_ptr: *const u8,
+        value_len: u32,
+    ) -> u32 {
+        let (key, value) = (slice(key_ptr, key_len).to_vec(), slice(value_ptr, value_len).to_vec());
+        STORAGE.with_borrow_mut(|storage| storage.insert(key, value));
+        0
+    }
+
+    pub unsafe fn get_storage(
+        key_ptr: *const u8,
+        key_len: u32,
+        out_ptr: *mut u8,
+        out_len_ptr: *mut u32,
+    ) -> u32 {
+        let Some(value) = stored(slice(key_ptr, key_len)) else {
+            *out_len_ptr = 0;
+            return 3;
+        };
+        write_output(&value, out_ptr, out_len_ptr);
+        0
+    }
+
+    pub unsafe fn get_storage_batch(
+        keys_ptr: *const u8,
+        keys_len: u32,
+        out_ptr: *mut u8,
+        out_len_ptr: *mut u32,
+    ) -> u32 {
+        let mut keys = slice(keys_ptr, keys_len);
+        let mut output = Vec::new();
+        while !keys.is_empty() {
+            let Ok((key, rest)) = storage::split_bytes(keys) else {
+                break;
+            };
+            match stored(key) {
+                Some(value) => storage::write_bytes(&mut output, &value),
+                None => output.extend_from_slice(&BATCH_MISSING.to_le_bytes()),
+            }
+            keys = rest;
+        }
+        write_output(&output, out_ptr, out_len_ptr);
+        0
+    }
+
+    pub unsafe fn contains_storage(key_ptr: *const u8, key_len: u32) -> u32 {
+        if stored(slice(key_ptr, key_len)).is_some() {
+            0
+        } else {
+            3
+        }
+    }
+
+    pub unsafe fn value_size(key_ptr: *const u8, key_len: u32, out_len_ptr: *mut u32) -> u32 {
+        let Some(value) = stored(slice(key_ptr, key_len)) else {
+            return 3;
+        };
+        *out_len_ptr = value.len() as u32;
+        0
+    }
+
+    pub unsafe fn take_storage(
+        key_ptr: *const u8,
+        key_len: u32,
+        out_ptr: *mut u8,
+        out_len_ptr: *mut u32,
+    ) -> u32 {
+        let key = slice(key_ptr, key_len);
+        let Some(value) = stored(key) else {
+            return 3;
+        };
+        let fits = value.len() <= *out_len_ptr as usize;
+        write_output(&value, out_ptr, out_len_ptr);
+        if fits {
+            STORAGE.with_borrow_mut(|storage| storage.remove(key));
+        }
+        0
+    }
+
+    pub unsafe fn clear_storage(key_ptr: *const u8, key_len: u32) -> u32 {
+        STORAGE.with_borrow_mut(|storage| storage.remove(slice(key_ptr, key_len)));
+        0
+    }
+}
+
+/// Result of a successful [`call`]: the value returned by `__ext_call`, the output handed to
+/// `__ext_return`, if any, and the changes handed to `__ext_notify`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Reply {
+    pub ret: u64,
+    pub output: Option<Vec<u8>>,
+    pub notified: Vec<Vec<u8>>,
+}
+
+/// Runs the call `code` of `__ext_call` on the trie `handle`, see [`crate::tries`]. The input
+/// is the one of `__ext_call` without its header. Failed calls are rolled back, as
+/// `__ext_call` does.
+pub fn call(handle: u32, code: u32, input: &[u8]) -> CallResult<Reply> {
+    host::version(ABI_VERSION);
+    crate::arena::begin();
+    OUTPUT.take();
+    NOTIFIED.take();
+    let result = tries::select(handle)
+        .and_then(|()| crate::with_layout!(L => storage::dispatch::<L>(code, input)))
+        .and_then(|ret| crate::error::take_deferred().map_or(Ok(ret), Err));
+    match result {
+        Ok(ret) => Ok(Reply { ret, output: OUTPUT.take(), notified: NOTIFIED.take() }),
+        Err(error) => {
+            crate::journal::rollback();
+            crate::arena::discard_cache();
+            Err(error)
+        },
+    }
+}
+
+/// Writes an entry of the host storage, keys include the namespace prefix of their trie.
+pub fn set_storage(key: &[u8], value: &[u8]) {
+    STORAGE.with_borrow_mut(|storage| storage.insert(key.to_vec(), value.to_vec()));
+}
+
+/// Clears the storage of the current thread, every trie included.
+pub fn clear_storage() {
+    STORAGE.with_borrow_mut(HashMap::clear);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::write_bytes;
+
+    fn entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut input = Vec::new();
+        write_bytes(&mut input, key);
+        write_bytes(&mut input, value);
+        input
+    }
+
+    #[test]
+    fn calls_run_against_the_native_host() {
+        clear_storage();
+        call(DEFAULT_HANDLE, 0, &entry(b"alpha", b"1")).unwrap();
+        let reply = call(DEFAULT_HANDLE, 3, b"alpha").unwrap();
+        assert_eq!(reply.output.as_deref(), Some(&b"1"[..]));
+        assert_eq!(call(DEFAULT_HANDLE, 2, b"beta").unwrap().ret, 0);
+        let root = call(DEFAULT_HANDLE, 4, &[]).unwrap().output.unwrap();
+
+        // A failed call is reported and leaves the storage as it was.
+        assert_eq!(call(DEFAULT_HANDLE, 0, &[1, 2]), Err(CallError::InvalidInput));
+        assert_eq!(call(DEFAULT_HANDLE, 4, &[]).unwrap().output.unwrap(), root);
+
+        // Named tries are independent of the default one.
+        let handle = call(DEFAULT_HANDLE, 59, b"other").unwrap().ret as u32;
+        assert_eq!(call(handle, 3, b"alpha").unwrap().output, None);
+        call(handle, 0, &entry(b"alpha", b"2")).unwrap();
+        assert_eq!(call(handle, 3, b"alpha").unwrap().output.as_deref(), Some(&b"2"[..]));
+        assert_eq!(call(DEFAULT_HANDLE, 3, b"alpha").unwrap().output.as_deref(), Some(&b"1"[..]));
+        assert_eq!(call(DEFAULT_HANDLE, 60, b"other").unwrap().ret, u64::from(handle));
+    }
+}
@@ -0,0 +1,148 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Owned representation of decoded trie nodes, for algorithms walking several tries at once
+//! where the borrowed `NodePlan` ranges get in the way.
+use crate::{
+    abort,
+    error::{CallError, CallResult},
+    fail,
+    primitives::H256,
+    storage::{hashed_null_node, TrieLayout},
+};
+use alloc::vec::Vec;
+use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+use trie_db::{
+    node::{NodeHandlePlan, NodePlan, ValuePlan},
+    DBValue, NodeCodec,
+};
+
+/// Reference to a child node, either by hash or the encoded node itself when it is inlined.
+#[derive(Clone, PartialEq, Eq)]
+pub enum NodeRef {
+    Hash(H256),
+    Inline(Vec<u8>),
+}
+
+impl NodeRef {
+    pub fn root(root: &H256) -> Self {
+        Self::Hash(*root)
+    }
+
+    fn from_plan(plan: NodeHandlePlan, bytes: &[u8]) -> Self {
+        match plan {
+            NodeHandlePlan::Hash(range) => Self::Hash(H256::from_slice(&bytes[range])),
+            NodeHandlePlan::Inline(range) => Self::Inline(bytes[range].to_vec()),
+        }
+    }
+}
+
+/// The value of a leaf or branch, large values are stored in a separated node.
+#[derive(Clone, PartialEq, Eq)]
+pub enum NodeValue {
+    Inline(Vec<u8>),
+    Node(H256),
+}
+
+impl NodeValue {
+    fn from_plan(plan: ValuePlan, bytes: &[u8]) -> Self {
+        match plan {
+            ValuePlan::Inline(range) => Self::Inline(bytes[range].to_vec()),
+            ValuePlan::Node(range) => Self::Node(H256::from_slice(&bytes[range])),
+        }
+    }
+
+    pub fn resolve<H: Hasher<Out = H256>>(
+        &self,
+        db: &dyn HashDB<H, DBValue>,
+    ) -> CallResult<Vec<u8>> {
+        match self {
+            Self::Inline(value) => Ok(value.clone()),
+            Self::Node(hash) => {
+                let Some(value) = db.get(hash, EMPTY_PREFIX) else {
+                    return fail!(
+                        CallError::KeyNotFound,
+                        key = hash.as_bytes(),
+                        "missing value node {hash:?}"
+                    );
+                };
+                Ok(value)
+            },
+        }
+    }
+}
+
+pub struct OwnedNode {
+    /// Partial key of the node, one nibble per byte.
+    pub partial: Vec<u8>,
+    pub value: Option<NodeValue>,
+    pub children: [Option<NodeRef>; 16],
+}
+
+impl OwnedNode {
+    /// Decodes a node, extension nodes are merged with their child so every node holds the
+    /// children of a branch.
+    pub fn decode<L: TrieLayout>(
+        db: &dyn HashDB<L::Hash, DBValue>,
+        bytes: &[u8],
+    ) -> CallResult<Self> {
+        let Ok(plan) = L::Codec::decode_plan(bytes) else {
+            abort!("decode_plan failed");
+        };
+        let mut node = Self { partial: Vec::new(), value: None, children: Default::default() };
+        let (partial, value, children) = match plan {
+            NodePlan::Empty => return Ok(node),
+            NodePlan::Leaf { partial, value } => (Some(partial), Some(value), None),
+            NodePlan::Extension { partial, child } => {
+                let child = Self::load::<L>(db, &NodeRef::from_plan(child, bytes))?;
+                node.partial.extend(partial.build(bytes).iter());
+                node.partial.extend(child.partial);
+                return Ok(Self { partial: node.partial, ..child });
+            },
+            NodePlan::Branch { value, children } => (None, value, Some(children)),
+            NodePlan::NibbledBranch { partial, value, children } => {
+                (Some(partial), value, Some(children))
+            },
+        };
+        if let Some(partial) = partial {
+            node.partial.extend(partial.build(bytes).iter());
+        }
+        node.value = value.map(|value| NodeValue::from_plan(value, bytes));
+        for (slot, child) in node.children.iter_mut().zip(children.into_iter().flatten()) {
+            *slot = child.map(|child| NodeRef::from_plan(child, bytes));
+        }
+        Ok(node)
+    }
+
+    /// Loads and decodes a node, fails with [`CallError::KeyNotFound`] when it isn't stored.
+    pub fn load<L: TrieLayout>(
+        db: &dyn HashDB<L::Hash, DBValue>,
+        node: &NodeRef,
+    ) -> CallResult<Self> {
+        match node {
+            NodeRef::Hash(hash) => {
+                if hash == &hashed_null_node::<L>() {
+                    return Self::decode::<L>(db, L::Codec::empty_node());
+                }
+                let Some(bytes) = db.get(hash, EMPTY_PREFIX) else {
+                    return fail!(
+                        CallError::KeyNotFound,
+                        key = hash.as_bytes(),
+                        "missing node {hash:?}"
+                    );
+                };
+                Self::decode::<L>(db, &bytes)
+            },
+            NodeRef::Inline(bytes) => Self::decode::<L>(db, bytes),
+        }
+    }
+}
+
+/// Packs a nibble path back into the key bytes, the path must have an even length.
+pub fn nibbles_to_key(nibbles: &[u8]) -> Vec<u8> {
+    if !nibbles.len().is_multiple_of(2) {
+        abort!("value at incomplete key");
+    }
+    nibbles.chunks_exact(2).map(|pair| (pair[0] << 4) | pair[1]).collect()
+}
@@ -0,0 +1,284 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Log of the committed operations, replayed to rebuild the trie.
+//!
+//! Every key changed by a committed call appends an entry under a reserved key, indexed by a
+//! sequence number like the root history: the op code, the key as stored in the trie, the hash
+//! of the inserted value and the root the call committed, shared by the entries of a call. The
+//! inserted values are stored once under their hash, so the log can be replayed against an
+//! empty trie.
+use crate::{
+    abort,
+    backend::TrieBackend,
+    checkpoint,
+    diff::Change,
+    error::{CallError, CallResult},
+    fail,
+    primitives::H256,
+    storage::{write_bytes, ExternalDB, TrieLayout},
+    trie::call_error,
+};
+use alloc::vec::Vec;
+use hash_db::Hasher;
+use trie_db::{TrieDBMutBuilder, TrieMut};
+
+const LOG_LEN_KEY: &[u8] = b":op_log";
+const LOG_PREFIX: &[u8] = b":op_log:";
+const VALUE_PREFIX: &[u8] = b":op_log_value:";
+
+fn entry_key(seq: u64) -> [u8; LOG_PREFIX.len() + 8] {
+    let mut key = [0u8; LOG_PREFIX.len() + 8];
+    key[..LOG_PREFIX.len()].copy_from_slice(LOG_PREFIX);
+    key[LOG_PREFIX.len()..].copy_from_slice(&seq.to_be_bytes());
+    key
+}
+
+fn value_key(hash: &H256) -> Vec<u8> {
+    [VALUE_PREFIX, hash.as_bytes()].concat()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    Insert = 0,
+    Remove = 1,
+}
+
+/// A recorded operation, stored as the op code, the root, the value hash of inserts and the key.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    op: Op,
+    key: Vec<u8>,
+    value: Option<H256>,
+    root: H256,
+}
+
+impl Entry {
+    fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(65 + self.key.len());
+        output.push(self.op as u8);
+        output.extend_from_slice(self.root.as_bytes());
+        if let Some(value) = self.value {
+            output.extend_from_slice(value.as_bytes());
+        }
+        output.extend_from_slice(&self.key);
+        output
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let (&op, bytes) = bytes.split_first()?;
+        let (root, bytes) = bytes.split_first_chunk::<32>()?;
+        let (op, value, key) = match op {
+            0 => {
+                let (value, key) = bytes.split_first_chunk::<32>()?;
+                (Op::Insert, Some(H256(*value)), key)
+            },
+            1 => (Op::Remove, None, bytes),
+            _ => return None,
+        };
+        Some(Self { op, key: key.to_vec(), value, root: H256(*root) })
+    }
+}
+
+/// Number of operations recorded so far.
+pub fn len<B: TrieBackend>(db: &ExternalDB<B>) -> u64 {
+    db.get_bytes::<8>(LOG_LEN_KEY).map(u64::from_le_bytes).unwrap_or_default()
+}
+
+/// Appends the `changes` committed at `root`, the values are stored unless the log holds them
+/// already.
+pub fn record<H: Hasher<Out = H256>, B: TrieBackend>(
+    db: &ExternalDB<B>,
+    changes: &[Change],
+    root: &H256,
+) {
+    if changes.is_empty() {
+        return;
+    }
+    let mut seq = len(db);
+    for change in changes {
+        let value = change.value().map(|value| {
+            let hash = H::hash(value);
+            let key = value_key(&hash);
+            if !db.contains(&key) {
+                db.set_bytes(&key, value);
+            }
+            hash
+        });
+        let op = if value.is_some() { Op::Insert } else { Op::Remove };
+        let entry = Entry { op, key: change.key().to_vec(), value, root: *root };
+        db.set_bytes(&entry_key(seq), &entry.encode());
+        seq += 1;
+    }
+    db.set_bytes(LOG_LEN_KEY, &seq.to_le_bytes());
+}
+
+fn read<B: TrieBackend>(db: &ExternalDB<B>, seq: u64) -> Entry {
+    let key = entry_key(seq);
+    let Some(entry) = db.get_value(&key).as_deref().and_then(Entry::decode) else {
+        abort!(key = &key, "op log: invalid entry {seq}");
+    };
+    entry
+}
+
+/// Lists up to `count` operations from the sequence number `start`, after the number of
+/// operations in the log. Each one is encoded as its op code, the length-prefixed key, the
+/// value hash of inserts and the root it committed.
+pub fn list<B: TrieBackend>(db: &ExternalDB<B>, start: u64, count: u64) -> Vec<u8> {
+    let len = len(db);
+    let mut output = Vec::new();
+    output.extend_from_slice(&len.to_le_bytes());
+    for seq in start..len.min(start.saturating_add(count)) {
+        let entry = read(db, seq);
+        output.push(entry.op as u8);
+        write_bytes(&mut output, &entry.key);
+        if let Some(value) = entry.value {
+            output.extend_from_slice(value.as_bytes());
+        }
+        output.extend_from_slice(entry.root.as_bytes());
+    }
+    output
+}
+
+/// Rebuilds the trie from an empty one by applying the first `count` operations of the log,
+/// and moves the live trie from `root` to it. Returns the new root, the log is kept as is and
+/// the replay is logged as any other call.
+pub fn replay<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &H256,
+    count: u64,
+) -> CallResult<H256> {
+    let mut operations = Vec::new();
+    for seq in 0..len(db).min(count) {
+        let entry = read(db, seq);
+        let value = match entry.value {
+            Some(hash) => {
+                let Some(value) = db.get_value(&value_key(&hash)) else {
+                    return fail!(CallError::KeyNotFound, key = hash.as_bytes(), "missing value");
+                };
+                Some(value)
+            },
+            None => None,
+        };
+        operations.push((entry.key, value));
+    }
+    let mut replayed = H256::zero();
+    {
+        let mut trie = TrieDBMutBuilder::<L>::new(db, &mut replayed).build();
+        for (key, value) in operations {
+            let result = match value {
+                Some(value) => trie.insert(&key, &value).map(drop),
+                None => trie.remove(&key).map(drop),
+            };
+            result.map_err(|error| call_error(error.as_ref()))?;
+        }
+    }
+    // The nodes of the replayed trie are referenced now, release the ones of the former trie.
+    checkpoint::release::<L, _>(db, root);
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MemoryBackend,
+        native::{call, clear_storage, DEFAULT_HANDLE},
+        primitives::{Blake2Hasher, LayoutV1},
+        storage::hashed_null_node,
+    };
+    use trie_db::TrieDBMutBuilder;
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    fn apply(db: &mut ExternalDB<MemoryBackend>, root: &mut H256, changes: &[Change]) {
+        {
+            let mut trie = TrieDBMutBuilder::<Layout>::from_existing(db, root).build();
+            for change in changes {
+                match change.value() {
+                    Some(value) => trie.insert(change.key(), value).map(drop).unwrap(),
+                    None => trie.remove(change.key()).map(drop).unwrap(),
+                }
+            }
+        }
+        db.commit_root(root);
+        record::<Blake2Hasher, _>(db, changes, root);
+    }
+
+    fn added(key: &[u8], value: &[u8]) -> Change {
+        Change::Added { key: key.to_vec(), value: value.to_vec() }
+    }
+
+    #[test]
+    fn replay_reaches_the_recorded_roots() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let large = [7u8; 64];
+        apply(&mut db, &mut root, &[added(b"alpha", &large)]);
+        let first = root;
+        apply(&mut db, &mut root, &[added(b"beta", &large)]);
+        let removed = Change::Removed { key: b"alpha".to_vec(), value: large.to_vec() };
+        apply(&mut db, &mut root, &[removed]);
+        assert_eq!(len(&db), 3);
+        assert_eq!(read(&db, 0).root, first);
+        assert_eq!(read(&db, 0).value, read(&db, 1).value);
+        assert_eq!(
+            read(&db, 2),
+            Entry { op: Op::Remove, key: b"alpha".to_vec(), value: None, root }
+        );
+
+        let listed = list(&db, 2, 10);
+        assert_eq!(listed[..8], 3u64.to_le_bytes());
+        assert_eq!(listed.len(), 8 + 1 + 4 + 5 + 32);
+
+        let last = root;
+        root = replay::<Layout, _>(&mut db, &root, 1).unwrap();
+        db.commit_root(&root);
+        assert_eq!(root, first);
+        root = replay::<Layout, _>(&mut db, &root, u64::MAX).unwrap();
+        db.commit_root(&root);
+        assert_eq!(root, last);
+        root = replay::<Layout, _>(&mut db, &root, 0).unwrap();
+        db.commit_root(&root);
+        assert_eq!(root, hashed_null_node::<Layout>());
+        assert_eq!(len(&db), 3);
+    }
+
+    #[test]
+    fn replay_spans_merges_and_reverts() {
+        let entry = |key: &[u8], value: &[u8]| {
+            let mut input = Vec::new();
+            write_bytes(&mut input, key);
+            write_bytes(&mut input, value);
+            input
+        };
+        let root = || call(DEFAULT_HANDLE, 4, &[]).unwrap().output.unwrap();
+        let len = || {
+            let listed = call(DEFAULT_HANDLE, 46, &[0; 8]).unwrap().output.unwrap();
+            u64::from_le_bytes(listed[..8].try_into().unwrap())
+        };
+        clear_storage();
+        call(DEFAULT_HANDLE, 0, &entry(b"alpha", b"1")).unwrap();
+        call(DEFAULT_HANDLE, 6, b"first").unwrap();
+        let first = root();
+        call(DEFAULT_HANDLE, 0, &entry(b"beta", b"2")).unwrap();
+        call(DEFAULT_HANDLE, 37, b"alpha").unwrap();
+        let mut merge = Vec::from([0]);
+        merge.extend_from_slice(&first);
+        call(DEFAULT_HANDLE, 13, &merge).unwrap();
+        call(DEFAULT_HANDLE, 0, &entry(b"gamma", b"3")).unwrap();
+        call(DEFAULT_HANDLE, 7, b"first").unwrap();
+        assert_eq!(root(), first);
+        let last = root();
+
+        // Every call was logged, the revert once per key it changed.
+        assert_eq!(len(), 7);
+        call(DEFAULT_HANDLE, 47, &0u32.to_le_bytes()).unwrap();
+        assert_ne!(root(), last);
+        let replayed = call(DEFAULT_HANDLE, 47, &7u32.to_le_bytes()).unwrap().output.unwrap();
+        assert_eq!(replayed, last);
+        assert_eq!(root(), last);
+    }
+}
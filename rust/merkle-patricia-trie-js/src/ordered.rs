@@ -0,0 +1,107 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Ordered tries, the payloads keyed by their index as Ethereum builds the transaction and
+//! receipt tries of a block, and Substrate the extrinsic trie.
+//!
+//! The layout encodes the indices, see [`trie_db::TrieConfiguration::encode_index`]: RLP for
+//! the Ethereum layout, SCALE compact for the Substrate ones. The indices aren't hashed, even
+//! when the storage hashes its keys. The trie is built on an overlay, the storage is left as it
+//! is.
+use crate::{
+    error::CallResult,
+    storage::{hashed_null_node, split_bytes, write_bytes, ExternalDB, OverlayDB, TrieLayout},
+    trie::call_error,
+};
+use alloc::vec::Vec;
+use hash_db::HashDB;
+use trie_db::{DBValue, TrieDBMutBuilder, TrieMut};
+
+/// Builds the ordered trie of the length-prefixed payloads in `input`.
+///
+/// The output is the root followed by the number of payloads as `u32`, then for each payload
+/// its length-prefixed key and its length-prefixed proof, the proof nodes length-prefixed as
+/// in the proofs of the storage.
+pub fn build<L: TrieLayout>(db: &ExternalDB, mut input: &[u8]) -> CallResult<Vec<u8>> {
+    let mut keys = Vec::new();
+    let mut overlay = OverlayDB::<L::Hash>::new(db);
+    let mut root = hashed_null_node::<L>();
+    let mut trie = TrieDBMutBuilder::<L>::new(&mut overlay, &mut root).build();
+    while !input.is_empty() {
+        let (payload, rest) = split_bytes(input)?;
+        let key = L::encode_index(keys.len() as u32);
+        trie.insert(&key, payload).map_err(|error| call_error(error.as_ref()))?;
+        keys.push(key);
+        input = rest;
+    }
+    drop(trie);
+    let db: &dyn HashDB<L::Hash, DBValue> = &overlay;
+
+    let mut output = Vec::with_capacity(36);
+    output.extend_from_slice(root.as_bytes());
+    output.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+    for key in &keys {
+        let nodes = trie_db::proof::generate_proof::<_, L, _, _>(&db, &root, [key])
+            .map_err(|error| call_error(error.as_ref()))?;
+        let mut proof = Vec::new();
+        for node in nodes {
+            write_bytes(&mut proof, &node);
+        }
+        write_bytes(&mut output, key);
+        write_bytes(&mut output, &proof);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ethereum::EthereumLayout,
+        native::{call, clear_storage, DEFAULT_HANDLE},
+        primitives::{Blake2Hasher, LayoutV1, H256},
+    };
+    use trie_db::TrieConfiguration;
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    #[test]
+    fn indices_follow_the_layout() {
+        assert_eq!(<EthereumLayout>::encode_index(0), [0x80]);
+        assert_eq!(<EthereumLayout>::encode_index(127), [0x7f]);
+        assert_eq!(<EthereumLayout>::encode_index(1024), [0x82, 0x04, 0x00]);
+        assert_eq!(Layout::encode_index(64), [0x01, 0x01]);
+    }
+
+    #[test]
+    fn roots_match_the_ordered_trie_root() {
+        let payloads = (0..200u32).map(|index| index.to_be_bytes().repeat(9)).collect::<Vec<_>>();
+        let mut input = Vec::new();
+        for payload in &payloads {
+            write_bytes(&mut input, payload);
+        }
+        clear_storage();
+        let output = call(DEFAULT_HANDLE, 72, &input).unwrap().output.unwrap();
+        let root = H256::from_slice(&output[..32]);
+        assert_eq!(root, Layout::ordered_trie_root(&payloads));
+        assert_eq!(output[32..36], 200u32.to_le_bytes());
+
+        let mut rest = &output[36..];
+        for payload in &payloads {
+            let (key, next) = split_bytes(rest).unwrap();
+            let (mut proof, next) = split_bytes(next).unwrap();
+            let mut nodes = Vec::new();
+            while !proof.is_empty() {
+                let (node, tail) = split_bytes(proof).unwrap();
+                nodes.push(node.to_vec());
+                proof = tail;
+            }
+            let items = [(key, Some(payload))];
+            let verified = trie_db::proof::verify_proof::<Layout, _, _, _>(&root, &nodes, &items);
+            assert!(verified.is_ok());
+            rest = next;
+        }
+        assert!(rest.is_empty());
+    }
+}
@@ -0,0 +1,26 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Hash type, hashers and Substrate layouts shared by the whole crate.
+//!
+//! `H256` is the one `sp_core` re-exports. The hashers and layouts come from `sp_core` and
+//! `sp_trie` with the `substrate` feature, the slim build replaces them by the local
+//! implementations of [`crate::hasher`] and [`crate::layout`], which encode the nodes and proofs
+//! the same way, so the roots don't depend on the build.
+pub use primitive_types::H256;
+
+#[cfg(feature = "substrate")]
+pub use sp_core::{Blake2Hasher, KeccakHasher};
+#[cfg(feature = "substrate")]
+pub use sp_trie::{
+    decode_compact, encode_compact, CompactProof, LayoutV0, LayoutV1, MemoryDB, StorageProof,
+};
+
+#[cfg(not(feature = "substrate"))]
+pub use crate::{
+    hasher::{Blake2Hasher, KeccakHasher},
+    layout::{
+        decode_compact, encode_compact, CompactProof, LayoutV0, LayoutV1, MemoryDB, StorageProof,
+    },
+};
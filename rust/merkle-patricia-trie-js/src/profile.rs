@@ -0,0 +1,97 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Profile of the heap allocations, behind the `alloc-profile` feature.
+//!
+//! Every allocation served by the heap is counted along with its size, the counters add up
+//! across calls until they are taken.
+use core::cell::RefCell;
+
+/// Size buckets of the histogram, by powers of four from `16` bytes, the last one holds
+/// allocations larger than `64KiB`.
+const BUCKETS: usize = 8;
+
+/// Encoded size of [`AllocProfile`].
+const ENCODED_SIZE: usize = 12 + BUCKETS * 4;
+
+#[derive(Clone, Copy, Default)]
+struct AllocProfile {
+    count: u32,
+    bytes: u64,
+    histogram: [u32; BUCKETS],
+}
+
+impl AllocProfile {
+    fn record(&mut self, size: usize) {
+        self.count = self.count.saturating_add(1);
+        self.bytes = self.bytes.saturating_add(size as u64);
+        let bucket = &mut self.histogram[bucket(size)];
+        *bucket = bucket.saturating_add(1);
+    }
+
+    /// Encodes `count` as u32, `bytes` as u64 and each bucket of the histogram as u32.
+    fn encode(&self) -> [u8; ENCODED_SIZE] {
+        let mut output = [0u8; ENCODED_SIZE];
+        output[..4].copy_from_slice(&self.count.to_le_bytes());
+        output[4..12].copy_from_slice(&self.bytes.to_le_bytes());
+        for (chunk, count) in output[12..].chunks_exact_mut(4).zip(self.histogram) {
+            chunk.copy_from_slice(&count.to_le_bytes());
+        }
+        output
+    }
+}
+
+/// Histogram bucket of an allocation of `size` bytes: up to `16`, `64`, `256`, `1KiB`,
+/// `4KiB`, `16KiB`, `64KiB` bytes, or larger.
+fn bucket(size: usize) -> usize {
+    let bits = usize::BITS - size.saturating_sub(1).leading_zeros();
+    (bits.saturating_sub(3) as usize / 2).min(BUCKETS - 1)
+}
+
+struct Profile(RefCell<AllocProfile>);
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for Profile {}
+
+static PROFILE: Profile =
+    Profile(RefCell::new(AllocProfile { count: 0, bytes: 0, histogram: [0; BUCKETS] }));
+
+/// Counts an allocation of `size` bytes. Doesn't allocate.
+pub fn record(size: usize) {
+    PROFILE.0.borrow_mut().record(size);
+}
+
+/// Resets the counters, returning them encoded as `count` u32, `bytes` u64 and the
+/// histogram buckets as u32.
+pub fn take() -> [u8; ENCODED_SIZE] {
+    PROFILE.0.take().encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_are_counted_in_their_bucket() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(16), 0);
+        assert_eq!(bucket(17), 1);
+        assert_eq!(bucket(256), 2);
+        assert_eq!(bucket(1025), 4);
+        assert_eq!(bucket(64 * 1024), 6);
+        assert_eq!(bucket(64 * 1024 + 1), 7);
+        assert_eq!(bucket(usize::MAX), 7);
+
+        let mut profile = AllocProfile::default();
+        profile.record(8);
+        profile.record(100);
+        profile.record(200);
+        let output = profile.encode();
+        assert_eq!(output[..4], 3u32.to_le_bytes());
+        assert_eq!(output[4..12], 308u64.to_le_bytes());
+        assert_eq!(output[12..16], 1u32.to_le_bytes());
+        assert_eq!(output[16..20], 0u32.to_le_bytes());
+        assert_eq!(output[20..24], 2u32.to_le_bytes());
+    }
+}
@@ -0,0 +1,552 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Generation and verification of trie proofs, the proof is the list of encoded nodes, with
+//! the values and child hashes known by the verifier omitted.
+use crate::{
+    error::{CallError, CallResult},
+    fail,
+    primitives::{decode_compact, encode_compact, CompactProof, MemoryDB, StorageProof, H256},
+    storage::{split_bytes, split_hash, trie_key, write_bytes, TrieLayout},
+    trie::call_error,
+};
+use alloc::{
+    collections::{btree_map::Entry, BTreeMap},
+    vec::Vec,
+};
+use codec::{Decode, Encode};
+use core::cell::{Cell, RefCell};
+use hash_db::{HashDB, HashDBRef, Hasher};
+use trie_db::{recorder::Record, DBValue, Recorder, Trie, TrieDBBuilder, TrieError};
+
+/// Reads a sequence of length-prefixed byte strings until the input is exhausted.
+fn split_list(mut input: &[u8]) -> CallResult<Vec<Vec<u8>>> {
+    let mut list = Vec::new();
+    while !input.is_empty() {
+        let (item, rest) = split_bytes(input)?;
+        list.push(item.to_vec());
+        input = rest;
+    }
+    Ok(list)
+}
+
+/// Generates a proof of inclusion (or non-inclusion) of the length-prefixed keys in `input`,
+/// the proof nodes are returned as a sequence of length-prefixed byte strings.
+///
+/// `root` can be any root whose nodes are still present in the storage, not only the current.
+pub fn generate<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    input: &[u8],
+) -> CallResult<Vec<u8>> {
+    let keys: Vec<_> =
+        split_list(input)?.iter().map(|key| trie_key::<L>(key).into_owned()).collect();
+    let nodes = trie_db::proof::generate_proof::<_, L, _, _>(&db, root, &keys)
+        .map_err(|error| call_error(error.as_ref()))?;
+    let mut output = Vec::new();
+    for node in nodes {
+        write_bytes(&mut output, &node);
+    }
+    Ok(output)
+}
+
+/// A key and its claimed value, `None` claims the key is absent.
+type Item<'a> = (&'a [u8], Option<&'a [u8]>);
+
+/// Splits a claimed item, the length-prefixed key followed by a flag telling whether the key
+/// is present and, if so, the length-prefixed value.
+fn split_item(input: &[u8]) -> CallResult<(Item<'_>, &[u8])> {
+    let (key, input) = split_bytes(input)?;
+    let Some((&present, input)) = input.split_first() else {
+        return fail!(CallError::InvalidInput, "invalid input");
+    };
+    match present {
+        0 => Ok(((key, None), input)),
+        1 => {
+            let (value, input) = split_bytes(input)?;
+            Ok(((key, Some(value)), input))
+        },
+        flag => fail!(CallError::InvalidInput, "invalid presence flag: {flag}"),
+    }
+}
+
+/// Verifies a proof, the input is the root hash, the number of claimed items as `u32`, the
+/// items themselves and the proof nodes as length-prefixed byte strings. The items must cover
+/// every key the proof was generated for.
+pub fn verify<L: TrieLayout>(input: &[u8]) -> CallResult<bool> {
+    let (root, input) = split_hash(input)?;
+    let Some((count, mut input)) = input.split_first_chunk::<4>() else {
+        return fail!(CallError::InvalidInput, "invalid input");
+    };
+    let mut items = Vec::new();
+    for _ in 0..u32::from_le_bytes(*count) {
+        let ((key, value), rest) = split_item(input)?;
+        items.push((trie_key::<L>(key), value));
+        input = rest;
+    }
+    let proof = split_list(input)?;
+    Ok(trie_db::proof::verify_proof::<L, _, _, _>(&root, &proof, &items).is_ok())
+}
+
+/// Values found by [`record`] and the records of the nodes read.
+type Recorded = (Vec<Option<Vec<u8>>>, Vec<Record<H256>>);
+
+/// Looks up every key recording the visited nodes, returns the values found and the records
+/// in the order the nodes were read.
+fn record<L: TrieLayout, K: AsRef<[u8]>>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    keys: &[K],
+) -> CallResult<Recorded> {
+    let mut recorder = Recorder::<L>::new();
+    let values = {
+        let trie = TrieDBBuilder::<L>::new(&db, root).with_recorder(&mut recorder).build();
+        keys.iter()
+            .map(|key| trie.get(&trie_key::<L>(key.as_ref())))
+            .collect::<Result<_, _>>()
+            .map_err(|error| call_error(error.as_ref()))?
+    };
+    Ok((values, recorder.drain()))
+}
+
+/// Returns the hash of every node a proof of the length-prefixed keys in `input` touches.
+pub fn path<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    input: &[u8],
+) -> CallResult<Vec<H256>> {
+    let (_, records) = record::<L, _>(db, root, &split_list(input)?)?;
+    Ok(records.into_iter().map(|record| record.hash).collect())
+}
+
+/// Looks up `key` in the trie at `root` tracing the nodes read. The output is a presence flag
+/// followed by the length-prefixed value when present, the total bytes read as `u32` and the
+/// hash of each node read.
+pub fn trace_get<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    key: &[u8],
+) -> CallResult<Vec<u8>> {
+    let (values, records) = record::<L, _>(db, root, &[key])?;
+    let mut output = Vec::new();
+    match values.into_iter().next().flatten() {
+        Some(value) => {
+            output.push(1);
+            write_bytes(&mut output, &value);
+        },
+        None => output.push(0),
+    }
+    let total = records.iter().map(|record| record.data.len() as u32).sum::<u32>();
+    output.extend_from_slice(&total.to_le_bytes());
+    for record in records {
+        output.extend_from_slice(record.hash.as_bytes());
+    }
+    Ok(output)
+}
+
+/// Generates a single compact proof covering every length-prefixed key in `input`. The output
+/// is the length-prefixed SCALE encoded `CompactProof` followed by, for each key, a presence
+/// flag and the length-prefixed value when present.
+pub fn generate_compact<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    input: &[u8],
+) -> CallResult<Vec<u8>> {
+    let keys = split_list(input)?;
+    let (values, records) = record::<L, _>(db, root, &keys)?;
+    let proof = StorageProof::new(records.into_iter().map(|record| record.data));
+    let proof_db = proof.into_memory_db::<L::Hash>();
+    let Ok(compact) = encode_compact::<L, _>(&proof_db, root) else {
+        return fail!(CallError::DecodeError, "failed to encode the compact proof");
+    };
+    let mut output = Vec::new();
+    write_bytes(&mut output, &compact.encode());
+    for value in values {
+        match value {
+            Some(value) => {
+                output.push(1);
+                write_bytes(&mut output, &value);
+            },
+            None => output.push(0),
+        }
+    }
+    Ok(output)
+}
+
+/// Verifies a compact proof, the input has the same layout as in [`verify`] but the proof is
+/// the SCALE encoded `CompactProof` instead of the node list.
+pub fn verify_compact<L: TrieLayout>(input: &[u8]) -> CallResult<bool> {
+    let (root, input) = split_hash(input)?;
+    let Some((count, mut input)) = input.split_first_chunk::<4>() else {
+        return fail!(CallError::InvalidInput, "invalid input");
+    };
+    let mut items = Vec::new();
+    for _ in 0..u32::from_le_bytes(*count) {
+        let (item, rest) = split_item(input)?;
+        items.push(item);
+        input = rest;
+    }
+    let Ok(compact) = CompactProof::decode(&mut &input[..]) else {
+        return Ok(false);
+    };
+    let Some((db, _)) = decode_compact_proof::<L>(&compact, Some(&root)) else {
+        return Ok(false);
+    };
+    let trie = TrieDBBuilder::<L>::new(&db, &root).build();
+    Ok(items.into_iter().all(|(key, value)| match trie.get(&trie_key::<L>(key)) {
+        Ok(found) => found.as_deref() == value,
+        Err(_) => false,
+    }))
+}
+
+/// Converts a full storage proof, the root hash followed by the proof nodes as length-prefixed
+/// byte strings, to its compact form. The output is the SCALE encoded size of the full proof
+/// and of the compact proof as `u32`, followed by the SCALE encoded `CompactProof`.
+pub fn to_compact<L: TrieLayout>(input: &[u8]) -> CallResult<Vec<u8>> {
+    let (root, input) = split_hash(input)?;
+    let proof = StorageProof::new(split_list(input)?);
+    let Ok(compact) = encode_compact::<L, _>(&proof.to_memory_db::<L::Hash>(), &root) else {
+        return fail!(CallError::DecodeError, "failed to encode the compact proof");
+    };
+    let encoded = compact.encode();
+    let mut output = Vec::with_capacity(encoded.len() + 8);
+    output.extend_from_slice(&(proof.encoded_size() as u32).to_le_bytes());
+    output.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    output.extend_from_slice(&encoded);
+    Ok(output)
+}
+
+/// Expands a SCALE encoded `CompactProof` back to the full storage proof, recovering the root.
+/// The output is the root hash, the SCALE encoded size of the full proof and of the compact
+/// proof as `u32`, followed by the proof nodes as length-prefixed byte strings.
+pub fn from_compact<L: TrieLayout>(input: &[u8]) -> CallResult<Vec<u8>> {
+    let Ok(compact) = CompactProof::decode(&mut &input[..]) else {
+        return fail!(CallError::DecodeError, "invalid compact proof");
+    };
+    let Some((mut db, root)) = decode_compact_proof::<L>(&compact, None) else {
+        return fail!(CallError::DecodeError, "failed to decode the compact proof");
+    };
+    let nodes = db.drain().into_values().filter(|(_, rc)| *rc > 0);
+    let proof = StorageProof::new(nodes.map(|(node, _)| node));
+    let mut output = Vec::from(root.as_bytes());
+    output.extend_from_slice(&(proof.encoded_size() as u32).to_le_bytes());
+    output.extend_from_slice(&(input.len() as u32).to_le_bytes());
+    for node in proof.into_iter_nodes() {
+        write_bytes(&mut output, &node);
+    }
+    Ok(output)
+}
+
+/// Decodes the nodes of a compact proof encoded in the layout `L`, along with its root.
+fn decode_compact_proof<L: TrieLayout>(
+    compact: &CompactProof,
+    expected_root: Option<&H256>,
+) -> Option<(MemoryDB<L::Hash>, H256)> {
+    let mut db = MemoryDB::<L::Hash>::new(&[]);
+    let nodes = compact.encoded_nodes.iter().map(Vec::as_slice);
+    let root = decode_compact::<L, _, _>(&mut db, nodes, expected_root).ok()?;
+    Some((db, root))
+}
+
+/// Forwards reads to the backend, keeping a copy of every node read. Once frozen only the nodes
+/// already recorded can be read.
+struct RecordingDB<'a, H: Hasher<Out = H256>> {
+    backend: &'a dyn HashDB<H, DBValue>,
+    nodes: RefCell<BTreeMap<H256, DBValue>>,
+    size: Cell<usize>,
+    frozen: Cell<bool>,
+}
+
+impl<H: Hasher<Out = H256>> HashDBRef<H, DBValue> for RecordingDB<'_, H> {
+    fn get(&self, key: &H256, prefix: hash_db::Prefix) -> Option<DBValue> {
+        match self.nodes.borrow_mut().entry(*key) {
+            Entry::Occupied(entry) => Some(entry.get().clone()),
+            Entry::Vacant(_) if self.frozen.get() => None,
+            Entry::Vacant(entry) => {
+                let value = self.backend.get(key, prefix)?;
+                self.size.set(self.size.get() + value.len());
+                Some(entry.insert(value).clone())
+            },
+        }
+    }
+
+    fn contains(&self, key: &H256, prefix: hash_db::Prefix) -> bool {
+        HashDBRef::get(self, key, prefix).is_some()
+    }
+}
+
+/// Generates a range proof: the input is the root hash, the length-prefixed start key and the
+/// proof size budget as `u32`. Entries are proven in key order starting at the start key until
+/// the proof exceeds the budget, the next proof should start at the last key proven. The output is
+/// a flag telling whether the range reaches the end of the trie, the number of entries proven as
+/// `u32` and the proof nodes as length-prefixed byte strings. Keys are taken as stored in the
+/// trie, hashed when secure keys are enabled.
+pub fn generate_range<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    input: &[u8],
+) -> CallResult<Vec<u8>> {
+    let (root, input) = split_hash(input)?;
+    let (start, input) = split_bytes(input)?;
+    let Some((budget, _)) = input.split_first_chunk::<4>() else {
+        return fail!(CallError::InvalidInput, "invalid input");
+    };
+    let budget = u32::from_le_bytes(*budget) as usize;
+    let recording = RecordingDB::<L::Hash> {
+        backend: db,
+        nodes: Default::default(),
+        size: Cell::new(0),
+        frozen: Cell::new(false),
+    };
+    let mut count = 0u32;
+    let mut complete = true;
+    {
+        let trie = TrieDBBuilder::<L>::new(&recording, &root).build();
+        let mut iter = trie.iter().map_err(|error| call_error(error.as_ref()))?;
+        iter.seek(start).map_err(|error| call_error(error.as_ref()))?;
+        // Once the budget is exhausted the recording is frozen, and the entries left in the
+        // nodes already read are proven as well, exactly like the verifier sees them. At least
+        // one entry past the start key is always read, so resuming from the last key proven
+        // always makes progress.
+        for item in iter {
+            match item {
+                Ok((key, _)) => {
+                    count += 1;
+                    if key != start && recording.size.get() >= budget {
+                        recording.frozen.set(true);
+                    }
+                },
+                Err(error) if recording.frozen.get() => {
+                    if !matches!(*error, TrieError::IncompleteDatabase(_)) {
+                        return Err(call_error(error.as_ref()));
+                    }
+                    complete = false;
+                    break;
+                },
+                Err(error) => return Err(call_error(error.as_ref())),
+            }
+        }
+    }
+    let mut output = Vec::new();
+    output.push(u8::from(complete));
+    output.extend_from_slice(&count.to_le_bytes());
+    for node in recording.nodes.into_inner().into_values() {
+        write_bytes(&mut output, &node);
+    }
+    Ok(output)
+}
+
+/// Verifies a range proof, the input is the root hash, the length-prefixed start key and the
+/// proof nodes as length-prefixed byte strings. The output is `0` if the proof is invalid,
+/// `1` when the range is partial and `2` when it reaches the end of the trie, followed by the
+/// proven entries as length-prefixed keys and values.
+pub fn verify_range<L: TrieLayout>(input: &[u8]) -> CallResult<Vec<u8>> {
+    let (root, input) = split_hash(input)?;
+    let (start, input) = split_bytes(input)?;
+    let db = StorageProof::new(split_list(input)?).into_memory_db::<L::Hash>();
+    let trie = TrieDBBuilder::<L>::new(&db, &root).build();
+    let mut output = Vec::from([0]);
+    let Ok(mut iter) = trie.iter() else {
+        return Ok(output);
+    };
+    if iter.seek(start).is_err() {
+        return Ok(output);
+    }
+    let mut status = 2;
+    for item in iter {
+        match item {
+            Ok((key, value)) => {
+                write_bytes(&mut output, &key);
+                write_bytes(&mut output, &value);
+            },
+            Err(error) if matches!(*error, TrieError::IncompleteDatabase(_)) => {
+                status = 1;
+                break;
+            },
+            Err(_) => return Ok(Vec::from([0])),
+        }
+    }
+    output[0] = status;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ethereum::EthereumLayout,
+        hasher::{Blake3Hasher, Sha256Hasher},
+        primitives::{Blake2Hasher, KeccakHasher, LayoutV0, LayoutV1, MemoryDB},
+        storage::SecureKeys,
+    };
+    use trie_db::{NodeCodec, TrieDBMutBuilder, TrieMut};
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    fn verify_input(root: &H256, items: &[Item], proof: &[u8]) -> Vec<u8> {
+        let mut input = root.as_bytes().to_vec();
+        input.extend_from_slice(&(items.len() as u32).to_le_bytes());
+        for (key, value) in items {
+            write_bytes(&mut input, key);
+            match value {
+                Some(value) => {
+                    input.push(1);
+                    write_bytes(&mut input, value);
+                },
+                None => input.push(0),
+            }
+        }
+        input.extend_from_slice(proof);
+        input
+    }
+
+    fn check_generated_proofs<L: TrieLayout>() {
+        let mut db = MemoryDB::<L::Hash>::new(L::Codec::empty_node());
+        let mut root = H256::zero();
+        let mut trie = TrieDBMutBuilder::<L>::new(&mut db, &mut root).build();
+        trie.insert(&trie_key::<L>(b"alpha"), b"1").unwrap();
+        trie.insert(&trie_key::<L>(b"beta"), b"2").unwrap();
+        drop(trie);
+
+        let mut keys = Vec::new();
+        write_bytes(&mut keys, b"alpha");
+        write_bytes(&mut keys, b"gamma");
+        let proof = generate::<L>(&db, &root, &keys).unwrap();
+
+        let items: [Item; 2] = [(b"alpha", Some(b"1")), (b"gamma", None)];
+        assert!(verify::<L>(&verify_input(&root, &items, &proof)).unwrap());
+        assert!(!verify::<L>(&verify_input(&H256::zero(), &items, &proof)).unwrap());
+        let items: [Item; 2] = [(b"alpha", Some(b"2")), (b"gamma", None)];
+        assert!(!verify::<L>(&verify_input(&root, &items, &proof)).unwrap());
+        let items: [Item; 2] = [(b"alpha", Some(b"1")), (b"gamma", Some(b"3"))];
+        assert!(!verify::<L>(&verify_input(&root, &items, &proof)).unwrap());
+    }
+
+    #[test]
+    fn generated_proofs_verify() {
+        check_generated_proofs::<Layout>();
+        check_generated_proofs::<LayoutV0<Blake2Hasher>>();
+        check_generated_proofs::<LayoutV1<KeccakHasher>>();
+        check_generated_proofs::<LayoutV1<Blake3Hasher>>();
+        check_generated_proofs::<LayoutV1<Sha256Hasher>>();
+        check_generated_proofs::<EthereumLayout>();
+        check_generated_proofs::<SecureKeys<Layout>>();
+        check_generated_proofs::<SecureKeys<EthereumLayout>>();
+    }
+
+    #[test]
+    fn range_proofs_resume_until_complete() {
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        let mut root = H256::zero();
+        let mut trie = TrieDBMutBuilder::<Layout>::new(&mut db, &mut root).build();
+        for i in 0..32u8 {
+            trie.insert(&[i, i], &[i; 8]).unwrap();
+        }
+        drop(trie);
+
+        let mut start = Vec::new();
+        let mut proven = Vec::new();
+        let mut rounds = 0;
+        loop {
+            rounds += 1;
+            let mut input = root.as_bytes().to_vec();
+            write_bytes(&mut input, &start);
+            input.extend_from_slice(&200u32.to_le_bytes());
+            let output = generate_range::<Layout>(&db, &input).unwrap();
+            let complete = output[0] == 1;
+
+            let mut input = root.as_bytes().to_vec();
+            write_bytes(&mut input, &start);
+            input.extend_from_slice(&output[5..]);
+            let verified = verify_range::<Layout>(&input).unwrap();
+            assert_eq!(verified[0], if complete { 2 } else { 1 });
+            let mut rest = &verified[1..];
+            while !rest.is_empty() {
+                let (key, tail) = split_bytes(rest).unwrap();
+                let (value, tail) = split_bytes(tail).unwrap();
+                assert_eq!(value, &[key[0]; 8]);
+                if proven.last().is_none_or(|last: &Vec<u8>| last.as_slice() < key) {
+                    proven.push(key.to_vec());
+                }
+                rest = tail;
+            }
+            if complete {
+                break;
+            }
+            start = proven.last().unwrap().clone();
+        }
+        assert!(rounds > 1);
+        assert_eq!(proven, (0..32u8).map(|i| vec![i, i]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn trace_get_lists_the_nodes_read() {
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        let mut root = H256::zero();
+        let mut trie = TrieDBMutBuilder::<Layout>::new(&mut db, &mut root).build();
+        trie.insert(b"alpha", &[1; 40]).unwrap();
+        trie.insert(b"beta", b"2").unwrap();
+        drop(trie);
+
+        let output = trace_get::<Layout>(&db, &root, b"alpha").unwrap();
+        let (flag, rest) = output.split_first().unwrap();
+        assert_eq!(*flag, 1);
+        let (value, rest) = split_bytes(rest).unwrap();
+        assert_eq!(value, &[1; 40]);
+        let (total, hashes) = rest.split_first_chunk::<4>().unwrap();
+        // The root branch, the leaf and the value node.
+        assert_eq!(hashes.len(), 3 * 32);
+        assert_eq!(&hashes[..32], root.as_bytes());
+        let total = u32::from_le_bytes(*total) as usize;
+        let expected = hashes
+            .chunks_exact(32)
+            .map(|hash| {
+                HashDB::get(&db, &H256::from_slice(hash), hash_db::EMPTY_PREFIX).unwrap().len()
+            })
+            .sum::<usize>();
+        assert_eq!(total, expected);
+    }
+
+    fn check_compact_proofs<L: TrieLayout>() {
+        let mut db = MemoryDB::<L::Hash>::new(L::Codec::empty_node());
+        let mut root = H256::zero();
+        let mut trie = TrieDBMutBuilder::<L>::new(&mut db, &mut root).build();
+        for i in 0..64u8 {
+            trie.insert(&[b'k', i], &[i; 40]).unwrap();
+        }
+        drop(trie);
+
+        let mut keys = Vec::new();
+        write_bytes(&mut keys, &[b'k', 1]);
+        write_bytes(&mut keys, &[b'k', 2]);
+        write_bytes(&mut keys, b"missing");
+        let output = generate_compact::<L>(&db, &root, &keys).unwrap();
+        let (compact, results) = split_bytes(&output).unwrap();
+        assert_eq!(
+            results,
+            [[1, 40, 0, 0, 0].as_slice(), &[1; 40], &[1, 40, 0, 0, 0], &[2; 40], &[0]].concat()
+        );
+
+        let items: [Item; 3] =
+            [(&[b'k', 1], Some(&[1; 40])), (&[b'k', 2], Some(&[2; 40])), (b"missing", None)];
+        assert!(verify_compact::<L>(&verify_input(&root, &items, compact)).unwrap());
+        let items: [Item; 1] = [(&[b'k', 1], Some(&[2; 40]))];
+        assert!(!verify_compact::<L>(&verify_input(&root, &items, compact)).unwrap());
+
+        // Round-trip through the full storage proof.
+        let full = from_compact::<L>(compact).unwrap();
+        let (recovered, rest) = split_hash(&full).unwrap();
+        assert_eq!(recovered, root);
+        let mut input = root.as_bytes().to_vec();
+        input.extend_from_slice(&rest[8..]);
+        let output = to_compact::<L>(&input).unwrap();
+        assert_eq!(&output[..8], &rest[..8]);
+        assert_eq!(&output[8..], compact);
+    }
+
+    #[test]
+    fn compact_proofs_cover_many_keys() {
+        check_compact_proofs::<Layout>();
+        check_compact_proofs::<EthereumLayout>();
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Random operation sequences run against the storage and a reference trie.
+//!
+//! Every step goes through [`MerklePatriciaTrie`] on an [`ExternalDB`] kept in memory, as the
+//! calls of the host do, and through a plain `TrieDBMut` on a `MemoryDB`. Both must agree on
+//! the root, the value of every key and the nodes stored with their reference counts, and the
+//! nodes left out of the new trie must be deleted.
+use crate::{
+    backend::MemoryBackend,
+    ethereum::EthereumLayout,
+    hash::xxhash64,
+    primitives::{Blake2Hasher, LayoutV0, LayoutV1, MemoryDB, H256},
+    storage::{hashed_null_node, trie_key, write_bytes, ExternalDB, SecureKeys, TrieLayout},
+    trie::{self, for_each_node, MerklePatriciaTrie},
+};
+use hash_db::{HashDB, EMPTY_PREFIX};
+use std::collections::BTreeMap;
+use trie_db::{DBValue, NodeCodec, Trie, TrieDBBuilder, TrieDBMutBuilder, TrieMut};
+
+/// Distinct keys of a sequence, few enough for keys to be written and removed repeatedly.
+const KEYS: u64 = 24;
+const STEPS: u32 = 300;
+
+/// Deterministic numbers, each one the hash of the previous.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 = xxhash64(self.0, &self.0.to_le_bytes());
+        self.0 % bound
+    }
+
+    /// Keys share prefixes, so branches and extensions are created and collapsed.
+    fn key(&mut self) -> Vec<u8> {
+        let index = self.below(KEYS);
+        let mut key = vec![0xab; (index % 4) as usize];
+        key.extend_from_slice(&index.to_be_bytes()[(index % 8) as usize..]);
+        key
+    }
+
+    /// Values on both sides of the inline limits of the layouts.
+    fn value(&mut self) -> Vec<u8> {
+        let len = [0, 1, 31, 32, 33, 100][self.below(6) as usize];
+        vec![self.below(256) as u8; len]
+    }
+}
+
+enum Operation {
+    Insert(Vec<u8>, Vec<u8>),
+    Remove(Vec<u8>),
+    Take(Vec<u8>),
+    Batch(Vec<(Vec<u8>, Option<Vec<u8>>)>),
+}
+
+impl Operation {
+    fn random(rng: &mut Rng) -> Self {
+        match rng.below(4) {
+            0 => Self::Remove(rng.key()),
+            1 => Self::Take(rng.key()),
+            2 => Self::Batch(
+                (0..rng.below(6))
+                    .map(|_| (rng.key(), (rng.below(3) != 0).then(|| rng.value())))
+                    .collect(),
+            ),
+            _ => Self::Insert(rng.key(), rng.value()),
+        }
+    }
+}
+
+/// Trie kept in a `MemoryDB`, updated with the bare `trie_db` calls.
+struct Reference<L: TrieLayout> {
+    db: MemoryDB<L::Hash>,
+    root: H256,
+}
+
+impl<L: TrieLayout> Reference<L> {
+    fn new() -> Self {
+        Self { db: MemoryDB::new(L::Codec::empty_node()), root: hashed_null_node::<L>() }
+    }
+
+    fn apply(&mut self, operation: &Operation) -> Option<Vec<u8>> {
+        let mut trie = TrieDBMutBuilder::<L>::from_existing(&mut self.db, &mut self.root).build();
+        let mut write = |key: &[u8], value: Option<&Vec<u8>>| {
+            let key = trie_key::<L>(key);
+            match value {
+                Some(value) => trie.insert(&key, value).map(drop).unwrap(),
+                None => trie.remove(&key).map(drop).unwrap(),
+            }
+        };
+        match operation {
+            Operation::Insert(key, value) => write(key, Some(value)),
+            Operation::Remove(key) => write(key, None),
+            Operation::Take(key) => {
+                let value = trie.get(&trie_key::<L>(key)).unwrap();
+                trie.remove(&trie_key::<L>(key)).unwrap();
+                return value;
+            },
+            Operation::Batch(entries) => {
+                for (key, value) in entries {
+                    write(key, value.as_ref());
+                }
+            },
+        }
+        None
+    }
+}
+
+/// Applies `operation` as the dispatcher does, committing the new root.
+fn apply<L: TrieLayout>(
+    db: &mut ExternalDB<MemoryBackend>,
+    root: &mut H256,
+    operation: &Operation,
+) -> Option<Vec<u8>> {
+    let mut taken = None;
+    {
+        let mut trie = MerklePatriciaTrie::<L>::open(db, root);
+        match operation {
+            Operation::Insert(key, value) => {
+                let mut input = Vec::new();
+                write_bytes(&mut input, key);
+                write_bytes(&mut input, value);
+                trie.insert(&input).unwrap();
+            },
+            Operation::Remove(key) => trie.remove(key).unwrap(),
+            Operation::Take(key) => taken = trie.take(key).unwrap(),
+            Operation::Batch(entries) => {
+                let mut input = Vec::new();
+                for (key, value) in entries {
+                    input.push(u8::from(value.is_none()));
+                    write_bytes(&mut input, key);
+                    if let Some(value) = value {
+                        write_bytes(&mut input, value);
+                    }
+                }
+                trie.apply_batch(&input).unwrap();
+            },
+        }
+    }
+    db.commit_root(root);
+    taken
+}
+
+/// Nodes reachable from `root` with the number of times they're referenced.
+fn nodes<L: TrieLayout>(db: &dyn HashDB<L::Hash, DBValue>, root: &H256) -> BTreeMap<H256, i32> {
+    let mut nodes = BTreeMap::new();
+    for_each_node::<L, _>(db, root, |node| *nodes.entry(*node).or_default() += 1);
+    nodes
+}
+
+fn check<L: TrieLayout>(seed: u64) {
+    let mut rng = Rng(seed);
+    let mut db = ExternalDB::with_backend::<L>(MemoryBackend::new());
+    let mut root = db.get_root_hash();
+    let mut reference = Reference::<L>::new();
+    let mut previous = BTreeMap::new();
+    for step in 0..STEPS {
+        let operation = Operation::random(&mut rng);
+        let taken = apply::<L>(&mut db, &mut root, &operation);
+        assert_eq!(taken, reference.apply(&operation), "seed {seed}, step {step}");
+        assert_eq!(root, reference.root, "seed {seed}, step {step}");
+
+        // Nodes are read back from the storage, not from the pending writes.
+        let stored = ExternalDB::with_backend::<L>(db.backend());
+        let expected = TrieDBBuilder::<L>::new(&reference.db, &reference.root).build();
+        for index in 0..KEYS {
+            let mut key = vec![0xab; (index % 4) as usize];
+            key.extend_from_slice(&index.to_be_bytes()[(index % 8) as usize..]);
+            assert_eq!(
+                trie::read_value::<L>(&stored, &root, &key).unwrap(),
+                expected.get(&trie_key::<L>(&key)).unwrap(),
+                "seed {seed}, step {step}"
+            );
+        }
+        let nodes = nodes::<L>(&stored, &root);
+        assert_eq!(nodes, self::nodes::<L>(&reference.db, &reference.root));
+        for (node, references) in &nodes {
+            assert_eq!(stored.counter(node), *references, "seed {seed}, step {step}");
+            assert!(HashDB::contains(&reference.db, node, EMPTY_PREFIX));
+        }
+        // Nodes released by the step are deleted.
+        for node in previous.keys().filter(|node| !nodes.contains_key(node)) {
+            assert!(
+                !HashDB::<L::Hash, _>::contains(&stored, node, EMPTY_PREFIX),
+                "seed {seed}, step {step}"
+            );
+        }
+        previous = nodes;
+    }
+}
+
+#[test]
+fn v1_matches_memory_db() {
+    for seed in 0..4 {
+        check::<LayoutV1<Blake2Hasher>>(seed);
+    }
+}
+
+#[test]
+fn v0_matches_memory_db() {
+    for seed in 0..4 {
+        check::<LayoutV0<Blake2Hasher>>(seed);
+    }
+}
+
+#[test]
+fn ethereum_matches_memory_db() {
+    for seed in 0..4 {
+        check::<EthereumLayout>(seed);
+    }
+}
+
+#[test]
+fn secure_keys_match_memory_db() {
+    for seed in 0..4 {
+        check::<SecureKeys<LayoutV1<Blake2Hasher>>>(seed);
+    }
+}
@@ -0,0 +1,395 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Imports the state of a Substrate chain from the dumps of its RPC methods.
+//!
+//! The pairs of `state_getPairs` come as JSON, the response itself or its `result`, a list of
+//! `["0x..", "0x.."]` pairs, or an object mapping the hex keys to the hex values as the raw
+//! chain specs do. A `null` value, as `state_getStorage` returns for a missing key, removes the
+//! key. The pairs can also be SCALE encoded, as a `Vec<(Vec<u8>, Vec<u8>)>`. Every pair is
+//! written in a single batch, the trie is committed once. A dump can also be checked against
+//! the state root claimed for it, without writing anything.
+use crate::{
+    backend::TrieBackend,
+    error::{CallError, CallResult},
+    fail,
+    node::{NodeRef, OwnedNode},
+    primitives::H256,
+    storage::{
+        hashed_null_node, split_hash, trie_key, write_bytes, ExternalDB, OverlayDB, TrieLayout,
+    },
+    trie::call_error,
+};
+use alloc::vec::Vec;
+use codec::Decode;
+use hash_db::{HashDB, EMPTY_PREFIX};
+use trie_db::{DBValue, TrieDBMutBuilder, TrieMut};
+
+const JSON: u8 = 0;
+const SCALE: u8 = 1;
+
+fn hex_digit(digit: u8) -> CallResult<u8> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        digit => fail!(CallError::DecodeError, "invalid hex digit {:?}", digit as char),
+    }
+}
+
+fn decode_hex(hex: &[u8]) -> CallResult<Vec<u8>> {
+    let Some(digits) = hex.strip_prefix(b"0x") else {
+        return fail!(CallError::DecodeError, "hex strings start with 0x");
+    };
+    let (pairs, []) = digits.as_chunks::<2>() else {
+        return fail!(CallError::DecodeError, "odd number of hex digits");
+    };
+    pairs
+        .iter()
+        .map(|[high, low]| Ok(hex_digit(*high)? << 4 | hex_digit(*low)?))
+        .collect()
+}
+
+/// Reads the pairs out of a JSON document, without building it.
+struct Parser<'a> {
+    input: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Next byte past the whitespace, without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        while self.input.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+            self.at += 1;
+        }
+        self.input.get(self.at).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> CallResult<()> {
+        if self.peek() != Some(byte) {
+            return fail!(CallError::DecodeError, "expected {:?} at {}", byte as char, self.at);
+        }
+        self.at += 1;
+        Ok(())
+    }
+
+    /// Contents of a string, escapes are skipped but left as is.
+    fn string(&mut self) -> CallResult<&'a [u8]> {
+        self.expect(b'"')?;
+        let start = self.at;
+        while let Some(&byte) = self.input.get(self.at) {
+            self.at += 1;
+            match byte {
+                b'"' => return Ok(&self.input[start..self.at - 1]),
+                b'\\' => self.at += 1,
+                _ => {},
+            }
+        }
+        fail!(CallError::DecodeError, "unterminated string")
+    }
+
+    /// A hex string, or `None` for `null`.
+    fn value(&mut self) -> CallResult<Option<Vec<u8>>> {
+        if self.peek() == Some(b'n') {
+            self.literal(b"null")?;
+            return Ok(None);
+        }
+        decode_hex(self.string()?).map(Some)
+    }
+
+    fn literal(&mut self, literal: &[u8]) -> CallResult<()> {
+        if !self.input[self.at..].starts_with(literal) {
+            return fail!(CallError::DecodeError, "invalid literal at {}", self.at);
+        }
+        self.at += literal.len();
+        Ok(())
+    }
+
+    /// Calls `item` for each item of the list or object whose opening byte was consumed.
+    fn items(
+        &mut self,
+        close: u8,
+        mut item: impl FnMut(&mut Self) -> CallResult<()>,
+    ) -> CallResult<()> {
+        if self.peek() == Some(close) {
+            self.at += 1;
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            match self.peek() {
+                Some(b',') => self.at += 1,
+                Some(byte) if byte == close => {
+                    self.at += 1;
+                    return Ok(());
+                },
+                _ => return fail!(CallError::DecodeError, "expected a separator at {}", self.at),
+            }
+        }
+    }
+
+    /// Skips a value of a field besides the pairs, like the `id` of the response.
+    fn skip(&mut self) -> CallResult<()> {
+        match self.peek() {
+            Some(b'"') => self.string().map(drop),
+            Some(b'[') => {
+                self.at += 1;
+                self.items(b']', Self::skip)
+            },
+            Some(b'{') => {
+                self.at += 1;
+                self.items(b'}', |parser| {
+                    parser.string()?;
+                    parser.expect(b':')?;
+                    parser.skip()
+                })
+            },
+            Some(_) => {
+                let end = self.input[self.at..].iter().position(|byte| {
+                    matches!(byte, b',' | b']' | b'}') || byte.is_ascii_whitespace()
+                });
+                self.at = end.map_or(self.input.len(), |end| self.at + end);
+                Ok(())
+            },
+            None => fail!(CallError::DecodeError, "unexpected end of the document"),
+        }
+    }
+
+    /// Reads the pairs of a list or of an object, the fields of an object whose name isn't a
+    /// hex key are skipped, but the `result` of a response.
+    fn pairs(
+        &mut self,
+        pair: &mut impl FnMut(Vec<u8>, Option<Vec<u8>>) -> CallResult<()>,
+    ) -> CallResult<()> {
+        match self.peek() {
+            Some(b'[') => {
+                self.at += 1;
+                self.items(b']', |parser| {
+                    parser.expect(b'[')?;
+                    let Some(key) = parser.value()? else {
+                        return fail!(CallError::DecodeError, "null key at {}", parser.at);
+                    };
+                    parser.expect(b',')?;
+                    let value = parser.value()?;
+                    parser.expect(b']')?;
+                    pair(key, value)
+                })
+            },
+            Some(b'{') => {
+                self.at += 1;
+                self.items(b'}', |parser| {
+                    let name = parser.string()?;
+                    parser.expect(b':')?;
+                    match name {
+                        b"result" => parser.pairs(pair),
+                        name if name.starts_with(b"0x") => pair(decode_hex(name)?, parser.value()?),
+                        _ => parser.skip(),
+                    }
+                })
+            },
+            _ => fail!(CallError::DecodeError, "expected a list or an object of pairs"),
+        }
+    }
+}
+
+/// Writes the pairs of the dump in `input`, its format followed by the document, to the trie at
+/// `root`. Returns the number of pairs read.
+fn write_pairs<L: TrieLayout>(
+    db: &mut dyn HashDB<L::Hash, DBValue>,
+    root: &mut H256,
+    input: &[u8],
+) -> CallResult<u32> {
+    let Some((&format, document)) = input.split_first() else {
+        return fail!(CallError::InvalidInput, "expected the format of the dump");
+    };
+    let mut count = 0;
+    let mut trie = TrieDBMutBuilder::<L>::from_existing(db, root).build();
+    let mut pair = |key: Vec<u8>, value: Option<Vec<u8>>| {
+        count += 1;
+        let key = trie_key::<L>(&key);
+        match value {
+            Some(value) => trie.insert(&key, &value).map(drop),
+            None => trie.remove(&key).map(drop),
+        }
+        .map_err(|error| call_error(error.as_ref()))
+    };
+    match format {
+        JSON => {
+            let mut parser = Parser { input: document, at: 0 };
+            parser.pairs(&mut pair)?;
+            if parser.peek().is_some() {
+                return fail!(CallError::DecodeError, "trailing data at {}", parser.at);
+            }
+        },
+        SCALE => {
+            let Ok(pairs) = Vec::<(Vec<u8>, Vec<u8>)>::decode(&mut &document[..]) else {
+                return fail!(CallError::DecodeError, "invalid SCALE encoded pairs");
+            };
+            for (key, value) in pairs {
+                pair(key, Some(value))?;
+            }
+        },
+        format => return fail!(CallError::InvalidInput, "invalid dump format: {format}"),
+    }
+    Ok(count)
+}
+
+/// Writes the pairs of the dump in `input` to the trie at `root`, see [`write_pairs`]. Returns
+/// the new root and the number of pairs read.
+pub fn import<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &H256,
+    input: &[u8],
+) -> CallResult<(H256, u32)> {
+    let mut root = *root;
+    let count = write_pairs::<L>(db, &mut root, input)?;
+    Ok((root, count))
+}
+
+/// Encoded node of a reference, its hash or the node itself when inlined.
+fn node_bytes(node: &NodeRef) -> &[u8] {
+    match node {
+        NodeRef::Hash(hash) => hash.as_bytes(),
+        NodeRef::Inline(bytes) => bytes,
+    }
+}
+
+/// Smallest subtree holding every difference between the trie at `expected` and the one at
+/// `actual`, found at the same path. Returns its nibble path and the nodes of both tries there.
+/// The walk stops at the nodes of `expected` that aren't stored, any other missing node fails
+/// with `KeyNotFound`.
+fn divergence<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    mut expected: NodeRef,
+    mut actual: NodeRef,
+) -> CallResult<(Vec<u8>, NodeRef, NodeRef)> {
+    let mut path = Vec::new();
+    loop {
+        if matches!(&expected, NodeRef::Hash(hash) if !db.contains(hash, EMPTY_PREFIX)) {
+            return Ok((path, expected, actual));
+        }
+        let ours = OwnedNode::load::<L>(db, &expected)?;
+        let theirs = OwnedNode::load::<L>(db, &actual)?;
+        if ours.partial != theirs.partial || ours.value != theirs.value {
+            return Ok((path, expected, actual));
+        }
+        // The walk goes on while a single child differs, present in both tries.
+        let children = ours.children.into_iter().zip(theirs.children).enumerate();
+        let mut differing = children.filter(|(_, (ours, theirs))| ours != theirs);
+        let (Some((nibble, (Some(next_expected), Some(next_actual)))), None) =
+            (differing.next(), differing.next())
+        else {
+            return Ok((path, expected, actual));
+        };
+        path.extend_from_slice(&ours.partial);
+        path.push(nibble as u8);
+        (expected, actual) = (next_expected, next_actual);
+    }
+}
+
+/// Rebuilds the trie of the dump in `input`, after the claimed state root, in an overlay left
+/// unwritten, and checks its root. The output is `1` and the computed root when it matches the
+/// claimed one, `0` and the computed root otherwise. Then, when the claimed trie is stored, its
+/// first divergent subtree: the length-prefixed nibble path and the length-prefixed nodes of
+/// both tries there, see [`node_bytes`].
+pub fn verify<L: TrieLayout>(db: &ExternalDB, input: &[u8]) -> CallResult<Vec<u8>> {
+    let (claimed, input) = split_hash(input)?;
+    let mut overlay = OverlayDB::<L::Hash>::new(db);
+    let mut computed = hashed_null_node::<L>();
+    write_pairs::<L>(&mut overlay, &mut computed, input)?;
+
+    let mut output = Vec::with_capacity(33);
+    output.push(u8::from(computed == claimed));
+    output.extend_from_slice(computed.as_bytes());
+    if computed != claimed && overlay.contains(&claimed, EMPTY_PREFIX) {
+        let (path, expected, actual) =
+            divergence::<L>(&overlay, NodeRef::root(&claimed), NodeRef::root(&computed))?;
+        write_bytes(&mut output, &path);
+        write_bytes(&mut output, node_bytes(&expected));
+        write_bytes(&mut output, node_bytes(&actual));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MemoryBackend,
+        primitives::{Blake2Hasher, LayoutV1, MemoryDB},
+        storage::split_bytes,
+        trie::read_value,
+    };
+    use codec::Encode;
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    #[test]
+    fn dumps_load_the_pairs() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let root = db.get_root_hash();
+        let response = br#"{"jsonrpc": "2.0", "result": [["0x616c706861", "0x01"],
+            ["0x62657461", "0x02FF"]], "id": 1}"#;
+        let (root, count) =
+            import::<Layout, _>(&mut db, &root, &[&[JSON][..], response].concat()).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(read_value::<Layout>(&db, &root, b"beta").unwrap(), Some(vec![2, 255]));
+
+        // Raw chain specs map the keys to the values, `null` removes a key.
+        let spec = br#"{"0x616c706861": null, "0x67616d6d61": "0x03"}"#;
+        let (root, _) = import::<Layout, _>(&mut db, &root, &[&[JSON][..], spec].concat()).unwrap();
+        assert_eq!(read_value::<Layout>(&db, &root, b"alpha").unwrap(), None);
+
+        let pairs = vec![(b"delta".to_vec(), vec![4])].encode();
+        let (root, _) =
+            import::<Layout, _>(&mut db, &root, &[&[SCALE][..], &pairs].concat()).unwrap();
+        assert_eq!(read_value::<Layout>(&db, &root, b"delta").unwrap(), Some(vec![4]));
+        assert_eq!(read_value::<Layout>(&db, &root, b"gamma").unwrap(), Some(vec![3]));
+
+        let invalid = [&[JSON][..], br#"[["0x61", "0x0"]]"#].concat();
+        assert_eq!(import::<Layout, _>(&mut db, &root, &invalid), Err(CallError::DecodeError));
+    }
+
+    #[test]
+    fn verify_finds_the_divergent_subtree() {
+        use crate::native::{call, clear_storage, DEFAULT_HANDLE};
+
+        clear_storage();
+        let pairs = br#"[["0x616c706861", "0x01"], ["0x62657461", "0x02"], ["0x6265", "0x03"]]"#;
+        call(DEFAULT_HANDLE, 69, &[&[JSON][..], pairs].concat()).unwrap();
+        let root = call(DEFAULT_HANDLE, 4, &[]).unwrap().output.unwrap();
+        let verify = |document: &[u8]| {
+            let input = [&root[..], &[JSON], document].concat();
+            call(DEFAULT_HANDLE, 70, &input).unwrap().output.unwrap()
+        };
+        assert_eq!(verify(pairs), [&[1][..], &root].concat());
+
+        // Only `beta` differs, the walk goes past the branch of `be` down to its leaf.
+        let output =
+            verify(br#"[["0x616c706861", "0x01"], ["0x62657461", "0x09"], ["0x6265", "0x03"]]"#);
+        assert_eq!(output[0], 0);
+        let (path, rest) = split_bytes(&output[33..]).unwrap();
+        assert_eq!(path, [6, 2, 6, 5, 7]);
+        let (expected, rest) = split_bytes(rest).unwrap();
+        let (actual, rest) = split_bytes(rest).unwrap();
+        assert!(expected != actual && rest.is_empty());
+
+        // Without the claimed trie, only the computed root is reported.
+        let input = [&[0u8; 32][..], &[JSON], pairs].concat();
+        assert_eq!(call(DEFAULT_HANDLE, 70, &input).unwrap().output.unwrap()[1..], root[..]);
+    }
+
+    #[test]
+    fn divergence_fails_on_missing_nodes() {
+        let mut db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        let mut root = hashed_null_node::<Layout>();
+        let pairs = vec![(b"alpha".to_vec(), vec![1]), (b"beta".to_vec(), vec![2])].encode();
+        write_pairs::<Layout>(&mut db, &mut root, &[&[SCALE][..], &pairs].concat()).unwrap();
+        let missing = NodeRef::root(&H256::repeat_byte(1));
+        assert_eq!(
+            divergence::<Layout>(&db, NodeRef::root(&root), missing).err(),
+            Some(CallError::KeyNotFound)
+        );
+    }
+}
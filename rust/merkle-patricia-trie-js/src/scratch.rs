@@ -0,0 +1,173 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Buffers reused by the storage reads and the listings.
+//!
+//! Reading a node, its counter or encoding a listing needs a short-lived buffer, on large
+//! batches allocating a fresh one each time is most of the heap churn. A buffer taken from the
+//! pool goes back to it, cleared, when dropped. The pool lasts as long as the heap: it is
+//! emptied by [`begin`] unless the arena is persistent, see [`crate::arena`].
+use crate::arena;
+use alloc::vec::Vec;
+use core::{
+    cell::RefCell,
+    mem::size_of,
+    ops::{Deref, DerefMut},
+};
+
+/// Buffers kept by each pool, the storage layer holds at most a key and a value at once.
+const MAX_BUFFERS: usize = 8;
+/// Buffers larger than this are freed instead of kept, so a large value read once doesn't pin
+/// its memory.
+const MAX_BYTES: usize = 64 * 1024;
+
+pub struct Pool<T>(RefCell<Vec<Vec<T>>>);
+
+// SAFETY: the module is single threaded.
+#[cfg(target_arch = "wasm32")]
+unsafe impl<T> Sync for Pool<T> {}
+
+impl<T> Pool<T> {
+    const fn new() -> Self {
+        Self(RefCell::new(Vec::new()))
+    }
+
+    fn forget(&self) {
+        let _buffers = self.0.take();
+        // The heap is reset right after, the buffers point to memory reused since.
+        #[cfg(not(feature = "dlmalloc"))]
+        core::mem::forget(_buffers);
+    }
+}
+
+/// Storage keys and values.
+#[cfg(target_arch = "wasm32")]
+static BYTES: Pool<u8> = Pool::new();
+/// UTF-16 code units of the hex strings of a listing.
+#[cfg(target_arch = "wasm32")]
+static UNITS: Pool<u16> = Pool::new();
+
+// The native tests run on several threads, each gets its own pools.
+#[cfg(not(target_arch = "wasm32"))]
+std::thread_local! {
+    static BYTES: Pool<u8> = const { Pool::new() };
+    static UNITS: Pool<u16> = const { Pool::new() };
+}
+
+/// Items of the buffers kept in a pool.
+pub trait Pooled: Sized + 'static {
+    fn with_pool<R>(f: impl FnOnce(&Pool<Self>) -> R) -> R;
+}
+
+impl Pooled for u8 {
+    fn with_pool<R>(f: impl FnOnce(&Pool<Self>) -> R) -> R {
+        #[cfg(target_arch = "wasm32")]
+        return f(&BYTES);
+        #[cfg(not(target_arch = "wasm32"))]
+        BYTES.with(f)
+    }
+}
+
+impl Pooled for u16 {
+    fn with_pool<R>(f: impl FnOnce(&Pool<Self>) -> R) -> R {
+        #[cfg(target_arch = "wasm32")]
+        return f(&UNITS);
+        #[cfg(not(target_arch = "wasm32"))]
+        UNITS.with(f)
+    }
+}
+
+/// Takes an empty buffer from the pool, allocated the first time only.
+pub fn take<T: Pooled>() -> Scratch<T> {
+    let buffer = T::with_pool(|pool| pool.0.borrow_mut().pop()).unwrap_or_default();
+    Scratch { buffer }
+}
+
+pub fn bytes() -> Scratch<u8> {
+    take()
+}
+
+pub fn units() -> Scratch<u16> {
+    take()
+}
+
+/// Empties the pools before the heap of a new call, they are kept by a persistent arena.
+pub fn begin() {
+    if !arena::is_persistent() {
+        wipe();
+    }
+}
+
+/// Empties the pools before the heap is wiped.
+pub fn wipe() {
+    u8::with_pool(Pool::forget);
+    u16::with_pool(Pool::forget);
+}
+
+/// Capacity in bytes of the buffers kept by the pools.
+#[cfg(test)]
+pub fn retained() -> usize {
+    let bytes = u8::with_pool(|pool| pool.0.borrow().iter().map(Vec::capacity).sum::<usize>());
+    let units = u16::with_pool(|pool| pool.0.borrow().iter().map(Vec::capacity).sum::<usize>());
+    bytes + units * size_of::<u16>()
+}
+
+/// Buffer borrowed from a [`Pool`].
+pub struct Scratch<T: Pooled> {
+    buffer: Vec<T>,
+}
+
+impl<T: Pooled> Deref for Scratch<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.buffer
+    }
+}
+
+impl<T: Pooled> DerefMut for Scratch<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.buffer
+    }
+}
+
+impl<T: Pooled> Drop for Scratch<T> {
+    fn drop(&mut self) {
+        if self.buffer.capacity() * size_of::<T>() > MAX_BYTES {
+            return;
+        }
+        let mut buffer = core::mem::take(&mut self.buffer);
+        buffer.clear();
+        T::with_pool(|pool| {
+            let mut buffers = pool.0.borrow_mut();
+            if buffers.len() < MAX_BUFFERS {
+                buffers.push(buffer);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused_cleared() {
+        let mut buffer = bytes();
+        buffer.extend_from_slice(b"alpha");
+        let pointer = buffer.as_ptr();
+        drop(buffer);
+        let buffer = bytes();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr(), pointer);
+    }
+
+    #[test]
+    fn large_buffers_are_freed() {
+        let mut buffer = bytes();
+        buffer.reserve(MAX_BYTES + 1);
+        drop(buffer);
+        assert!(bytes().capacity() <= MAX_BYTES);
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Snapshots of the trie, saved as a SCALE encoded `CompactProof` of every node.
+//!
+//! A snapshot of a prefix holds the trie of the entries under it only, with a root of its own.
+//! Keys are taken as stored in the trie, hashed when secure keys are enabled.
+use crate::{
+    backend::TrieBackend,
+    error::{CallError, CallResult},
+    fail,
+    primitives::{decode_compact, encode_compact, CompactProof, MemoryDB, H256},
+    storage::{hashed_null_node, ExternalDB, TrieLayout},
+    trie::call_error,
+};
+use alloc::vec::Vec;
+use codec::{Decode, Encode};
+use hash_db::HashDB;
+use trie_db::{DBValue, NodeCodec, TrieDBBuilder, TrieDBIterator, TrieDBMutBuilder, TrieMut};
+
+/// Entries of the trie at `root` whose key starts with `prefix`.
+pub(crate) fn entries<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    prefix: &[u8],
+) -> CallResult<Vec<(Vec<u8>, Vec<u8>)>> {
+    let trie = TrieDBBuilder::<L>::new(&db, root).build();
+    let iter = TrieDBIterator::new_prefixed(&trie, prefix).map_err(|error| call_error(&error))?;
+    iter.map(|item| item.map_err(|error| call_error(&error))).collect()
+}
+
+/// Exports the entries of the trie at `root` under `prefix`, the whole trie when it's empty.
+pub fn export<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    prefix: &[u8],
+) -> CallResult<Vec<u8>> {
+    let mut memory_db = MemoryDB::<L::Hash>::new(L::Codec::empty_node());
+    let mut snapshot = H256::zero();
+    {
+        let mut trie = TrieDBMutBuilder::<L>::new(&mut memory_db, &mut snapshot).build();
+        for (key, value) in entries::<L>(db, root, prefix)? {
+            trie.insert(&key, &value).map_err(|error| call_error(error.as_ref()))?;
+        }
+    }
+    let Ok(compact) = encode_compact::<L, _>(&memory_db, &snapshot) else {
+        return fail!(CallError::DecodeError, "failed to encode the snapshot");
+    };
+    Ok(compact.encode())
+}
+
+/// Rebuilds the trie saved in `snapshot` in the empty storage at `root`, returning its root.
+pub fn import<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &H256,
+    snapshot: &[u8],
+) -> CallResult<H256> {
+    if *root != hashed_null_node::<L>() {
+        return fail!(CallError::InvalidCall, "snapshots are imported into an empty trie");
+    }
+    let Ok(compact) = CompactProof::decode(&mut &snapshot[..]) else {
+        return fail!(CallError::DecodeError, "invalid snapshot");
+    };
+    let mut memory_db = MemoryDB::<L::Hash>::new(&[]);
+    let nodes = compact.encoded_nodes.iter().map(Vec::as_slice);
+    let Ok(expected) = decode_compact::<L, _, _>(&mut memory_db, nodes, None) else {
+        return fail!(CallError::DecodeError, "failed to decode the snapshot");
+    };
+    // A snapshot missing nodes fails here, before anything is written.
+    let entries = entries::<L>(&memory_db, &expected, &[])?;
+    let mut imported = H256::zero();
+    {
+        let mut trie = TrieDBMutBuilder::<L>::new(db, &mut imported).build();
+        for (key, value) in entries {
+            trie.insert(&key, &value).map_err(|error| call_error(error.as_ref()))?;
+        }
+    }
+    if imported != expected {
+        return fail!(CallError::DecodeError, key = expected.as_bytes(), "snapshot root mismatch");
+    }
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MemoryBackend,
+        ethereum::EthereumLayout,
+        primitives::{Blake2Hasher, LayoutV1},
+        storage::trie_key,
+        trie::read_value,
+    };
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    fn check_snapshots<L: TrieLayout>() {
+        let mut memory_db = MemoryDB::<L::Hash>::new(L::Codec::empty_node());
+        let mut root = H256::zero();
+        let large = [7u8; 64];
+        {
+            let mut trie = TrieDBMutBuilder::<L>::new(&mut memory_db, &mut root).build();
+            for (key, value) in [(&b"alpha"[..], &b"1"[..]), (b"alphabet", &large), (b"beta", b"2")]
+            {
+                trie.insert(&trie_key::<L>(key), value).unwrap();
+            }
+        }
+
+        let snapshot = export::<L>(&memory_db, &root, &[]).unwrap();
+        let mut db = ExternalDB::with_backend::<L>(MemoryBackend::new());
+        let empty = db.get_root_hash();
+        let imported = import::<L, _>(&mut db, &empty, &snapshot).unwrap();
+        db.commit_root(&imported);
+        assert_eq!(imported, root);
+        assert_eq!(read_value::<L>(&db, &root, b"alphabet").unwrap(), Some(large.to_vec()));
+
+        let snapshot = export::<L>(&memory_db, &root, b"alpha").unwrap();
+        let mut db = ExternalDB::with_backend::<L>(MemoryBackend::new());
+        let imported = import::<L, _>(&mut db, &empty, &snapshot).unwrap();
+        assert_ne!(imported, root);
+        assert_eq!(read_value::<L>(&db, &imported, b"alpha").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(read_value::<L>(&db, &imported, b"beta").unwrap(), None);
+
+        let snapshot = export::<L>(&memory_db, &root, b"gamma").unwrap();
+        let mut db = ExternalDB::with_backend::<L>(MemoryBackend::new());
+        let imported = import::<L, _>(&mut db, &empty, &snapshot).unwrap();
+        assert_eq!(imported, empty);
+    }
+
+    #[test]
+    fn snapshots_restore_the_trie() {
+        check_snapshots::<Layout>();
+        check_snapshots::<EthereumLayout>();
+    }
+}
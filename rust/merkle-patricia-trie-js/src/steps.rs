@@ -0,0 +1,158 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Teaching mode, the structural steps of an insert or a removal for animating them.
+//!
+//! The operation is applied on an overlay, the storage is left as it is. Each step is a node
+//! graph encoded as JSON, see [`crate::json`], preceded by its phase: the lookup of the key in
+//! the former trie, then the restructured trie with the new nodes highlighted and their hash
+//! pending, then one step per new node as its hash is computed, bottom up. The last step is the
+//! resulting trie.
+use crate::{
+    children::NodeId,
+    error::{CallError, CallResult},
+    fail, json,
+    primitives::H256,
+    storage::{split_bytes, trie_key, write_bytes, ExternalDB, OverlayDB, TrieLayout},
+    trie::{decode_nodes, mark_lookup, MerklePatriciaTrie, NodeArena},
+};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+
+/// The nodes read by the lookup of the key are highlighted.
+const LOOKUP: u8 = 0;
+/// The nodes written by the operation are highlighted, their id is `null`.
+const RESTRUCTURE: u8 = 1;
+/// The node whose hash was just computed is highlighted.
+const HASH: u8 = 2;
+
+fn write_step(output: &mut Vec<u8>, phase: u8, index: NodeId, nodes: &NodeArena) {
+    output.push(phase);
+    write_bytes(output, json::encode(index, nodes).as_bytes());
+}
+
+/// Code and key of the single operation in `input`, in the format of `apply_batch`.
+pub(crate) fn split_operation(input: &[u8]) -> CallResult<(u8, &[u8])> {
+    let Some((&op, rest)) = input.split_first() else {
+        return fail!(CallError::InvalidInput, "expected an operation");
+    };
+    let (key, rest) = split_bytes(rest)?;
+    let rest = match op {
+        0 => split_bytes(rest)?.1,
+        1 => rest,
+        op => return fail!(CallError::InvalidInput, "invalid batch operation: {op}"),
+    };
+    if !rest.is_empty() {
+        return fail!(CallError::InvalidInput, "expected a single operation");
+    }
+    Ok((op, key))
+}
+
+/// Code and input of the call applying the single operation in `input`, see
+/// [`split_operation`]. Insertions take the key and value following the code of the operation,
+/// removals the bare key.
+pub(crate) fn operation_call(input: &[u8]) -> CallResult<(u32, &[u8])> {
+    match split_operation(input)? {
+        (0, _) => Ok((0, &input[1..])),
+        (_, key) => Ok((1, key)),
+    }
+}
+
+/// Steps of the operation in `input` on the trie at `root`, as length-prefixed JSON graphs
+/// each preceded by its phase.
+pub fn steps<L: TrieLayout>(db: &ExternalDB, root: &H256, input: &[u8]) -> CallResult<Vec<u8>> {
+    let (_, key) = split_operation(input)?;
+    let mut output = Vec::new();
+
+    let (index, mut old) = decode_nodes::<L>(db, root, &[], u32::MAX);
+    mark_lookup(&mut old, index, &trie_key::<L>(key));
+    for node in old.iter_mut() {
+        node.highlighted = node.matched_nibbles.is_some();
+    }
+    write_step(&mut output, LOOKUP, index, &old);
+
+    let mut overlay = OverlayDB::<L::Hash>::new(db);
+    let mut new_root = *root;
+    MerklePatriciaTrie::<L>::open(&mut overlay, &mut new_root).apply_batch(input)?;
+    let (index, mut nodes) = decode_nodes::<L>(&overlay, &new_root, &[], u32::MAX);
+
+    // Inline nodes have no hash, they are new unless the former trie had the same encoding.
+    let mut ids = old.iter().filter_map(|node| node.id).collect::<Vec<_>>();
+    ids.sort_unstable();
+    let inline = old
+        .iter()
+        .filter(|node| node.id.is_none())
+        .map(|node| node.raw_bytes.as_slice());
+    let inline = inline.collect::<Vec<_>>();
+    let mut pending = Vec::new();
+    for position in nodes.ids() {
+        let node = &mut nodes[position];
+        node.highlighted = match node.id {
+            Some(id) => ids.binary_search(&id).is_err(),
+            None => !inline.contains(&node.raw_bytes.as_slice()),
+        };
+        if let Some(id) = node.id.take_if(|_| node.highlighted) {
+            pending.push((position, id));
+        }
+    }
+    write_step(&mut output, RESTRUCTURE, index, &nodes);
+
+    // Children are hashed before their parent, which holds their hash.
+    pending.sort_by_key(|&(position, _)| Reverse(nodes[position].depth));
+    for (position, id) in pending {
+        for node in nodes.iter_mut() {
+            node.highlighted = false;
+        }
+        nodes[position].id = Some(id);
+        nodes[position].highlighted = true;
+        write_step(&mut output, HASH, index, &nodes);
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        native::{call, clear_storage, DEFAULT_HANDLE},
+        storage::{split_bytes, write_bytes},
+    };
+
+    fn insert(key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut input = vec![0];
+        write_bytes(&mut input, key);
+        write_bytes(&mut input, value);
+        input
+    }
+
+    #[test]
+    fn last_step_is_the_resulting_trie() {
+        clear_storage();
+        call(DEFAULT_HANDLE, 0, &insert(b"alpha", b"1")[1..]).unwrap();
+        let root = call(DEFAULT_HANDLE, 4, &[]).unwrap().output;
+
+        // The leaf of `alpha` becomes a branch holding both keys.
+        let operation = insert(b"alphabet", b"2");
+        let output = call(DEFAULT_HANDLE, 64, &operation).unwrap().output.unwrap();
+        assert_eq!(call(DEFAULT_HANDLE, 4, &[]).unwrap().output, root);
+        let mut steps = Vec::new();
+        let mut rest = output.as_slice();
+        while let Some((&phase, next)) = rest.split_first() {
+            let (graph, next) = split_bytes(next).unwrap();
+            steps.push((phase, graph.to_vec()));
+            rest = next;
+        }
+        let phases = steps.iter().map(|(phase, _)| *phase).collect::<Vec<_>>();
+        assert_eq!(phases[..2], [0, 1]);
+        assert!(phases[2..].iter().all(|phase| *phase == 2) && phases.len() > 2);
+        assert!(String::from_utf8_lossy(&steps[1].1).contains("\"id\":null"));
+
+        call(DEFAULT_HANDLE, 0, &operation[1..]).unwrap();
+        let trie = call(DEFAULT_HANDLE, 40, &[]).unwrap().output.unwrap();
+        let last = String::from_utf8(steps.pop().unwrap().1).unwrap();
+        assert_eq!(
+            last.replace("\"highlighted\":true", "\"highlighted\":false"),
+            String::from_utf8(trie).unwrap()
+        );
+    }
+}
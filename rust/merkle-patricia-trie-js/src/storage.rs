@@ -2,175 +2,975 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::{abort, host::HostFnImpl, trie::MerklePatriciaTrie};
+use crate::{
+    abort, arena,
+    backend::{HostBackend, TrieBackend},
+    bench,
+    cache::NodeCache,
+    checkpoint, child, cursor, diff,
+    error::{self, CallError, CallResult},
+    ethereum::{self, EthereumLayout},
+    explain, fail, hash,
+    hints::ValueHints,
+    history,
+    host::{HostError, HostFnImpl},
+    migration, oplog, ordered, proof, rpc, scratch, snapshot, steps, substrate, sync,
+    trie::{self, MerklePatriciaTrie},
+    tries, value_index, watch,
+};
 
-use core::{num::NonZeroUsize, ptr::NonNull};
+use crate::primitives::{LayoutV0, LayoutV1, MemoryDB, H256};
+use alloc::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use core::{cell::RefCell, fmt::Debug, marker::PhantomData, num::NonZeroUsize, ptr::NonNull};
 use hash_db::{AsHashDB, HashDB, Hasher};
-use sp_core::{bounded::alloc::vec::Vec, Blake2Hasher, H256};
-use sp_trie::{DBValue, TrieDBMutBuilder};
+use trie_db::{DBValue, NodeCodec};
 
-pub const HASHED_NULL_NODE: H256 =
-    H256(hex_literal::hex!("03170a2e7597b7b7e3d84c05391d139a62b157e78786d8c082f29dcf4c111314"));
 pub const EMPTY_PTR: &mut [u8] = unsafe {
     let mut ptr = NonNull::<u8>::without_provenance(NonZeroUsize::new(1).unwrap());
     core::slice::from_raw_parts_mut(ptr.as_mut(), 0)
 };
 
-pub type Layout = sp_trie::LayoutV1<Blake2Hasher>;
+/// Trie layouts the storage can hold, every node is referenced by a 256-bit hash.
+pub trait TrieLayout:
+    trie_db::TrieConfiguration<Hash: Hasher<Out = H256>, Codec: NodeCodec<Error: Debug>>
+{
+    /// Whether keys are hashed with the layout hasher before reaching the trie.
+    const SECURE_KEYS: bool = false;
+}
+
+impl<H: Hasher<Out = H256>> TrieLayout for LayoutV0<H> {}
+
+impl<H: Hasher<Out = H256>> TrieLayout for LayoutV1<H> {}
+
+impl<H: Hasher<Out = H256>> TrieLayout for EthereumLayout<H> {}
+
+/// Wraps a layout so keys are hashed before reaching the trie, like Ethereum's secure trie.
+/// Keys then follow the order of their hashes, so iterations and range proofs start from a
+/// hashed key.
+pub struct SecureKeys<L>(PhantomData<L>);
+
+impl<L: TrieLayout> trie_db::TrieLayout for SecureKeys<L> {
+    const USE_EXTENSION: bool = L::USE_EXTENSION;
+    const ALLOW_EMPTY: bool = L::ALLOW_EMPTY;
+    const MAX_INLINE_VALUE: Option<u32> = L::MAX_INLINE_VALUE;
+
+    type Hash = L::Hash;
+    type Codec = L::Codec;
+}
+
+impl<L: TrieLayout> trie_db::TrieConfiguration for SecureKeys<L> {
+    fn encode_index(index: u32) -> Vec<u8> {
+        L::encode_index(index)
+    }
+}
+
+impl<L: TrieLayout> TrieLayout for SecureKeys<L> {
+    const SECURE_KEYS: bool = true;
+}
 
-pub struct ExternalDB;
+/// Hash of the encoded empty node, which is also the root of an empty trie.
+pub fn hashed_null_node<L: TrieLayout>() -> H256 {
+    L::Codec::hashed_null_node()
+}
+
+/// Key under which `key` is stored in the trie.
+pub fn trie_key<L: TrieLayout>(key: &[u8]) -> Cow<'_, [u8]> {
+    if L::SECURE_KEYS {
+        Cow::Owned(L::Hash::hash(key).as_bytes().to_vec())
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+#[cfg(any(
+    all(feature = "keccak", feature = "blake3"),
+    all(feature = "keccak", feature = "sha256"),
+    all(feature = "blake3", feature = "sha256"),
+))]
+compile_error!("the `keccak`, `blake3` and `sha256` features are mutually exclusive");
+
+const ARCHIVE_KEY: &[u8] = b":archive";
+const CONFIG_KEY: &[u8] = b":config";
+const CACHE_CAPACITY_KEY: &[u8] = b":cache_capacity";
+const VALUE_HINTS_KEY: &[u8] = b":value_hints";
+/// Storage format marker, absent from the storages written before the counters were inlined.
+const FORMAT_KEY: &[u8] = b":format";
+
+/// Format where each node entry starts with its reference counter, a little endian `i32`. The
+/// former format stored the counter in a separate `key || 0xff` entry.
+const INLINE_COUNTERS: u8 = 1;
+
+/// Number of nodes kept by the read cache unless configured otherwise.
+const DEFAULT_CACHE_CAPACITY: u32 = 64;
+
+/// Hash function of the trie nodes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HasherKind {
+    Blake2 = 0,
+    Keccak = 1,
+    Blake3 = 2,
+    Sha256 = 3,
+}
+
+impl HasherKind {
+    /// Hasher used until another one is configured, selected by the build features.
+    #[cfg(not(any(feature = "keccak", feature = "blake3", feature = "sha256")))]
+    const DEFAULT: Self = Self::Blake2;
+    #[cfg(feature = "keccak")]
+    const DEFAULT: Self = Self::Keccak;
+    #[cfg(feature = "blake3")]
+    const DEFAULT: Self = Self::Blake3;
+    #[cfg(feature = "sha256")]
+    const DEFAULT: Self = Self::Sha256;
+}
+
+/// Encoding of the trie nodes. `V1` stores values longer than 32 bytes in separated nodes
+/// referenced by hash while `V0` always inlines them, both are Substrate layouts. `Ethereum`
+/// is the RLP encoding of [`crate::ethereum`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LayoutKind {
+    V0 = 0,
+    V1 = 1,
+    Ethereum = 2,
+}
+
+/// Trie configuration of the storage, read by every call to select the trie layout.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub hasher: HasherKind,
+    pub layout: LayoutKind,
+    pub secure_keys: bool,
+}
+
+impl Config {
+    const DEFAULT: Self =
+        Self { hasher: HasherKind::DEFAULT, layout: LayoutKind::V1, secure_keys: false };
+
+    /// Reads the configuration of the storage, the default one unless set otherwise.
+    pub fn load() -> Self {
+        Self::from_stored(read_bytes::<3>(CONFIG_KEY))
+    }
+
+    /// Decodes the stored configuration, the default one when none is stored.
+    fn from_stored(bytes: Option<[u8; 3]>) -> Self {
+        let Some(bytes) = bytes else {
+            return Self::DEFAULT;
+        };
+        let Some(config) = Self::decode(&bytes) else {
+            abort!("invalid trie configuration: {bytes:?}");
+        };
+        config
+    }
+
+    /// Decodes the hasher, layout and secure keys flag, one byte each.
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let [hasher, layout, secure_keys] = bytes else {
+            return None;
+        };
+        let hasher = match hasher {
+            0 => HasherKind::Blake2,
+            1 => HasherKind::Keccak,
+            2 => HasherKind::Blake3,
+            3 => HasherKind::Sha256,
+            _ => return None,
+        };
+        let layout = match layout {
+            0 => LayoutKind::V0,
+            1 => LayoutKind::V1,
+            2 => LayoutKind::Ethereum,
+            _ => return None,
+        };
+        let secure_keys = match secure_keys {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        Some(Self { hasher, layout, secure_keys })
+    }
+
+    pub fn encode(&self) -> [u8; 3] {
+        [self.hasher as u8, self.layout as u8, u8::from(self.secure_keys)]
+    }
+
+    /// Whether tries written with `self` can be read with `other`. Only the handling of large
+    /// values differs between `V0` and `V1`, the other changes alter the node hashes or keys.
+    fn is_compatible(&self, other: &Self) -> bool {
+        let substrate = |layout| matches!(layout, LayoutKind::V0 | LayoutKind::V1);
+        self.hasher == other.hasher &&
+            self.secure_keys == other.secure_keys &&
+            (self.layout == other.layout || substrate(self.layout) && substrate(other.layout))
+    }
+}
+
+/// Reads a fixed size value stored under one of the reserved keys.
+fn read_bytes<const N: usize>(key: &[u8]) -> Option<[u8; N]> {
+    let mut bytes = [0u8; N];
+    let mut buffer = bytes.as_mut_slice();
+    match HostFnImpl::get_storage(key, &mut buffer) {
+        Ok(()) => {},
+        Err(HostError::KeyNotFound) => return None,
+        Err(error) => abort!("get_bytes: get_storage failed: {error}"),
+    }
+    if buffer.len() != N {
+        abort!("get_bytes: expected {N}, got {}", buffer.len());
+    }
+    Some(bytes)
+}
+
+/// A node touched by the current call, written back to the host storage by
+/// [`ExternalDB::flush`].
+struct PendingNode {
+    /// Reference counter found in the host storage when the node was first touched, `0` when
+    /// the node was taken from the storage.
+    stored: i32,
+    counter: i32,
+    /// Encoded node, read along with the counter when they share the entry, otherwise only
+    /// kept when the node is new to the host storage.
+    value: Option<DBValue>,
+}
+
+pub struct ExternalDB<B: TrieBackend = HostBackend> {
+    backend: B,
+    /// When set nodes are never deleted, so every historical root stays readable.
+    archive: bool,
+    /// Hash and encoding of the empty node of the layout in use, which are never stored.
+    null_node: H256,
+    empty_node: &'static [u8],
+    /// Whether the reference counters are stored in front of the node bytes, see
+    /// [`INLINE_COUNTERS`].
+    inline_counters: bool,
+    /// Prefix of the node keys, empty for the main trie, see [`ExternalDB::child`].
+    keyspace: Vec<u8>,
+    /// Nodes inserted or removed since the last flush, a node updated several times by a trie
+    /// operation costs a single write.
+    pending: RefCell<BTreeMap<H256, PendingNode>>,
+    /// Nodes recently read from the host storage, the upper nodes are read by every lookup.
+    cache: RefCell<NodeCache>,
+    /// Nodes read ahead by [`NodeSource::prefetch`], until they are read.
+    prefetched: RefCell<BTreeMap<H256, DBValue>>,
+}
+
+// SAFETY: the module is single threaded, `HashDB` only requires `Send` and `Sync` for its own
+// users.
+unsafe impl<B: TrieBackend> Send for ExternalDB<B> {}
+unsafe impl<B: TrieBackend> Sync for ExternalDB<B> {}
 
 impl ExternalDB {
-    pub fn get_root_hash(&self) -> H256 {
-        let mut hash = H256::zero();
-        let buffer = &mut hash.as_bytes_mut();
-        let Some(code) = HostFnImpl::get_storage(EMPTY_PTR, buffer) else {
-            match buffer.len() {
-                32 => {},
-                0 => {
-                    hash = HASHED_NULL_NODE;
-                    self.set_root_hash(&hash);
-                },
-                len => abort!("get_root_hash: expected 32, got {len}"),
-            }
-            return hash;
+    /// Opens the storage of the JS host.
+    pub fn load<L: TrieLayout>() -> Self {
+        let db = Self::with_backend::<L>(HostBackend);
+        let capacity = db.cache.borrow().capacity();
+        let cache = HostFnImpl::with_namespace(|namespace| arena::take_cache(namespace, capacity));
+        if let Some(cache) = cache {
+            db.cache.replace(cache);
+        }
+        db
+    }
+}
+
+impl<B: TrieBackend> Drop for ExternalDB<B> {
+    /// Hands the cache of the main trie to the next call, see [`arena::retain_cache`].
+    fn drop(&mut self) {
+        if self.keyspace.is_empty() && arena::is_persistent() {
+            let cache = self.cache.replace(NodeCache::new(0));
+            HostFnImpl::with_namespace(|namespace| arena::retain_cache(namespace, cache));
+        }
+    }
+}
+
+impl<B: TrieBackend> ExternalDB<B> {
+    pub fn with_backend<L: TrieLayout>(backend: B) -> Self {
+        let mut db = Self {
+            backend,
+            archive: false,
+            null_node: hashed_null_node::<L>(),
+            empty_node: L::Codec::empty_node(),
+            inline_counters: true,
+            keyspace: Vec::new(),
+            pending: RefCell::new(BTreeMap::new()),
+            cache: RefCell::new(NodeCache::new(0)),
+            prefetched: RefCell::new(BTreeMap::new()),
         };
-        if code.get() == 3 {
-            hash = HASHED_NULL_NODE;
-            self.set_root_hash(&hash);
+        db.inline_counters = db.load_format();
+        db.archive = db.get_bytes::<1>(ARCHIVE_KEY).is_some_and(|flag| flag[0] != 0);
+        let capacity = db.get_bytes::<4>(CACHE_CAPACITY_KEY).map(u32::from_le_bytes);
+        db.cache =
+            RefCell::new(NodeCache::new(capacity.unwrap_or(DEFAULT_CACHE_CAPACITY) as usize));
+        db
+    }
+
+    /// Opens the child trie whose nodes are stored under `keyspace`, their counters are
+    /// independent of the nodes of the parent trie.
+    pub fn child(&self, keyspace: Vec<u8>) -> ExternalDB<&B> {
+        ExternalDB {
+            backend: &self.backend,
+            archive: self.archive,
+            null_node: self.null_node,
+            empty_node: self.empty_node,
+            inline_counters: self.inline_counters,
+            keyspace,
+            pending: RefCell::new(BTreeMap::new()),
+            cache: RefCell::new(NodeCache::new(self.cache.borrow().capacity())),
+            prefetched: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Storage key of a node, prefixed with the keyspace of child tries.
+    fn node_key<'a>(&self, key: &'a H256) -> Cow<'a, [u8]> {
+        if self.keyspace.is_empty() {
+            return Cow::Borrowed(key.as_bytes());
+        }
+        Cow::Owned([&self.keyspace[..], key.as_bytes()].concat())
+    }
+
+    /// Appends the storage key of the counter of a node in the former format to `buffer`.
+    fn counter_key(&self, key: &H256, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.node_key(key));
+        buffer.push(0xff);
+    }
+
+    /// Tells whether the storage keeps the counters inline. A storage without format marker
+    /// starts in the inline format, unless it already holds nodes written in the former one.
+    fn load_format(&self) -> bool {
+        match self.get_bytes::<1>(FORMAT_KEY) {
+            Some([INLINE_COUNTERS]) => true,
+            Some([format]) => abort!("unknown storage format {format}"),
+            None => {
+                if self.get_root_hash() != self.null_node || history::len(self) > 0 {
+                    return false;
+                }
+                self.set_bytes(FORMAT_KEY, &[INLINE_COUNTERS]);
+                true
+            },
+        }
+    }
+
+    /// Rewrites every node stored in the former format with its counter inline, returns the
+    /// number of nodes rewritten. The nodes are found from the current root and the recorded
+    /// roots, which include the roots of the checkpoints, roots already deleted are skipped.
+    pub fn migrate_format<L: TrieLayout>(&mut self, root: &H256) -> u32 {
+        if self.inline_counters {
+            return 0;
+        }
+        self.flush();
+        let history = history::list(self);
+        let roots = core::iter::once(*root).chain(history.chunks_exact(32).map(H256::from_slice));
+        let mut nodes = BTreeSet::new();
+        for root in roots {
+            if self.counter(&root) > 0 {
+                trie::for_each_node::<L, _>(self, &root, |node| {
+                    nodes.insert(*node);
+                });
+            }
+        }
+        for key in nodes.iter() {
+            let counter = self.get_storage_counter(key);
+            let node_key = self.node_key(key);
+            let Some(value) = self.backend.get(&node_key) else {
+                abort!(key = key.as_bytes(), "migrate_format: missing node {key:?}");
+            };
+            self.backend.set(&node_key, &encode_entry(counter, &value));
+            self.backend.record(&node_key, Some(value));
+            self.set_storage_counter(key, counter, 0);
+        }
+        self.inline_counters = true;
+        self.set_bytes(FORMAT_KEY, &[INLINE_COUNTERS]);
+        nodes.len() as u32
+    }
+
+    /// Stores the configuration used by the following calls. Switching between `V0` and `V1`
+    /// keeps the trie, nodes already stored keep their encoding until they are rewritten. Any
+    /// other change requires an empty trie, whose root is cleared so the next call starts from
+    /// the empty root of the new layout.
+    pub fn set_config(&self, root: &H256, config: Config) {
+        let current = self.config();
+        if !current.is_compatible(&config) {
+            if root != &self.null_node {
+                abort!(
+                    "the trie must be empty to change the hasher, the node encoding or the keys"
+                );
+            }
+            self.clear(EMPTY_PTR);
+        }
+        self.set_bytes(CONFIG_KEY, &config.encode());
+    }
+
+    /// Reads the configuration of the storage, see [`Config::load`].
+    pub fn config(&self) -> Config {
+        Config::from_stored(self.get_bytes(CONFIG_KEY))
+    }
+
+    /// Sets the number of nodes kept by the read cache of the following calls, `0` disables it.
+    pub fn set_cache_capacity(&self, capacity: u32) {
+        self.set_bytes(CACHE_CAPACITY_KEY, &capacity.to_le_bytes());
+    }
+
+    /// Stores the value hints of the listings, encoded as read by [`ValueHints::decode`].
+    pub fn set_value_hints(&self, hints: &[u8]) -> CallResult<()> {
+        // Fails before storing hints the listings can't read.
+        ValueHints::decode(hints)?;
+        if hints.is_empty() {
+            self.clear(VALUE_HINTS_KEY);
         } else {
-            abort!("get_root_hash: get_storage failed with code: {}", code.get());
+            self.set_bytes(VALUE_HINTS_KEY, hints);
         }
-        hash
+        Ok(())
     }
 
-    pub fn set_root_hash(&self, hash: &H256) {
-        if let Some(error) = HostFnImpl::set_storage(EMPTY_PTR, hash.as_bytes()) {
-            abort!("set_root_hash: set_storage failed with code {}", error.get());
+    pub fn value_hints(&self) -> ValueHints {
+        self.backend
+            .get(VALUE_HINTS_KEY)
+            .and_then(|hints| ValueHints::decode(&hints).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn set_archive(&mut self, archive: bool) {
+        if archive {
+            self.set_bytes(ARCHIVE_KEY, &[1]);
+        } else {
+            self.clear(ARCHIVE_KEY);
+        }
+        self.archive = archive;
+    }
+
+    /// Writes the pending nodes and sets the root hash, which is appended to the root history.
+    pub fn commit_root(&self, hash: &H256) {
+        self.flush();
+        self.set_root_hash(hash);
+        history::record(self, hash);
+    }
+
+    pub fn get_root_hash(&self) -> H256 {
+        match self.backend.get(EMPTY_PTR) {
+            Some(bytes) if bytes.len() == 32 => H256::from_slice(&bytes),
+            Some(bytes) if !bytes.is_empty() => {
+                abort!("get_root_hash: expected 32, got {}", bytes.len())
+            },
+            _ => {
+                let hash = self.null_node;
+                self.set_root_hash(&hash);
+                hash
+            },
         }
     }
 
+    pub fn set_root_hash(&self, hash: &H256) {
+        self.backend.write(EMPTY_PTR, Some(hash.as_bytes()));
+    }
+
+    /// Reads a fixed size value stored under one of the reserved keys.
+    pub fn get_bytes<const N: usize>(&self, key: &[u8]) -> Option<[u8; N]> {
+        let bytes = self.backend.get(key)?;
+        let Ok(bytes) = <[u8; N]>::try_from(bytes.as_slice()) else {
+            abort!("get_bytes: expected {N}, got {}", bytes.len());
+        };
+        Some(bytes)
+    }
+
+    pub fn set_bytes(&self, key: &[u8], value: &[u8]) {
+        self.backend.write(key, Some(value));
+    }
+
+    /// Reads a value of any size stored under one of the reserved keys.
+    pub fn get_value(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.backend.get(key)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.backend.contains(key)
+    }
+
+    pub fn get_hash(&self, key: &[u8]) -> Option<H256> {
+        self.get_bytes::<32>(key).map(H256)
+    }
+
+    pub fn set_hash(&self, key: &[u8], hash: &H256) {
+        self.set_bytes(key, hash.as_bytes());
+    }
+
+    pub fn clear(&self, key: &[u8]) {
+        self.backend.write(key, None);
+    }
+
+    /// Increments the reference counter of a node already present in the storage, so it
+    /// survives the removal of one of its owners.
+    pub fn retain(&self, key: &H256) {
+        self.with_pending(key, |node| {
+            if node.counter <= 0 {
+                abort!(key = key.as_bytes(), "retain: node {key:?} not found");
+            }
+            node.counter += 1;
+        });
+    }
+
     pub fn internal_emplace(&self, key: &H256, value: &[u8]) {
-        let counter = self.get_storage_counter(key) + 1;
-        if counter == 1 {
-            if let Some(error) = HostFnImpl::set_storage(key.as_bytes(), value) {
-                abort!("internal_emplace: set_storage failed with code {}", error.get());
+        self.with_pending(key, |node| {
+            if node.stored == 0 && node.value.is_none() {
+                node.value = Some(value.to_vec());
+            }
+            node.counter += 1;
+        });
+    }
+
+    /// Calls `f` with the pending state of the node `key`, read from the host storage the first
+    /// time the node is touched.
+    /// Reads a node, the ones released since the last flush are only read when `released` is
+    /// set: their entries are still in the host storage, or their bytes kept while pending.
+    fn read_node(&self, key: &H256, released: bool) -> Option<DBValue> {
+        if key == &self.null_node {
+            return Some(self.empty_node.to_vec());
+        }
+        if let Some(node) = self.pending.borrow().get(key) {
+            if node.counter <= 0 && !released {
+                return None;
+            }
+            if let Some(value) = node.value.as_ref() {
+                return Some(value.clone());
             }
         }
-        self.set_storage_counter(key, counter);
+        if let Some(value) = self.cache.borrow_mut().get(key) {
+            return Some(value);
+        }
+        if let Some(value) = self.prefetched.borrow_mut().remove(key) {
+            self.cache.borrow_mut().insert(*key, value.clone());
+            return Some(value);
+        }
+        let value = if self.inline_counters {
+            self.read_entry(key)?.1
+        } else {
+            self.backend.get(&self.node_key(key))?
+        };
+        self.cache.borrow_mut().insert(*key, value.clone());
+        Some(value)
     }
 
-    pub fn get_storage_counter(&self, key: &H256) -> i32 {
-        let mut bytes = [0u8; 4];
-        let mut counter_key = [0u8; 33];
-        counter_key[32] = 0xff;
-        counter_key[0..32].copy_from_slice(&key.0);
-        {
-            let mut buffer = bytes.as_mut_slice();
-            if let Some(error) = HostFnImpl::get_storage(&counter_key[..], &mut buffer) {
-                if error.get() == 3 {
-                    return 0;
-                }
-                abort!("get_storage_counter: get_storage failed with code {}", error.get());
+    fn with_pending<R>(&self, key: &H256, f: impl FnOnce(&mut PendingNode) -> R) -> R {
+        let mut pending = self.pending.borrow_mut();
+        let node = pending.entry(*key).or_insert_with(|| {
+            if !self.inline_counters {
+                let stored = self.get_storage_counter(key);
+                return PendingNode { stored, counter: stored, value: None };
+            }
+            match self.read_entry(key) {
+                Some((stored, value)) => {
+                    PendingNode { stored, counter: stored, value: Some(value) }
+                },
+                None => PendingNode { stored: 0, counter: 0, value: None },
             }
-            // let res = HostFn::get_storage(key.as_ptr(), 16, bytes.as_mut_ptr(), &mut len);
-            if buffer.is_empty() {
-                return 0;
+        });
+        f(node)
+    }
+
+    /// Reference counter of a node, `0` when it isn't stored.
+    pub fn counter(&self, key: &H256) -> i32 {
+        match self.pending.borrow().get(key) {
+            Some(node) => node.counter,
+            None => self.get_storage_counter(key),
+        }
+    }
+
+    /// Byte length of a stored node, without reading it from the host storage.
+    pub fn node_size(&self, key: &H256) -> Option<u32> {
+        if key == &self.null_node {
+            return Some(self.empty_node.len() as u32);
+        }
+        if let Some(node) = self.pending.borrow().get(key) {
+            if node.counter <= 0 {
+                return None;
             }
-            if buffer.len() != 4 {
-                abort!("get_storage_counter: len != 4");
+            if let Some(value) = node.value.as_ref() {
+                return Some(value.len() as u32);
             }
         }
-        i32::from_ne_bytes(bytes)
+        let size = self.backend.value_size(&self.node_key(key))?;
+        if self.inline_counters {
+            return Some(size.saturating_sub(4));
+        }
+        Some(size)
     }
 
-    fn set_storage_counter(&self, key: &H256, counter: i32) {
-        let mut counter_key = [0u8; 33];
-        counter_key[32] = 0xff;
-        counter_key[0..32].copy_from_slice(&key.0);
-        if counter == 0 {
-            if let Some(error) = HostFnImpl::clear_storage(&counter_key[..]) {
-                abort!("set_storage_counter: clear_storage failed with code {}", error.get());
+    /// Writes the nodes inserted or removed since the last flush to the host storage, nodes
+    /// whose counter ends unchanged are skipped.
+    ///
+    /// Each write is journaled with the entry it replaces, known from the first time the node
+    /// was touched, so the storage isn't read again. Only the nodes cleared in the former
+    /// format are read first.
+    pub fn flush(&self) {
+        let pending = core::mem::take(&mut *self.pending.borrow_mut());
+        for (key, node) in pending {
+            if node.counter == node.stored {
+                continue;
             }
-        } else {
-            let bytes = counter.to_ne_bytes();
-            if let Some(error) = HostFnImpl::set_storage(&counter_key[..], bytes.as_slice()) {
-                abort!("set_storage_counter: set_storage failed with code {}", error.get());
+            if node.counter == 0 {
+                self.cache.borrow_mut().remove(&key);
+            }
+            let node_key = self.node_key(&key);
+            if self.inline_counters {
+                // A node taken from the storage has no entry left, the take recorded it.
+                let previous = node.value.as_ref().filter(|_| node.stored != 0);
+                let previous = previous.map(|value| encode_entry(node.stored, value).to_vec());
+                self.backend.record(&node_key, previous);
+                match node.value {
+                    _ if node.counter == 0 => self.backend.clear(&node_key),
+                    Some(value) => self.backend.set(&node_key, &encode_entry(node.counter, &value)),
+                    None => {
+                        abort!(
+                            key = key.as_bytes(),
+                            "[bug] flush: missing value of the node {key:?}"
+                        )
+                    },
+                }
+                continue;
+            }
+            if node.counter == 0 {
+                self.backend.write(&node_key, None);
+            } else if node.stored == 0 {
+                let Some(value) = node.value else {
+                    abort!(
+                        key = key.as_bytes(),
+                        "[bug] flush: missing value of the new node {key:?}"
+                    );
+                };
+                self.backend.record(&node_key, None);
+                self.backend.set(&node_key, &value);
             }
+            self.set_storage_counter(&key, node.stored, node.counter);
+        }
+    }
+
+    /// Reads the counter and the bytes of a node stored in the inline format.
+    fn read_entry(&self, key: &H256) -> Option<(i32, DBValue)> {
+        let mut entry = scratch::bytes();
+        if !self.backend.get_into(&self.node_key(key), &mut entry) {
+            return None;
+        }
+        Some((entry_counter(key, &entry), entry[4..].to_vec()))
+    }
+
+    /// Same as [`Self::read_entry`], also clearing the entry.
+    fn take_entry(&self, key: &H256) -> Option<(i32, DBValue)> {
+        let entry = self.backend.take(&self.node_key(key))?;
+        Some(split_entry(key, entry))
+    }
+
+    /// Reads the counter of a node, from its own entry in the former format.
+    fn get_storage_counter(&self, key: &H256) -> i32 {
+        let mut value = scratch::bytes();
+        if self.inline_counters {
+            let found = self.backend.get_into(&self.node_key(key), &mut value);
+            return if found { entry_counter(key, &value) } else { 0 };
+        }
+        let mut counter_key = scratch::bytes();
+        self.counter_key(key, &mut counter_key);
+        match self.backend.get_into(&counter_key, &mut value) {
+            true => decode_counter(&value),
+            false => 0,
+        }
+    }
+
+    /// Writes the counter of a node in the former format, replacing the `stored` one.
+    fn set_storage_counter(&self, key: &H256, stored: i32, counter: i32) {
+        let mut counter_key = scratch::bytes();
+        self.counter_key(key, &mut counter_key);
+        let previous = (stored != 0).then(|| stored.to_ne_bytes().to_vec());
+        self.backend.record(&counter_key, previous);
+        if counter == 0 {
+            self.backend.clear(&counter_key);
+        } else {
+            self.backend.set(&counter_key, &counter.to_ne_bytes());
         }
     }
 }
 
-impl AsHashDB<Blake2Hasher, trie_db::DBValue> for ExternalDB {
-    fn as_hash_db(&self) -> &dyn HashDB<Blake2Hasher, trie_db::DBValue> {
+impl<H: Hasher<Out = H256>, B: TrieBackend> AsHashDB<H, trie_db::DBValue> for ExternalDB<B> {
+    fn as_hash_db(&self) -> &dyn HashDB<H, trie_db::DBValue> {
         self
     }
 
-    fn as_hash_db_mut<'a>(
-        &'a mut self,
-    ) -> &'a mut (dyn HashDB<Blake2Hasher, trie_db::DBValue> + 'a) {
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, trie_db::DBValue> + 'a) {
         self
     }
 }
 
-// const MAX_VALUE_SIZE: usize = 8192;
-const MAX_VALUE_SIZE: usize = 4096;
-impl HashDB<Blake2Hasher, DBValue> for ExternalDB {
+impl<H: Hasher<Out = H256>, B: TrieBackend> HashDB<H, DBValue> for ExternalDB<B> {
     fn get(&self, key: &H256, _prefix: hash_db::Prefix) -> Option<DBValue> {
-        if key == &HASHED_NULL_NODE {
-            return Some([0].to_vec());
-        }
-        let mut buffer = [0u8; MAX_VALUE_SIZE];
-        let mut buffer_slice = &mut buffer[..];
-        if let Some(error) = HostFnImpl::get_storage(key.as_bytes(), &mut buffer_slice) {
-            if error.get() == 3 {
-                return None;
-            }
-            abort!("[get_storage]: failed with code {}", error.get());
-        }
-        if buffer_slice.len() >= MAX_VALUE_SIZE {
-            abort!("[get_storage]: buffer overflow: {} < {}", MAX_VALUE_SIZE, buffer_slice.len());
-        }
-        Some(Vec::from(buffer_slice))
+        self.read_node(key, false)
     }
 
     fn contains(&self, key: &H256, _prefix: hash_db::Prefix) -> bool {
-        if key == &HASHED_NULL_NODE {
+        if key == &self.null_node {
             return true;
         }
-        self.get_storage_counter(key) > 0
+        if let Some(node) = self.pending.borrow().get(key) {
+            return node.counter > 0;
+        }
+        // Entries are cleared once their counter drops to zero, a stored node is referenced.
+        self.cache.borrow().contains(key) || self.backend.contains(&self.node_key(key))
     }
 
     fn insert(&mut self, _prefix: hash_db::Prefix, value: &[u8]) -> H256 {
-        if value.is_empty() || matches!(value, &[0]) {
-            return HASHED_NULL_NODE;
+        if value.is_empty() || value == self.empty_node {
+            return self.null_node;
         }
 
-        let key = <Blake2Hasher as Hasher>::hash(value);
+        let key = H::hash(value);
         self.internal_emplace(&key, value);
         key
     }
 
     fn emplace(&mut self, key: H256, _prefix: hash_db::Prefix, value: DBValue) {
-        if value.is_empty() || key == HASHED_NULL_NODE {
+        if value.is_empty() || key == self.null_node {
             return;
         }
         self.internal_emplace(&key, value.as_ref());
     }
 
     fn remove(&mut self, key: &H256, _prefix: hash_db::Prefix) {
-        if key == &HASHED_NULL_NODE {
+        if key == &self.null_node || self.archive {
+            return;
+        }
+        // A node removed before being touched is taken from the storage in one call, most
+        // counters drop to zero, otherwise the flush writes the entry back.
+        if self.inline_counters && !self.pending.borrow().contains_key(key) {
+            if let Some((stored, value)) = self.take_entry(key) {
+                let counter = (stored - 1).max(0);
+                if counter == 0 {
+                    self.cache.borrow_mut().remove(key);
+                }
+                let node = PendingNode { stored: 0, counter, value: Some(value) };
+                self.pending.borrow_mut().insert(*key, node);
+                return;
+            }
+        }
+        self.with_pending(key, |node| {
+            if node.counter > 0 {
+                node.counter -= 1;
+            }
+        });
+    }
+}
+
+impl<H: Hasher<Out = H256>, B: TrieBackend> trie::NodeSource<H> for ExternalDB<B> {
+    fn prefetch(&self, keys: &[H256]) {
+        let keys = {
+            let pending = self.pending.borrow();
+            let cache = self.cache.borrow();
+            let prefetched = self.prefetched.borrow();
+            keys.iter()
+                .filter(|key| **key != self.null_node)
+                .filter(|key| !pending.contains_key(key) && !cache.contains(key))
+                .filter(|key| !prefetched.contains_key(key))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        if keys.is_empty() {
             return;
         }
-        let counter = self.get_storage_counter(key);
-        if counter == 1 {
-            if let Some(error) = HostFnImpl::clear_storage(key.as_bytes()) {
-                abort!("remove: clear_storage failed with code {}", error.get());
+        let node_keys = keys.iter().map(|key| self.node_key(key)).collect::<Vec<_>>();
+        let node_keys = node_keys.iter().map(|key| key.as_ref()).collect::<Vec<_>>();
+        let values = self.backend.get_batch(&node_keys);
+        let mut prefetched = self.prefetched.borrow_mut();
+        for (key, value) in keys.iter().zip(values) {
+            let Some(mut value) = value else {
+                continue;
+            };
+            if self.inline_counters {
+                if value.len() < 4 {
+                    abort!(
+                        key = key.as_bytes(),
+                        "prefetch: entry of {key:?} shorter than its counter"
+                    );
+                }
+                value.drain(..4);
+            }
+            prefetched.insert(*key, value);
+        }
+    }
+
+    fn counters(&self, keys: &[H256]) -> Vec<i32> {
+        let mut counters = vec![0; keys.len()];
+        let mut stored = Vec::new();
+        {
+            let pending = self.pending.borrow();
+            for (index, key) in keys.iter().enumerate() {
+                match pending.get(key) {
+                    Some(node) => counters[index] = node.counter,
+                    None if *key != self.null_node => stored.push(index),
+                    None => {},
+                }
             }
         }
-        if counter > 0 {
-            self.set_storage_counter(key, counter - 1);
+        // The storage keys are written one after the other in a single buffer.
+        let mut buffer = scratch::bytes();
+        let mut ends = Vec::with_capacity(stored.len());
+        for &index in stored.iter() {
+            match self.inline_counters {
+                true => buffer.extend_from_slice(&self.node_key(&keys[index])),
+                false => self.counter_key(&keys[index], &mut buffer),
+            }
+            ends.push(buffer.len());
+        }
+        let starts = core::iter::once(0).chain(ends.iter().copied());
+        let storage_keys = starts.zip(&ends).map(|(start, &end)| &buffer[start..end]);
+        let values = self.backend.get_batch(&storage_keys.collect::<Vec<_>>());
+        for (index, value) in stored.into_iter().zip(values) {
+            let Some(value) = value else {
+                continue;
+            };
+            counters[index] = match self.inline_counters {
+                true => entry_counter(&keys[index], &value),
+                false => decode_counter(&value),
+            };
+        }
+        counters
+    }
+}
+
+/// Decodes a counter stored in its own entry, by the former format.
+fn decode_counter(bytes: &[u8]) -> i32 {
+    if bytes.is_empty() {
+        return 0;
+    }
+    let Ok(bytes) = <[u8; 4]>::try_from(bytes) else {
+        abort!("get_storage_counter: len != 4");
+    };
+    i32::from_ne_bytes(bytes)
+}
+
+/// Splits an entry of the inline format into the counter and the node bytes.
+fn split_entry(key: &H256, mut entry: Vec<u8>) -> (i32, DBValue) {
+    let counter = entry_counter(key, &entry);
+    entry.drain(..4);
+    (counter, entry)
+}
+
+/// Entry of the inline format, the counter in front of the node bytes.
+fn encode_entry(counter: i32, value: &[u8]) -> scratch::Scratch<u8> {
+    let mut entry = scratch::bytes();
+    entry.extend_from_slice(&counter.to_le_bytes());
+    entry.extend_from_slice(value);
+    entry
+}
+
+/// Counter of an entry of the inline format, in front of the node bytes.
+fn entry_counter(key: &H256, entry: &[u8]) -> i32 {
+    let Some(counter) = entry.first_chunk::<4>() else {
+        abort!(key = key.as_bytes(), "read_entry: entry of {key:?} shorter than its counter");
+    };
+    i32::from_le_bytes(*counter)
+}
+
+/// Buffers every write in memory while reading through to the host storage, used to compute
+/// the resulting root of a set of operations without persisting anything.
+pub struct OverlayDB<'a, H: Hasher<Out = H256>> {
+    backend: &'a ExternalDB,
+    overlay: MemoryDB<H>,
+}
+
+impl<'a, H: Hasher<Out = H256>> OverlayDB<'a, H> {
+    pub fn new(backend: &'a ExternalDB) -> Self {
+        Self { backend, overlay: MemoryDB::new(backend.empty_node) }
+    }
+}
+
+impl<H: Hasher<Out = H256>> AsHashDB<H, trie_db::DBValue> for OverlayDB<'_, H> {
+    fn as_hash_db(&self) -> &dyn HashDB<H, trie_db::DBValue> {
+        self
+    }
+
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, trie_db::DBValue> + 'a) {
+        self
+    }
+}
+
+impl<H: Hasher<Out = H256>> HashDB<H, DBValue> for OverlayDB<'_, H> {
+    fn get(&self, key: &H256, prefix: hash_db::Prefix) -> Option<DBValue> {
+        if let Some(value) = self.overlay.get(key, prefix) {
+            return Some(value);
+        }
+        HashDB::<H, _>::get(self.backend, key, prefix)
+    }
+
+    fn contains(&self, key: &H256, prefix: hash_db::Prefix) -> bool {
+        self.overlay.contains(key, prefix) || HashDB::<H, _>::contains(self.backend, key, prefix)
+    }
+
+    fn insert(&mut self, prefix: hash_db::Prefix, value: &[u8]) -> H256 {
+        if value.is_empty() || value == self.backend.empty_node {
+            return self.backend.null_node;
+        }
+        self.overlay.insert(prefix, value)
+    }
+
+    fn emplace(&mut self, key: H256, prefix: hash_db::Prefix, value: DBValue) {
+        if value.is_empty() || key == self.backend.null_node {
+            return;
         }
+        self.overlay.emplace(key, prefix, value)
+    }
+
+    fn remove(&mut self, key: &H256, prefix: hash_db::Prefix) {
+        if key == &self.backend.null_node {
+            return;
+        }
+        self.overlay.remove(key, prefix)
+    }
+}
+
+/// Nodes only written to the overlay aren't stored yet, their counter reads `0`.
+impl<H: Hasher<Out = H256>> trie::NodeSource<H> for OverlayDB<'_, H> {
+    fn prefetch(&self, keys: &[H256]) {
+        trie::NodeSource::<H>::prefetch(self.backend, keys)
+    }
+
+    fn counters(&self, keys: &[H256]) -> Vec<i32> {
+        trie::NodeSource::<H>::counters(self.backend, keys)
+    }
+}
+
+/// Read-only view of an [`ExternalDB`] still reading the nodes released since the last flush,
+/// so the trie a call moved from can be compared with the new one before the flush deletes
+/// them.
+struct Released<'a, B: TrieBackend>(&'a ExternalDB<B>);
+
+impl<H: Hasher<Out = H256>, B: TrieBackend> AsHashDB<H, trie_db::DBValue> for Released<'_, B> {
+    fn as_hash_db(&self) -> &dyn HashDB<H, trie_db::DBValue> {
+        self
+    }
+
+    fn as_hash_db_mut<'a>(&'a mut self) -> &'a mut (dyn HashDB<H, trie_db::DBValue> + 'a) {
+        self
+    }
+}
+
+impl<H: Hasher<Out = H256>, B: TrieBackend> HashDB<H, DBValue> for Released<'_, B> {
+    fn get(&self, key: &H256, _prefix: hash_db::Prefix) -> Option<DBValue> {
+        self.0.read_node(key, true)
+    }
+
+    fn contains(&self, key: &H256, _prefix: hash_db::Prefix) -> bool {
+        self.0.read_node(key, true).is_some()
+    }
+
+    fn insert(&mut self, _prefix: hash_db::Prefix, _value: &[u8]) -> H256 {
+        abort!("[bug] Released: the view is read-only")
+    }
+
+    fn emplace(&mut self, _key: H256, _prefix: hash_db::Prefix, _value: DBValue) {
+        abort!("[bug] Released: the view is read-only")
+    }
+
+    fn remove(&mut self, _key: &H256, _prefix: hash_db::Prefix) {
+        abort!("[bug] Released: the view is read-only")
     }
 }
 
@@ -184,56 +984,859 @@ fn __load_input(buffer: &'static mut [u8], _len: &mut u32) -> &'static mut [u8]
     buffer
 }
 
+/// Copies the call input from the host into a heap buffer. The input starts with a header
+/// selecting the trie of the call: its handle as `u32`, see [`tries`], or on hosts older than
+/// ABI 10 the namespace length as `u8` followed by the namespace itself. The rest of the input
+/// is returned.
+pub fn try_load_input(input_len: u32) -> CallResult<&'static mut [u8]> {
+    // Fails early when the host skipped the handshake, rather than on a missing import.
+    let version = HostFnImpl::version();
+    let input = crate::Allocator::pre_allocate_buffer(input_len, __load_input);
+    if version >= 10 {
+        let Some((handle, input)) = input.split_first_chunk_mut::<4>() else {
+            return fail!(CallError::InvalidInput, "missing trie handle");
+        };
+        tries::select(u32::from_le_bytes(*handle))?;
+        return Ok(input);
+    }
+    let Some((&mut len, input)) = input.split_first_mut() else {
+        return fail!(CallError::InvalidInput, "missing namespace header");
+    };
+    let Some((namespace, input)) = input.split_at_mut_checked(usize::from(len)) else {
+        return fail!(CallError::InvalidInput, "namespace out of bounds");
+    };
+    HostFnImpl::set_namespace(namespace);
+    Ok(input)
+}
+
+/// Same as [`try_load_input`], for the exports aborting on invalid input.
+pub fn load_input(input_len: u32) -> &'static mut [u8] {
+    match try_load_input(input_len) {
+        Ok(input) => input,
+        Err(_) => crate::__abort(),
+    }
+}
+
 #[export_name = "__ext_call"]
 pub unsafe extern "C" fn call(code: u32, input_len: u32) -> u64 {
-    use crate::bump_allocator::BumpAllocator;
-    // Reset Heap Memory
-    BumpAllocator::reset();
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
+
+    // Reading the host call counters isn't counted, the call reads no input and the counters
+    // of its output are dropped.
+    if code == 38 {
+        output(crate::metrics::take());
+        crate::metrics::take();
+        return 0;
+    }
+
+    // Reading the allocation profile, taken before the output is allocated.
+    #[cfg(all(feature = "alloc-profile", not(feature = "dlmalloc")))]
+    if code == 39 {
+        return output(crate::profile::take().to_vec());
+    }
 
     crate::debug_log!("__ext_call({code}, {input_len})");
 
     // Read input
-    let input = BumpAllocator::pre_allocate_buffer(input_len, __load_input);
+    let result = try_load_input(input_len)
+        .and_then(|input| crate::with_layout!(L => dispatch::<L>(code, input)))
+        .and_then(|ret| error::take_deferred().map_or(Ok(ret), Err));
+
+    // Failed calls leave the storage untouched, the instance stays usable.
+    match result {
+        Ok(ret) => ret,
+        Err(error) => {
+            // Older hosts don't decode the errors, the call traps instead.
+            if HostFnImpl::version() < 6 {
+                crate::__abort();
+            }
+            crate::journal::rollback();
+            arena::discard_cache();
+            error.encode()
+        },
+    }
+}
+
+/// Commits `root` once a call moved the trie from `previous`, then hands the keys it changed to
+/// the watches, the op log and the value index, every root-changing call goes through it. The
+/// changes are read before the flush, which deletes the released nodes of the former trie.
+fn commit<L: TrieLayout, B: TrieBackend>(
+    db: &ExternalDB<B>,
+    previous: &H256,
+    root: &H256,
+) -> CallResult<()> {
+    let changes = if previous == root {
+        Vec::new()
+    } else {
+        diff::changes::<L>(&Released(db), previous, root)?
+    };
+    db.commit_root(root);
+    watch::notify::<L, _>(db, &changes, root);
+    oplog::record::<L::Hash, _>(db, &changes, root);
+    value_index::update(db, &changes);
+    Ok(())
+}
 
+/// Processes a call using the trie layout `L`. Results with [`error::ERROR_FLAG`] set are
+/// reserved for the errors.
+pub(crate) fn dispatch<L: TrieLayout>(code: u32, input: &[u8]) -> CallResult<u64> {
     // Load trie root
-    let mut db = ExternalDB;
+    let mut db = ExternalDB::load::<L>();
     let mut root = db.get_root_hash();
-    let trie_db = TrieDBMutBuilder::<Layout>::from_existing(&mut db, &mut root).build();
-    let mut trie = MerklePatriciaTrie::new(trie_db);
+    let previous = root;
+
+    // Queries read the trie at `root` as is, without opening it for writes.
+    match code {
+        2 => {
+            return Ok(u64::from(trie::contains_key::<L>(&db, &root, input)?));
+        },
+        3 => {
+            if let Some(value) = trie::read_value::<L>(&db, &root, input)? {
+                return Ok(output(value));
+            }
+            return Ok(0);
+        },
+        4 => {
+            return Ok(output(root.as_bytes().to_vec()));
+        },
+        6 => {
+            checkpoint::create::<L, _>(&mut db, &root, input);
+            db.flush();
+            return Ok(0);
+        },
+        7 => {
+            let root = checkpoint::revert::<L, _>(&mut db, &root, input)?;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(0);
+        },
+        8 => {
+            checkpoint::remove::<L, _>(&mut db, input)?;
+            db.flush();
+            return Ok(0);
+        },
+        9 => {
+            return Ok(output(history::list(&db)));
+        },
+        10 => {
+            let (root, key) = split_hash(input)?;
+            if let Some(value) = trie::read_value::<L>(&db, &root, key)? {
+                return Ok(output(value));
+            }
+            return Ok(0);
+        },
+        11 => {
+            let archive = match input {
+                [0] => false,
+                [1] => true,
+                _ => return fail!(CallError::InvalidInput, "invalid archive mode"),
+            };
+            db.set_archive(archive);
+            return Ok(0);
+        },
+        12 => {
+            let (old, input) = split_hash(input)?;
+            let (new, _) = split_hash(input)?;
+            return Ok(output(diff::diff::<L>(&db, &old, &new)?));
+        },
+        14 => {
+            let (at, keys) = split_hash(input)?;
+            let proof = proof::generate::<L>(&db, &at, keys)?;
+            crate::debug_log!(Debug, Proof, "generate: {} bytes at {at:?}", proof.len());
+            return Ok(output(proof));
+        },
+        15 => {
+            return Ok(u64::from(proof::verify::<L>(input)?));
+        },
+        16 => {
+            let (at, keys) = split_hash(input)?;
+            let proof = proof::generate_compact::<L>(&db, &at, keys)?;
+            crate::debug_log!(Debug, Proof, "generate_compact: {} bytes at {at:?}", proof.len());
+            return Ok(output(proof));
+        },
+        17 => {
+            return Ok(u64::from(proof::verify_compact::<L>(input)?));
+        },
+        18 => {
+            return Ok(output(proof::trace_get::<L>(&db, &root, input)?));
+        },
+        19 => {
+            return Ok(output(proof::to_compact::<L>(input)?));
+        },
+        20 => {
+            return Ok(output(proof::from_compact::<L>(input)?));
+        },
+        21 => {
+            return Ok(output(proof::generate_range::<L>(&db, input)?));
+        },
+        22 => {
+            return Ok(output(proof::verify_range::<L>(input)?));
+        },
+        23 => {
+            let layout = match input {
+                [0] => LayoutKind::V0,
+                [1] => LayoutKind::V1,
+                _ => return fail!(CallError::InvalidInput, "invalid state version"),
+            };
+            db.set_config(&root, Config { layout, ..Config::load() });
+            return Ok(0);
+        },
+        24 => {
+            return Ok(Config::load().layout as u64);
+        },
+        25 => {
+            let layout = match input {
+                [0] => LayoutKind::V1,
+                [1] => LayoutKind::Ethereum,
+                _ => return fail!(CallError::InvalidInput, "invalid trie layout"),
+            };
+            db.set_config(&root, Config { layout, ..Config::load() });
+            return Ok(0);
+        },
+        26 => {
+            let Some(config) = Config::decode(input) else {
+                return fail!(CallError::InvalidInput, "invalid trie configuration");
+            };
+            db.set_config(&root, config);
+            return Ok(0);
+        },
+        27 => {
+            let [hasher, layout, secure_keys] = Config::load().encode();
+            return Ok(u64::from_le_bytes([hasher, layout, secure_keys, 0, 0, 0, 0, 0]));
+        },
+        28 => {
+            let config = Config::load();
+            if config.layout != LayoutKind::V0 {
+                return fail!(
+                    CallError::InvalidInput,
+                    "only tries using state version V0 can be migrated"
+                );
+            }
+            let changed = migration::migrate::<L::Hash>(&mut db, &mut root)?;
+            db.commit_root(&root);
+            db.set_config(&root, Config { layout: LayoutKind::V1, ..config });
+            return Ok(u64::from(changed));
+        },
+        29 => {
+            let Ok(capacity) = <[u8; 4]>::try_from(input) else {
+                return fail!(CallError::InvalidInput, "invalid input");
+            };
+            db.set_cache_capacity(u32::from_le_bytes(capacity));
+            return Ok(0);
+        },
+        30 => {
+            return Ok(u64::from(db.migrate_format::<L>(&root)));
+        },
+        31 => {
+            return Ok(output(trie::stats::<L>(&db, &root, |node| db.counter(node)).encode()));
+        },
+        32 => {
+            let (storage_key, input) = split_bytes(input)?;
+            let (key, input) = split_bytes(input)?;
+            let (value, _) = split_bytes(input)?;
+            child::insert::<L, _>(&mut db, &mut root, storage_key, key, value)?;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(0);
+        },
+        33 => {
+            let (storage_key, key) = split_bytes(input)?;
+            if let Some(value) = child::get::<L, _>(&db, &root, storage_key, key)? {
+                return Ok(output(value));
+            }
+            return Ok(0);
+        },
+        34 => {
+            let (storage_key, key) = split_bytes(input)?;
+            child::remove::<L, _>(&mut db, &mut root, storage_key, key)?;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(0);
+        },
+        35 => {
+            return Ok(output(child::root::<L, _>(&db, &root, input)?.as_bytes().to_vec()));
+        },
+        36 => {
+            if let Some(size) = trie::value_size::<L, _>(&db, &root, input)? {
+                return Ok(output(size.to_le_bytes().to_vec()));
+            }
+            return Ok(0);
+        },
+        37 => {
+            let mut trie = MerklePatriciaTrie::<L>::open(&mut db, &mut root);
+            let value = trie.take(input)?;
+            drop(trie);
+            commit::<L, _>(&db, &previous, &root)?;
+            if let Some(value) = value {
+                return Ok(output(value));
+            }
+            return Ok(0);
+        },
+        40 => {
+            return Ok(output(trie::nodes_json::<L>(&db, &root).into_bytes()));
+        },
+        41 => {
+            return Ok(output(trie::nodes_dot::<L>(&db, &root).into_bytes()));
+        },
+        42 => {
+            let Some((limit, token)) = input.split_first_chunk::<4>() else {
+                return fail!(CallError::InvalidInput, "invalid page limit");
+            };
+            let limit = u32::from_le_bytes(*limit);
+            return Ok(output(trie::nodes_page::<L>(&db, &root, token, limit)));
+        },
+        43 => {
+            return Ok(output(trie::shape::<L>(&db, &root).encode()));
+        },
+        44 => {
+            return Ok(output(trie::nodes_cbor::<L>(&db, &root)));
+        },
+        45 => {
+            db.set_value_hints(input)?;
+            return Ok(0);
+        },
+        46 => {
+            let Some((start, input)) = input.split_first_chunk::<4>() else {
+                return fail!(CallError::InvalidInput, "invalid start");
+            };
+            let Some((count, _)) = input.split_first_chunk::<4>() else {
+                return fail!(CallError::InvalidInput, "invalid count");
+            };
+            let (start, count) = (u32::from_le_bytes(*start), u32::from_le_bytes(*count));
+            return Ok(output(oplog::list(&db, start.into(), count.into())));
+        },
+        47 => {
+            let Some((count, _)) = input.split_first_chunk::<4>() else {
+                return fail!(CallError::InvalidInput, "invalid count");
+            };
+            let count = u32::from_le_bytes(*count);
+            let root = oplog::replay::<L, _>(&mut db, &root, count.into())?;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(output(root.as_bytes().to_vec()));
+        },
+        48 => {
+            return Ok(cursor::open(&root, input)?.into());
+        },
+        49 => {
+            let Some((handle, input)) = input.split_first_chunk::<4>() else {
+                return fail!(CallError::InvalidInput, "invalid cursor");
+            };
+            let Some((count, _)) = input.split_first_chunk::<4>() else {
+                return fail!(CallError::InvalidInput, "invalid count");
+            };
+            let (handle, count) = (u32::from_le_bytes(*handle), u32::from_le_bytes(*count));
+            return Ok(output(cursor::next::<L>(&db, handle, count)?));
+        },
+        50 => {
+            let Ok(handle) = <[u8; 4]>::try_from(input) else {
+                return fail!(CallError::InvalidInput, "invalid cursor");
+            };
+            cursor::close(u32::from_le_bytes(handle))?;
+            return Ok(0);
+        },
+        51 => {
+            watch::set(&db, input)?;
+            return Ok(0);
+        },
+        52 => {
+            return Ok(output(bench::run::<L>(&db, &root, input)?));
+        },
+        53 => {
+            return Ok(output(snapshot::export::<L>(&db, &root, input)?));
+        },
+        54 => {
+            let root = snapshot::import::<L, _>(&mut db, &root, input)?;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(output(root.as_bytes().to_vec()));
+        },
+        55 => {
+            let (root, missing) = sync::start::<L, _>(&mut db, &root, input)?;
+            // The root only moves once the sync completes.
+            if root == previous {
+                db.flush();
+            } else {
+                commit::<L, _>(&db, &previous, &root)?;
+            }
+            return Ok(missing.into());
+        },
+        56 => {
+            let (root, progress) = sync::feed::<L, _>(&mut db, &root, input)?;
+            if root == previous {
+                db.flush();
+            } else {
+                commit::<L, _>(&db, &previous, &root)?;
+            }
+            return Ok(output(progress.encode()));
+        },
+        57 => {
+            let Ok(limit) = <[u8; 4]>::try_from(input) else {
+                return fail!(CallError::InvalidInput, "invalid limit");
+            };
+            return Ok(output(sync::missing(&db, u32::from_le_bytes(limit))?));
+        },
+        58 => {
+            sync::cancel::<L, _>(&db)?;
+            return Ok(0);
+        },
+        59 => {
+            return Ok(tries::create(input)?.into());
+        },
+        60 => {
+            return Ok(tries::open(input)?.into());
+        },
+        61 => {
+            tries::unregister()?;
+            checkpoint::release::<L, _>(&mut db, &root);
+            db.flush();
+            db.clear(EMPTY_PTR);
+            return Ok(0);
+        },
+        62 => {
+            let enabled = match input {
+                [0] => false,
+                [1] => true,
+                _ => return fail!(CallError::InvalidInput, "invalid value index mode"),
+            };
+            value_index::set::<L, _>(&db, &root, enabled)?;
+            return Ok(0);
+        },
+        63 => {
+            return Ok(output(value_index::lookup(&db, input)?));
+        },
+        64 => {
+            return Ok(output(steps::steps::<L>(&db, &root, input)?));
+        },
+        65 => {
+            let (code, call_input) = steps::operation_call(input)?;
+            output(explain::explain::<L>(&db, &root, input)?);
+            drop(db);
+            return dispatch::<L>(code, call_input);
+        },
+        66 => {
+            return Ok(output(trie::key_path::<L>(&db, &root, input)));
+        },
+        67 => {
+            return Ok(output(substrate::storage_key(input)?));
+        },
+        68 => {
+            let entry = substrate::item_entry(input)?;
+            drop(db);
+            return dispatch::<L>(0, &entry);
+        },
+        69 => {
+            let (root, count) = rpc::import::<L, _>(&mut db, &root, input)?;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(u64::from(count));
+        },
+        70 => {
+            return Ok(output(rpc::verify::<L>(&db, input)?));
+        },
+        71 => {
+            let entry = ethereum::account_entry(input)?;
+            drop(db);
+            return dispatch::<L>(0, &entry);
+        },
+        72 => {
+            return Ok(output(ordered::build::<L>(&db, input)?));
+        },
+        73 => {
+            let Some((seed, data)) = input.split_first_chunk::<8>() else {
+                return fail!(CallError::InvalidInput, "invalid seed");
+            };
+            let hash = hash::xxh3_128(u64::from_le_bytes(*seed), data);
+            return Ok(output(hash.to_le_bytes().to_vec()));
+        },
+        _ => {},
+    }
+
+    // Dry-run calls never touch the host storage, writes are kept in an overlay instead.
+    if code == 5 {
+        let mut overlay = OverlayDB::<L::Hash>::new(&db);
+        let mut trie = MerklePatriciaTrie::<L>::open(&mut overlay, &mut root);
+        trie.apply_batch(input)?;
+        return Ok(output(Vec::<u8>::from(trie.root().as_fixed_bytes())));
+    }
+
+    crate::debug_log!(Trace, Trie, "open {root:?}");
+    let mut trie = MerklePatriciaTrie::<L>::open(&mut db, &mut root);
 
     // Process the call
     match code {
         0 => {
-            trie.insert(input);
+            trie.insert(input)?;
             drop(trie);
-            db.set_root_hash(&root);
-            return 0;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(0);
         },
         1 => {
-            trie.remove(input);
+            trie.remove(input)?;
             drop(trie);
-            db.set_root_hash(&root);
-            return 0;
-        },
-        2 => {
-            return u64::from(trie.exists(input));
-        },
-        3 => {
-            let value = trie.get(input).map(Vec::<u8>::leak);
-            if let Some(value) = value {
-                let ptr = value.as_mut_ptr() as u32;
-                let len = value.len() as u32;
-                return (ptr as u64) << 32 | (len as u64);
-            }
-            return 0;
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(0);
         },
-        4 => {
-            let value = Vec::<u8>::from(trie.root().as_fixed_bytes()).leak();
-            let ptr = value.as_mut_ptr() as u32;
-            let len = value.len() as u32;
-            return (ptr as u64) << 32 | (len as u64);
+        13 => {
+            let Some((&policy, input)) = input.split_first() else {
+                return fail!(CallError::InvalidInput, "invalid input");
+            };
+            let (theirs, _) = split_hash(input)?;
+            let count = trie.merge(&theirs, policy == 1)?;
+            drop(trie);
+            commit::<L, _>(&db, &previous, &root)?;
+            return Ok(u64::from(count));
         },
         _ => {},
     }
-    abort!("invalid call");
+    fail!(CallError::InvalidCall, "invalid call {code}")
+}
+
+/// Splits a 32 bytes hash from the beginning of the input.
+pub fn split_hash(input: &[u8]) -> CallResult<(H256, &[u8])> {
+    let Some((hash, rest)) = input.split_first_chunk::<32>() else {
+        return fail!(CallError::InvalidInput, "invalid input, expected a 32 bytes hash");
+    };
+    Ok((H256(*hash), rest))
+}
+
+/// Splits a length-prefixed byte string from the beginning of the input.
+pub fn split_bytes(input: &[u8]) -> CallResult<(&[u8], &[u8])> {
+    let Some((len, rest)) = input.split_first_chunk::<4>() else {
+        return fail!(CallError::InvalidInput, "invalid input, expected a length prefix");
+    };
+    let Some((bytes, rest)) = rest.split_at_checked(u32::from_le_bytes(*len) as usize) else {
+        return fail!(CallError::InvalidInput, "invalid input, length out of bounds");
+    };
+    Ok((bytes, rest))
+}
+
+/// Appends a length-prefixed byte string to the output.
+pub fn write_bytes(output: &mut Vec<u8>, bytes: &[u8]) {
+    output.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    output.extend_from_slice(bytes);
+}
+
+/// Returns `value` to the host, the call itself returns `0`.
+fn output(value: Vec<u8>) -> u64 {
+    HostFnImpl::return_value(&value);
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MemoryBackend,
+        journal::Journal,
+        primitives::{Blake2Hasher, LayoutV1},
+        trie::for_each_node,
+    };
+    use core::cell::Cell;
+    use hash_db::EMPTY_PREFIX;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use trie_db::{node::Value, ChildReference, TrieDBMutBuilder, TrieMut};
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    /// Inserts the entries with a value and removes the others, then commits the new root.
+    fn apply<B: TrieBackend>(
+        db: &mut ExternalDB<B>,
+        root: &mut H256,
+        entries: &[(&[u8], Option<&[u8]>)],
+    ) {
+        {
+            let mut trie = TrieDBMutBuilder::<Layout>::from_existing(db, root).build();
+            for (key, value) in entries {
+                match value {
+                    Some(value) => trie.insert(key, value).map(drop).unwrap(),
+                    None => trie.remove(key).map(drop).unwrap(),
+                }
+            }
+        }
+        db.commit_root(root);
+    }
+
+    /// Storage in memory whose writes fail once `writes_left` is spent, journaled like the
+    /// host storage.
+    struct FailingBackend {
+        inner: MemoryBackend,
+        journal: Journal,
+        writes_left: Cell<usize>,
+    }
+
+    impl FailingBackend {
+        fn spend(&self) {
+            let Some(left) = self.writes_left.get().checked_sub(1) else {
+                panic!("storage failure");
+            };
+            self.writes_left.set(left);
+        }
+    }
+
+    impl TrieBackend for FailingBackend {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.inner.get(key)
+        }
+
+        fn set(&self, key: &[u8], value: &[u8]) {
+            self.spend();
+            self.inner.set(key, value);
+        }
+
+        fn clear(&self, key: &[u8]) {
+            self.spend();
+            self.inner.clear(key);
+        }
+
+        fn record(&self, key: &[u8], previous: Option<Vec<u8>>) {
+            self.journal.record(key, previous);
+        }
+    }
+
+    #[test]
+    fn rollback_restores_a_failed_flush() {
+        let backend = FailingBackend {
+            inner: MemoryBackend::new(),
+            journal: Journal::new(),
+            writes_left: Cell::new(usize::MAX),
+        };
+        let mut db = ExternalDB::with_backend::<Layout>(backend);
+        let mut root = db.get_root_hash();
+        let large = [7u8; 64];
+        apply(&mut db, &mut root, &[(b"alpha", Some(&large)), (b"beta", Some(&large))]);
+        // The next call starts with an empty journal.
+        db.backend.journal.rollback(|_, _| {});
+        let committed = (root, db.backend.inner.entries());
+
+        // The flush writes the new nodes and the counters, it fails after two of them.
+        db.backend.writes_left.set(2);
+        let operation = [(&b"gamma"[..], Some(&large[..])), (b"alpha", None)];
+        let result = catch_unwind(AssertUnwindSafe(|| apply(&mut db, &mut root, &operation)));
+        assert!(result.is_err());
+        assert_ne!(db.backend.inner.entries(), committed.1);
+
+        let inner = &db.backend.inner;
+        db.backend.journal.rollback(|key, value| match value {
+            Some(value) => inner.set(key, value),
+            None => inner.clear(key),
+        });
+        assert_eq!(inner.entries(), committed.1);
+        let db = ExternalDB::with_backend::<Layout>(core::mem::take(&mut db.backend.inner));
+        assert_eq!(db.get_root_hash(), committed.0);
+        assert_eq!(
+            trie::read_value::<Layout>(&db, &committed.0, b"alpha").unwrap(),
+            Some(large.to_vec())
+        );
+    }
+
+    #[test]
+    fn roots_match_memory_db() {
+        let large = [7u8; 64];
+        let entries: [(&[u8], &[u8]); 3] =
+            [(b"alpha", b"1"), (b"alphabet", &large), (b"beta", b"2")];
+        let mut expected = H256::zero();
+        let mut memory_db = MemoryDB::<Blake2Hasher>::new(&[0u8]);
+        {
+            let mut trie = TrieDBMutBuilder::<Layout>::new(&mut memory_db, &mut expected).build();
+            for (key, value) in entries {
+                trie.insert(key, value).unwrap();
+            }
+        }
+
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        apply(&mut db, &mut root, &entries.map(|(key, value)| (key, Some(value))));
+        assert_eq!(root, expected);
+
+        // A fresh database reads the committed trie back.
+        let db = ExternalDB::with_backend::<Layout>(core::mem::take(&mut db.backend));
+        assert_eq!(db.get_root_hash(), expected);
+        assert_eq!(
+            trie::read_value::<Layout>(&db, &root, b"alphabet").unwrap(),
+            Some(large.to_vec())
+        );
+    }
+
+    #[test]
+    fn migrated_nodes_keep_their_counters() {
+        let large = [7u8; 64];
+        let entries: [(&[u8], Option<&[u8]>); 3] =
+            [(b"alpha", Some(&large)), (b"alphabet", Some(&large)), (b"beta", Some(b"2"))];
+
+        // Seeds a storage in the former format, written before the format marker existed.
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        db.inline_counters = false;
+        db.backend.clear(FORMAT_KEY);
+        let mut root = db.get_root_hash();
+        apply(&mut db, &mut root, &entries);
+        let mut nodes = Vec::new();
+        for_each_node::<Layout, _>(&db, &root, |node| nodes.push(*node));
+        // Both values are stored once, the node is listed twice.
+        nodes.sort();
+        nodes.dedup();
+        let counters = nodes.iter().map(|node| db.counter(node)).collect::<Vec<_>>();
+        assert!(counters.contains(&2) && counters.iter().all(|counter| *counter > 0));
+        assert!(db.backend.entries().keys().any(|key| key.len() == 33 && key[32] == 0xff));
+
+        let mut db = ExternalDB::with_backend::<Layout>(core::mem::take(&mut db.backend));
+        assert!(!db.inline_counters);
+        assert_eq!(db.migrate_format::<Layout>(&root), nodes.len() as u32);
+        assert_eq!(db.migrate_format::<Layout>(&root), 0);
+
+        // The next call opens the storage in the inline format, with the same counters.
+        let mut db = ExternalDB::with_backend::<Layout>(core::mem::take(&mut db.backend));
+        assert!(db.inline_counters);
+        assert!(!db.backend.entries().keys().any(|key| key.len() == 33 && key[32] == 0xff));
+        assert_eq!(nodes.iter().map(|node| db.counter(node)).collect::<Vec<_>>(), counters);
+        assert_eq!(db.get_root_hash(), root);
+
+        // Writes after the migration reach the same root as a storage never migrated.
+        apply(&mut db, &mut root, &[(b"gamma", Some(&large)), (b"alpha", None)]);
+        let mut expected_db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut expected = expected_db.get_root_hash();
+        apply(&mut expected_db, &mut expected, &entries);
+        apply(&mut expected_db, &mut expected, &[(b"gamma", Some(&large)), (b"alpha", None)]);
+        assert_eq!(root, expected);
+        assert_eq!(
+            trie::read_value::<Layout>(&db, &root, b"alphabet").unwrap(),
+            Some(large.to_vec())
+        );
+    }
+
+    #[test]
+    fn prefetched_nodes_match_reads() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let large = [7u8; 64];
+        apply(&mut db, &mut root, &[(b"alpha", Some(&large)), (b"beta", Some(&large))]);
+        let mut nodes = Vec::new();
+        for_each_node::<Layout, _>(&db, &root, |node| nodes.push(*node));
+        nodes.push(H256::repeat_byte(1));
+
+        let db = ExternalDB::with_backend::<Layout>(core::mem::take(&mut db.backend));
+        let expected = ExternalDB::with_backend::<Layout>(&db.backend);
+        trie::NodeSource::<Blake2Hasher>::prefetch(&db, &nodes);
+        for node in &nodes {
+            assert_eq!(
+                HashDB::<Blake2Hasher, _>::get(&db, node, EMPTY_PREFIX),
+                HashDB::<Blake2Hasher, _>::get(&expected, node, EMPTY_PREFIX),
+            );
+        }
+        assert!(db.prefetched.borrow().is_empty());
+    }
+
+    #[test]
+    fn listing_stops_at_the_depth_limit() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let large = [7u8; 64];
+        apply(&mut db, &mut root, &[(b"alpha", Some(&large)), (b"beta", Some(&large))]);
+
+        let (index, nodes) = trie::decode_nodes::<Layout>(&db, &root, &[], 0);
+        assert_eq!(nodes.len(), 1);
+        assert!(nodes[index].truncated);
+
+        let (index, nodes) = trie::decode_nodes::<Layout>(&db, &root, &[], u32::MAX);
+        assert_eq!(nodes.len(), 3);
+        assert!(nodes.iter().all(|node| !node.truncated));
+        assert_eq!(nodes[index].id, Some(root));
+        assert_eq!(nodes[index].kind, trie::NodeKind::NibbledBranch);
+        assert_eq!(nodes[index].depth, 0);
+        assert!(nodes
+            .iter()
+            .filter(|node| node.depth == 1)
+            .all(|node| { node.kind == trie::NodeKind::Leaf && node.raw_bytes.len() > 32 }));
+        assert!(nodes.iter().all(|node| node.references == db.counter(&node.id.unwrap())));
+
+        // The counters are read once the nodes are written back to the storage.
+        let db = ExternalDB::with_backend::<Layout>(core::mem::take(&mut db.backend));
+        let (_, nodes) = trie::decode_nodes::<Layout>(&db, &root, &[], u32::MAX);
+        assert!(nodes.iter().all(|node| node.references == 1));
+    }
+
+    #[test]
+    fn listing_a_deep_trie_keeps_the_call_stack_flat() {
+        type Codec = <Layout as trie_db::TrieLayout>::Codec;
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let codec = |child: Option<H256>| match child {
+            None => Codec::leaf_node([].into_iter(), 0, Value::Inline(&[1])),
+            Some(child) => Codec::branch_node_nibbled(
+                [].into_iter(),
+                0,
+                (0..16).map(|nibble| (nibble == 0).then_some(ChildReference::Hash(child))),
+                Some(Value::Inline(&[1])),
+            ),
+        };
+        // A chain of branches with a single child, far deeper than a recursive walk could go.
+        let mut root = HashDB::<Blake2Hasher, _>::insert(&mut db, EMPTY_PREFIX, &codec(None));
+        for _ in 0..50_000 {
+            root = HashDB::<Blake2Hasher, _>::insert(&mut db, EMPTY_PREFIX, &codec(Some(root)));
+        }
+
+        let (index, nodes) = trie::decode_nodes::<Layout>(&db, &root, &[], u32::MAX);
+        assert_eq!(nodes.len(), 50_001);
+        assert_eq!(nodes.ids().next_back(), Some(index));
+        assert_eq!(nodes[index].depth, 0);
+        let mut ids = nodes.ids();
+        let (leaf, parent) = (ids.next().unwrap(), ids.next().unwrap());
+        assert_eq!(nodes[leaf].kind, trie::NodeKind::Leaf);
+        assert_eq!(nodes[leaf].depth, 50_000);
+        assert_eq!(nodes[parent].children.get(0), Some(leaf));
+    }
+
+    #[test]
+    fn value_sizes_match_values() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let large = [7u8; 64];
+        apply(&mut db, &mut root, &[(b"alpha", Some(b"1")), (b"beta", Some(&large))]);
+        assert_eq!(trie::value_size::<Layout, _>(&db, &root, b"alpha").unwrap(), Some(1));
+        assert_eq!(trie::value_size::<Layout, _>(&db, &root, b"beta").unwrap(), Some(64));
+        assert_eq!(trie::value_size::<Layout, _>(&db, &root, b"gamma").unwrap(), None);
+    }
+
+    #[test]
+    fn shared_nodes_survive_a_removal() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let large = [7u8; 64];
+        apply(&mut db, &mut root, &[(b"alpha", Some(&large)), (b"beta", Some(&large))]);
+        let value = <Blake2Hasher as Hasher>::hash(&large);
+        assert_eq!(db.counter(&value), 2);
+
+        apply(&mut db, &mut root, &[(b"alpha", None)]);
+        assert_eq!(db.counter(&value), 1);
+        assert_eq!(trie::read_value::<Layout>(&db, &root, b"beta").unwrap(), Some(large.to_vec()));
+        apply(&mut db, &mut root, &[(b"beta", None)]);
+        assert!(!db.backend.contains(db.node_key(&value).as_ref()));
+    }
+
+    #[test]
+    fn removed_nodes_are_deleted() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let large = [7u8; 64];
+        apply(&mut db, &mut root, &[(b"alpha", Some(b"1")), (b"alphabet", Some(&large))]);
+        let mut nodes = Vec::new();
+        for_each_node::<Layout, _>(&db, &root, |node| nodes.push(*node));
+        assert!(!nodes.is_empty());
+        assert!(nodes.iter().all(|node| db.counter(node) == 1));
+
+        apply(&mut db, &mut root, &[(b"alpha", None), (b"alphabet", None)]);
+        assert_eq!(root, hashed_null_node::<Layout>());
+        assert!(nodes.iter().all(|node| !db.backend.contains(node.as_bytes())));
+    }
+
+    #[test]
+    fn checkpoints_keep_their_nodes() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        apply(&mut db, &mut root, &[(b"alpha", Some(b"1")), (b"beta", Some(b"2"))]);
+        let checkpoint = root;
+        checkpoint::create::<Layout, _>(&mut db, &root, b"saved");
+        db.flush();
+
+        apply(&mut db, &mut root, &[(b"alpha", None), (b"beta", None)]);
+        assert_eq!(root, hashed_null_node::<Layout>());
+        root = checkpoint::revert::<Layout, _>(&mut db, &root, b"saved").unwrap();
+        db.commit_root(&root);
+        assert_eq!(root, checkpoint);
+        assert_eq!(trie::read_value::<Layout>(&db, &root, b"beta").unwrap(), Some(b"2".to_vec()));
+    }
 }
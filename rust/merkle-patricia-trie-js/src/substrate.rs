@@ -0,0 +1,123 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Storage keys of Substrate pallets, so the demo can reproduce the state layout of a chain.
+//!
+//! The key of a storage item is the `twox_128` of the pallet name followed by the `twox_128` of
+//! the item name, the keys of a map are appended hashed by the hasher of the map. A key is built
+//! from parts, each the code of a [`StorageHasher`] followed by the length-prefixed data hashed
+//! by it.
+use crate::{
+    error::{CallError, CallResult},
+    fail,
+    storage::{split_bytes, write_bytes},
+};
+use alloc::vec::Vec;
+use sp_crypto_hashing::{blake2_128, blake2_256, twox_128, twox_256, twox_64};
+
+/// Hashers of the storage maps, coded in the order of the runtime metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StorageHasher {
+    Blake2_128,
+    Blake2_256,
+    Blake2_128Concat,
+    Twox128,
+    Twox256,
+    Twox64Concat,
+    Identity,
+}
+
+impl StorageHasher {
+    fn from_code(code: u8) -> CallResult<Self> {
+        Ok(match code {
+            0 => Self::Blake2_128,
+            1 => Self::Blake2_256,
+            2 => Self::Blake2_128Concat,
+            3 => Self::Twox128,
+            4 => Self::Twox256,
+            5 => Self::Twox64Concat,
+            6 => Self::Identity,
+            code => return fail!(CallError::InvalidInput, "invalid storage hasher: {code}"),
+        })
+    }
+
+    /// Appends `data` hashed to `key`, the `Concat` hashers keep the data after its hash.
+    fn hash_into(self, key: &mut Vec<u8>, data: &[u8]) {
+        match self {
+            Self::Blake2_128 => key.extend_from_slice(&blake2_128(data)),
+            Self::Blake2_256 => key.extend_from_slice(&blake2_256(data)),
+            Self::Blake2_128Concat => {
+                key.extend_from_slice(&blake2_128(data));
+                key.extend_from_slice(data);
+            },
+            Self::Twox128 => key.extend_from_slice(&twox_128(data)),
+            Self::Twox256 => key.extend_from_slice(&twox_256(data)),
+            Self::Twox64Concat => {
+                key.extend_from_slice(&twox_64(data));
+                key.extend_from_slice(data);
+            },
+            Self::Identity => key.extend_from_slice(data),
+        }
+    }
+}
+
+/// Concatenates the parts of `input` hashed by their hasher.
+pub fn storage_key(mut input: &[u8]) -> CallResult<Vec<u8>> {
+    let mut key = Vec::with_capacity(input.len() + 32);
+    while let Some((&code, rest)) = input.split_first() {
+        let hasher = StorageHasher::from_code(code)?;
+        let (data, rest) = split_bytes(rest)?;
+        hasher.hash_into(&mut key, data);
+        input = rest;
+    }
+    Ok(key)
+}
+
+/// Entry of a storage item in the input format of an insert, from the length-prefixed value
+/// followed by the parts of the key.
+pub fn item_entry(input: &[u8]) -> CallResult<Vec<u8>> {
+    let (value, parts) = split_bytes(input)?;
+    if parts.is_empty() {
+        return fail!(CallError::InvalidInput, "expected the parts of the storage key");
+    }
+    let mut entry = Vec::with_capacity(value.len() + parts.len() + 48);
+    write_bytes(&mut entry, &storage_key(parts)?);
+    write_bytes(&mut entry, value);
+    Ok(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    fn part(hasher: StorageHasher, data: &[u8]) -> Vec<u8> {
+        let mut part = vec![hasher as u8];
+        write_bytes(&mut part, data);
+        part
+    }
+
+    #[test]
+    fn keys_match_the_substrate_layout() {
+        // `System::Account`, the map keys are hashed by `Blake2_128Concat`.
+        let account = [7u8; 32];
+        let input = [
+            part(StorageHasher::Twox128, b"System"),
+            part(StorageHasher::Twox128, b"Account"),
+            part(StorageHasher::Blake2_128Concat, &account),
+        ]
+        .concat();
+        let key = storage_key(&input).unwrap();
+        assert_eq!(
+            key[..32],
+            hex!("26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9")
+        );
+        assert_eq!(key[32..48], blake2_128(&account));
+        assert_eq!(key[48..], account);
+
+        let key = storage_key(&part(StorageHasher::Twox64Concat, b"id")).unwrap();
+        assert_eq!(key, [&twox_64(b"id")[..], b"id"].concat());
+        assert_eq!(storage_key(&[7, 0, 0, 0, 0]), Err(CallError::InvalidInput));
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Sync of a trie from nodes fetched elsewhere, like a light node syncing from a full node.
+//!
+//! The host starts a sync towards a target root, then feeds the nodes it asks for, see
+//! [`missing`]. Each node must hash to a missing node, it's kept aside under a reserved key and
+//! its children are requested in turn, unless they're already stored. Once nothing is missing
+//! the nodes are moved to the storage, counting their references, and the live trie moves to
+//! the target root.
+use crate::{
+    abort,
+    backend::TrieBackend,
+    checkpoint,
+    error::{CallError, CallResult},
+    fail,
+    primitives::H256,
+    storage::{hashed_null_node, split_bytes, split_hash, ExternalDB, TrieLayout},
+    trie::try_push_references,
+};
+use alloc::{collections::BTreeMap, vec::Vec};
+use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+
+/// Target root followed by the missing nodes, each one as its hash and whether it's a value.
+const SYNC_KEY: &[u8] = b":sync";
+const NODE_PREFIX: &[u8] = b":sync_node:";
+
+fn node_key(hash: &H256) -> Vec<u8> {
+    [NODE_PREFIX, hash.as_bytes()].concat()
+}
+
+/// Running sync, the flag of the missing nodes tells whether they're values.
+struct Sync {
+    target: H256,
+    missing: BTreeMap<H256, bool>,
+}
+
+impl Sync {
+    fn load<B: TrieBackend>(db: &ExternalDB<B>) -> Option<Self> {
+        let bytes = db.get_value(SYNC_KEY)?;
+        let Some((target, entries)) = bytes.split_first_chunk::<32>() else {
+            abort!(key = SYNC_KEY, "invalid sync state");
+        };
+        let (entries, []) = entries.as_chunks::<33>() else {
+            abort!(key = SYNC_KEY, "invalid sync state");
+        };
+        let missing = entries
+            .iter()
+            .map(|entry| (H256::from_slice(&entry[..32]), entry[32] == 1))
+            .collect();
+        Some(Self { target: H256(*target), missing })
+    }
+
+    fn save<B: TrieBackend>(&self, db: &ExternalDB<B>) {
+        let mut bytes = Vec::with_capacity(32 + self.missing.len() * 33);
+        bytes.extend_from_slice(self.target.as_bytes());
+        for (hash, is_value) in &self.missing {
+            bytes.extend_from_slice(hash.as_bytes());
+            bytes.push(u8::from(*is_value));
+        }
+        db.set_bytes(SYNC_KEY, &bytes);
+    }
+
+    /// Whether the node `hash` was received or is stored already.
+    fn has<B: TrieBackend, H: Hasher<Out = H256>>(db: &ExternalDB<B>, hash: &H256) -> bool {
+        db.contains(&node_key(hash)) || HashDB::<H, _>::contains(db, hash, EMPTY_PREFIX)
+    }
+}
+
+/// Nodes accepted and rejected by [`feed`], and the nodes still missing.
+pub struct Progress {
+    pub accepted: u32,
+    pub rejected: u32,
+    pub missing: u32,
+}
+
+impl Progress {
+    pub fn encode(&self) -> Vec<u8> {
+        [self.accepted, self.rejected, self.missing]
+            .iter()
+            .flat_map(|n| n.to_le_bytes())
+            .collect()
+    }
+}
+
+/// Starts syncing the trie at `target`, returning the number of missing nodes. The sync is
+/// completed right away when the trie is stored already.
+pub fn start<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &H256,
+    target: &[u8],
+) -> CallResult<(H256, u32)> {
+    let (target, _) = split_hash(target)?;
+    if db.contains(SYNC_KEY) {
+        return fail!(CallError::InvalidCall, "a sync is already running");
+    }
+    let mut sync = Sync { target, missing: BTreeMap::new() };
+    if !Sync::has::<B, L::Hash>(db, &target) {
+        sync.missing.insert(target, false);
+    }
+    complete_or_save::<L, B>(db, root, &sync)
+}
+
+/// Stores the nodes of `input`, each one encoded as its hash followed by its length-prefixed
+/// bytes. Nodes not missing or not matching their hash are rejected. Returns the new root once
+/// nothing is missing anymore, the current one otherwise.
+pub fn feed<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &H256,
+    mut input: &[u8],
+) -> CallResult<(H256, Progress)> {
+    let Some(mut sync) = Sync::load(db) else {
+        return fail!(CallError::InvalidCall, "no sync is running");
+    };
+    let mut progress = Progress { accepted: 0, rejected: 0, missing: 0 };
+    while !input.is_empty() {
+        let (hash, rest) = split_hash(input)?;
+        let (bytes, rest) = split_bytes(rest)?;
+        input = rest;
+        let mut children = Vec::new();
+        let accepted = match sync.missing.get(&hash) {
+            Some(_) if L::Hash::hash(bytes) != hash => false,
+            Some(true) => true,
+            Some(false) => try_push_references::<L>(bytes, &mut children),
+            None => false,
+        };
+        if !accepted {
+            progress.rejected += 1;
+            continue;
+        }
+        progress.accepted += 1;
+        sync.missing.remove(&hash);
+        db.set_bytes(&node_key(&hash), bytes);
+        for (child, is_value) in children {
+            if !sync.missing.contains_key(&child) && !Sync::has::<B, L::Hash>(db, &child) {
+                sync.missing.insert(child, is_value);
+            }
+        }
+    }
+    let (root, missing) = complete_or_save::<L, B>(db, root, &sync)?;
+    progress.missing = missing;
+    Ok((root, progress))
+}
+
+/// Lists the hashes of up to `limit` missing nodes.
+pub fn missing<B: TrieBackend>(db: &ExternalDB<B>, limit: u32) -> CallResult<Vec<u8>> {
+    let Some(sync) = Sync::load(db) else {
+        return fail!(CallError::InvalidCall, "no sync is running");
+    };
+    Ok(sync.missing.keys().take(limit as usize).flat_map(|hash| hash.0).collect())
+}
+
+/// Stops the running sync, dropping the nodes received so far.
+pub fn cancel<L: TrieLayout, B: TrieBackend>(db: &ExternalDB<B>) -> CallResult<()> {
+    let Some(sync) = Sync::load(db) else {
+        return fail!(CallError::InvalidCall, "no sync is running");
+    };
+    let mut stack = Vec::from([(sync.target, false)]);
+    while let Some((hash, is_value)) = stack.pop() {
+        let Some(bytes) = db.get_value(&node_key(&hash)) else {
+            continue;
+        };
+        if !is_value {
+            try_push_references::<L>(&bytes, &mut stack);
+        }
+        db.clear(&node_key(&hash));
+    }
+    db.clear(SYNC_KEY);
+    Ok(())
+}
+
+/// Moves the received nodes to the storage once nothing is missing, releasing the trie at
+/// `root`. Returns the live root and the number of missing nodes.
+fn complete_or_save<L: TrieLayout, B: TrieBackend>(
+    db: &mut ExternalDB<B>,
+    root: &H256,
+    sync: &Sync,
+) -> CallResult<(H256, u32)> {
+    if !sync.missing.is_empty() {
+        sync.save(db);
+        return Ok((*root, sync.missing.len() as u32));
+    }
+    if sync.target != hashed_null_node::<L>() {
+        // Every reference is counted, as `for_each_node` visits the nodes.
+        let mut stack = Vec::from([(sync.target, false)]);
+        while let Some((hash, is_value)) = stack.pop() {
+            let bytes = match db.get_value(&node_key(&hash)) {
+                Some(bytes) => {
+                    db.clear(&node_key(&hash));
+                    bytes
+                },
+                None => match HashDB::<L::Hash, _>::get(db, &hash, EMPTY_PREFIX) {
+                    Some(bytes) => bytes,
+                    None => abort!(key = hash.as_bytes(), "missing node {hash:?}"),
+                },
+            };
+            if !is_value {
+                try_push_references::<L>(&bytes, &mut stack);
+            }
+            HashDB::<L::Hash, _>::emplace(db, hash, EMPTY_PREFIX, bytes);
+        }
+    }
+    checkpoint::release::<L, _>(db, root);
+    db.clear(SYNC_KEY);
+    Ok((sync.target, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MemoryBackend,
+        primitives::{Blake2Hasher, LayoutV1},
+        storage::write_bytes,
+        trie::for_each_node,
+    };
+    use trie_db::{TrieDBMutBuilder, TrieMut};
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    #[test]
+    fn synced_trie_matches_the_source() {
+        let mut source = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut target = source.get_root_hash();
+        let large = [7u8; 64];
+        {
+            let mut trie =
+                TrieDBMutBuilder::<Layout>::from_existing(&mut source, &mut target).build();
+            for key in [&b"alpha"[..], b"alphabet", b"beta", b"gamma"] {
+                trie.insert(key, &large).unwrap();
+            }
+        }
+        source.commit_root(&target);
+
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let (_, mut missing) = start::<Layout, _>(&mut db, &root, target.as_bytes()).unwrap();
+        assert_eq!(missing, 1);
+        let mut rounds = 0;
+        while missing > 0 {
+            let hashes = super::missing(&db, 2).unwrap();
+            let mut input = Vec::new();
+            for hash in hashes.chunks_exact(32).map(H256::from_slice) {
+                let bytes = HashDB::<Blake2Hasher, _>::get(&source, &hash, EMPTY_PREFIX).unwrap();
+                input.extend_from_slice(hash.as_bytes());
+                write_bytes(&mut input, &bytes);
+            }
+            // A node no one asked for is rejected.
+            input.extend_from_slice(H256::repeat_byte(1).as_bytes());
+            write_bytes(&mut input, &large);
+            let progress;
+            (root, progress) = feed::<Layout, _>(&mut db, &root, &input).unwrap();
+            assert_eq!(progress.accepted as usize, hashes.len() / 32);
+            assert_eq!(progress.rejected, 1);
+            missing = progress.missing;
+            rounds += 1;
+        }
+        db.commit_root(&root);
+        assert_eq!(root, target);
+        assert!(rounds > 1);
+        assert!(!db.contains(SYNC_KEY));
+
+        let mut nodes = BTreeMap::<H256, i32>::new();
+        for_each_node::<Layout, _>(&source, &target, |node| *nodes.entry(*node).or_default() += 1);
+        for (node, references) in nodes {
+            assert_eq!(db.counter(&node), references);
+            assert!(!db.contains(&node_key(&node)));
+        }
+
+        // The trie is stored now, syncing it again completes right away.
+        let (synced, missing) = start::<Layout, _>(&mut db, &root, target.as_bytes()).unwrap();
+        assert_eq!((synced, missing), (target, 0));
+    }
+}
@@ -2,107 +2,116 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::{abort, children::Children, storage::Layout, trie_builder::JSMerklePatriciaTrie};
-use hash_db::{HashDB, EMPTY_PREFIX};
-use sp_core::{bounded::alloc::vec::Vec, Blake2Hasher, H256};
-use sp_trie::{NodeCodec, NodePlan, ValuePlan};
+use crate::{
+    abort,
+    backend::TrieBackend,
+    children::{Children, NodeId},
+    diff::{self, Change},
+    error::{CallError, CallResult},
+    fail,
+    hash::BuildXxHasher64,
+    primitives::H256,
+    storage::{hashed_null_node, trie_key, write_bytes, ExternalDB, TrieLayout},
+    trie_builder::{ByteEncoding, JSMerklePatriciaTrie},
+};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+    vec::Vec,
+};
+use core::{
+    fmt::Debug,
+    ops::{Index, IndexMut},
+};
+use hash_db::{HashDB, Hasher, EMPTY_PREFIX};
+use hashbrown::HashMap;
 use trie_db::{
-    node::{NibbleSlicePlan, NodeHandlePlan},
-    DBValue, NibbleSlice, NodeCodec as NodeCodecT, TrieDBMutBuilder, TrieMut,
+    node::{NibbleSlicePlan, NodeHandlePlan, NodePlan, ValuePlan},
+    DBValue, NibbleSlice, NodeCodec, Trie, TrieDBBuilder, TrieDBMut, TrieDBMutBuilder, TrieError,
+    TrieMut,
 };
 use wasm_bindgen::prelude::*;
 
-type TrieDBMut<'a> = trie_db::TrieDBMut<'a, Layout>;
-type TrieError = sp_trie::TrieError<Layout>;
+/// Variants of [`TrieError`], recorded with the last failure for the host to branch on.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrieErrorKind {
+    /// The root node isn't stored.
+    InvalidStateRoot = 0,
+    /// A node on the path isn't stored.
+    IncompleteDatabase = 1,
+    /// A value was found at a key of an odd number of nibbles.
+    ValueAtIncompleteKey = 2,
+    /// A stored node can't be decoded.
+    DecoderError = 3,
+    /// A node refers to a child by a hash of the wrong length.
+    InvalidHash = 4,
+}
 
-#[cfg(feature = "enable-debug-log")]
-fn abort_on_trie_error(error: &TrieError) -> ! {
-    match error {
-        TrieError::InvalidStateRoot(ref root) => abort!("Invalid state root: {root:?}"),
-        TrieError::IncompleteDatabase(ref missing) => {
-            abort!("Database missing expected key: {missing:?}")
-        },
-        TrieError::ValueAtIncompleteKey(ref bytes, ref extra) => {
-            abort!("Value found in trie at incomplete key {:?} + {:?}", bytes, extra)
+impl TrieErrorKind {
+    /// Whether the error names a node hash, rather than a key.
+    pub fn has_hash(self) -> bool {
+        self != Self::ValueAtIncompleteKey
+    }
+}
+
+/// Result code of a trie error, the error is recorded with its kind and the hash or key it
+/// names.
+pub fn call_error<E: Debug>(error: &TrieError<H256, E>) -> CallError {
+    let (kind, subject) = match error {
+        TrieError::InvalidStateRoot(hash) => (TrieErrorKind::InvalidStateRoot, hash.as_bytes()),
+        TrieError::IncompleteDatabase(hash) => (TrieErrorKind::IncompleteDatabase, hash.as_bytes()),
+        TrieError::ValueAtIncompleteKey(key, _) => {
+            (TrieErrorKind::ValueAtIncompleteKey, key.as_slice())
         },
-        TrieError::DecoderError(ref hash, ref decoder_err) => {
-            abort!("Decoding failed for hash {:?}; err: {:?}", hash, decoder_err)
+        TrieError::DecoderError(hash, _) => (TrieErrorKind::DecoderError, hash.as_bytes()),
+        TrieError::InvalidHash(hash, _) => (TrieErrorKind::InvalidHash, hash.as_bytes()),
+    };
+    let code = match kind {
+        TrieErrorKind::InvalidStateRoot | TrieErrorKind::IncompleteDatabase => {
+            CallError::KeyNotFound
         },
-        TrieError::InvalidHash(ref hash, ref data) => abort!(
-            "Encoded node {:?} contains invalid hash reference with length: {}",
-            hash,
-            data.len()
-        ),
-    }
+        _ => CallError::DecodeError,
+    };
+    crate::error::record(code as u8, subject, format_args!("{error:?}"));
+    crate::error::set_trie_error(kind);
+    code
 }
 
-#[cfg(not(feature = "enable-debug-log"))]
-fn abort_on_trie_error(_error: &TrieError) -> ! {
-    crate::__abort();
+/// Database read by the node listing, the children of a branch are read at once.
+pub trait NodeSource<H: Hasher>: HashDB<H, DBValue> {
+    /// Reads the nodes `keys` ahead of the following `get` calls.
+    fn prefetch(&self, keys: &[H256]);
+
+    /// Reads the reference counters of the nodes `keys` at once, `0` for the nodes not stored.
+    fn counters(&self, keys: &[H256]) -> Vec<i32>;
 }
 
-fn decode_child_recursive(
-    parent: &mut TrieNode,
-    child: NodeHandlePlan,
-    partial: Option<u8>,
-    bytes: &'static [u8],
-    db: &dyn HashDB<Blake2Hasher, DBValue>,
-    nodes: &mut Vec<TrieNode>,
-) {
-    let Some(partial) = partial else {
-        abort!("extension node not supported");
-    };
-    match child {
-        NodeHandlePlan::Hash(range) => {
-            let key = {
-                let bytes = &bytes[range.start..range.end];
-                let bytes = match TryInto::<&[u8; 32]>::try_into(bytes) {
-                    Ok(bytes) => bytes,
-                    Err(error) => abort!("{error}"),
-                };
-                H256(*bytes)
-            };
-            let maybe_index = nodes.iter().enumerate().find_map(|(index, node)| {
-                if let Some(id) = node.id.as_ref() {
-                    if &key == id {
-                        return Some(index);
-                    }
-                }
-                None
-            });
-            if let Some(index) = maybe_index {
-                parent.children.push(index, partial);
-                return;
-            }
-            if let Some(value) = db.get(&key, EMPTY_PREFIX) {
-                let value = value.leak();
-                let index = decode_recursive(value, Some(key), db, nodes);
-                parent.children.push(index, partial);
-            } else {
-                abort!("invalid children");
-            }
-        },
-        NodeHandlePlan::Inline(range) => {
-            let bytes = &bytes[range.start..range.end];
-            let index = decode_recursive(bytes, None, db, nodes);
-            parent.children.push(index, partial);
-        },
-    }
+/// Nodes decoded so far, the ones stored under their hash are indexed by it.
+struct DecodedNodes {
+    nodes: NodeArena,
+    ids: HashMap<H256, NodeId, BuildXxHasher64>,
 }
 
-fn decode_children_recursive(
-    parent: &mut TrieNode,
-    children: [Option<NodeHandlePlan>; 16],
-    bytes: &'static [u8],
-    db: &dyn HashDB<Blake2Hasher, DBValue>,
-    nodes: &mut Vec<TrieNode>,
-) {
-    for (partial, child) in children.into_iter().enumerate() {
-        let Some(child) = child else {
-            continue;
-        };
-        let partial = partial as u8;
-        decode_child_recursive(parent, child, Some(partial), bytes, db, nodes);
+impl DecodedNodes {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: NodeArena::with_capacity(capacity),
+            ids: HashMap::with_capacity_and_hasher(capacity, Default::default()),
+        }
+    }
+
+    fn find(&self, id: &H256) -> Option<NodeId> {
+        self.ids.get(id).copied()
+    }
+
+    fn push(&mut self, node: TrieNode) -> NodeId {
+        let id = node.id;
+        let index = self.nodes.push(node);
+        if let Some(id) = id {
+            self.ids.entry(id).or_insert(index);
+        }
+        index
     }
 }
 
@@ -113,10 +122,7 @@ pub fn value_to_bytes<'b>(plan: &ValuePlan, data: &'b [u8]) -> &'b [u8] {
     }
 }
 
-pub fn nibble_to_str(
-    partial: NibbleSlicePlan,
-    bytes: &'static [u8],
-) -> Option<NibbleSlice<'static>> {
+pub fn nibble_to_str<'b>(partial: &NibbleSlicePlan, bytes: &'b [u8]) -> Option<NibbleSlice<'b>> {
     let nibbles = partial.build(bytes);
     if nibbles.is_empty() {
         return None;
@@ -124,153 +130,749 @@ pub fn nibble_to_str(
     Some(nibbles)
 }
 
+/// Pushes the hashes referenced by an encoded node, the flag tells whether the hash points to a
+/// value node, which is opaque and must not be decoded.
+fn push_references<L: TrieLayout>(bytes: &[u8], stack: &mut Vec<(H256, bool)>) {
+    if !try_push_references::<L>(bytes, stack) {
+        abort!("decode_plan failed");
+    }
+}
+
+/// Same as [`push_references`], returning `false` for bytes which aren't a node, for the nodes
+/// received from the host.
+pub(crate) fn try_push_references<L: TrieLayout>(
+    bytes: &[u8],
+    stack: &mut Vec<(H256, bool)>,
+) -> bool {
+    let Ok(node) = L::Codec::decode_plan(bytes) else {
+        return false;
+    };
+    let mut children = [const { None }; 16];
+    let value = match node {
+        NodePlan::Empty => return true,
+        NodePlan::Leaf { value, .. } => Some(value),
+        NodePlan::Extension { child, .. } => {
+            children[0] = Some(child);
+            None
+        },
+        NodePlan::Branch { value, children: plan } |
+        NodePlan::NibbledBranch { value, children: plan, .. } => {
+            children = plan;
+            value
+        },
+    };
+    if let Some(ValuePlan::Node(range)) = value {
+        stack.push((H256::from_slice(&bytes[range]), true));
+    }
+    children.into_iter().flatten().all(|child| match child {
+        NodeHandlePlan::Hash(range) => {
+            stack.push((H256::from_slice(&bytes[range]), false));
+            true
+        },
+        NodeHandlePlan::Inline(range) => try_push_references::<L>(&bytes[range], stack),
+    })
+}
+
+/// Visits every node stored in the database under `root`, including the value nodes, once per
+/// reference. Shared subtrees are visited once for each parent referencing them, which mirrors
+/// how the reference counters are incremented.
+pub fn for_each_node<L: TrieLayout, F: FnMut(&H256)>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    mut callback: F,
+) {
+    if root == &hashed_null_node::<L>() {
+        return;
+    }
+    let mut stack = vec![(*root, false)];
+    while let Some((key, is_value)) = stack.pop() {
+        if !is_value {
+            let Some(bytes) = db.get(&key, EMPTY_PREFIX) else {
+                abort!(key = key.as_bytes(), "missing node {key:?}");
+            };
+            push_references::<L>(&bytes, &mut stack);
+        }
+        callback(&key);
+    }
+}
+
+/// Lists up to `limit` nodes of the trie breadth-first, starting from the nodes whose hashes
+/// are concatenated in `token`, or from `root` when it is empty. Inline nodes are part of
+/// their parent. The output holds the node count as `u32`, then each node as its hash, the
+/// `u32` length of its encoding and the encoding, then the `u32` length of the token of the
+/// next page, empty once every node was listed. A node shared by several parents can be
+/// listed again on a following page.
+pub fn nodes_page<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    token: &[u8],
+    limit: u32,
+) -> Vec<u8> {
+    if !token.len().is_multiple_of(32) {
+        abort!("invalid page token");
+    }
+    let mut queue = if token.is_empty() {
+        vec![(*root, false)]
+    } else {
+        token.chunks_exact(32).map(|hash| (H256::from_slice(hash), false)).collect()
+    };
+    let mut listed = BTreeSet::new();
+    let mut output = 0u32.to_le_bytes().to_vec();
+    let mut next = 0;
+    while next < queue.len() && listed.len() < limit as usize {
+        let (key, is_value) = queue[next];
+        next += 1;
+        if is_value || !listed.insert(key) {
+            continue;
+        }
+        let Some(bytes) = db.get(&key, EMPTY_PREFIX) else {
+            abort!(key = key.as_bytes(), "missing node {key:?}");
+        };
+        push_references::<L>(&bytes, &mut queue);
+        output.extend_from_slice(key.as_bytes());
+        output.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        output.extend_from_slice(&bytes);
+    }
+    output[..4].copy_from_slice(&(listed.len() as u32).to_le_bytes());
+    let token = queue[next..].iter().filter(|(_, is_value)| !is_value).collect::<Vec<_>>();
+    output.extend_from_slice(&(token.len() as u32 * 32).to_le_bytes());
+    for (key, _) in token {
+        output.extend_from_slice(key.as_bytes());
+    }
+    output
+}
+
+/// Aggregate metrics of the nodes stored under a root, each node counted once. Inline nodes
+/// are part of their parent and aren't counted.
+#[derive(Default)]
+pub struct TrieStats {
+    pub nodes: u32,
+    /// Total encoded bytes of the nodes.
+    pub bytes: u64,
+    pub leaves: u32,
+    /// Branches, with or without partial key.
+    pub branches: u32,
+    pub extensions: u32,
+    /// Values stored in their own node, by state version `V1`.
+    pub values: u32,
+    /// Number of nodes for each reference counter.
+    pub counters: BTreeMap<i32, u32>,
+}
+
+impl TrieStats {
+    /// Encodes the metrics as little endian integers, the counter distribution comes last as
+    /// `(counter: i32, nodes: u32)` pairs in increasing counter order.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(32 + self.counters.len() * 8);
+        output.extend_from_slice(&self.nodes.to_le_bytes());
+        output.extend_from_slice(&self.bytes.to_le_bytes());
+        output.extend_from_slice(&self.leaves.to_le_bytes());
+        output.extend_from_slice(&self.branches.to_le_bytes());
+        output.extend_from_slice(&self.extensions.to_le_bytes());
+        output.extend_from_slice(&self.values.to_le_bytes());
+        for (counter, nodes) in self.counters.iter() {
+            output.extend_from_slice(&counter.to_le_bytes());
+            output.extend_from_slice(&nodes.to_le_bytes());
+        }
+        output
+    }
+}
+
+/// Walks the nodes stored under `root` once each, `counter` returns the reference counter of a
+/// node.
+pub fn stats<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    counter: impl Fn(&H256) -> i32,
+) -> TrieStats {
+    let mut stats = TrieStats::default();
+    if root == &hashed_null_node::<L>() {
+        return stats;
+    }
+    let mut visited = BTreeSet::new();
+    let mut stack = vec![(*root, false)];
+    while let Some((key, is_value)) = stack.pop() {
+        if !visited.insert(key) {
+            continue;
+        }
+        let Some(bytes) = db.get(&key, EMPTY_PREFIX) else {
+            abort!(key = key.as_bytes(), "missing node {key:?}");
+        };
+        stats.nodes += 1;
+        stats.bytes += bytes.len() as u64;
+        *stats.counters.entry(counter(&key)).or_default() += 1;
+        if is_value {
+            stats.values += 1;
+            continue;
+        }
+        match L::Codec::decode_plan(&bytes) {
+            Ok(NodePlan::Leaf { .. }) => stats.leaves += 1,
+            Ok(NodePlan::Extension { .. }) => stats.extensions += 1,
+            Ok(NodePlan::Branch { .. } | NodePlan::NibbledBranch { .. }) => stats.branches += 1,
+            Ok(NodePlan::Empty) => {},
+            Err(_) => abort!("decode_plan failed"),
+        }
+        push_references::<L>(&bytes, &mut stack);
+    }
+    stats
+}
+
+/// Levels counted apart by [`TrieShape::depths`], deeper nodes are counted in the last one.
+pub const SHAPE_DEPTHS: usize = 16;
+
+/// Shape of the trie as seen by lookups, a node shared by several parents is counted at each
+/// of its positions and the inline nodes are counted as well.
+pub struct TrieShape {
+    /// Number of nodes of each [`NodeKind`], by declaration order.
+    pub kinds: [u32; 5],
+    /// Number of nodes at each level below the root.
+    pub depths: [u32; SHAPE_DEPTHS],
+    /// Number of branches for each number of children, from 0 to 16.
+    pub branching: [u32; 17],
+    pub values: u32,
+    /// Sum of the levels of the nodes holding a value.
+    pub value_depths: u64,
+    /// Level of the deepest node holding a value.
+    pub max_value_depth: u32,
+}
+
+impl TrieShape {
+    /// Encodes the fields in declaration order as little endian integers, 168 bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(168);
+        let counts = self.kinds.iter().chain(&self.depths).chain(&self.branching);
+        for count in counts.chain([&self.values]) {
+            output.extend_from_slice(&count.to_le_bytes());
+        }
+        output.extend_from_slice(&self.value_depths.to_le_bytes());
+        output.extend_from_slice(&self.max_value_depth.to_le_bytes());
+        output
+    }
+}
+
+/// Walks every position of the trie at `root`, see [`TrieShape`].
+pub fn shape<L: TrieLayout>(db: &dyn HashDB<L::Hash, DBValue>, root: &H256) -> TrieShape {
+    let mut shape = TrieShape {
+        kinds: [0; 5],
+        depths: [0; SHAPE_DEPTHS],
+        branching: [0; 17],
+        values: 0,
+        value_depths: 0,
+        max_value_depth: 0,
+    };
+    if root == &hashed_null_node::<L>() {
+        return shape;
+    }
+    let Some(bytes) = db.get(root, EMPTY_PREFIX) else {
+        abort!(key = root.as_bytes(), "no value for the root key: {root:?}");
+    };
+    let mut stack = vec![(bytes, 0u32)];
+    while let Some((bytes, level)) = stack.pop() {
+        let Ok(plan) = L::Codec::decode_plan(&bytes) else {
+            abort!("decode_plan failed");
+        };
+        let (kind, has_value, children) = match plan {
+            NodePlan::Empty => (NodeKind::Empty, false, Default::default()),
+            NodePlan::Leaf { .. } => (NodeKind::Leaf, true, Default::default()),
+            NodePlan::Extension { child, .. } => {
+                let mut children: [Option<NodeHandlePlan>; 16] = Default::default();
+                children[0] = Some(child);
+                (NodeKind::Extension, false, children)
+            },
+            NodePlan::Branch { value, children } => (NodeKind::Branch, value.is_some(), children),
+            NodePlan::NibbledBranch { value, children, .. } => {
+                (NodeKind::NibbledBranch, value.is_some(), children)
+            },
+        };
+        shape.kinds[kind as usize] += 1;
+        shape.depths[(level as usize).min(SHAPE_DEPTHS - 1)] += 1;
+        if matches!(kind, NodeKind::Branch | NodeKind::NibbledBranch) {
+            shape.branching[children.iter().flatten().count()] += 1;
+        }
+        if has_value {
+            shape.values += 1;
+            shape.value_depths += u64::from(level);
+            shape.max_value_depth = shape.max_value_depth.max(level);
+        }
+        for child in children.into_iter().flatten() {
+            let child = match child {
+                NodeHandlePlan::Hash(range) => {
+                    let key = H256::from_slice(&bytes[range]);
+                    let Some(child) = db.get(&key, EMPTY_PREFIX) else {
+                        abort!(key = key.as_bytes(), "missing node {key:?}");
+                    };
+                    child
+                },
+                NodeHandlePlan::Inline(range) => bytes[range].to_vec(),
+            };
+            stack.push((child, level + 1));
+        }
+    }
+    shape
+}
+
+/// Reads the value of `key` in the trie at `root`, which doesn't need to be the current root.
+pub fn read_value<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    key: &[u8],
+) -> CallResult<Option<Vec<u8>>> {
+    TrieDBBuilder::<L>::new(&db, root)
+        .build()
+        .get(&trie_key::<L>(key))
+        .map_err(|error| call_error(error.as_ref()))
+}
+
+/// Whether `key` has a value in the trie at `root`, read without opening the trie for writes.
+pub fn contains_key<L: TrieLayout>(
+    db: &dyn HashDB<L::Hash, DBValue>,
+    root: &H256,
+    key: &[u8],
+) -> CallResult<bool> {
+    TrieDBBuilder::<L>::new(&db, root)
+        .build()
+        .contains(&trie_key::<L>(key))
+        .map_err(|error| call_error(error.as_ref()))
+}
+
+/// Byte length of the value of `key` in the trie at `root`. Values stored in a node of their
+/// own are measured by the storage without being read.
+pub fn value_size<L: TrieLayout, B: TrieBackend>(
+    db: &ExternalDB<B>,
+    root: &H256,
+    key: &[u8],
+) -> CallResult<Option<u32>> {
+    let key = trie_key::<L>(key);
+    let hash_db: &dyn HashDB<L::Hash, DBValue> = db;
+    let trie = TrieDBBuilder::<L>::new(&hash_db, root).build();
+    let Some(hash) = trie.get_hash(&key).map_err(|error| call_error(error.as_ref()))? else {
+        return Ok(None);
+    };
+    // An inline value whose hash is stored has the same bytes as the stored node.
+    if let Some(size) = db.node_size(&hash) {
+        return Ok(Some(size));
+    }
+    trie.get_with(&key, |value: &[u8]| value.len() as u32)
+        .map_err(|error| call_error(error.as_ref()))
+}
+
+/// Kind of a decoded node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeKind {
+    Empty,
+    Leaf,
+    Extension,
+    Branch,
+    NibbledBranch,
+}
+
+impl NodeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Empty => "empty",
+            Self::Leaf => "leaf",
+            Self::Extension => "extension",
+            Self::Branch => "branch",
+            Self::NibbledBranch => "nibbledBranch",
+        }
+    }
+}
+
+/// Change of a node between two roots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeStatus {
+    Unchanged,
+    Added,
+    Removed,
+    Modified,
+}
+
+impl NodeStatus {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unchanged => "unchanged",
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Modified => "modified",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct TrieNode {
     pub id: Option<H256>,
-    pub nibbles: Option<NibbleSlice<'static>>,
+    pub kind: NodeKind,
+    /// Levels between the node and the first listed node, inline nodes count as a level.
+    pub depth: u32,
+    /// Partial key of the node within [`TrieNode::raw_bytes`], see [`TrieNode::nibbles`].
+    pub partial: Option<NibbleSlicePlan>,
     pub value: Option<Vec<u8>>,
     pub children: Children,
-    pub raw_bytes: Option<&'static [u8]>,
+    /// Encoded node, as stored for the hashed nodes. The node owns it, so the buffers of a
+    /// listing are freed with its [`NodeArena`].
+    pub raw_bytes: Vec<u8>,
+    pub highlighted: bool,
+    /// Whether children stored under their hash were left out by the depth limit.
+    pub truncated: bool,
+    /// Change of the node between two roots, only set by [`diff_nodes`].
+    pub status: Option<NodeStatus>,
+    /// Reference counter of the stored node, `0` for the inline nodes, only read by
+    /// [`decode_nodes`].
+    pub references: i32,
+    /// Key nibbles matched up to the end of the node partial key, when the node is on the
+    /// route of a lookup, see [`mark_lookup`].
+    pub matched_nibbles: Option<u32>,
+    /// Nibbles of the single-child nodes collapsed into this one, one per byte, preceding the
+    /// partial key of the node, see [`collapse_paths`].
+    pub collapsed: Vec<u8>,
+}
+
+impl TrieNode {
+    /// Partial key of the node, `None` when it has none or it is empty.
+    pub fn nibbles(&self) -> Option<NibbleSlice<'_>> {
+        self.partial
+            .as_ref()
+            .and_then(|partial| nibble_to_str(partial, &self.raw_bytes))
+    }
 }
 
-fn decode_recursive(
-    bytes: &'static [u8],
+/// Decoded nodes, each referenced by the [`NodeId`] it got when pushed. The nodes are never
+/// removed, so the id of a node stays valid as long as its arena.
+#[derive(Clone, Default)]
+pub struct NodeArena {
+    nodes: Vec<TrieNode>,
+}
+
+impl NodeArena {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { nodes: Vec::with_capacity(capacity) }
+    }
+
+    pub fn push(&mut self, node: TrieNode) -> NodeId {
+        let id = NodeId::new(self.nodes.len());
+        self.nodes.push(node);
+        id
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&TrieNode> {
+        self.nodes.get(id.index())
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Ids of the nodes, in the order they were pushed.
+    pub fn ids(&self) -> impl DoubleEndedIterator<Item = NodeId> {
+        (0..self.nodes.len()).map(NodeId::new)
+    }
+
+    pub fn iter(&self) -> core::slice::Iter<'_, TrieNode> {
+        self.nodes.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> core::slice::IterMut<'_, TrieNode> {
+        self.nodes.iter_mut()
+    }
+
+    /// Moves the nodes of `other` after the ones of the arena, keeping the links between them.
+    /// Returns the id `node`, a node of `other`, gets in the arena.
+    pub fn append(&mut self, other: NodeArena, node: NodeId) -> NodeId {
+        let offset = self.nodes.len();
+        for mut node in other.nodes {
+            node.children.offset(offset);
+            self.nodes.push(node);
+        }
+        NodeId::new(node.index() + offset)
+    }
+}
+
+impl Index<NodeId> for NodeArena {
+    type Output = TrieNode;
+
+    fn index(&self, id: NodeId) -> &TrieNode {
+        match self.nodes.get(id.index()) {
+            Some(node) => node,
+            None => abort!("child at index {id} not found"),
+        }
+    }
+}
+
+impl IndexMut<NodeId> for NodeArena {
+    fn index_mut(&mut self, id: NodeId) -> &mut TrieNode {
+        match self.nodes.get_mut(id.index()) {
+            Some(node) => node,
+            None => abort!("child at index {id} not found"),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a NodeArena {
+    type Item = &'a TrieNode;
+    type IntoIter = core::slice::Iter<'a, TrieNode>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Decodes the node itself, without its children.
+fn decode_node<L: TrieLayout>(
+    bytes: Vec<u8>,
     node_id: Option<H256>,
-    db: &dyn HashDB<Blake2Hasher, DBValue>,
-    nodes: &mut Vec<TrieNode>,
-) -> usize {
-    let raw_bytes = if node_id.is_none() {
-        Some(bytes)
-    } else {
-        None
+    level: u32,
+) -> (TrieNode, NodePlan) {
+    let Ok(plan) = L::Codec::decode_plan(&bytes) else {
+        abort!("decode_plan failed");
+    };
+    let (kind, partial, value) = match &plan {
+        NodePlan::Empty => (NodeKind::Empty, None, None),
+        NodePlan::Leaf { partial, value } => (NodeKind::Leaf, Some(partial), Some(value)),
+        NodePlan::Extension { partial, .. } => (NodeKind::Extension, Some(partial), None),
+        NodePlan::Branch { value, .. } => (NodeKind::Branch, None, value.as_ref()),
+        NodePlan::NibbledBranch { partial, value, .. } => {
+            (NodeKind::NibbledBranch, Some(partial), value.as_ref())
+        },
+    };
+    let node = TrieNode {
+        id: node_id,
+        kind,
+        depth: level,
+        partial: partial.cloned(),
+        value: value.map(|value| Vec::<u8>::from(value_to_bytes(value, &bytes))),
+        children: Children::new(),
+        raw_bytes: bytes,
+        highlighted: false,
+        truncated: false,
+        status: None,
+        references: 0,
+        matched_nibbles: None,
+        collapsed: Vec::new(),
     };
-    if let Ok(node) = NodeCodec::<Blake2Hasher>::decode_plan(bytes) {
-        let node = match node {
-            NodePlan::Empty => {
-                TrieNode {
-                    id: node_id,
-                    nibbles: None,
-                    value: None,
-                    children: Children::new(),
-                    raw_bytes,
+    (node, plan)
+}
+
+/// Node whose children are being decoded by [`decode_tree`].
+struct PendingNode {
+    node: TrieNode,
+    /// Nibble under which the node is linked to its parent, `None` for the child of an
+    /// extension node.
+    partial: Option<u8>,
+    /// Children left to decode, in reverse order.
+    children: Vec<(Option<u8>, NodeHandlePlan)>,
+    /// Levels left to decode below the node.
+    depth: u32,
+}
+
+impl PendingNode {
+    /// Decodes the node in `bytes`, reading ahead the children stored under their hash.
+    fn open<L: TrieLayout>(
+        bytes: Vec<u8>,
+        node_id: Option<H256>,
+        partial: Option<u8>,
+        db: &dyn NodeSource<L::Hash>,
+        nodes: &DecodedNodes,
+        depth: u32,
+        level: u32,
+    ) -> Self {
+        let (node, plan) = decode_node::<L>(bytes, node_id, level);
+        let mut children = Vec::new();
+        match plan {
+            NodePlan::Branch { children: plans, .. } |
+            NodePlan::NibbledBranch { children: plans, .. } => {
+                let keys = plans
+                    .iter()
+                    .filter_map(|child| match child {
+                        Some(NodeHandlePlan::Hash(range)) => {
+                            Some(H256::from_slice(&node.raw_bytes[range.clone()]))
+                        },
+                        _ => None,
+                    })
+                    .filter(|key| nodes.find(key).is_none())
+                    .collect::<Vec<_>>();
+                if keys.len() > 1 && depth > 0 {
+                    db.prefetch(&keys);
+                }
+                for (partial, child) in plans.into_iter().enumerate().rev() {
+                    if let Some(child) = child {
+                        children.push((Some(partial as u8), child));
+                    }
                 }
             },
-            NodePlan::Leaf { partial, value } => TrieNode {
-                id: node_id,
-                nibbles: nibble_to_str(partial, bytes),
-                value: Some(Vec::<u8>::from(value_to_bytes(&value, bytes))),
-                children: Children::new(),
-                raw_bytes,
-            },
-            NodePlan::Branch { value, children } => {
-                let mut parent = TrieNode {
-                    id: node_id,
-                    nibbles: None,
-                    value: value.map(|range| Vec::<u8>::from(value_to_bytes(&range, bytes))),
-                    children: Children::new(),
-                    raw_bytes,
-                };
-                decode_children_recursive(&mut parent, children, bytes, db, nodes);
-                parent
+            NodePlan::Extension { child, .. } => children.push((None, child)),
+            NodePlan::Empty | NodePlan::Leaf { .. } => {},
+        }
+        Self { node, partial, children, depth }
+    }
+
+    fn link(&mut self, index: NodeId, partial: Option<u8>) {
+        match partial {
+            Some(partial) => self.node.children.push(index, partial),
+            None => self.node.children.set_extension(index),
+        }
+    }
+}
+
+/// Decodes the node in `bytes` and its subtree down to `depth` levels, returning the index of
+/// the node. A child stored under its hash is only read while levels are left, otherwise its
+/// parent is marked as truncated.
+///
+/// The nodes are pushed after their children, walking the trie with a stack of its own rather
+/// than recursion, so a deep trie can't overflow the call stack of the wasm module.
+fn decode_tree<L: TrieLayout>(
+    bytes: Vec<u8>,
+    node_id: Option<H256>,
+    db: &dyn NodeSource<L::Hash>,
+    nodes: &mut DecodedNodes,
+    depth: u32,
+    level: u32,
+) -> NodeId {
+    let root = PendingNode::open::<L>(bytes, node_id, None, db, nodes, depth, level);
+    let mut stack = Vec::from([root]);
+    while let Some(mut pending) = stack.pop() {
+        let Some((partial, child)) = pending.children.pop() else {
+            let index = nodes.push(pending.node);
+            match stack.last_mut() {
+                Some(parent) => parent.link(index, pending.partial),
+                None => return index,
+            }
+            continue;
+        };
+        let bytes = &pending.node.raw_bytes;
+        let level = pending.node.depth + 1;
+        let child = match child {
+            NodeHandlePlan::Hash(_) if pending.depth == 0 => {
+                pending.node.truncated = true;
+                None
             },
-            NodePlan::NibbledBranch { partial, value, children } => {
-                let mut parent = TrieNode {
-                    id: node_id,
-                    nibbles: nibble_to_str(partial, bytes),
-                    value: value.map(|range| Vec::<u8>::from(value_to_bytes(&range, bytes))),
-                    children: Children::new(),
-                    raw_bytes,
+            NodeHandlePlan::Hash(range) => {
+                let key = {
+                    let bytes = &bytes[range.start..range.end];
+                    let bytes = match TryInto::<&[u8; 32]>::try_into(bytes) {
+                        Ok(bytes) => bytes,
+                        Err(error) => abort!("{error}"),
+                    };
+                    H256(*bytes)
                 };
-                decode_children_recursive(&mut parent, children, bytes, db, nodes);
-                parent
+                if let Some(index) = nodes.find(&key) {
+                    pending.link(index, partial);
+                    None
+                } else if let Some(value) = db.get(&key, EMPTY_PREFIX) {
+                    let depth = pending.depth - 1;
+                    Some(PendingNode::open::<L>(value, Some(key), partial, db, nodes, depth, level))
+                } else {
+                    abort!("invalid children");
+                }
             },
-            NodePlan::Extension { partial, child } => {
-                let mut parent = TrieNode {
-                    id: node_id,
-                    nibbles: nibble_to_str(partial, bytes),
-                    value: None,
-                    children: Children::new(),
-                    raw_bytes,
-                };
-                decode_child_recursive(&mut parent, child, None, bytes, db, nodes);
-                parent
+            NodeHandlePlan::Inline(range) => {
+                let bytes = bytes[range.start..range.end].to_vec();
+                let depth = pending.depth.saturating_sub(1);
+                Some(PendingNode::open::<L>(bytes, None, partial, db, nodes, depth, level))
             },
         };
-        let id = nodes.len();
-        nodes.push(node);
-        id
-    } else {
-        abort!("decode_plan failed");
+        stack.push(pending);
+        stack.extend(child);
     }
+    abort!("[bug] the root node was never pushed");
 }
 
-pub struct MerklePatriciaTrie<'a> {
-    trie: TrieDBMut<'a>,
+pub struct MerklePatriciaTrie<'a, L: TrieLayout> {
+    trie: TrieDBMut<'a, L>,
 }
 
-impl<'a> MerklePatriciaTrie<'a> {
-    pub fn new(trie: TrieDBMut<'a>) -> Self {
+impl<'a, L: TrieLayout> MerklePatriciaTrie<'a, L> {
+    pub fn new(trie: TrieDBMut<'a, L>) -> Self {
         Self { trie }
     }
-    fn extract_input(input: &mut [u8]) -> (Vec<u8>, &mut [u8]) {
-        let Some((key_len, input)) = input.split_first_chunk_mut::<4>() else {
-            abort!("invalid key");
+
+    pub fn open(db: &'a mut dyn HashDB<L::Hash, DBValue>, root: &'a mut H256) -> Self {
+        Self::new(TrieDBMutBuilder::<L>::from_existing(db, root).build())
+    }
+
+    /// Splits a length-prefixed key or value from the beginning of the input.
+    pub(crate) fn extract_input(input: &[u8]) -> CallResult<(&[u8], &[u8])> {
+        let Some((key_len, input)) = input.split_first_chunk::<4>() else {
+            return fail!(CallError::InvalidInput, "invalid key");
         };
         let key_len = u32::from_le_bytes(*key_len) as usize;
-        let Some((key, rest)) = input.split_at_mut_checked(key_len) else {
-            abort!("key out of bounds");
+        let Some((key, rest)) = input.split_at_checked(key_len) else {
+            return fail!(CallError::InvalidInput, "key out of bounds");
         };
-        let vec = unsafe { Vec::from_raw_parts(key.as_mut_ptr(), key.len(), key.len()) };
-        (vec, rest)
+        Ok((key, rest))
     }
 
-    pub fn insert(&mut self, input: &mut [u8]) {
-        let (key, input) = Self::extract_input(input);
-        let (value, input) = Self::extract_input(input);
+    pub fn insert(&mut self, input: &[u8]) -> CallResult<()> {
+        let (key, input) = Self::extract_input(input)?;
+        let (value, input) = Self::extract_input(input)?;
         if !input.is_empty() {
-            abort!("invalid input");
-        }
-        match self.trie.insert(&key, &value) {
-            Ok(_) => self.trie.commit(),
-            Err(error) => abort_on_trie_error(error.as_ref()),
+            return fail!(CallError::InvalidInput, "invalid input");
         }
+        self.trie
+            .insert(&trie_key::<L>(key), value)
+            .map_err(|error| call_error(error.as_ref()))?;
+        self.trie.commit();
+        Ok(())
     }
 
-    pub fn remove(&mut self, key: &mut [u8]) {
-        let key = unsafe { Vec::from_raw_parts(key.as_mut_ptr(), key.len(), key.len()) };
-        match self.trie.remove(&key) {
-            Ok(_) => self.trie.commit(),
-            Err(error) => abort_on_trie_error(error.as_ref()),
+    /// Applies a sequence of operations, each one encoded as an op byte (`0` insert, `1` remove)
+    /// followed by the length-prefixed key and, for inserts, the length-prefixed value.
+    pub fn apply_batch(&mut self, mut input: &[u8]) -> CallResult<()> {
+        while let Some((&op, rest)) = input.split_first() {
+            let (key, rest) = Self::extract_input(rest)?;
+            let result = match op {
+                0 => {
+                    let (value, rest) = Self::extract_input(rest)?;
+                    input = rest;
+                    self.trie.insert(&trie_key::<L>(key), value).map(drop)
+                },
+                1 => {
+                    input = rest;
+                    self.trie.remove(&trie_key::<L>(key)).map(drop)
+                },
+                op => return fail!(CallError::InvalidInput, "invalid batch operation: {op}"),
+            };
+            result.map_err(|error| call_error(error.as_ref()))?;
         }
+        self.trie.commit();
+        Ok(())
     }
 
-    pub fn get(&self, key: &mut [u8]) -> Option<Vec<u8>> {
-        let key = unsafe { Vec::from_raw_parts(key.as_mut_ptr(), key.len(), key.len()) };
-        match self.trie.get(&key) {
-            Ok(value) => value,
-            Err(error) => abort_on_trie_error(error.as_ref()),
+    /// Imports every entry of the trie at `theirs` missing in this trie. Keys present in both
+    /// tries keep our value, unless `take_theirs` is set. Returns the number of keys written.
+    pub fn merge(&mut self, theirs: &H256, take_theirs: bool) -> CallResult<u32> {
+        let ours = *self.trie.root();
+        let mut count = 0;
+        for change in diff::changes::<L>(self.trie.db(), &ours, theirs)? {
+            let (key, value) = match change {
+                Change::Added { key, value } => (key, value),
+                Change::Modified { key, new, .. } if take_theirs => (key, new),
+                Change::Modified { .. } | Change::Removed { .. } => continue,
+            };
+            // Keys read from the trie are already hashed in secure mode.
+            self.trie.insert(&key, &value).map_err(|error| call_error(error.as_ref()))?;
+            count += 1;
         }
+        self.trie.commit();
+        Ok(count)
     }
 
-    pub fn exists(&self, key: &mut [u8]) -> bool {
-        let key = unsafe { Vec::from_raw_parts(key.as_mut_ptr(), key.len(), key.len()) };
-        match self.trie.contains(&key) {
-            Ok(exists) => exists,
-            Err(error) => abort_on_trie_error(error.as_ref()),
-        }
+    pub fn remove(&mut self, key: &[u8]) -> CallResult<()> {
+        self.trie
+            .remove(&trie_key::<L>(key))
+            .map_err(|error| call_error(error.as_ref()))?;
+        self.trie.commit();
+        Ok(())
     }
 
-    pub fn nodes(&mut self) -> JSMerklePatriciaTrie {
-        let root_key = *self.trie.root();
-        let mut nodes = Vec::<TrieNode>::with_capacity(512);
-        let Some(root_data) = self.trie.db().get(&root_key, EMPTY_PREFIX) else {
-            abort!("no value for the root key: {root_key:?}");
+    /// Removes `key`, returning its value.
+    pub fn take(&mut self, key: &[u8]) -> CallResult<Option<Vec<u8>>> {
+        let key = trie_key::<L>(key);
+        let Some(value) = self.trie.get(&key).map_err(|error| call_error(error.as_ref()))? else {
+            return Ok(None);
         };
-        let root_data = root_data.leak();
-        let index = decode_recursive(root_data, Some(root_key), self.trie.db(), &mut nodes);
-        let Some(node) = nodes.get(index) else {
-            abort!("invalid node index {index}");
-        };
-        JSMerklePatriciaTrie::new(node, &nodes)
+        self.trie.remove(&key).map_err(|error| call_error(error.as_ref()))?;
+        self.trie.commit();
+        Ok(Some(value))
     }
 
     pub fn root(&mut self) -> H256 {
@@ -278,18 +880,756 @@ impl<'a> MerklePatriciaTrie<'a> {
     }
 }
 
+/// Lists the nodes of the trie at `root` down to `max_depth` levels below it, highlighting the
+/// ones whose hash is in `path`.
+pub fn nodes_with_path<L: TrieLayout>(
+    db: &dyn NodeSource<L::Hash>,
+    root: &H256,
+    path: &[H256],
+    max_depth: u32,
+    encoding: ByteEncoding,
+) -> JSMerklePatriciaTrie {
+    let (index, nodes) = decode_nodes::<L>(db, root, path, max_depth);
+    JSMerklePatriciaTrie::new(&nodes[index], &nodes, encoding)
+}
+
+/// Visits the nodes of the trie at `root` depth-first, parents before their children, without
+/// keeping the visited nodes. `visit` gets the index of the node in visiting order, the index
+/// of its parent and the nibble labeling it, `None` for the root and the child of an extension,
+/// the node hash unless it is inlined, its partial key and its value. Returns the number of
+/// nodes visited.
+pub fn stream_nodes<L: TrieLayout>(
+    db: &dyn NodeSource<L::Hash>,
+    root: &H256,
+    mut visit: impl FnMut(u32, Option<u32>, Option<u8>, Option<&H256>, NibbleSlice, Option<&[u8]>),
+) -> u32 {
+    let Some(root_data) = db.get(root, EMPTY_PREFIX) else {
+        abort!(key = root.as_bytes(), "no value for the root key: {root:?}");
+    };
+    let mut stack = vec![(root_data, Some(*root), None, None)];
+    let mut index = 0;
+    while let Some((bytes, id, parent, nibble)) = stack.pop() {
+        let Ok(plan) = L::Codec::decode_plan(&bytes) else {
+            abort!("decode_plan failed");
+        };
+        let is_extension = matches!(plan, NodePlan::Extension { .. });
+        let (partial, value, children) = match plan {
+            NodePlan::Empty => (None, None, Default::default()),
+            NodePlan::Leaf { partial, value } => (Some(partial), Some(value), Default::default()),
+            NodePlan::Extension { partial, child } => {
+                let mut children: [Option<NodeHandlePlan>; 16] = Default::default();
+                children[0] = Some(child);
+                (Some(partial), None, children)
+            },
+            NodePlan::Branch { value, children } => (None, value, children),
+            NodePlan::NibbledBranch { partial, value, children } => {
+                (Some(partial), value, children)
+            },
+        };
+        let partial = partial.map(|partial| partial.build(&bytes)).unwrap_or(NibbleSlice::new(&[]));
+        let value = value.as_ref().map(|value| value_to_bytes(value, &bytes));
+        visit(index, parent, nibble, id.as_ref(), partial, value);
+        for (label, child) in children.into_iter().enumerate().rev() {
+            let Some(child) = child else {
+                continue;
+            };
+            let (child, child_id) = match child {
+                NodeHandlePlan::Hash(range) => {
+                    let key = H256::from_slice(&bytes[range]);
+                    let Some(child) = db.get(&key, EMPTY_PREFIX) else {
+                        abort!(key = key.as_bytes(), "missing node {key:?}");
+                    };
+                    (child, Some(key))
+                },
+                NodeHandlePlan::Inline(range) => (bytes[range].to_vec(), None),
+            };
+            let label = (!is_extension).then_some(label as u8);
+            stack.push((child, child_id, Some(index), label));
+        }
+        index += 1;
+    }
+    index
+}
+
+/// Encodes the nodes of the trie at `root` as JSON, see [`crate::json`].
+pub fn nodes_json<L: TrieLayout>(db: &dyn NodeSource<L::Hash>, root: &H256) -> String {
+    let (index, nodes) = decode_nodes::<L>(db, root, &[], u32::MAX);
+    crate::json::encode(index, &nodes)
+}
+
+/// Encodes the nodes of the trie at `root` as CBOR, see [`crate::cbor`].
+pub fn nodes_cbor<L: TrieLayout>(db: &dyn NodeSource<L::Hash>, root: &H256) -> Vec<u8> {
+    let (index, nodes) = decode_nodes::<L>(db, root, &[], u32::MAX);
+    crate::cbor::encode(index, &nodes)
+}
+
+/// Renders the nodes of the trie at `root` as a Graphviz DOT document, see [`crate::dot`].
+pub fn nodes_dot<L: TrieLayout>(db: &dyn NodeSource<L::Hash>, root: &H256) -> String {
+    let (index, nodes) = decode_nodes::<L>(db, root, &[], u32::MAX);
+    crate::dot::encode(index, &nodes)
+}
+
+/// Decodes the nodes of the trie at `root` down to `max_depth` levels below it, highlighting
+/// the ones whose hash is in `path`. Returns the index of the root node and the nodes.
+pub fn decode_nodes<L: TrieLayout>(
+    db: &dyn NodeSource<L::Hash>,
+    root: &H256,
+    path: &[H256],
+    max_depth: u32,
+) -> (NodeId, NodeArena) {
+    let mut decoded = DecodedNodes::with_capacity(512);
+    let Some(root_data) = db.get(root, EMPTY_PREFIX) else {
+        abort!(key = root.as_bytes(), "no value for the root key: {root:?}");
+    };
+    let index = decode_tree::<L>(root_data, Some(*root), db, &mut decoded, max_depth, 0);
+    let mut nodes = decoded.nodes;
+    let ids = nodes.iter().filter_map(|node| node.id).collect::<Vec<_>>();
+    let mut counters = db.counters(&ids).into_iter();
+    for node in nodes.iter_mut() {
+        node.highlighted = node.id.as_ref().is_some_and(|id| path.contains(id));
+        if node.id.is_some() {
+            node.references = counters.next().unwrap_or_default();
+        }
+    }
+    if nodes.get(index).is_none() {
+        abort!("invalid node index {index}");
+    }
+    (index, nodes)
+}
+
+/// Encoded node referenced by a parent, and its hash unless it is inlined.
+type NodeHandle = (Vec<u8>, Option<H256>);
+
+fn load_node(db: &dyn NodeSource<impl Hasher<Out = H256>>, hash: &H256) -> Vec<u8> {
+    let Some(bytes) = db.get(hash, EMPTY_PREFIX) else {
+        abort!(key = hash.as_bytes(), "missing node {hash:?}");
+    };
+    bytes
+}
+
+fn load_handle<L: TrieLayout>(
+    db: &dyn NodeSource<L::Hash>,
+    child: NodeHandlePlan,
+    bytes: &[u8],
+) -> NodeHandle {
+    match child {
+        NodeHandlePlan::Hash(range) => {
+            let hash = H256::from_slice(&bytes[range]);
+            (load_node(db, &hash), Some(hash))
+        },
+        NodeHandlePlan::Inline(range) => (bytes[range].to_vec(), None),
+    }
+}
+
+/// Children of a decoded node, labeled by a nibble or the single child of an extension.
+fn child_handles<L: TrieLayout>(
+    db: &dyn NodeSource<L::Hash>,
+    plan: NodePlan,
+    bytes: &[u8],
+) -> ([Option<NodeHandle>; 16], Option<NodeHandle>) {
+    match plan {
+        NodePlan::Branch { children, .. } | NodePlan::NibbledBranch { children, .. } => {
+            (children.map(|child| child.map(|child| load_handle::<L>(db, child, bytes))), None)
+        },
+        NodePlan::Extension { child, .. } => {
+            (Default::default(), Some(load_handle::<L>(db, child, bytes)))
+        },
+        NodePlan::Empty | NodePlan::Leaf { .. } => (Default::default(), None),
+    }
+}
+
+/// Decodes the subtree of `node` down to `depth` levels, every node tagged with `status`.
+fn decode_tagged<L: TrieLayout>(
+    node: NodeHandle,
+    db: &dyn NodeSource<L::Hash>,
+    nodes: &mut NodeArena,
+    depth: u32,
+    level: u32,
+    status: NodeStatus,
+) -> NodeId {
+    // Decoded apart, so the subtree doesn't link to a node of the other trie sharing its hash.
+    let mut subtree = DecodedNodes::with_capacity(0);
+    let index = decode_tree::<L>(node.0, node.1, db, &mut subtree, depth, level);
+    for node in subtree.nodes.iter_mut() {
+        node.status = Some(status);
+    }
+    nodes.append(subtree.nodes, index)
+}
+
+fn diff_recursive<L: TrieLayout>(
+    old: Option<NodeHandle>,
+    new: Option<NodeHandle>,
+    db: &dyn NodeSource<L::Hash>,
+    nodes: &mut NodeArena,
+    level: u32,
+) -> Option<NodeId> {
+    let (old, new) = match (old, new) {
+        (None, None) => return None,
+        (Some(old), None) => {
+            return Some(decode_tagged::<L>(old, db, nodes, u32::MAX, level, NodeStatus::Removed))
+        },
+        (None, Some(new)) => {
+            return Some(decode_tagged::<L>(new, db, nodes, u32::MAX, level, NodeStatus::Added))
+        },
+        (Some(old), Some(new)) => (old, new),
+    };
+    if old == new {
+        // Shared subtree, its children stored under their hash are left truncated.
+        return Some(decode_tagged::<L>(new, db, nodes, 0, level, NodeStatus::Unchanged));
+    }
+    let (old, old_plan) = decode_node::<L>(old.0, old.1, level);
+    let (mut node, new_plan) = decode_node::<L>(new.0, new.1, level);
+    node.status = Some(NodeStatus::Modified);
+    let (old_children, old_extension) = child_handles::<L>(db, old_plan, &old.raw_bytes);
+    let (new_children, new_extension) = child_handles::<L>(db, new_plan, &node.raw_bytes);
+    if new_extension.is_some() {
+        if let Some(index) = diff_recursive::<L>(old_extension, new_extension, db, nodes, level + 1)
+        {
+            node.children.set_extension(index);
+        }
+    } else {
+        let children = old_children.into_iter().zip(new_children);
+        for (nibble, (old, new)) in children.enumerate() {
+            if let Some(index) = diff_recursive::<L>(old, new, db, nodes, level + 1) {
+                node.children.push(index, nibble as u8);
+            }
+        }
+        if node.children.is_empty() {
+            if let Some(index) = diff_recursive::<L>(old_extension, None, db, nodes, level + 1) {
+                node.children.set_extension(index);
+            }
+        }
+    }
+    Some(nodes.push(node))
+}
+
+/// Merges the tries at `old` and `new` in one graph, each node tagged with its
+/// [`NodeStatus`]: nodes at the same position with different hashes are modified, and their
+/// children are compared in turn, while shared subtrees are listed as unchanged without
+/// being loaded. The nodes of `new` are listed in place of the modified ones, and the children
+/// of an old node that don't fit the new one, when an extension became a branch or back, are
+/// left out. Returns the index of the root node and the nodes.
+pub fn diff_nodes<L: TrieLayout>(
+    db: &dyn NodeSource<L::Hash>,
+    old: &H256,
+    new: &H256,
+) -> (NodeId, NodeArena) {
+    let mut nodes = NodeArena::with_capacity(512);
+    let old = (load_node(db, old), Some(*old));
+    let new = (load_node(db, new), Some(*new));
+    let Some(index) = diff_recursive::<L>(Some(old), Some(new), db, &mut nodes, 0) else {
+        abort!("[bug] missing diff root");
+    };
+    (index, nodes)
+}
+
+/// The single child of a node without value, with the nibble labeling it in a branch.
+fn single_child(node: &TrieNode) -> Option<(NodeId, Option<u8>)> {
+    if node.value.is_some() || node.truncated {
+        return None;
+    }
+    if let Some(child) = node.children.extension() {
+        return Some((child, None));
+    }
+    let mut children = node.children.iter();
+    match (children.next(), children.next()) {
+        (Some((child, nibble)), None) => Some((child, Some(nibble))),
+        _ => None,
+    }
+}
+
+fn collapse_recursive(
+    nodes: &NodeArena,
+    mut index: NodeId,
+    level: u32,
+    output: &mut NodeArena,
+) -> NodeId {
+    let mut collapsed = Vec::new();
+    while let Some((child, nibble)) = nodes.get(index).and_then(single_child) {
+        if let Some(partial) = nodes[index].nibbles() {
+            collapsed.extend(partial.iter());
+        }
+        collapsed.extend(nibble);
+        index = child;
+    }
+    let node = &nodes[index];
+    let mut children = Children::new();
+    for (child, nibble) in node.children.iter() {
+        children.push(collapse_recursive(nodes, child, level + 1, output), nibble);
+    }
+    if let Some(child) = node.children.extension() {
+        children.set_extension(collapse_recursive(nodes, child, level + 1, output));
+    }
+    output.push(TrieNode { depth: level, children, collapsed, ..node.clone() })
+}
+
+/// Collapses the chains of nodes with a single child and no value into their last node, which
+/// carries the nibbles of the whole chain. A node shared by several parents is copied under
+/// each of them. Returns the index of the root node and the nodes.
+pub fn collapse_paths(index: NodeId, nodes: &NodeArena) -> (NodeId, NodeArena) {
+    let mut output = NodeArena::with_capacity(nodes.len());
+    let index = collapse_recursive(nodes, index, 0, &mut output);
+    (index, output)
+}
+
+/// Marks the nodes read by a lookup of `key`, from the node at `index` down to the leaf or
+/// branch holding the value, or to the node where the key diverges when it is missing.
+pub fn mark_lookup(nodes: &mut NodeArena, mut index: NodeId, key: &[u8]) {
+    let key = NibbleSlice::new(key);
+    let mut matched = 0;
+    loop {
+        let node = &mut nodes[index];
+        let partial = node.nibbles().unwrap_or_else(|| NibbleSlice::new(&[]));
+        let (common, partial_len) = (partial.common_prefix(&key.mid(matched)), partial.len());
+        matched += common;
+        node.matched_nibbles = Some(matched as u32);
+        if common < partial_len {
+            return;
+        }
+        let next = match node.children.extension() {
+            Some(child) => Some(child),
+            None if matched < key.len() => {
+                let nibble = key.at(matched);
+                let child = node.children.iter().find(|(_, at)| *at == nibble);
+                child.map(|(child, _)| {
+                    matched += 1;
+                    child
+                })
+            },
+            None => None,
+        };
+        match next {
+            Some(child) => index = child,
+            None => return,
+        }
+    }
+}
+
+/// Written in place of the branch nibble of a segment when none was consumed, after an
+/// extension node or at the end of the lookup.
+pub const NO_CHILD: u8 = 16;
+
+/// Breaks down the lookup of `key` in the trie at `root`, nibble by nibble. The output holds
+/// the length-prefixed nibbles of the key as stored in the trie, one per byte, then whether the
+/// key has a value and the key nibble where the lookup stopped, as `u32`. Then a segment per
+/// node on the route: its kind, its length-prefixed hash, empty when inlined, the
+/// length-prefixed nibbles of its partial key, how many of them the key matched, as `u32`, and
+/// the branch nibble consumed to reach the next node, see [`NO_CHILD`].
+pub fn key_path<L: TrieLayout>(db: &dyn NodeSource<L::Hash>, root: &H256, key: &[u8]) -> Vec<u8> {
+    let key = trie_key::<L>(key);
+    let (mut index, mut nodes) = decode_nodes::<L>(db, root, &[], u32::MAX);
+    mark_lookup(&mut nodes, index, &key);
+    let key = NibbleSlice::new(&key);
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let (found, stopped) = loop {
+        let node = &nodes[index];
+        let matched = node.matched_nibbles.unwrap_or_default();
+        let partial = node.nibbles().map_or_else(Vec::new, |slice| slice.iter().collect());
+        let next = node.children.extension().map(|child| (child, NO_CHILD)).or_else(|| {
+            node.children.iter().find(|&(child, _)| nodes[child].matched_nibbles.is_some())
+        });
+        segments.push(node.kind as u8);
+        write_bytes(&mut segments, node.id.as_ref().map_or(&[][..], H256::as_bytes));
+        write_bytes(&mut segments, &partial);
+        segments.extend_from_slice(&(matched - start).to_le_bytes());
+        match next.filter(|(child, _)| nodes[*child].matched_nibbles.is_some()) {
+            Some((child, nibble)) => {
+                segments.push(nibble);
+                start = matched + u32::from(nibble != NO_CHILD);
+                index = child;
+            },
+            None => {
+                segments.push(NO_CHILD);
+                let complete = matched - start == partial.len() as u32;
+                break (complete && matched as usize == key.len() && node.value.is_some(), matched);
+            },
+        }
+    };
+
+    let mut output = Vec::with_capacity(key.len() + segments.len() + 9);
+    write_bytes(&mut output, &key.iter().collect::<Vec<_>>());
+    output.push(u8::from(found));
+    output.extend_from_slice(&stopped.to_le_bytes());
+    output.extend_from_slice(&segments);
+    output
+}
+
+/// Lists the nodes of the trie down to `max_depth` levels below the root, the input only holds
+/// the namespace header. The bytes of the nodes are `Uint8Array`s, or hex strings formatted
+/// by `format`, see [`ByteEncoding::from_flags`]. Chains of single-child nodes are merged when
+/// `collapse` is set, see [`collapse_paths`].
 #[wasm_bindgen(js_name = "__ext_list_nodes")]
-pub fn list_nodes() -> JSMerklePatriciaTrie {
-    use crate::storage::ExternalDB;
+pub fn list_nodes(
+    input_len: u32,
+    format: u32,
+    max_depth: u32,
+    collapse: bool,
+) -> JSMerklePatriciaTrie {
+    use crate::storage::{load_input, ExternalDB};
+
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
 
-    // Reset Heap Memory
-    #[cfg(not(feature = "dlmalloc"))]
-    crate::bump_allocator::BumpAllocator::reset();
+    load_input(input_len);
 
     // Load trie root
-    let mut db = ExternalDB;
-    let mut root = db.get_root_hash();
-    let trie_db = TrieDBMutBuilder::<Layout>::from_existing(&mut db, &mut root).build();
-    let mut trie = MerklePatriciaTrie::new(trie_db);
-    trie.nodes()
+    crate::with_layout!(L => {
+        let db = ExternalDB::load::<L>();
+        let root = db.get_root_hash();
+        let (index, nodes) = decode_nodes::<L>(&db, &root, &[], max_depth);
+        let (index, nodes) = if collapse { collapse_paths(index, &nodes) } else { (index, nodes) };
+        let encoding = ByteEncoding::from_flags(format);
+        JSMerklePatriciaTrie::with_hints(&nodes[index], &nodes, encoding, &db.value_hints())
+    })
+}
+
+/// Lists the nodes below the node whose hash is the input, like `__ext_list_nodes`. Expands
+/// a node truncated by the depth limit of a previous listing.
+#[wasm_bindgen(js_name = "__ext_expand_node")]
+pub fn expand_node(input_len: u32, format: u32, max_depth: u32) -> JSMerklePatriciaTrie {
+    use crate::storage::{load_input, ExternalDB};
+
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
+
+    let input = load_input(input_len);
+    let Ok(hash) = <[u8; 32]>::try_from(&input[..]) else {
+        abort!("invalid node hash");
+    };
+    crate::with_layout!(L => {
+        let db = ExternalDB::load::<L>();
+        nodes_with_path::<L>(&db, &H256(hash), &[], max_depth, ByteEncoding::from_flags(format))
+    })
+}
+
+/// Lists the nodes of the child trie whose storage key is the input.
+#[wasm_bindgen(js_name = "__ext_list_child_nodes")]
+pub fn list_child_nodes(input_len: u32, format: u32) -> JSMerklePatriciaTrie {
+    use crate::{
+        child,
+        storage::{load_input, ExternalDB},
+    };
+
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
+
+    let storage_key = load_input(input_len);
+    crate::with_layout!(L => {
+        let db = ExternalDB::load::<L>();
+        let root = db.get_root_hash();
+        let Ok(child_root) = child::root::<L, _>(&db, &root, storage_key) else {
+            abort!("invalid child root");
+        };
+        let child_db = db.child(child::keyspace(storage_key));
+        nodes_with_path::<L>(
+            &child_db,
+            &child_root,
+            &[],
+            u32::MAX,
+            ByteEncoding::from_flags(format),
+        )
+    })
+}
+
+/// Lists the nodes like `__ext_list_nodes`, highlighting the nodes touched by a proof of the
+/// length-prefixed keys in the input.
+#[wasm_bindgen(js_name = "__ext_list_proof_nodes")]
+pub fn list_proof_nodes(input_len: u32, format: u32) -> JSMerklePatriciaTrie {
+    use crate::storage::{load_input, ExternalDB};
+
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
+
+    let input = load_input(input_len);
+    crate::with_layout!(L => {
+        let db = ExternalDB::load::<L>();
+        let root = db.get_root_hash();
+        let Ok(path) = crate::proof::path::<L>(&db, &root, input) else {
+            abort!("invalid proof keys");
+        };
+        let (index, nodes) = decode_nodes::<L>(&db, &root, &path, u32::MAX);
+        let encoding = ByteEncoding::from_flags(format);
+        JSMerklePatriciaTrie::with_hints(&nodes[index], &nodes, encoding, &db.value_hints())
+    })
+}
+
+/// Lists the nodes of the tries at the two roots in the input, the old root followed by the
+/// new one, merged in one graph tagged with the status of each node, see [`diff_nodes`].
+#[wasm_bindgen(js_name = "__ext_list_diff_nodes")]
+pub fn list_diff_nodes(input_len: u32, format: u32) -> JSMerklePatriciaTrie {
+    use crate::storage::{load_input, split_hash, ExternalDB};
+
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
+
+    let input = load_input(input_len);
+    let Ok((old, input)) = split_hash(input) else {
+        abort!("invalid input, expected the old root");
+    };
+    let Ok((new, _)) = split_hash(input) else {
+        abort!("invalid input, expected the new root");
+    };
+    crate::with_layout!(L => {
+        let db = ExternalDB::load::<L>();
+        let (index, nodes) = diff_nodes::<L>(&db, &old, &new);
+        let encoding = ByteEncoding::from_flags(format);
+        JSMerklePatriciaTrie::with_hints(&nodes[index], &nodes, encoding, &db.value_hints())
+    })
+}
+
+/// Lists the nodes like `__ext_list_nodes`, marking the route of a lookup of the key in the
+/// input with the key nibbles matched at each node, see [`mark_lookup`].
+#[wasm_bindgen(js_name = "__ext_list_lookup_nodes")]
+pub fn list_lookup_nodes(input_len: u32, format: u32) -> JSMerklePatriciaTrie {
+    use crate::storage::{load_input, ExternalDB};
+
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
+
+    let input = load_input(input_len);
+    crate::with_layout!(L => {
+        let db = ExternalDB::load::<L>();
+        let root = db.get_root_hash();
+        let (index, mut nodes) = decode_nodes::<L>(&db, &root, &[], u32::MAX);
+        mark_lookup(&mut nodes, index, &trie_key::<L>(input));
+        let encoding = ByteEncoding::from_flags(format);
+        JSMerklePatriciaTrie::with_hints(&nodes[index], &nodes, encoding, &db.value_hints())
+    })
+}
+
+/// Streams the nodes of the trie to `callback` instead of building the whole tree, see
+/// [`stream_nodes`]. The callback is called with the index of the node, the index of its
+/// parent and the nibble labeling it or `null`, the node id, its partial key and its value or
+/// `undefined`, encoded like `__ext_list_nodes`. Returns the number of nodes, an exception
+/// thrown by the callback ends the call.
+#[wasm_bindgen(js_name = "__ext_stream_nodes")]
+pub fn stream_nodes_to(input_len: u32, format: u32, callback: &js_sys::Function) -> u32 {
+    use crate::storage::{load_input, ExternalDB};
+
+    // Reset Heap Memory, unless the arena is persistent
+    crate::arena::begin();
+
+    load_input(input_len);
+    let encoding = ByteEncoding::from_flags(format);
+    let mut buffer = Vec::<u16>::with_capacity(1024);
+    crate::with_layout!(L => {
+        let db = ExternalDB::load::<L>();
+        let root = db.get_root_hash();
+        stream_nodes::<L>(&db, &root, |index, parent, nibble, id, partial, value| {
+            let optional = |value: Option<JsValue>| value.unwrap_or(JsValue::UNDEFINED);
+            let result = callback.call6(
+                &JsValue::NULL,
+                &JsValue::from(index),
+                &parent.map_or(JsValue::NULL, JsValue::from),
+                &nibble.map_or(JsValue::NULL, JsValue::from),
+                &optional(id.map(|id| encoding.bytes(&mut buffer, id.as_bytes()))),
+                &optional((!partial.is_empty()).then(|| encoding.digits(&mut buffer, partial.iter()))),
+                &optional(value.map(|value| encoding.bytes(&mut buffer, value))),
+            );
+            if let Err(error) = result {
+                wasm_bindgen::throw_val(error);
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MemoryBackend,
+        ethereum::EthereumLayout,
+        primitives::{Blake2Hasher, LayoutV1},
+        storage::split_bytes,
+    };
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    /// Value stored in a node of its own, shorter values are inlined in their leaf.
+    const LARGE: [u8; 64] = [7; 64];
+
+    /// Inserts the entries with a value and removes the others, then commits the new root.
+    fn apply<L: TrieLayout>(
+        db: &mut ExternalDB<MemoryBackend>,
+        root: &mut H256,
+        entries: &[(&[u8], Option<&[u8]>)],
+    ) {
+        {
+            let mut trie = TrieDBMutBuilder::<L>::from_existing(db, root).build();
+            for (key, value) in entries {
+                match value {
+                    Some(value) => trie.insert(key, value).map(drop).unwrap(),
+                    None => trie.remove(key).map(drop).unwrap(),
+                }
+            }
+        }
+        db.commit_root(root);
+    }
+
+    /// Trie in memory holding `entries`, along with its root.
+    fn trie_with<L: TrieLayout>(entries: &[(&[u8], &[u8])]) -> (ExternalDB<MemoryBackend>, H256) {
+        let mut db = ExternalDB::with_backend::<L>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        let entries = entries.iter().map(|(key, value)| (*key, Some(*value))).collect::<Vec<_>>();
+        apply::<L>(&mut db, &mut root, &entries);
+        (db, root)
+    }
+
+    #[test]
+    fn shape_counts_every_position() {
+        let (db, root) =
+            trie_with::<Layout>(&[(b"alpha", &LARGE), (b"alphabet", b"1"), (b"beta", b"2")]);
+
+        let shape = shape::<Layout>(&db, &root);
+        // The root branches on `alpha` and `beta`, `alpha` branches again on `alphabet`.
+        assert_eq!(shape.kinds, [0, 2, 0, 0, 2]);
+        assert_eq!(shape.depths[..3], [1, 2, 1]);
+        assert_eq!(shape.branching[1], 1);
+        assert_eq!(shape.branching[2], 1);
+        assert_eq!((shape.values, shape.value_depths, shape.max_value_depth), (3, 4, 2));
+        assert_eq!(shape.encode().len(), 168);
+    }
+
+    #[test]
+    fn streamed_nodes_follow_their_parent() {
+        let (db, root) =
+            trie_with::<Layout>(&[(b"alpha", &LARGE), (b"beta", &LARGE), (b"gamma", b"3")]);
+
+        let mut visited = Vec::new();
+        let count = stream_nodes::<Layout>(&db, &root, |index, parent, nibble, id, _, value| {
+            visited.push((index, parent, nibble, id.copied(), value.map(<[u8]>::to_vec)));
+        });
+        assert_eq!(count, 4);
+        assert_eq!(visited[0], (0, None, None, Some(root), None));
+        // The leaf of `gamma` is inline.
+        let children = visited[1..]
+            .iter()
+            .map(|(_, parent, nibble, id, _)| (*parent, *nibble, id.is_some()));
+        assert_eq!(
+            children.collect::<Vec<_>>(),
+            vec![(Some(0), Some(1), true), (Some(0), Some(2), true), (Some(0), Some(7), false)]
+        );
+        assert_eq!(visited[3].4, Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn key_path_splits_the_key_by_node() {
+        let (db, root) = trie_with::<Layout>(&[(b"alpha", &LARGE), (b"beta", &LARGE)]);
+        // Whether the key was found, where the lookup stopped, then the partial key, the
+        // nibbles matched and the branch nibble of each node.
+        let breakdown = |key: &[u8]| {
+            let output = key_path::<Layout>(&db, &root, key);
+            let (nibbles, rest) = split_bytes(&output).unwrap();
+            assert_eq!(nibbles.len(), key.len() * 2);
+            let stopped = u32::from_le_bytes(rest[1..5].try_into().unwrap());
+            let mut rest = &rest[5..];
+            let mut segments = Vec::new();
+            while let Some((_, next)) = rest.split_first() {
+                let (_, next) = split_bytes(next).unwrap();
+                let (partial, next) = split_bytes(next).unwrap();
+                let matched = u32::from_le_bytes(next[..4].try_into().unwrap());
+                segments.push((partial.to_vec(), matched, next[4]));
+                rest = &next[5..];
+            }
+            (output[nibbles.len() + 4] == 1, stopped, segments)
+        };
+        let leaf = vec![6, 12, 7, 0, 6, 8, 6, 1];
+        assert_eq!(
+            breakdown(b"alpha"),
+            (true, 10, vec![(vec![6], 1, 1), (leaf.clone(), 8, NO_CHILD)])
+        );
+        assert_eq!(breakdown(b"alps"), (false, 6, vec![(vec![6], 1, 1), (leaf, 4, NO_CHILD)]));
+        assert_eq!(breakdown(b"zeta"), (false, 0, vec![(vec![6], 0, NO_CHILD)]));
+    }
+
+    #[test]
+    fn node_diff_tags_changed_nodes() {
+        let (mut db, mut root) = trie_with::<Layout>(&[(b"alpha", &LARGE), (b"beta", &LARGE)]);
+        db.set_archive(true);
+        let old = root;
+        apply::<Layout>(&mut db, &mut root, &[(b"alpha", None), (b"gamma", Some(&LARGE))]);
+
+        let (index, nodes) = diff_nodes::<Layout>(&db, &old, &root);
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(nodes[index].id, Some(root));
+        assert_eq!(nodes[index].status, Some(NodeStatus::Modified));
+        let status = |nibble| nodes[nodes[index].children.get(nibble).unwrap()].status;
+        assert_eq!(status(1), Some(NodeStatus::Removed));
+        assert_eq!(status(2), Some(NodeStatus::Unchanged));
+        assert_eq!(status(7), Some(NodeStatus::Added));
+
+        let (index, nodes) = diff_nodes::<Layout>(&db, &root, &root);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[index].status, Some(NodeStatus::Unchanged));
+        assert!(nodes[index].truncated);
+    }
+
+    #[test]
+    fn lookup_marks_the_route_to_the_key() {
+        let (db, root) = trie_with::<Layout>(&[(b"alpha", &LARGE), (b"beta", &LARGE)]);
+        let matched = |key: &[u8]| {
+            let (index, mut nodes) = decode_nodes::<Layout>(&db, &root, &[], u32::MAX);
+            mark_lookup(&mut nodes, index, key);
+            let mut matched =
+                nodes.iter().filter_map(|node| node.matched_nibbles).collect::<Vec<_>>();
+            matched.sort();
+            matched
+        };
+        // The root holds the nibble `6` shared by both keys.
+        assert_eq!(matched(b"alpha"), vec![1, 10]);
+        assert_eq!(matched(b"alps"), vec![1, 6]);
+        assert_eq!(matched(b"zeta"), vec![0]);
+    }
+
+    #[test]
+    fn collapsed_paths_merge_extensions() {
+        let (db, root) = trie_with::<EthereumLayout>(&[(b"alpha", &LARGE), (b"alps", &LARGE)]);
+
+        let (index, nodes) = decode_nodes::<EthereumLayout>(&db, &root, &[], u32::MAX);
+        assert_eq!(nodes.len(), 4);
+        assert_eq!(nodes[index].kind, NodeKind::Extension);
+
+        let (index, collapsed) = collapse_paths(index, &nodes);
+        assert_eq!(collapsed.len(), 3);
+        assert_eq!(collapsed[index].kind, NodeKind::Branch);
+        assert_eq!(collapsed[index].collapsed, vec![6, 1, 6, 12, 7, 0]);
+        assert_eq!(collapsed[index].depth, 0);
+        assert!(collapsed
+            .iter()
+            .filter(|node| node.kind == NodeKind::Leaf)
+            .all(|node| node.collapsed.is_empty() && node.depth == 1));
+    }
+
+    #[test]
+    fn pages_list_every_node() {
+        let (db, root) =
+            trie_with::<Layout>(&[(b"alpha", &LARGE), (b"beta", &LARGE), (b"gamma", b"3")]);
+        let mut expected = Vec::new();
+        for_each_node::<Layout, _>(&db, &root, |node| expected.push(*node));
+
+        let mut listed = Vec::new();
+        let mut token = Vec::new();
+        loop {
+            let page = nodes_page::<Layout>(&db, &root, &token, 2);
+            let count = u32::from_le_bytes(page[..4].try_into().unwrap());
+            assert!(count <= 2);
+            let mut rest = &page[4..];
+            for _ in 0..count {
+                listed.push(H256::from_slice(&rest[..32]));
+                let len = u32::from_le_bytes(rest[32..36].try_into().unwrap()) as usize;
+                rest = &rest[36 + len..];
+            }
+            let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+            token = rest[4..4 + len].to_vec();
+            if token.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(listed[0], root);
+        // The leaf of `gamma` is inline and the value nodes aren't listed.
+        assert_eq!(listed.len(), 3);
+        assert!(listed.iter().all(|node| expected.contains(node)));
+    }
 }
@@ -2,12 +2,19 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
-use crate::{abort, host::HostFnImpl, trie::TrieNode};
+use crate::{
+    hints::ValueHints,
+    node::nibbles_to_key,
+    scratch::{self, Scratch},
+    trie::{NodeArena, TrieNode},
+};
 use alloc::vec::Vec;
-use js_sys::JsString;
+use js_sys::{JsString, Uint8Array};
 use wasm_bindgen::prelude::*;
 
-#[wasm_bindgen(raw_module = "../src/lib.exports.ts")]
+// Bound like the host functions, see `host::ext`.
+#[cfg_attr(not(feature = "node-host"), wasm_bindgen(raw_module = "../src/lib.exports.ts"))]
+#[cfg_attr(feature = "node-host", wasm_bindgen(js_namespace = trieHost))]
 extern "C" {
     pub type JSMerklePatriciaTrie;
 
@@ -17,130 +24,237 @@ extern "C" {
     pub fn new() -> JSTrieBuilder;
 
     #[wasm_bindgen(method, getter)]
-    pub fn id(this: &JSTrieBuilder) -> Option<js_sys::JsString>;
+    pub fn id(this: &JSTrieBuilder) -> JsValue;
 
     #[wasm_bindgen(method, setter)]
-    pub fn set_id(this: &JSTrieBuilder, id: Option<js_sys::JsString>);
+    pub fn set_id(this: &JSTrieBuilder, id: &JsValue);
 
     #[wasm_bindgen(method, getter)]
-    pub fn nibbles(this: &JSTrieBuilder) -> Option<js_sys::JsString>;
+    pub fn nibbles(this: &JSTrieBuilder) -> JsValue;
 
     #[wasm_bindgen(method, setter)]
-    pub fn set_nibbles(this: &JSTrieBuilder, nibbles: Option<js_sys::JsString>);
+    pub fn set_nibbles(this: &JSTrieBuilder, nibbles: &JsValue);
 
     #[wasm_bindgen(method, getter)]
-    pub fn value(this: &JSTrieBuilder) -> Option<js_sys::JsString>;
+    pub fn value(this: &JSTrieBuilder) -> JsValue;
 
     #[wasm_bindgen(method, setter)]
-    pub fn set_value(this: &JSTrieBuilder, value: Option<js_sys::JsString>);
+    pub fn set_value(this: &JSTrieBuilder, value: &JsValue);
 
     #[wasm_bindgen(method, getter)]
-    pub fn raw_bytes(this: &JSTrieBuilder) -> Option<js_sys::JsString>;
+    pub fn raw_bytes(this: &JSTrieBuilder) -> JsValue;
 
     #[wasm_bindgen(method, setter)]
-    pub fn set_raw_bytes(this: &JSTrieBuilder, bytes: Option<js_sys::JsString>);
+    pub fn set_raw_bytes(this: &JSTrieBuilder, bytes: &JsValue);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_decoded(this: &JSTrieBuilder, decoded: &str);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_highlighted(this: &JSTrieBuilder, highlighted: bool);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_truncated(this: &JSTrieBuilder, truncated: bool);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_kind(this: &JSTrieBuilder, kind: &str);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_status(this: &JSTrieBuilder, status: &str);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_matched_nibbles(this: &JSTrieBuilder, matched: u32);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_collapsed(this: &JSTrieBuilder, collapsed: bool);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_references(this: &JSTrieBuilder, references: i32);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_inline(this: &JSTrieBuilder, inline: bool);
+
+    #[wasm_bindgen(method, setter)]
+    pub fn set_encoded_size(this: &JSTrieBuilder, size: u32);
 
     #[wasm_bindgen(method)]
     pub fn push_child(this: &JSTrieBuilder, nibble: u8, child: &JSTrieBuilder);
 
+    #[wasm_bindgen(method)]
+    pub fn set_extension(this: &JSTrieBuilder, child: &JSTrieBuilder);
+
     #[wasm_bindgen(method)]
     pub fn build(this: &JSTrieBuilder) -> JSMerklePatriciaTrie;
 }
 
-fn bytes2js_string<I: core::iter::Iterator<Item = u8>>(
-    buffer: &mut Vec<u16>,
-    bytes: I,
-) -> js_sys::JsString {
-    unsafe {
-        buffer.set_len(0);
+/// Formatting of the hex strings, applied to every field of the nodes.
+#[derive(Clone, Copy)]
+pub struct HexFormat {
+    pub lowercase: bool,
+    /// Whether the strings start with `0x`.
+    pub prefix: bool,
+    /// Digits kept before the string is cut with an ellipsis, `0` keeps them all.
+    pub max_digits: u16,
+}
+
+/// How the node ids, nibbles, values and raw bytes cross the boundary.
+#[derive(Clone, Copy)]
+pub enum ByteEncoding {
+    /// Hex strings, nibbles are one hex digit each.
+    Hex(HexFormat),
+    /// `Uint8Array`s, nibbles are one byte each. The bytes are copied out of the wasm memory,
+    /// which is reset by the next call.
+    Binary,
+}
+
+impl ByteEncoding {
+    /// Decodes the format flags of a listing: bit 0 selects hex strings, bit 1 lowercase
+    /// digits, bit 2 drops the `0x` prefix, and the upper 16 bits hold the maximum number of
+    /// digits of each string.
+    pub fn from_flags(flags: u32) -> Self {
+        if flags & 1 == 0 {
+            return Self::Binary;
+        }
+        Self::Hex(HexFormat {
+            lowercase: flags & 2 != 0,
+            prefix: flags & 4 == 0,
+            max_digits: (flags >> 16) as u16,
+        })
+    }
+
+    /// Encodes hex digits, or nibbles, one per item.
+    pub fn digits<I: Iterator<Item = u8>>(self, buffer: &mut Vec<u16>, digits: I) -> JsValue {
+        unsafe {
+            buffer.set_len(0);
+        }
+        match self {
+            Self::Hex(format) => {
+                let alphabet = if format.lowercase { ALPHABET_LOWER } else { ALPHABET };
+                if format.prefix {
+                    buffer.push(b'0' as u16);
+                    buffer.push(b'x' as u16);
+                }
+                let max_digits = match format.max_digits {
+                    0 => usize::MAX,
+                    max_digits => usize::from(max_digits),
+                };
+                let mut digits = digits.map(|digit| alphabet[(digit & 15) as usize] as u16);
+                buffer.extend(digits.by_ref().take(max_digits));
+                if digits.next().is_some() {
+                    buffer.push(ELLIPSIS);
+                }
+                JsString::from_char_code(buffer).into()
+            },
+            Self::Binary => {
+                let mut bytes = scratch::bytes();
+                bytes.extend(digits.map(|digit| digit & 15));
+                Uint8Array::from(&bytes[..]).into()
+            },
+        }
     }
-    buffer.push(b'0' as u16);
-    buffer.push(b'x' as u16);
 
-    for byte in bytes {
-        buffer.push(ALPHABET[(byte >> 4) as usize] as u16);
-        buffer.push(ALPHABET[(byte & 15) as usize] as u16);
+    pub fn bytes(self, buffer: &mut Vec<u16>, bytes: &[u8]) -> JsValue {
+        match self {
+            Self::Hex(_) => {
+                self.digits(buffer, bytes.iter().flat_map(|byte| [byte >> 4, byte & 15]))
+            },
+            Self::Binary => Uint8Array::from(bytes).into(),
+        }
     }
-    JsString::from_char_code(buffer)
 }
 
 const ALPHABET: &[u8; 16] = b"0123456789ABCDEF";
+const ALPHABET_LOWER: &[u8; 16] = b"0123456789abcdef";
+const ELLIPSIS: u16 = 0x2026;
+/// State shared by the nodes of a listing.
+struct Listing<'a> {
+    nodes: &'a NodeArena,
+    encoding: ByteEncoding,
+    /// Code units of the hex strings, taken from the pool of [`crate::scratch`].
+    buffer: Scratch<u16>,
+    /// Nibbles from the first listed node down to the current one.
+    path: Vec<u8>,
+    hints: &'a ValueHints,
+}
+
 impl JSTrieBuilder {
-    fn from_trie(
-        node: &TrieNode,
-        nodes: &[TrieNode],
-        parent_nibble: Option<u8>,
-        buffer: &mut Vec<u16>,
-    ) -> Self {
+    fn from_trie(node: &TrieNode, listing: &mut Listing) -> Self {
         let root = JSTrieBuilder::new();
-        if let Some(slice) = node.nibbles.as_ref() {
-            if !slice.is_empty() || parent_nibble.is_some() {
-                unsafe {
-                    buffer.set_len(0);
-                }
-                buffer.push(b'0' as u16);
-                buffer.push(b'x' as u16);
-                if let Some(parent_nibble) = parent_nibble {
-                    buffer.push(ALPHABET[(parent_nibble & 15) as usize] as u16);
-                }
-                for nibble in slice.iter() {
-                    buffer.push(ALPHABET[(nibble & 15) as usize] as u16);
-                }
-                let bytes = JsString::from_char_code(buffer);
-                root.set_nibbles(Some(bytes));
-            }
+        let encoding = listing.encoding;
+        let buffer = &mut *listing.buffer;
+        let path_len = listing.path.len();
+        listing.path.extend_from_slice(&node.collapsed);
+        listing.path.extend(node.nibbles().iter().flat_map(|slice| slice.iter()));
+        if let Some(partial) = listing.path.get(path_len..).filter(|partial| !partial.is_empty()) {
+            root.set_nibbles(&encoding.digits(buffer, partial.iter().copied()));
         }
-        let has_raw_bytes = node.raw_bytes.is_some();
         if let Some(id) = node.id.as_ref() {
-            let id_bytes = &id.0;
-            let id_str = bytes2js_string(buffer, id_bytes.iter().copied());
-            root.set_id(Some(id_str));
-            if !has_raw_bytes {
-                unsafe {
-                    buffer.set_len(0);
-                    let len = buffer.capacity() * 2;
-                    let mut output = core::slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, len);
-                    if HostFnImpl::get_storage(id_bytes, &mut output).is_none() {
-                        let offset = (output.len() + 2) >> 1;
-                        buffer.set_len(offset);
-                        buffer.push(b'0' as u16);
-                        buffer.push(b'x' as u16);
-
-                        for byte in output {
-                            let byte = *byte;
-                            buffer.push(ALPHABET[(byte >> 4) as usize] as u16);
-                            buffer.push(ALPHABET[(byte & 15) as usize] as u16);
-                        }
-                        let raw_bytes = JsString::from_char_code(&buffer[offset..]);
-                        root.set_raw_bytes(Some(raw_bytes));
-                    }
+            root.set_id(&encoding.bytes(buffer, id.as_bytes()));
+        }
+        if let Some(value) = node.value.as_ref() {
+            root.set_value(&encoding.bytes(buffer, value));
+            if listing.path.len().is_multiple_of(2) {
+                let hint = listing.hints.find(&nibbles_to_key(&listing.path));
+                if let Some(decoded) = hint.and_then(|hint| hint.decode(value)) {
+                    root.set_decoded(&decoded);
                 }
             }
         }
-        if let Some(value) = node.value.as_ref() {
-            let value = bytes2js_string(buffer, value.iter().copied());
-            root.set_value(Some(value));
+        root.set_raw_bytes(&encoding.bytes(buffer, &node.raw_bytes));
+        root.set_kind(node.kind.name());
+        root.set_inline(node.id.is_none());
+        root.set_encoded_size(node.raw_bytes.len() as u32);
+        if node.id.is_some() {
+            root.set_references(node.references);
+        }
+        if node.highlighted {
+            root.set_highlighted(true);
+        }
+        if node.truncated {
+            root.set_truncated(true);
+        }
+        if let Some(status) = node.status {
+            root.set_status(status.name());
         }
-        if let Some(raw_bytes) = node.raw_bytes {
-            let raw_bytes = bytes2js_string(buffer, raw_bytes.iter().copied());
-            root.set_raw_bytes(Some(raw_bytes));
+        if !node.collapsed.is_empty() {
+            root.set_collapsed(true);
+        }
+        if let Some(matched) = node.matched_nibbles {
+            root.set_matched_nibbles(matched);
         }
         for (index, nibble) in node.children.iter() {
-            let Some(child) = nodes.get(index) else {
-                abort!("child at index {} not found", index);
-            };
-            // let child = JSTrieBuilder::from_trie(child, nodes, Some(nibble), buffer);
-            let child = JSTrieBuilder::from_trie(child, nodes, None, buffer);
+            let child = &listing.nodes[index];
+            listing.path.push(nibble);
+            let child = JSTrieBuilder::from_trie(child, listing);
+            listing.path.pop();
             root.push_child(nibble, &child);
         }
+        if let Some(index) = node.children.extension() {
+            let child = &listing.nodes[index];
+            let child = JSTrieBuilder::from_trie(child, listing);
+            root.set_extension(&child);
+        }
+        listing.path.truncate(path_len);
         root
     }
 }
 
 impl JSMerklePatriciaTrie {
-    pub fn new(node: &TrieNode, nodes: &[TrieNode]) -> Self {
-        let mut buffer = Vec::<u16>::with_capacity(16384);
-        let builder = JSTrieBuilder::from_trie(node, nodes, None, &mut buffer);
-        builder.build()
+    pub fn new(node: &TrieNode, nodes: &NodeArena, encoding: ByteEncoding) -> Self {
+        Self::with_hints(node, nodes, encoding, &ValueHints::default())
+    }
+
+    /// Same as [`JSMerklePatriciaTrie::new`], decoding the values matching `hints`. `node`
+    /// must be the root of the trie, the keys are read from the nibbles of the listed nodes.
+    pub fn with_hints(
+        node: &TrieNode,
+        nodes: &NodeArena,
+        encoding: ByteEncoding,
+        hints: &ValueHints,
+    ) -> Self {
+        let mut buffer = scratch::units();
+        buffer.reserve(16384);
+        let mut listing = Listing { nodes, encoding, buffer, path: Vec::with_capacity(64), hints };
+        JSTrieBuilder::from_trie(node, &mut listing).build()
     }
 }
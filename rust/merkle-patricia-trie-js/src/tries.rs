@@ -0,0 +1,166 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Tries addressed by a handle.
+//!
+//! From host ABI 10 on, every call starts with the handle of the trie it runs on. Handle `0`
+//! is the default trie, in the empty namespace. The other tries are created with a name, which
+//! becomes their namespace, so the tries of older hosts are opened by the namespace they used.
+//! The registry of the handles is kept in the empty namespace.
+use crate::{
+    backend::{HostBackend, TrieBackend},
+    error::{CallError, CallResult},
+    fail,
+    host::HostFnImpl,
+};
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+/// Handle of the trie in the empty namespace, which can't be dropped.
+pub const DEFAULT_HANDLE: u32 = 0;
+
+/// Last handle given out, handles aren't reused.
+const LAST_HANDLE_KEY: &[u8] = b":trie_handle";
+/// Name of each trie, under its handle.
+const HANDLE_PREFIX: &[u8] = b":trie_handle:";
+/// Handle of each trie, under its name.
+const NAME_PREFIX: &[u8] = b":trie_name:";
+
+struct Handle(Cell<u32>);
+
+// SAFETY: the module is single threaded.
+unsafe impl Sync for Handle {}
+
+static HANDLE: Handle = Handle(Cell::new(DEFAULT_HANDLE));
+
+fn handle_key(handle: u32) -> Vec<u8> {
+    [HANDLE_PREFIX, &handle.to_le_bytes()].concat()
+}
+
+fn name_key(name: &[u8]) -> Vec<u8> {
+    [NAME_PREFIX, name].concat()
+}
+
+/// Runs `f` on the storage of the empty namespace, where the registry is kept.
+fn registry<R>(f: impl FnOnce(&HostBackend) -> R) -> R {
+    let namespace = HostFnImpl::with_namespace(<[u8]>::to_vec);
+    HostFnImpl::set_namespace(&[]);
+    let result = f(&HostBackend);
+    HostFnImpl::set_namespace(&namespace);
+    result
+}
+
+fn read_handle(bytes: &[u8]) -> u32 {
+    let Ok(handle) = <[u8; 4]>::try_from(bytes) else {
+        crate::abort!(key = bytes, "invalid trie handle");
+    };
+    u32::from_le_bytes(handle)
+}
+
+/// Selects the trie `handle` for the current call, setting its namespace.
+pub fn select(handle: u32) -> CallResult<()> {
+    HostFnImpl::set_namespace(&[]);
+    HANDLE.0.set(handle);
+    if handle == DEFAULT_HANDLE {
+        return Ok(());
+    }
+    let Some(name) = HostBackend.get(&handle_key(handle)) else {
+        return fail!(CallError::InvalidCall, "unknown trie handle {handle}");
+    };
+    HostFnImpl::set_namespace(&name);
+    Ok(())
+}
+
+/// Handle of the trie of the current call.
+pub fn current() -> u32 {
+    HANDLE.0.get()
+}
+
+/// Registers the trie `name`, returning its handle.
+pub fn create(name: &[u8]) -> CallResult<u32> {
+    if name.is_empty() || name.len() > usize::from(u8::MAX) {
+        return fail!(CallError::InvalidInput, "trie names are 1 to 255 bytes long");
+    }
+    registry(|storage| {
+        if storage.contains(&name_key(name)) {
+            return fail!(CallError::InvalidCall, key = name, "the trie already exists");
+        }
+        let handle =
+            storage.get(LAST_HANDLE_KEY).map_or(DEFAULT_HANDLE, |last| read_handle(&last)) + 1;
+        storage.write(LAST_HANDLE_KEY, Some(&handle.to_le_bytes()));
+        storage.write(&handle_key(handle), Some(name));
+        storage.write(&name_key(name), Some(&handle.to_le_bytes()));
+        Ok(handle)
+    })
+}
+
+/// Handle of the trie `name`.
+pub fn open(name: &[u8]) -> CallResult<u32> {
+    match registry(|storage| storage.get(&name_key(name))) {
+        Some(handle) => Ok(read_handle(&handle)),
+        None => fail!(CallError::KeyNotFound, key = name, "unknown trie"),
+    }
+}
+
+/// Unregisters the trie of the current call, its handle can't be used anymore.
+pub fn unregister() -> CallResult<()> {
+    let handle = current();
+    if handle == DEFAULT_HANDLE {
+        return fail!(CallError::InvalidCall, "the default trie can't be dropped");
+    }
+    registry(|storage| {
+        if let Some(name) = storage.take(&handle_key(handle)) {
+            storage.write(&name_key(&name), None);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        native::{call, clear_storage, CallError},
+        storage::write_bytes,
+    };
+
+    fn entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut input = Vec::new();
+        write_bytes(&mut input, key);
+        write_bytes(&mut input, value);
+        input
+    }
+
+    fn root(handle: u32) -> Vec<u8> {
+        call(handle, 4, &[]).unwrap().output.unwrap()
+    }
+
+    #[test]
+    fn handles_keep_separate_roots() {
+        clear_storage();
+        let first = call(DEFAULT_HANDLE, 59, b"first").unwrap().ret as u32;
+        let second = call(DEFAULT_HANDLE, 59, b"second").unwrap().ret as u32;
+        assert_ne!(first, second);
+        assert_eq!(call(DEFAULT_HANDLE, 59, b"first"), Err(CallError::InvalidCall));
+
+        call(first, 0, &entry(b"alpha", b"1")).unwrap();
+        call(second, 0, &entry(b"alpha", b"2")).unwrap();
+        let first_root = root(first);
+        let second_root = root(second);
+        assert_ne!(first_root, second_root);
+        assert_ne!(first_root, root(DEFAULT_HANDLE));
+
+        // Selecting a handle by name leaves the roots of the others unchanged.
+        assert_eq!(call(DEFAULT_HANDLE, 60, b"second").unwrap().ret, u64::from(second));
+        call(second, 0, &entry(b"beta", b"3")).unwrap();
+        assert_eq!(root(first), first_root);
+        assert_ne!(root(second), second_root);
+
+        call(first, 61, &[]).unwrap();
+        assert_eq!(call(first, 4, &[]), Err(CallError::InvalidCall));
+        assert_eq!(call(DEFAULT_HANDLE, 60, b"first"), Err(CallError::KeyNotFound));
+        assert_eq!(call(second, 3, b"alpha").unwrap().output.as_deref(), Some(&b"2"[..]));
+        assert_eq!(call(DEFAULT_HANDLE, 61, &[]), Err(CallError::InvalidCall));
+    }
+}
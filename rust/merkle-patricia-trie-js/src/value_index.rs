@@ -0,0 +1,222 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Reverse index from the Blake2 hash of the values to the keys holding them.
+//!
+//! Once enabled, every key changed by a committed call updates the index, kept apart from the
+//! trie under reserved key prefixes: the keys holding a value under its hash, and the value hash
+//! of each key so it's unlisted when the key changes. Keys are taken as stored in the trie,
+//! hashed when secure keys are enabled.
+use crate::{
+    abort,
+    backend::TrieBackend,
+    diff::Change,
+    error::{CallError, CallResult},
+    fail,
+    primitives::{Blake2Hasher, H256},
+    snapshot::entries,
+    storage::{split_bytes, split_hash, write_bytes, ExternalDB, TrieLayout},
+};
+use alloc::vec::Vec;
+use hash_db::Hasher;
+
+const ENABLED_KEY: &[u8] = b":value_index";
+/// Length-prefixed keys holding a value, under its hash.
+const VALUE_PREFIX: &[u8] = b":value_index:";
+/// Value hash of each indexed key.
+const KEY_PREFIX: &[u8] = b":value_index_key:";
+
+fn value_key(hash: &H256) -> Vec<u8> {
+    [VALUE_PREFIX, hash.as_bytes()].concat()
+}
+
+fn key_key(key: &[u8]) -> Vec<u8> {
+    [KEY_PREFIX, key].concat()
+}
+
+pub fn is_enabled<B: TrieBackend>(db: &ExternalDB<B>) -> bool {
+    db.contains(ENABLED_KEY)
+}
+
+/// Lists `key` among the keys holding the value `hash`.
+fn add<B: TrieBackend>(db: &ExternalDB<B>, key: &[u8], hash: &H256) {
+    let mut keys = db.get_value(&value_key(hash)).unwrap_or_default();
+    write_bytes(&mut keys, key);
+    db.set_bytes(&value_key(hash), &keys);
+    db.set_hash(&key_key(key), hash);
+}
+
+/// Unlists `key` from the keys holding its former value.
+fn remove<B: TrieBackend>(db: &ExternalDB<B>, key: &[u8]) {
+    let Some(hash) = db.get_hash(&key_key(key)) else {
+        return;
+    };
+    db.clear(&key_key(key));
+    let Some(listed) = db.get_value(&value_key(&hash)) else {
+        return;
+    };
+    let mut keys = Vec::with_capacity(listed.len());
+    let mut rest = listed.as_slice();
+    while !rest.is_empty() {
+        let Ok((other, next)) = split_bytes(rest) else {
+            abort!(key = hash.as_bytes(), "invalid value index entry");
+        };
+        if other != key {
+            write_bytes(&mut keys, other);
+        }
+        rest = next;
+    }
+    if keys.is_empty() {
+        db.clear(&value_key(&hash));
+    } else {
+        db.set_bytes(&value_key(&hash), &keys);
+    }
+}
+
+/// Updates the index with the committed `changes`.
+pub fn update<B: TrieBackend>(db: &ExternalDB<B>, changes: &[Change]) {
+    if changes.is_empty() || !is_enabled(db) {
+        return;
+    }
+    for change in changes {
+        remove(db, change.key());
+        if let Some(value) = change.value() {
+            add(db, change.key(), &Blake2Hasher::hash(value));
+        }
+    }
+}
+
+/// Enables the index of the trie at `root`, building it from its entries, or disables it.
+pub fn set<L: TrieLayout, B: TrieBackend>(
+    db: &ExternalDB<B>,
+    root: &H256,
+    enabled: bool,
+) -> CallResult<()> {
+    let entries = entries::<L>(db, root, &[])?;
+    // Clearing the former index first also drops the keys it listed wrongly.
+    for (key, _) in &entries {
+        remove(db, key);
+    }
+    if enabled {
+        for (key, value) in &entries {
+            add(db, key, &Blake2Hasher::hash(value));
+        }
+        db.set_bytes(ENABLED_KEY, &[1]);
+    } else {
+        db.clear(ENABLED_KEY);
+    }
+    Ok(())
+}
+
+/// Length-prefixed keys holding the value whose Blake2 hash is `input`.
+pub fn lookup<B: TrieBackend>(db: &ExternalDB<B>, input: &[u8]) -> CallResult<Vec<u8>> {
+    if !is_enabled(db) {
+        return fail!(CallError::InvalidCall, "the value index is disabled");
+    }
+    let (hash, _) = split_hash(input)?;
+    Ok(db.get_value(&value_key(&hash)).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backend::MemoryBackend,
+        native::{call, clear_storage, DEFAULT_HANDLE},
+        primitives::LayoutV1,
+    };
+    use trie_db::{TrieDBMutBuilder, TrieMut};
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    fn keys(db: &ExternalDB<MemoryBackend>, value: &[u8]) -> Vec<Vec<u8>> {
+        let listed = lookup(db, Blake2Hasher::hash(value).as_bytes()).unwrap();
+        let mut keys = Vec::new();
+        let mut rest = listed.as_slice();
+        while !rest.is_empty() {
+            let (key, next) = split_bytes(rest).unwrap();
+            keys.push(key.to_vec());
+            rest = next;
+        }
+        keys
+    }
+
+    #[test]
+    fn index_follows_the_updates() {
+        let mut db = ExternalDB::with_backend::<Layout>(MemoryBackend::new());
+        let mut root = db.get_root_hash();
+        {
+            let mut trie = TrieDBMutBuilder::<Layout>::from_existing(&mut db, &mut root).build();
+            trie.insert(b"alpha", b"same").unwrap();
+            trie.insert(b"beta", b"same").unwrap();
+            trie.insert(b"gamma", b"other").unwrap();
+        }
+        db.commit_root(&root);
+
+        set::<Layout, _>(&db, &root, true).unwrap();
+        assert_eq!(keys(&db, b"same"), [b"alpha".to_vec(), b"beta".to_vec()]);
+        assert_eq!(keys(&db, b"other"), [b"gamma".to_vec()]);
+
+        let changes = [
+            Change::Modified {
+                key: b"alpha".to_vec(),
+                old: b"same".to_vec(),
+                new: b"other".to_vec(),
+            },
+            Change::Removed { key: b"beta".to_vec(), value: b"same".to_vec() },
+            Change::Added { key: b"delta".to_vec(), value: b"other".to_vec() },
+        ];
+        update(&db, &changes);
+        assert!(keys(&db, b"same").is_empty());
+        assert!(!db.contains(&value_key(&Blake2Hasher::hash(b"same"))));
+        assert_eq!(keys(&db, b"other"), [b"gamma".to_vec(), b"alpha".to_vec(), b"delta".to_vec()]);
+
+        // Disabling drops the entries of the keys in the trie.
+        {
+            let mut trie = TrieDBMutBuilder::<Layout>::from_existing(&mut db, &mut root).build();
+            trie.insert(b"alpha", b"other").unwrap();
+            trie.remove(b"beta").unwrap();
+            trie.insert(b"delta", b"other").unwrap();
+        }
+        db.commit_root(&root);
+        set::<Layout, _>(&db, &root, false).unwrap();
+        assert!(!is_enabled(&db));
+        for key in [&b"alpha"[..], b"beta", b"gamma", b"delta"] {
+            assert!(!db.contains(&key_key(key)));
+        }
+        assert!(!db.contains(&value_key(&Blake2Hasher::hash(b"other"))));
+    }
+    #[test]
+    fn merges_and_reverts_are_indexed() {
+        let entry = |key: &[u8], value: &[u8]| {
+            let mut input = Vec::new();
+            write_bytes(&mut input, key);
+            write_bytes(&mut input, value);
+            input
+        };
+        let lookup = |value: &[u8]| {
+            let hash = Blake2Hasher::hash(value);
+            call(DEFAULT_HANDLE, 63, hash.as_bytes()).unwrap().output.unwrap_or_default()
+        };
+        clear_storage();
+        call(DEFAULT_HANDLE, 62, &[1]).unwrap();
+        call(DEFAULT_HANDLE, 0, &entry(b"alpha", b"1")).unwrap();
+        call(DEFAULT_HANDLE, 6, b"first").unwrap();
+        let first = call(DEFAULT_HANDLE, 4, &[]).unwrap().output.unwrap();
+        call(DEFAULT_HANDLE, 1, b"alpha").unwrap();
+        call(DEFAULT_HANDLE, 0, &entry(b"beta", b"2")).unwrap();
+        assert!(lookup(b"1").is_empty());
+
+        let mut merge = Vec::from([0]);
+        merge.extend_from_slice(&first);
+        call(DEFAULT_HANDLE, 13, &merge).unwrap();
+        let mut alpha = Vec::new();
+        write_bytes(&mut alpha, b"alpha");
+        assert_eq!(lookup(b"1"), alpha);
+
+        call(DEFAULT_HANDLE, 7, b"first").unwrap();
+        assert_eq!(lookup(b"1"), alpha);
+        assert!(lookup(b"2").is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+// Copyright 2025 Lohann Paterno Coutinho Ferreira <developer@lohann.dev>
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Keys watched by the host.
+//!
+//! The host registers keys and key prefixes under a reserved key of its namespace. Once a call
+//! changing the root is committed, the watched keys it changed are sent to `__ext_notify` with
+//! the new root. Prefixes match the keys as stored in the trie, hashed when secure keys are
+//! enabled, a child trie changes the key of its root in the parent.
+use crate::{
+    abort,
+    backend::TrieBackend,
+    diff::Change,
+    error::{CallError, CallResult},
+    fail,
+    host::HostFnImpl,
+    primitives::H256,
+    storage::{split_bytes, trie_key, write_bytes, ExternalDB, TrieLayout},
+};
+use alloc::vec::Vec;
+
+const WATCH_KEY: &[u8] = b":watched";
+
+/// A watched key, or every key starting with `key` when `prefix` is set.
+#[derive(Debug, PartialEq, Eq)]
+struct Watch<'a> {
+    prefix: bool,
+    key: &'a [u8],
+}
+
+impl Watch<'_> {
+    /// Whether the trie key `key` is watched, watched keys are compared in the form of the
+    /// trie.
+    fn matches<L: TrieLayout>(&self, key: &[u8]) -> bool {
+        if self.prefix {
+            key.starts_with(self.key)
+        } else {
+            key == &*trie_key::<L>(self.key)
+        }
+    }
+}
+
+/// Decodes the watches, each one encoded as `0` for a key or `1` for a prefix followed by the
+/// length-prefixed key.
+fn decode(mut bytes: &[u8]) -> CallResult<Vec<Watch<'_>>> {
+    let mut watches = Vec::new();
+    while let Some((&kind, rest)) = bytes.split_first() {
+        let prefix = match kind {
+            0 => false,
+            1 => true,
+            kind => return fail!(CallError::InvalidInput, "invalid watch kind: {kind}"),
+        };
+        let (key, rest) = split_bytes(rest)?;
+        watches.push(Watch { prefix, key });
+        bytes = rest;
+    }
+    Ok(watches)
+}
+
+/// Replaces the watched keys with the encoded `watches`, an empty input stops watching.
+pub fn set<B: TrieBackend>(db: &ExternalDB<B>, watches: &[u8]) -> CallResult<()> {
+    decode(watches)?;
+    if watches.is_empty() {
+        db.clear(WATCH_KEY);
+    } else {
+        db.set_bytes(WATCH_KEY, watches);
+    }
+    Ok(())
+}
+
+/// Notifies the host of the watched keys among the `changes` committed at `root`, in one
+/// call, the watched keys are sent as registered and the keys under a prefix as stored.
+pub fn notify<L: TrieLayout, B: TrieBackend>(db: &ExternalDB<B>, changes: &[Change], root: &H256) {
+    if changes.is_empty() {
+        return;
+    }
+    let Some(watched) = db.get_value(WATCH_KEY) else {
+        return;
+    };
+    let Ok(watches) = decode(&watched) else {
+        abort!(key = WATCH_KEY, "invalid watched keys");
+    };
+    let mut notified = Vec::from(root.as_bytes());
+    let mut found = false;
+    for change in changes {
+        let key = change.key();
+        let Some(watch) = watches.iter().find(|watch| watch.matches::<L>(key)) else {
+            continue;
+        };
+        write_bytes(&mut notified, if watch.prefix { key } else { watch.key });
+        found = true;
+    }
+    if found {
+        HostFnImpl::notify(&notified);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        native::{call, clear_storage, DEFAULT_HANDLE},
+        primitives::{Blake2Hasher, LayoutV1},
+    };
+
+    type Layout = LayoutV1<Blake2Hasher>;
+
+    fn entry(key: &[u8], value: &[u8]) -> Vec<u8> {
+        let mut input = Vec::new();
+        write_bytes(&mut input, key);
+        write_bytes(&mut input, value);
+        input
+    }
+
+    /// Keys of the only notification of a call, along with its root.
+    fn notified(code: u32, input: &[u8]) -> (Vec<Vec<u8>>, Vec<u8>) {
+        let reply = call(DEFAULT_HANDLE, code, input).unwrap();
+        let [changes] = &reply.notified[..] else {
+            panic!("expected one notification, got {:?}", reply.notified);
+        };
+        let (root, mut rest) = changes.split_at(32);
+        let mut keys = Vec::new();
+        while !rest.is_empty() {
+            let (key, next) = split_bytes(rest).unwrap();
+            keys.push(key.to_vec());
+            rest = next;
+        }
+        (keys, root.to_vec())
+    }
+
+    fn root() -> Vec<u8> {
+        call(DEFAULT_HANDLE, 4, &[]).unwrap().output.unwrap()
+    }
+
+    #[test]
+    fn merges_and_reverts_notify() {
+        clear_storage();
+        let mut watches = Vec::from([0]);
+        write_bytes(&mut watches, b"alpha");
+        watches.push(1);
+        write_bytes(&mut watches, b"be");
+        call(DEFAULT_HANDLE, 51, &watches).unwrap();
+
+        let (keys, _) = notified(0, &entry(b"alpha", b"1"));
+        assert_eq!(keys, [b"alpha".to_vec()]);
+        call(DEFAULT_HANDLE, 6, b"first").unwrap();
+        let first = root();
+        assert!(call(DEFAULT_HANDLE, 0, &entry(b"gamma", b"3")).unwrap().notified.is_empty());
+        notified(1, b"alpha");
+        notified(0, &entry(b"beta", b"2"));
+
+        // Merging the checkpoint brings alpha back.
+        let mut merge = Vec::from([0]);
+        merge.extend_from_slice(&first);
+        let (keys, root) = notified(13, &merge);
+        assert_eq!(keys, [b"alpha".to_vec()]);
+        assert_eq!(root, self::root());
+
+        // Reverting to it drops beta and gamma, only beta is watched.
+        let (keys, root) = notified(7, b"first");
+        assert_eq!(keys, [b"beta".to_vec()]);
+        assert_eq!(root, first);
+
+        // Reverting again doesn't change the root.
+        assert!(call(DEFAULT_HANDLE, 7, b"first").unwrap().notified.is_empty());
+    }
+
+    #[test]
+    fn watches_match_keys_and_prefixes() {
+        let mut bytes = Vec::from([0]);
+        write_bytes(&mut bytes, b"alpha");
+        bytes.push(1);
+        write_bytes(&mut bytes, b"be");
+        let watches = decode(&bytes).unwrap();
+        assert_eq!(
+            watches,
+            [Watch { prefix: false, key: b"alpha" }, Watch { prefix: true, key: b"be" }]
+        );
+        let matches = |key: &[u8]| watches.iter().any(|watch| watch.matches::<Layout>(key));
+        assert!(matches(b"alpha"));
+        assert!(!matches(b"alphabet"));
+        assert!(matches(b"beta"));
+        assert!(matches(b"be"));
+        assert!(!matches(b"b"));
+        assert!(decode(&[]).unwrap().is_empty());
+    }
+}
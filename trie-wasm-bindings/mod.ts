@@ -1,11 +1,74 @@
-export { type TrieStorage, WasmContext } from "./src/lib.exports.ts";
+export {
+  type AllocationProfile,
+  type BenchmarkOptions,
+  type BenchmarkPhase,
+  type BenchmarkResult,
+  CallError,
+  CallErrorCode,
+  type CollapseOptions,
+  type CursorPage,
+  type DepthOptions,
+  EMPTY_CODE_HASH,
+  EMPTY_STORAGE_ROOT,
+  type ErrorDetail,
+  type EthereumAccount,
+  type HostCall,
+  type HostCallMetrics,
+  installNodeHost,
+  type KeyPath,
+  type KeyPathSegment,
+  type KeyWatch,
+  type ListedTrie,
+  type ListOptions,
+  LogCategory,
+  type LoggedOperation,
+  LogLevel,
+  type MemoryStats,
+  type MutationExplanation,
+  type NodeChange,
+  type NodePage,
+  type OperationLog,
+  type OrderedTrie,
+  OutOfMemoryError,
+  type ProofItem,
+  type ProofSizes,
+  type RangeProof,
+  type StorageHasher,
+  type StorageKeyPart,
+  type StreamedNode,
+  type StateRootCheck,
+  type StateVersion,
+  type SyncProgress,
+  type TracedGet,
+  type TrieChange,
+  type TrieConfig,
+  type TrieHasher,
+  type TrieLayoutKind,
+  type TrieOperation,
+  type TrieShape,
+  type TrieStats,
+  type TrieStep,
+  type TrieStorage,
+  type ValueHint,
+  type VerifiedRange,
+  type WatchListener,
+  WasmContext,
+} from "./src/lib.exports.ts";
 export { ChildrenIterator, type Nib, TrieChildren } from "./src/nibbles.ts";
 export {
   JSMerklePatriciaTrie,
   JSTrieBuilder,
   type MerklePatriciaTrieNode,
+  type NodeBytes,
+  type NodeKind,
+  type NodeStatus,
 } from "./src/trie.ts";
-export { type SyncInitInput } from "./lib/trie.js";
+export {
+  type SyncInitInput,
+  TrieErrorKind,
+  xxhash32,
+  xxhash64,
+} from "./lib/trie.js";
 
 import { atobRaw } from "@scoped/utils/decoder";
 import { encodeHex } from "@scoped/utils/encoder";
@@ -14,8 +77,13 @@ import { type TrieWasmModule, WasmContext } from "./src/lib.exports.ts";
 import { unzlibSync } from "./src/fflate.ts";
 import { initSync as wasmInitSync, type SyncInitInput } from "./lib/trie.js";
 
-const EMPTY_ROOT =
-  "03170a2e7597b7b7e3d84c05391d139a62b157e78786d8c082f29dcf4c111314";
+// Empty root of the Blake2-256 (default), Keccak-256, BLAKE3 and SHA-256 builds.
+const EMPTY_ROOTS = [
+  "03170a2e7597b7b7e3d84c05391d139a62b157e78786d8c082f29dcf4c111314",
+  "bc36789e7a1e281436464229828f817d6612f7b477d66591ff96a9e064bcc98a",
+  "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213",
+  "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+];
 let CONTEXT: WasmContext | null = null;
 export const initialize = async (): Promise<WasmContext> => {
   if (CONTEXT !== null) {
@@ -48,8 +116,8 @@ export const initialize = async (): Promise<WasmContext> => {
   const context = new WasmContext(instance);
   const root = encodeHex(context.root());
 
-  if (root !== EMPTY_ROOT) {
-    throw new Error(`invalid empty root, expected ${EMPTY_ROOT}, got ${root}.`);
+  if (!EMPTY_ROOTS.includes(root)) {
+    throw new Error(`invalid empty root, got ${root}.`);
   }
 
   CONTEXT = context;
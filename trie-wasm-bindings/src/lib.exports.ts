@@ -1,7 +1,8 @@
 import { ByteBuffer, ByteMap, type Key, key2bytes } from "@scoped/utils";
-import type { JSMerklePatriciaTrie } from "./trie.ts";
-import type { InitOutput } from "../lib/trie.d.ts";
-export { JSTrieBuilder } from "./trie.ts";
+import type { JSMerklePatriciaTrie, NodeBytes, NodeKind } from "./trie.ts";
+import type { InitOutput, TrieErrorKind } from "../lib/trie.d.ts";
+import { JSTrieBuilder } from "./trie.ts";
+export { JSTrieBuilder };
 export type TrieWasmModule = InitOutput;
 
 function __ext_log(memory: Uint8Array, ptr: number, len: number) {
@@ -14,6 +15,31 @@ function __ext_log(memory: Uint8Array, ptr: number, len: number) {
   console.log(text);
 }
 
+function __ext_log_entry(
+  memory: Uint8Array,
+  level: LogLevel,
+  category: LogCategory,
+  ptr: number,
+  len: number,
+) {
+  ptr = ptr >>> 0;
+  if ((ptr + len) >= memory.length) {
+    console.error("ptr out of bounds");
+    return;
+  }
+  const text = decodeText(memory.subarray(ptr / 1, ptr / 1 + len));
+  const prefix = `[${LogCategory[category] ?? category}]`;
+  if (level >= LogLevel.Error) {
+    console.error(prefix, text);
+  } else if (level === LogLevel.Warn) {
+    console.warn(prefix, text);
+  } else if (level === LogLevel.Info) {
+    console.info(prefix, text);
+  } else {
+    console.debug(prefix, text);
+  }
+}
+
 function __ext_input(
   memory: Uint8Array,
   buffer_ptr: number,
@@ -83,8 +109,52 @@ enum ReturnCode {
 
   /// The provided key does not exist in storage.
   KeyNotFound = 3,
+
+  /// The output buffer is too small for the value.
+  BufferTooSmall = 4,
+
+  /// The storage can't hold the value.
+  StorageFull = 5,
+}
+
+/**
+ * Version of the host functions implemented by `HostFn`, the wasm module
+ * skips the functions added after the version it is given.
+ */
+export const HOST_ABI_VERSION = 10;
+
+/** Severity of the messages logged by the wasm module. */
+export enum LogLevel {
+  Trace = 0,
+  Debug = 1,
+  Info = 2,
+  Warn = 3,
+  Error = 4,
 }
 
+/** Part of the wasm module a logged message comes from. */
+export enum LogCategory {
+  /** Entry and result of the calls. */
+  Call = 0,
+  /** Reads and writes of the storage. */
+  Storage = 1,
+  /** Decoding and updates of the trie nodes. */
+  Trie = 2,
+  /** Generation and verification of proofs. */
+  Proof = 3,
+  /** Heap usage. */
+  Memory = 4,
+}
+
+/** Bit set in the result of a call that failed without trapping. */
+const CALL_ERROR_FLAG = 1n << 63n;
+
+/**
+ * Oldest wasm module ABI supported by this host, the same baseline as
+ * `MIN_HOST_VERSION` in `rust/merkle-patricia-trie-js/src/host.rs`.
+ */
+const MIN_MODULE_ABI_VERSION = 1;
+
 export class HostFn {
   private static instance?: WasmContext = undefined;
 
@@ -99,6 +169,18 @@ export class HostFn {
     __ext_log(memory, ptr, len);
   }
 
+  public static __ext_log_entry(
+    level: number,
+    category: number,
+    ptr: number,
+    len: number,
+  ): void {
+    const instance = HostFn.instance;
+    if (!instance) return;
+    const memory = instance.getMemory();
+    __ext_log_entry(memory, level, category, ptr, len);
+  }
+
   public static __ext_input(buffer_ptr: number, buffer_len_ptr: number): void {
     const instance = HostFn.instance;
     if (!instance) return;
@@ -112,6 +194,18 @@ export class HostFn {
     }
   }
 
+  public static __ext_return(ptr: number, len: number): number {
+    const instance = HostFn.instance;
+    if (!instance) return ReturnCode.Trapped;
+    const memory = instance.getMemory();
+    ptr = ptr >>> 0;
+    if ((ptr + len) > memory.length) {
+      return ReturnCode.Reverted;
+    }
+    instance.outputs.push(memory.slice(ptr, ptr + len));
+    return ReturnCode.Success;
+  }
+
   public static __ext_get_storage(
     key_ptr: number,
     key_len: number,
@@ -133,6 +227,46 @@ export class HostFn {
     return value ? ReturnCode.Success : ReturnCode.KeyNotFound;
   }
 
+  public static __ext_get_storage_batch(
+    keys_ptr: number,
+    keys_len: number,
+    out_ptr: number,
+    out_len_ptr: number,
+  ): number {
+    const instance = HostFn.instance;
+    if (!instance) return ReturnCode.Trapped;
+    const memory = instance.getMemory();
+    if ((keys_ptr + keys_len) >= memory.length || out_ptr >= memory.length) {
+      return ReturnCode.Reverted;
+    }
+    const keys = new DataView(memory.buffer, memory.byteOffset + keys_ptr, keys_len);
+    const values: (Uint8Array | undefined)[] = [];
+    let outputLen = 0;
+    for (let offset = 0; offset < keys_len;) {
+      if (offset + 4 > keys_len) return ReturnCode.Reverted;
+      const keyLen = keys.getUint32(offset, true);
+      offset += 4;
+      if (offset + keyLen > keys_len) return ReturnCode.Reverted;
+      const key = memory.subarray(keys_ptr + offset, keys_ptr + offset + keyLen);
+      offset += keyLen;
+      const value = instance.storage.getStorage(key);
+      values.push(value);
+      outputLen += 4 + (value ? value.length : 0);
+    }
+    const output = new Uint8Array(outputLen);
+    const view = new DataView(output.buffer);
+    let offset = 0;
+    for (const value of values) {
+      view.setUint32(offset, value ? value.length : 0xffffffff, true);
+      offset += 4;
+      if (value) {
+        output.set(value, offset);
+        offset += value.length;
+      }
+    }
+    return __ext_input(memory, out_ptr, out_len_ptr, output);
+  }
+
   public static __ext_set_storage(
     key_ptr: number,
     key_len: number,
@@ -154,10 +288,101 @@ export class HostFn {
     k.set(key);
     const v = new Uint8Array(value.length);
     v.set(value);
-    instance.storage.setStorage(k, v);
+    try {
+      instance.storage.setStorage(k, v);
+    } catch (error) {
+      console.error("__ext_set_storage:", error);
+      return ReturnCode.StorageFull;
+    }
+    return ReturnCode.Success;
+  }
+
+  public static __ext_contains_storage(key_ptr: number, key_len: number): number {
+    const instance = HostFn.instance;
+    if (!instance) return ReturnCode.Trapped;
+    const memory = instance.getMemory();
+    if ((key_ptr + key_len) >= memory.length) {
+      return ReturnCode.Reverted;
+    }
+    const key = memory.subarray(key_ptr / 1, key_ptr / 1 + key_len);
+    return instance.storage.hasStorage(key) ? ReturnCode.Success : ReturnCode.KeyNotFound;
+  }
+
+  public static __ext_take_storage(
+    key_ptr: number,
+    key_len: number,
+    val_ptr: number,
+    val_len_ptr: number,
+  ): number {
+    const instance = HostFn.instance;
+    if (!instance) return ReturnCode.Trapped;
+    const memory = instance.getMemory();
+    if ((key_ptr + key_len) >= memory.length || val_ptr >= memory.length) {
+      return ReturnCode.Reverted;
+    }
+    const key = memory.subarray(key_ptr / 1, key_ptr / 1 + key_len);
+    const value = instance.storage.getStorage(key);
+    if (!value) return ReturnCode.KeyNotFound;
+    const maxlen = new DataView(memory.buffer).getUint32(val_len_ptr >>> 0, true);
+    const result = __ext_input(memory, val_ptr, val_len_ptr, value);
+    if (result != ReturnCode.Success) {
+      return result;
+    }
+    // A value larger than the buffer is kept, the module takes it again.
+    if (value.length <= maxlen) {
+      instance.storage.deleteStorage(key);
+    }
+    return ReturnCode.Success;
+  }
+
+  public static __ext_value_size(
+    key_ptr: number,
+    key_len: number,
+    out_len_ptr: number,
+  ): number {
+    const instance = HostFn.instance;
+    if (!instance) return ReturnCode.Trapped;
+    const memory = instance.getMemory();
+    if ((key_ptr + key_len) >= memory.length || out_len_ptr >= memory.length) {
+      return ReturnCode.Reverted;
+    }
+    const key = memory.subarray(key_ptr / 1, key_ptr / 1 + key_len);
+    const value = instance.storage.getStorage(key);
+    if (!value) return ReturnCode.KeyNotFound;
+    new DataView(memory.buffer).setUint32(out_len_ptr >>> 0, value.length, true);
     return ReturnCode.Success;
   }
 
+  public static __ext_out_of_memory(requested: number, max_pages: number): void {
+    const instance = HostFn.instance;
+    if (!instance) return;
+    instance.outOfMemory = new OutOfMemoryError(requested, max_pages);
+  }
+
+  public static __ext_now(): number {
+    return performance.now();
+  }
+
+  public static __ext_notify(changes_ptr: number, changes_len: number): void {
+    const instance = HostFn.instance;
+    if (!instance) return;
+    const memory = instance.getMemory();
+    changes_ptr = changes_ptr >>> 0;
+    if ((changes_ptr + changes_len) > memory.length) {
+      console.error("__ext_notify: changes out of bounds");
+      return;
+    }
+    const reader = new OutputReader(
+      memory.slice(changes_ptr, changes_ptr + changes_len),
+    );
+    const root = reader.readHash();
+    const keys: Uint8Array[] = [];
+    while (reader.hasMore()) {
+      keys.push(reader.readBytes());
+    }
+    instance.notifications.push([keys, root]);
+  }
+
   public static __ext_clear_storage(key_ptr: number, key_len: number): number {
     const instance = HostFn.instance;
     if (!instance) return ReturnCode.Trapped;
@@ -171,6 +396,604 @@ export class HostFn {
   }
 }
 
+export type TrieOperation =
+  | { op: "insert"; key: Key; value: Key }
+  | { op: "remove"; key: Key };
+
+/**
+ * A step of an insert or removal, see `WasmContext.operationSteps`. The graph
+ * is the JSON tree of `valuesJson`, its highlighted nodes are the ones read by
+ * the lookup, the ones written by the operation, their `id` still `null`, or
+ * the node whose hash was just computed.
+ */
+export interface TrieStep {
+  phase: "lookup" | "restructure" | "hash";
+  graph: string;
+}
+
+const STEP_PHASES = ["lookup", "restructure", "hash"] as const;
+
+/** A node changed by an operation, see `WasmContext.applyExplained`. */
+export interface NodeChange {
+  change: "created" | "deleted" | "rehashed";
+  kind: NodeKind;
+  /** Nibbles leading to the node from the root, one per byte. */
+  path: Uint8Array;
+  /** Hash of the former node, `undefined` when created or inlined. */
+  oldHash?: Uint8Array;
+  /** Hash of the new node, `undefined` when deleted or inlined. */
+  newHash?: Uint8Array;
+}
+
+export interface MutationExplanation {
+  /** Hashes computed from the changed nodes up to the new root. */
+  hashesRecomputed: number;
+  /** Key nibble where an insert split the partial key of a node. */
+  splitNibble?: number;
+  changes: NodeChange[];
+}
+
+const NODE_CHANGES = ["created", "deleted", "rehashed"] as const;
+const NODE_KINDS: NodeKind[] = [
+  "empty",
+  "leaf",
+  "extension",
+  "branch",
+  "nibbledBranch",
+];
+
+/** Hashers of the Substrate storage maps, in the order of the metadata. */
+export type StorageHasher =
+  | "blake2_128"
+  | "blake2_256"
+  | "blake2_128Concat"
+  | "twox128"
+  | "twox256"
+  | "twox64Concat"
+  | "identity";
+
+const STORAGE_HASHERS: StorageHasher[] = [
+  "blake2_128",
+  "blake2_256",
+  "blake2_128Concat",
+  "twox128",
+  "twox256",
+  "twox64Concat",
+  "identity",
+];
+
+/** Data hashed into a storage key, see `WasmContext.storageKey`. */
+export interface StorageKeyPart {
+  hasher: StorageHasher;
+  data: Key;
+}
+
+/**
+ * Account of the Ethereum state trie, stored RLP encoded under the Keccak-256
+ * hash of its address. The accounts without storage or code default to the
+ * empty trie root and the hash of the empty code.
+ */
+export interface EthereumAccount {
+  nonce: number | bigint;
+  balance: bigint;
+  storageRoot?: Uint8Array;
+  codeHash?: Uint8Array;
+}
+
+function hexBytes(hex: string): Uint8Array {
+  return Uint8Array.from(hex.match(/../g)!, (byte) => parseInt(byte, 16));
+}
+
+export const EMPTY_STORAGE_ROOT = hexBytes(
+  "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+);
+export const EMPTY_CODE_HASH = hexBytes(
+  "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+);
+
+/** Parts of the key of a pallet storage item, followed by its map keys. */
+function palletKeyParts(
+  pallet: string,
+  item: string,
+  mapKeys: StorageKeyPart[],
+): StorageKeyPart[] {
+  return [
+    { hasher: "twox128", data: pallet },
+    { hasher: "twox128", data: item },
+    ...mapKeys,
+  ];
+}
+
+function writeStorageKeyParts(buffer: ByteBuffer, parts: StorageKeyPart[]) {
+  for (const part of parts) {
+    const dataEncoded = key2bytes(part.data);
+    buffer.writeU8(STORAGE_HASHERS.indexOf(part.hasher));
+    buffer.writeU32(dataEncoded.length, true);
+    buffer.writeU8List(dataEncoded);
+  }
+}
+
+/** Writes `operation` in the batch format of `dryRun`. */
+function writeOperation(buffer: ByteBuffer, operation: TrieOperation) {
+  const keyEncoded = key2bytes(operation.key);
+  buffer.writeU8(operation.op === "insert" ? 0 : 1);
+  buffer.writeU32(keyEncoded.length, true);
+  buffer.writeU8List(keyEncoded);
+  if (operation.op === "insert") {
+    const valueEncoded = key2bytes(operation.value);
+    buffer.writeU32(valueEncoded.length, true);
+    buffer.writeU8List(valueEncoded);
+  }
+}
+
+export interface TrieChange {
+  kind: "added" | "removed" | "modified";
+  key: Uint8Array;
+  oldValue?: Uint8Array;
+  newValue?: Uint8Array;
+}
+
+/**
+ * Exposes the host functions on the `trieHost` global, where modules built
+ * with the `node-host` feature read them, so scripts without a bundler, like
+ * Node.js tests, can drive the module. Call it before instantiating it.
+ */
+export function installNodeHost(): void {
+  (globalThis as { trieHost?: unknown }).trieHost = { HostFn, JSTrieBuilder };
+}
+
+/** An operation recorded by the wasm module, see `WasmContext.operationLog`. */
+export interface LoggedOperation {
+  op: "insert" | "remove";
+  /** Key as stored in the trie, hashed when secure keys are enabled. */
+  key: Uint8Array;
+  /** Hash of the inserted value. */
+  valueHash?: Uint8Array;
+  /** Root committed by the call, shared by the keys it changed. */
+  root: Uint8Array;
+}
+
+export interface OperationLog {
+  /** Number of operations in the log. */
+  length: number;
+  operations: LoggedOperation[];
+}
+
+/**
+ * Reads the length-prefixed byte strings used by the wasm module outputs.
+ */
+class OutputReader {
+  private view: DataView;
+  public offset: number;
+
+  constructor(private bytes: Uint8Array) {
+    this.view = new DataView(bytes.buffer, bytes.byteOffset, bytes.byteLength);
+    this.offset = 0;
+  }
+
+  public hasMore(): boolean {
+    return this.offset < this.bytes.length;
+  }
+
+  public readU8(): number {
+    return this.view.getUint8(this.offset++);
+  }
+
+  public readU32(): number {
+    const value = this.view.getUint32(this.offset, true);
+    this.offset += 4;
+    return value;
+  }
+
+  public readHash(): Uint8Array {
+    const hash = this.bytes.slice(this.offset, this.offset + 32);
+    this.offset += 32;
+    return hash;
+  }
+
+  public readBytes(): Uint8Array {
+    const len = this.view.getUint32(this.offset, true);
+    this.offset += 4;
+    const bytes = this.bytes.slice(this.offset, this.offset + len);
+    this.offset += len;
+    return bytes;
+  }
+}
+
+/**
+ * A key and its claimed value, `undefined` claims the key is absent.
+ */
+export type ProofItem = [key: Key, value: Key | undefined];
+
+export interface TracedGet {
+  value?: Uint8Array;
+  /** Total encoded bytes of the nodes read. */
+  bytesRead: number;
+  /** Hashes of the nodes read, in lookup order. */
+  nodes: Uint8Array[];
+}
+
+/** Result of `WasmContext.verifyStateRoot`. */
+export interface StateRootCheck {
+  matches: boolean;
+  computedRoot: Uint8Array;
+  /**
+   * Smallest subtree holding every difference, its nibble path and the node
+   * of each trie there, the hash or the node itself when inlined.
+   */
+  divergence?: { path: Uint8Array; expected: Uint8Array; actual: Uint8Array };
+}
+
+/** Result of `WasmContext.orderedTrie`, a proof per payload index. */
+export interface OrderedTrie {
+  root: Uint8Array;
+  /** Key of each index, RLP encoded by Ethereum and SCALE compact otherwise. */
+  keys: Uint8Array[];
+  proofs: Uint8Array[][];
+}
+
+/** A node on the route of a key, see `WasmContext.keyPath`. */
+export interface KeyPathSegment {
+  kind: NodeKind;
+  /** Hash of the node, `undefined` when inlined in its parent. */
+  hash?: Uint8Array;
+  /** Partial key of the node, one nibble per byte. */
+  partial: Uint8Array;
+  /** Nibbles of the partial key matched by the key. */
+  matched: number;
+  /** Branch nibble consumed to reach the next node. */
+  branchNibble?: number;
+}
+
+export interface KeyPath {
+  /** Nibbles of the key as stored in the trie, one per byte. */
+  nibbles: Uint8Array;
+  found: boolean;
+  /** Key nibble where the lookup stopped, where it diverged when absent. */
+  stoppedAt: number;
+  segments: KeyPathSegment[];
+}
+
+export interface ProofSizes {
+  /** SCALE encoded size of the full storage proof. */
+  fullSize: number;
+  /** SCALE encoded size of the compact proof. */
+  compactSize: number;
+}
+
+/** Host functions, in the order the wasm module encodes their counters. */
+const HOST_CALLS = [
+  "log",
+  "input",
+  "return",
+  "getStorage",
+  "getStorageBatch",
+  "containsStorage",
+  "valueSize",
+  "takeStorage",
+  "setStorage",
+  "clearStorage",
+] as const;
+
+export type HostCall = typeof HOST_CALLS[number];
+
+/** Decodes the host call counters encoded by the wasm module from `offset`. */
+function readHostMetrics(
+  view: DataView,
+  offset: number,
+): Record<HostCall, HostCallMetrics> {
+  const metrics = {} as Record<HostCall, HostCallMetrics>;
+  HOST_CALLS.forEach((call, index) => {
+    metrics[call] = {
+      calls: view.getUint32(offset + index * 12, true),
+      bytes: Number(view.getBigUint64(offset + index * 12 + 4, true)),
+    };
+  });
+  return metrics;
+}
+
+export interface HostCallMetrics {
+  calls: number;
+  /** Bytes copied across the boundary, keys and values included. */
+  bytes: number;
+}
+
+/** Workload of `WasmContext.benchmark`. */
+export interface BenchmarkOptions {
+  /** Number of generated keys inserted. */
+  inserts: number;
+  /** Byte length of the generated keys. */
+  keySize: number;
+  /** Byte length of the generated values. */
+  valueSize: number;
+  /** Number of reads of the inserted keys, in insertion order. */
+  reads: number;
+  /** Number of inserted keys removed, at most `inserts`. */
+  removals: number;
+}
+
+export interface BenchmarkPhase {
+  operations: number;
+  /** Milliseconds measured by the host clock. */
+  elapsed: number;
+  opsPerSecond: number;
+}
+
+export interface BenchmarkResult {
+  inserts: BenchmarkPhase;
+  reads: BenchmarkPhase;
+  removals: BenchmarkPhase;
+  /** Largest number of heap bytes in use during the call. */
+  peakMemory: number;
+  /** Host calls made by the workload. */
+  hostCalls: Record<HostCall, HostCallMetrics>;
+}
+
+/** Heap usage of the last call, addresses are offsets in the wasm memory. */
+export interface MemoryStats {
+  /** Start of the next allocation. */
+  next: number;
+  /** End of the memory available to the heap. */
+  upperLimit: number;
+  /** Wasm pages of 64 KiB requested during the call. */
+  pagesGrown: number;
+  /** Largest number of heap bytes in use during the call. */
+  peakUsage: number;
+}
+
+/**
+ * Thrown when a call exceeds the heap limit set by `setHeapLimit`. The wasm
+ * module reports the failed allocation through `__ext_out_of_memory` then
+ * traps on purpose, an allocation can't fail back to its caller. The call is
+ * rolled back and the next one starts from the initial memory, a persistent
+ * arena included, so the context stays usable.
+ */
+export class OutOfMemoryError extends Error {
+  constructor(
+    /** Size of the allocation that failed, in bytes. */
+    public readonly requested: number,
+    /** Heap limit in wasm pages of 64 KiB. */
+    public readonly maxPages: number,
+  ) {
+    super(
+      `out of memory: failed to allocate ${requested} bytes within ${maxPages} pages`,
+    );
+    this.name = "OutOfMemoryError";
+  }
+}
+
+/** Recoverable errors of the wasm calls. */
+export enum CallErrorCode {
+  /** The call code is unknown. */
+  InvalidCall = 1,
+  /** The input is truncated, out of bounds or holds an unknown flag. */
+  InvalidInput = 2,
+  /** A node, root or checkpoint referenced by the call isn't stored. */
+  KeyNotFound = 3,
+  /** The storage can't hold a value written by the call. */
+  ValueTooLarge = 4,
+  /** A stored node or a proof can't be decoded. */
+  DecodeError = 5,
+}
+
+/**
+ * Thrown when a call fails on its input or on the stored nodes. The call is
+ * rolled back and the context stays usable, unlike after a trap.
+ */
+export class CallError extends Error {
+  constructor(
+    public readonly code: CallErrorCode,
+    /** Code of the wasm call that failed. */
+    public readonly call: number,
+    /** Details recorded by the wasm module, see `WasmContext.lastError`. */
+    public readonly detail?: ErrorDetail,
+  ) {
+    const name = CallErrorCode[code] ?? `error ${code}`;
+    super(
+      `call ${call} failed: ${name}${detail ? `, ${detail.message}` : ""}`,
+    );
+    this.name = "CallError";
+  }
+}
+
+/** Details of the last failure recorded by the wasm module. */
+export interface ErrorDetail {
+  /** A `CallErrorCode`, or `0` for the failures trapping the instance. */
+  code: number;
+  /** Offending key or node hash, empty when the failure names none. */
+  subject: Uint8Array;
+  message: string;
+  /** Kind of the trie error behind the failure, if any. */
+  trieError?: TrieErrorKind;
+  /** Node hash named by the trie error, if any. */
+  hash?: Uint8Array;
+}
+
+export interface ListOptions {
+  /**
+   * Lists the node bytes as `0x` prefixed hex strings, as before the
+   * `Uint8Array` encoding became the default.
+   */
+  hex?: boolean;
+  /** Writes the hex digits in lowercase, they are uppercase by default. */
+  lowercase?: boolean;
+  /** Starts the hex strings with `0x`, the default. */
+  prefix?: boolean;
+  /**
+   * Digits kept in each hex string, longer strings are cut with an ellipsis.
+   * Every digit is kept by default.
+   */
+  maxDigits?: number;
+}
+
+/** Format flags of the node listings, see `ByteEncoding::from_flags`. */
+function formatFlags(options?: ListOptions): number {
+  if (!options?.hex) return 0;
+  const maxDigits = options.maxDigits ?? 0;
+  if (!Number.isInteger(maxDigits) || maxDigits < 0 || maxDigits > 0xffff) {
+    throw new Error(`invalid maxDigits ${maxDigits}`);
+  }
+  let flags = 1;
+  if (options.lowercase) flags |= 2;
+  if (options.prefix === false) flags |= 4;
+  return (flags | (maxDigits << 16)) >>> 0;
+}
+
+export interface DepthOptions extends ListOptions {
+  /**
+   * Levels listed below the first node, the nodes whose children were left
+   * out are `truncated`. The whole trie is listed by default.
+   */
+  maxDepth?: number;
+}
+
+export interface CollapseOptions extends DepthOptions {
+  /**
+   * Merges the chains of nodes with a single child and no value into their
+   * last node, which is flagged as `collapsed` and carries the nibbles of the
+   * whole chain.
+   */
+  collapse?: boolean;
+}
+
+/** Maximum depth passed to the wasm module to list every level. */
+const UNLIMITED_DEPTH = 0xffffffff;
+
+/** Trie listed with `options`, whose bytes are hex strings when `hex` is set. */
+export type ListedTrie<O extends ListOptions> = JSMerklePatriciaTrie<
+  O extends { hex: true } ? string : Uint8Array
+>;
+
+/** Decoding of the values stored under a key prefix, for display. */
+export interface ValueHint {
+  prefix: Key;
+  /** `u32` and `u128` are SCALE encoded, as little endian integers. */
+  kind: "utf8" | "u32" | "u128" | "h256" | "account";
+}
+
+const VALUE_HINT_CODES = { utf8: 0, u32: 1, u128: 2, h256: 3, account: 4 };
+
+/** Node visited by `WasmContext.streamNodes`, in depth-first order. */
+export interface StreamedNode<B extends NodeBytes = NodeBytes> {
+  index: number;
+  /** Index of the parent node, `null` for the root. */
+  parent: number | null;
+  /** Nibble labeling the node in its parent, `null` below an extension. */
+  nibble: number | null;
+  /** Hash of the node, `undefined` when it is inlined in its parent. */
+  id?: B;
+  nibbles?: B;
+  value?: B;
+}
+
+/** Nodes listed by `WasmContext.nodesPage`. */
+export interface NodePage {
+  /** Nodes stored under their hash, inline nodes are part of their parent. */
+  nodes: { id: Uint8Array; encoded: Uint8Array }[];
+  /** Token of the next page, `undefined` once every node was listed. */
+  next?: Uint8Array;
+}
+
+/** Heap allocations counted by a module built with `alloc-profile`. */
+export interface AllocationProfile {
+  count: number;
+  bytes: number;
+  /**
+   * Allocations by size, up to 16, 64, 256 bytes, 1, 4, 16 and 64 KiB, and
+   * larger.
+   */
+  histogram: number[];
+}
+
+export interface TrieStats {
+  /** Number of stored nodes, inline nodes are part of their parent. */
+  nodes: number;
+  /** Total encoded bytes of the stored nodes. */
+  bytes: number;
+  averageNodeSize: number;
+  leaves: number;
+  branches: number;
+  extensions: number;
+  /** Values stored in their own node, by state version `1`. */
+  values: number;
+  /** Number of nodes for each reference counter. */
+  counters: Map<number, number>;
+}
+
+/**
+ * Shape of the trie as seen by lookups, a node shared by several parents is
+ * counted at each of its positions and the inline nodes are counted as well.
+ */
+export interface TrieShape {
+  /** Number of nodes of each kind. */
+  kinds: Record<NodeKind, number>;
+  /**
+   * Number of nodes at each level below the root, the last level counts the
+   * deeper nodes as well.
+   */
+  depths: number[];
+  /** Number of branches for each number of children, from 0 to 16. */
+  branching: number[];
+  values: number;
+  /** Average level of the nodes holding a value. */
+  averageValueDepth: number;
+  /** Level of the deepest node holding a value. */
+  maxValueDepth: number;
+}
+
+export interface RangeProof {
+  /** Whether the proof reaches the last key of the trie. */
+  complete: boolean;
+  /** Number of entries read while generating the proof. */
+  count: number;
+  nodes: Uint8Array[];
+}
+
+export interface VerifiedRange {
+  /** Whether the proven range reaches the last key of the trie. */
+  complete: boolean;
+  entries: [key: Uint8Array, value: Uint8Array][];
+}
+
+/** A watched key, or every key starting with `prefix`. */
+export type KeyWatch = { key: Key } | { prefix: Key };
+
+/**
+ * Called with the watched keys changed by a call, and the root it committed.
+ */
+export type WatchListener = (keys: Uint8Array[], root: Uint8Array) => void;
+
+/** Entries read by a cursor, see `WasmContext.cursorNext`. */
+export interface CursorPage {
+  /** Whether the cursor reached the last key of the trie. */
+  done: boolean;
+  entries: [key: Uint8Array, value: Uint8Array][];
+}
+
+/** Outcome of `WasmContext.feedSync`. */
+export interface SyncProgress {
+  /** Nodes stored, each one was missing and matches its hash. */
+  accepted: number;
+  rejected: number;
+  /** Nodes still missing, `0` once the trie moved to the synced root. */
+  missing: number;
+}
+
+export type StateVersion = 0 | 1;
+
+export type TrieHasher = "blake2" | "keccak" | "blake3" | "sha256";
+export type TrieLayoutKind = "v0" | "v1" | "ethereum";
+
+export interface TrieConfig {
+  hasher: TrieHasher;
+  layout: TrieLayoutKind;
+  /** Hash the keys before inserting them, like Ethereum's secure trie. */
+  secureKeys: boolean;
+}
+
+const TRIE_HASHERS: TrieHasher[] = ["blake2", "keccak", "blake3", "sha256"];
+const TRIE_LAYOUTS: TrieLayoutKind[] = ["v0", "v1", "ethereum"];
+
 export interface TrieStorage {
   getStorage(key: Uint8Array): Uint8Array | undefined;
   hasStorage(key: Uint8Array): boolean;
@@ -204,6 +1027,28 @@ export class DefaultTrieStorage extends ByteMap<Uint8Array>
   clear(): void {
     this.map.clear();
   }
+
+  /**
+   * Every entry as its length-prefixed key followed by its length-prefixed
+   * value, the dump read by the `trie-inspect` binary.
+   */
+  dump(): Uint8Array {
+    let size = 0;
+    for (const [key, value] of this.map.values()) {
+      size += 8 + key.length + value.length;
+    }
+    const output = new Uint8Array(size);
+    const view = new DataView(output.buffer);
+    let offset = 0;
+    for (const [key, value] of this.map.values()) {
+      for (const bytes of [key, value]) {
+        view.setUint32(offset, bytes.length, true);
+        output.set(bytes, offset + 4);
+        offset += 4 + bytes.length;
+      }
+    }
+    return output;
+  }
 }
 
 export class WasmContext {
@@ -214,13 +1059,58 @@ export class WasmContext {
   public initialState: Uint8Array;
   public inputLen: number;
 
+  /** ABI version reported by the wasm module during the handshake. */
+  public readonly abiVersion: number;
+
   /**
-   * The max capacity is 16 megabytes.
+   * Buffers returned by the current call through `__ext_return`, copied out
+   * of the wasm memory before the call returns.
    */
-  public static MAX_INPUT_BUFFER_SIZE: number = 1024 * 1024 * 16;
+  public outputs: Uint8Array[] = [];
+
+  /** Set by `__ext_out_of_memory` when the current call ran out of heap. */
+  public outOfMemory?: OutOfMemoryError;
 
   /**
-   * The minimal capacity is 8 bytes.
+   * Watched keys changed by the current call and the new root, received
+   * through `__ext_notify` and handed to the listener once the call succeeds.
+   */
+  public notifications: [keys: Uint8Array[], root: Uint8Array][] = [];
+
+  /** Listener of the watched keys, see `watch`. */
+  private watchListener?: WatchListener;
+
+  /** Max wasm pages the heap may grow to, unlimited when undefined. */
+  private heapLimit?: number;
+
+  /** Minimum level and category mask of the logged messages. */
+  private logFilter: [LogLevel, number] = [LogLevel.Trace, 0xffffffff];
+
+  /** Whether the wasm heap is kept across calls, see `setPersistentArena`. */
+  private persistentArena = false;
+
+  /** Set when a call trapped, the next call starts from the initial memory. */
+  private trapped = false;
+
+  /**
+   * Namespace prefixed to every storage key, distinct namespaces hold
+   * independent tries in the same storage.
+   */
+  private namespace: Uint8Array = new Uint8Array(0);
+
+  /**
+   * Handle of the trie the calls run on, `0` for the default trie. Replaces
+   * the namespace from ABI version 10 on.
+   */
+  private handle = 0;
+
+  /**
+   * The max capacity is 16 megabytes.
+   */
+  public static MAX_INPUT_BUFFER_SIZE: number = 1024 * 1024 * 16;
+
+  /**
+   * The minimal capacity is 8 bytes.
    */
   public static SHARED_INPUT_BUFFER: ByteBuffer = new ByteBuffer({
     capacity: 1024,
@@ -232,47 +1122,194 @@ export class WasmContext {
     this.memory = new Uint8Array(wasm.memory.buffer);
     this.storage = storage ?? new DefaultTrieStorage();
     this.inputLen = 0;
+    this.merkleRoot = new Uint8Array(32);
+    this.abiVersion = wasm.__ext_version(HOST_ABI_VERSION);
+    if (this.abiVersion < MIN_MODULE_ABI_VERSION) {
+      throw new Error(
+        `unsupported wasm module ABI version ${this.abiVersion}, expected at least ${MIN_MODULE_ABI_VERSION}`,
+      );
+    }
+    // The snapshot restored before every call keeps the negotiated version.
     this.initialState = new Uint8Array(this.memory.length);
     this.initialState.set(this.memory);
-    this.merkleRoot = new Uint8Array(32);
     this._updateRoot();
   }
 
   private _updateRoot(): void {
     this.reset();
     HostFn.attach(this);
-    const ptr = BigInt.asUintN(64, this.instance.__ext_call(4, this.inputLen));
-    const len = Number(BigInt.asUintN(32, ptr));
-    const offset = Number(ptr >> 32n);
-    const root = this.memory.subarray(offset, offset + len);
-    this.merkleRoot.set(root);
+    this.merkleRoot.set(this._callOutput(4, 0)!);
   }
 
-  private _readMemory(ptr: bigint): Uint8Array | undefined {
-    if (ptr === 0n) {
-      // For this specific wasm binary, the convetion
-      // is that ptr === 0n means NULL or undefined.
-      return undefined;
-    }
-    ptr = ptr < 0n ? BigInt.asUintN(64, ptr) : ptr;
-    const len: bigint = BigInt.asUintN(32, ptr);
-    const begin: bigint = ptr >> 32n;
-    const end: bigint = begin + len;
-    const memSize: bigint = BigInt(this.memory.length);
-    if (end >= memSize) {
-      throw new Error(
-        `memory out of bounds!, memory size: ${memSize}, ptr[${begin}:${end}]`,
-      );
+  /**
+   * Prepends the trie handle, or the namespace for modules older than ABI
+   * version 10, to the first `inputLen` bytes of the shared input buffer,
+   * returns the length of the resulting input.
+   */
+  private _writeHeader(inputLen: number): number {
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    const payload = buffer.slice(0, inputLen);
+    buffer.cursor = 0;
+    if (this.abiVersion >= 10) {
+      buffer.writeU32(this.handle, true);
+    } else {
+      buffer.writeU8(this.namespace.length);
+      buffer.writeU8List(this.namespace);
     }
-    return this.memory.subarray(Number(begin), Number(end));
+    buffer.writeU8List(payload);
+    return buffer.cursor;
   }
 
   private _call(code: number, inputLen: number): bigint {
+    inputLen = this._writeHeader(inputLen);
     this.inputLen = inputLen;
+    this.outputs = [];
+    this.notifications = [];
     HostFn.attach(this);
-    const ptr = this.instance.__ext_call(code, inputLen);
+    const ret = this._invoke(() => this.instance.__ext_call(code, inputLen));
     this.inputLen = 0;
-    return ptr;
+    if (ret & CALL_ERROR_FLAG) {
+      throw new CallError(Number(ret & 0xffn), code, this.lastError());
+    }
+    // The listener may call the module again, which clears the notifications.
+    const notifications = this.notifications;
+    this.notifications = [];
+    for (const [keys, root] of notifications) {
+      this.watchListener?.(keys, root);
+    }
+    return ret;
+  }
+
+  /**
+   * Runs a wasm export, a trap caused by exhausting the heap is rethrown as
+   * an `OutOfMemoryError`.
+   */
+  private _invoke<T>(run: () => T): T {
+    this.outOfMemory = undefined;
+    try {
+      return run();
+    } catch (error) {
+      this.inputLen = 0;
+      this.trapped = true;
+      throw this.outOfMemory ?? error;
+    }
+  }
+
+  /**
+   * Caps the memory the wasm heap may grow to, in pages of 64 KiB. Calls
+   * exceeding it throw an `OutOfMemoryError`. `undefined` removes the cap.
+   */
+  public setHeapLimit(pages?: number): void {
+    this.heapLimit = pages;
+  }
+
+  /**
+   * Logs the messages of the wasm module of at least `level`, from the given
+   * categories only, or from every category when omitted. Only the builds
+   * with the debug log enabled log anything.
+   */
+  public setLogFilter(level: LogLevel, categories?: LogCategory[]): void {
+    const mask = categories
+      ? categories.reduce((mask, category) => mask | (1 << category), 0)
+      : 0xffffffff;
+    this.logFilter = [level, mask >>> 0];
+    if (this.abiVersion >= 7) {
+      this.instance.__ext_set_log_filter(...this.logFilter);
+    }
+  }
+
+  /**
+   * Keeps the wasm heap and the node cache across calls instead of restoring
+   * the initial memory before each one. Call `resetArena` after changing the
+   * storage outside of this context, the cached nodes may be stale.
+   */
+  public setPersistentArena(enabled: boolean): void {
+    this.persistentArena = enabled;
+    this.instance.__ext_set_persistent(enabled ? 1 : 0);
+  }
+
+  /** Wipes the heap and the node cache kept by a persistent arena. */
+  public resetArena(): void {
+    this.instance.__ext_reset();
+  }
+
+  /**
+   * Calls the wasm module and returns the first buffer it returned, if any.
+   */
+  private _callOutput(code: number, inputLen: number): Uint8Array | undefined {
+    this._call(code, inputLen);
+    return this.outputs[0];
+  }
+
+  /**
+   * Selects the namespace of the following calls, each namespace holds an
+   * independent trie with its own root. The default is the empty namespace.
+   */
+  public setNamespace(namespace: Key): void {
+    const bytes = key2bytes(namespace);
+    if (bytes.length > 255) {
+      throw new Error("namespace longer than 255 bytes");
+    }
+    this.namespace = bytes.slice();
+    if (this.abiVersion >= 10) {
+      // The named tries keep their keys under their name.
+      this.handle = bytes.length === 0
+        ? 0
+        : this.openTrie(bytes) ?? this.createTrie(bytes);
+    }
+    this._updateRoot();
+  }
+
+  /**
+   * Creates the trie `name` and returns its handle, see `selectTrie`. Needs
+   * a wasm module implementing ABI version 10.
+   */
+  public createTrie(name: Key): number {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(name));
+    return Number(this._call(59, buffer.cursor));
+  }
+
+  /** Handle of the trie `name`, `undefined` when it doesn't exist. */
+  public openTrie(name: Key): number | undefined {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(name));
+    try {
+      return Number(this._call(60, buffer.cursor));
+    } catch (error) {
+      if (
+        error instanceof CallError && error.code === CallErrorCode.KeyNotFound
+      ) {
+        return undefined;
+      }
+      throw error;
+    }
+  }
+
+  /**
+   * Runs the following calls on the trie `handle`, `0` selects the default
+   * trie.
+   */
+  public selectTrie(handle: number): void {
+    this.handle = handle;
+    this._updateRoot();
+  }
+
+  /**
+   * Drops the selected trie, releasing its nodes, and selects the default
+   * trie. Its checkpoints, history and other reserved keys are left in the
+   * storage.
+   */
+  public dropTrie(): void {
+    this.reset();
+    this._call(61, 0);
+    this.handle = 0;
+    this.namespace = new Uint8Array(0);
+    this._updateRoot();
   }
 
   public root(): Uint8Array {
@@ -303,41 +1340,1522 @@ export class WasmContext {
     this._updateRoot();
   }
 
-  public contains(key: Key): boolean {
+  /**
+   * Applies the operations in memory and returns the resulting root,
+   * without modifying the storage.
+   */
+  public dryRun(operations: TrieOperation[]): Uint8Array {
     this.reset();
     const buffer = WasmContext.SHARED_INPUT_BUFFER;
     buffer.cursor = 0;
-    buffer.writeU8List(key2bytes(key));
-    return this._call(2, buffer.cursor) == 1n;
+    for (const operation of operations) {
+      writeOperation(buffer, operation);
+    }
+    return this._callOutput(5, buffer.cursor)!;
   }
 
-  public get(key: Key): Uint8Array | undefined {
+  /**
+   * Intermediate graphs of the operation, for animating how nodes are split
+   * or merged and their hashes recomputed, without modifying the storage.
+   * The last step is the resulting trie.
+   */
+  public operationSteps(operation: TrieOperation): TrieStep[] {
     this.reset();
     const buffer = WasmContext.SHARED_INPUT_BUFFER;
     buffer.cursor = 0;
-    buffer.writeU8List(key2bytes(key));
-    const ptr = this._call(3, buffer.cursor);
-    const memorySlice = this._readMemory(ptr);
+    writeOperation(buffer, operation);
+    const reader = new OutputReader(this._callOutput(64, buffer.cursor)!);
+    const steps: TrieStep[] = [];
+    while (reader.hasMore()) {
+      const phase = STEP_PHASES[reader.readU8()];
+      steps.push({ phase, graph: decodeText(reader.readBytes()) });
+    }
+    return steps;
+  }
+
+  /**
+   * Applies the operation, like `insert` or `remove`, and explains why the
+   * root changed: the nodes created, deleted or rehashed, the nibble where a
+   * partial key was split and the number of hashes recomputed.
+   */
+  public applyExplained(operation: TrieOperation): MutationExplanation {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    writeOperation(buffer, operation);
+    const output = this._callOutput(65, buffer.cursor)!;
+    this._updateRoot();
+    const view = new DataView(output.buffer, output.byteOffset);
+    const reader = new OutputReader(output);
+    reader.offset = 4;
+    const explanation: MutationExplanation = {
+      hashesRecomputed: view.getUint32(0, true),
+      changes: [],
+    };
+    if (reader.readU8() === 1) {
+      explanation.splitNibble = view.getUint32(reader.offset, true);
+      reader.offset += 4;
+    }
+    while (reader.hasMore()) {
+      const change = NODE_CHANGES[reader.readU8()];
+      const kind = NODE_KINDS[reader.readU8()];
+      const path = reader.readBytes();
+      const oldHash = reader.readBytes();
+      const newHash = reader.readBytes();
+      explanation.changes.push({
+        change,
+        kind,
+        path,
+        oldHash: oldHash.length > 0 ? oldHash : undefined,
+        newHash: newHash.length > 0 ? newHash : undefined,
+      });
+    }
+    return explanation;
+  }
+
+  /**
+   * Same as `get`, but also returns the nodes read during the lookup, which
+   * is how runtimes measure the proof size of a read.
+   */
+  public traceGet(key: Key): TracedGet {
+    this._callWithKey(18, key);
+    const memorySlice = this.outputs[0]!;
+    const reader = new OutputReader(memorySlice);
+    const value = reader.readU8() === 1 ? reader.readBytes() : undefined;
+    const view = new DataView(
+      memorySlice.buffer,
+      memorySlice.byteOffset + reader.offset,
+      4,
+    );
+    const bytesRead = view.getUint32(0, true);
+    const nodes: Uint8Array[] = [];
+    for (let offset = reader.offset + 4; offset < memorySlice.length; offset += 32) {
+      nodes.push(memorySlice.slice(offset, offset + 32));
+    }
+    return { value, bytesRead, nodes };
+  }
+
+  /**
+   * Concatenates the parts hashed by their hasher, as Substrate builds the
+   * keys of its storage items.
+   */
+  public storageKey(parts: StorageKeyPart[]): Uint8Array {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    writeStorageKeyParts(buffer, parts);
+    return this._callOutput(67, buffer.cursor) ?? new Uint8Array();
+  }
+
+  public twox128(data: Key): Uint8Array {
+    return this.storageKey([{ hasher: "twox128", data }]);
+  }
+
+  public twox64Concat(data: Key): Uint8Array {
+    return this.storageKey([{ hasher: "twox64Concat", data }]);
+  }
+
+  public blake2_128Concat(data: Key): Uint8Array {
+    return this.storageKey([{ hasher: "blake2_128Concat", data }]);
+  }
+
+  /**
+   * Key of the storage item `item` of the pallet `pallet`, for instance
+   * `System` and `Account`, followed by the map keys hashed by their hasher.
+   */
+  public palletStorageKey(
+    pallet: string,
+    item: string,
+    mapKeys: StorageKeyPart[] = [],
+  ): Uint8Array {
+    return this.storageKey(palletKeyParts(pallet, item, mapKeys));
+  }
+
+  /** Inserts `value` under the key of `palletStorageKey`. */
+  public insertStorageItem(
+    pallet: string,
+    item: string,
+    value: Key,
+    mapKeys: StorageKeyPart[] = [],
+  ): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    const valueEncoded = key2bytes(value);
+    buffer.writeU32(valueEncoded.length, true);
+    buffer.writeU8List(valueEncoded);
+    writeStorageKeyParts(buffer, palletKeyParts(pallet, item, mapKeys));
+    this._call(68, buffer.cursor);
+    this._updateRoot();
+  }
+
+  /**
+   * Stores the accounts of an Ethereum state trie from now on: Keccak-256,
+   * RLP nodes and hashed keys, with the values listed as decoded accounts.
+   * Replaces the value hints, only allowed while the trie is empty.
+   */
+  public useAccountTrie(): void {
+    this.configure({ hasher: "keccak", layout: "ethereum", secureKeys: true });
+    this.setValueHints([{ prefix: new Uint8Array(), kind: "account" }]);
+  }
+
+  /** Inserts `account` under the hash of `address`, see `useAccountTrie`. */
+  public insertAccount(address: Key, account: EthereumAccount): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    const addressEncoded = key2bytes(address);
+    buffer.writeU32(addressEncoded.length, true);
+    buffer.writeU8List(addressEncoded);
+    buffer.writeU64(BigInt(account.nonce), true);
+    const balance = new Uint8Array(32);
+    let remaining = account.balance;
+    for (let i = 31; i >= 0 && remaining > 0n; i--) {
+      balance[i] = Number(remaining & 0xffn);
+      remaining >>= 8n;
+    }
+    if (remaining > 0n || account.balance < 0n) {
+      throw new RangeError("the balance must fit in 256 bits");
+    }
+    buffer.writeU8List(balance);
+    buffer.writeU8List(account.storageRoot ?? EMPTY_STORAGE_ROOT);
+    buffer.writeU8List(account.codeHash ?? EMPTY_CODE_HASH);
+    this._call(71, buffer.cursor);
+    this._updateRoot();
+  }
+
+  /**
+   * Breaks the key down into nibbles and shows how the nodes on its route
+   * consume them, segment by segment, up to its value or to the nibble where
+   * the lookup diverges when the key is absent.
+   */
+  public keyPath(key: Key): KeyPath {
+    this._callWithKey(66, key);
+    const reader = new OutputReader(this.outputs[0]!);
+    const nibbles = reader.readBytes();
+    const found = reader.readU8() === 1;
+    const stoppedAt = reader.readU32();
+    const segments: KeyPathSegment[] = [];
+    while (reader.hasMore()) {
+      const kind = NODE_KINDS[reader.readU8()];
+      const hash = reader.readBytes();
+      const partial = reader.readBytes();
+      const matched = reader.readU32();
+      const branchNibble = reader.readU8();
+      segments.push({
+        kind,
+        hash: hash.length > 0 ? hash : undefined,
+        partial,
+        matched,
+        branchNibble: branchNibble < 16 ? branchNibble : undefined,
+      });
+    }
+    return { nibbles, found, stoppedAt, segments };
+  }
+
+  /**
+   * Creates a checkpoint of the current root, the nodes reachable from it
+   * are kept in the storage until the checkpoint is dropped.
+   */
+  public checkpoint(name: Key): void {
+    this._callWithKey(6, name);
+  }
+
+  /**
+   * Reverts the trie to a previously created checkpoint.
+   */
+  public revert(name: Key): void {
+    this._callWithKey(7, name);
+    this._updateRoot();
+  }
+
+  public dropCheckpoint(name: Key): void {
+    this._callWithKey(8, name);
+  }
+
+  /**
+   * Lists every committed root, oldest first.
+   */
+  public history(): Uint8Array[] {
+    this.reset();
+    const memorySlice = this._callOutput(9, 0);
+    const roots: Uint8Array[] = [];
     if (memorySlice === undefined) {
-      return undefined;
+      return roots;
     }
-    return memorySlice.slice();
+    for (let offset = 0; offset < memorySlice.length; offset += 32) {
+      roots.push(memorySlice.slice(offset, offset + 32));
+    }
+    return roots;
   }
 
-  public values(): JSMerklePatriciaTrie {
+  /**
+   * Lists up to `count` of the keys changed by the calls committed so far,
+   * from the sequence number `start`, oldest first.
+   */
+  public operationLog(start = 0, count = 0xffffffff): OperationLog {
     this.reset();
-    return this.instance.__ext_list_nodes();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(start, true);
+    buffer.writeU32(count, true);
+    const output = this._callOutput(46, buffer.cursor)!;
+    const view = new DataView(
+      output.buffer,
+      output.byteOffset,
+      output.byteLength,
+    );
+    const reader = new OutputReader(output);
+    reader.offset = 8;
+    const operations: LoggedOperation[] = [];
+    while (reader.hasMore()) {
+      const op = reader.readU8() === 0 ? "insert" : "remove";
+      const key = reader.readBytes();
+      const valueHash = op === "insert" ? reader.readHash() : undefined;
+      operations.push({ op, key, valueHash, root: reader.readHash() });
+    }
+    return { length: Number(view.getBigUint64(0, true)), operations };
   }
 
-  public getStorage(): TrieStorage {
-    return this.storage;
+  /**
+   * Rebuilds the trie from an empty one by replaying the first `count`
+   * operations of the log, see `operationLog`, and returns the new root. The
+   * log itself is kept, so a later replay can move forward again, and the
+   * replay is logged as any other call.
+   */
+  public replay(count: number): Uint8Array {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(count, true);
+    const root = this._callOutput(47, buffer.cursor)!;
+    this._updateRoot();
+    return root;
   }
 
-  private reset() {
-    const memory = this.getMemory();
-    memory.set(this.initialState);
-    if (memory.length > this.initialState.length) {
-      memory.fill(0, this.initialState.length, memory.length);
+  /**
+   * Watches the given keys and prefixes of the current namespace, replacing
+   * the previous ones. `listener` is called after every call changing one of
+   * them, prefixes match the keys as stored in the trie. Needs a wasm module
+   * implementing ABI version 8.
+   */
+  public watch(watches: KeyWatch[], listener: WatchListener): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    for (const watch of watches) {
+      const key = key2bytes("key" in watch ? watch.key : watch.prefix);
+      buffer.writeU8("key" in watch ? 0 : 1);
+      buffer.writeU32(key.length, true);
+      buffer.writeU8List(key);
+    }
+    this._call(51, buffer.cursor);
+    this.watchListener = listener;
+  }
+
+  /** Stops watching the keys of the current namespace. */
+  public unwatch(): void {
+    this.reset();
+    this._call(51, 0);
+    this.watchListener = undefined;
+  }
+
+  /**
+   * Opens a cursor over the current trie from the first key not below
+   * `start`, and returns its handle. Cursors are kept between calls, so they
+   * need a persistent arena, see `setPersistentArena`, and are closed when
+   * the arena is wiped. Later writes aren't seen by the cursor.
+   */
+  public openCursor(start: Key = new Uint8Array()): number {
+    return Number(this._callWithKey(48, start));
+  }
+
+  /** Reads up to `count` entries of the cursor `handle` and moves past them. */
+  public cursorNext(handle: number, count: number): CursorPage {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(handle, true);
+    buffer.writeU32(count, true);
+    const reader = new OutputReader(this._callOutput(49, buffer.cursor)!);
+    const done = reader.readU8() === 1;
+    const entries: [Uint8Array, Uint8Array][] = [];
+    while (reader.hasMore()) {
+      entries.push([reader.readBytes(), reader.readBytes()]);
+    }
+    return { done, entries };
+  }
+
+  public closeCursor(handle: number): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(handle, true);
+    this._call(50, buffer.cursor);
+  }
+
+  /**
+   * Reads a value from the trie at an arbitrary root, the nodes of old roots
+   * are only guaranteed to exist when archive mode is enabled.
+   */
+  public getAt(root: Uint8Array, key: Key): Uint8Array | undefined {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(root);
+    buffer.writeU8List(key2bytes(key));
+    const memorySlice = this._callOutput(10, buffer.cursor);
+    return memorySlice?.slice();
+  }
+
+  /**
+   * When enabled, nodes are never deleted so every historical root stays readable.
+   */
+  public setArchive(archive: boolean): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8(archive ? 1 : 0);
+    this._call(11, buffer.cursor);
+  }
+
+  /**
+   * Selects the node encoding of the following writes, state version `1`
+   * (the default) stores values longer than 32 bytes in separated nodes.
+   */
+  public setStateVersion(version: StateVersion): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8(version);
+    this._call(23, buffer.cursor);
+  }
+
+  public stateVersion(): StateVersion {
+    this.reset();
+    return Number(this._call(24, 0)) as StateVersion;
+  }
+
+  /**
+   * Migrates a trie using state version `0` to state version `1`, moving the
+   * values longer than 32 bytes to separated nodes. Returns the number of
+   * nodes the migrated trie holds that the original didn't.
+   */
+  public migrateToV1(): number {
+    this.reset();
+    return Number(this._call(28, 0));
+  }
+
+  /**
+   * Rewrites a storage written before the reference counters were stored
+   * along with the node bytes, so each node takes a single entry. Returns the
+   * number of nodes rewritten, `0` when the storage is already migrated.
+   */
+  public migrateStorageFormat(): number {
+    this.reset();
+    return Number(this._call(30, 0));
+  }
+
+  /**
+   * Returns the host calls made since the last time the metrics were read,
+   * then resets them.
+   */
+  public hostMetrics(): Record<HostCall, HostCallMetrics> {
+    this.reset();
+    const memorySlice = this._callOutput(38, 0)!;
+    const view = new DataView(
+      memorySlice.buffer,
+      memorySlice.byteOffset,
+      memorySlice.byteLength,
+    );
+    return readHostMetrics(view, 0);
+  }
+
+  /**
+   * Runs a workload of generated keys and values inside the wasm module and
+   * times it with the host clock. Every operation is committed on its own,
+   * in memory, so the storage isn't changed. Needs a wasm module
+   * implementing ABI version 9.
+   */
+  public benchmark(options: BenchmarkOptions): BenchmarkResult {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(options.inserts, true);
+    buffer.writeU32(options.keySize, true);
+    buffer.writeU32(options.valueSize, true);
+    buffer.writeU32(options.reads, true);
+    buffer.writeU32(options.removals, true);
+    const output = this._callOutput(52, buffer.cursor)!;
+    const view = new DataView(
+      output.buffer,
+      output.byteOffset,
+      output.byteLength,
+    );
+    const phase = (offset: number): BenchmarkPhase => {
+      const operations = view.getUint32(offset, true);
+      const elapsed = view.getFloat64(offset + 4, true);
+      const opsPerSecond = elapsed > 0 ? operations * 1000 / elapsed : 0;
+      return { operations, elapsed, opsPerSecond };
+    };
+    return {
+      inserts: phase(0),
+      reads: phase(12),
+      removals: phase(24),
+      peakMemory: view.getUint32(36, true),
+      hostCalls: readHostMetrics(view, 40),
+    };
+  }
+
+  /**
+   * Saves the entries under `prefix`, the whole trie by default, as a SCALE
+   * encoded compact proof, see `importSnapshot`. A prefix snapshot holds a
+   * trie of its own, with a different root.
+   */
+  public exportSnapshot(prefix: Key = new Uint8Array()): Uint8Array {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(prefix));
+    return this._callOutput(53, buffer.cursor)!.slice();
+  }
+
+  /**
+   * Restores a snapshot saved by `exportSnapshot` into an empty trie and
+   * returns its root.
+   */
+  public importSnapshot(snapshot: Uint8Array): Uint8Array {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(snapshot);
+    const root = this._callOutput(54, buffer.cursor)!;
+    this._updateRoot();
+    return root;
+  }
+
+  /**
+   * Loads the pairs of a `state_getPairs` dump of a Substrate node into the
+   * trie and returns how many were read. A string is the JSON response, its
+   * `result` or an object mapping the hex keys to the hex values, `null`
+   * values remove their key. Bytes are the SCALE encoded pairs.
+   */
+  public importStatePairs(dump: string | Uint8Array): number {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8(typeof dump === "string" ? 0 : 1);
+    buffer.writeU8List(key2bytes(dump));
+    const count = Number(this._call(69, buffer.cursor));
+    this._updateRoot();
+    return count;
+  }
+
+  /**
+   * Rebuilds the trie of a `state_getPairs` dump, see `importStatePairs`,
+   * without writing it, and checks its root against the claimed one. When
+   * the claimed trie is stored, a mismatch reports its first divergent
+   * subtree.
+   */
+  public verifyStateRoot(
+    claimedRoot: Uint8Array,
+    dump: string | Uint8Array,
+  ): StateRootCheck {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(claimedRoot);
+    buffer.writeU8(typeof dump === "string" ? 0 : 1);
+    buffer.writeU8List(key2bytes(dump));
+    const reader = new OutputReader(this._callOutput(70, buffer.cursor)!);
+    const matches = reader.readU8() === 1;
+    const computedRoot = reader.readHash();
+    if (!reader.hasMore()) {
+      return { matches, computedRoot };
+    }
+    const divergence = {
+      path: reader.readBytes(),
+      expected: reader.readBytes(),
+      actual: reader.readBytes(),
+    };
+    return { matches, computedRoot, divergence };
+  }
+
+  /**
+   * Builds the trie of the payloads keyed by their index, as the transaction
+   * and receipt roots of an Ethereum block or the extrinsics root of a
+   * Substrate one, without touching the storage. The indices are encoded by
+   * the configured layout and never hashed, so the proofs verify with
+   * `verifyProof` while the keys aren't hashed.
+   */
+  public orderedTrie(payloads: Key[]): OrderedTrie {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    for (const payload of payloads) {
+      const payloadEncoded = key2bytes(payload);
+      buffer.writeU32(payloadEncoded.length, true);
+      buffer.writeU8List(payloadEncoded);
+    }
+    const reader = new OutputReader(this._callOutput(72, buffer.cursor)!);
+    const root = reader.readHash();
+    const count = reader.readU32();
+    const keys: Uint8Array[] = [];
+    const proofs: Uint8Array[][] = [];
+    for (let index = 0; index < count; index++) {
+      keys.push(reader.readBytes());
+      const proofReader = new OutputReader(reader.readBytes());
+      const proof: Uint8Array[] = [];
+      while (proofReader.hasMore()) {
+        proof.push(proofReader.readBytes());
+      }
+      proofs.push(proof);
+    }
+    return { root, keys, proofs };
+  }
+
+  /**
+   * XXH3 128-bit hash of `data`, a fast fingerprint of node payloads and
+   * snapshots, returned as 16 little-endian bytes.
+   */
+  public xxh3_128(data: Key, seed = 0n): Uint8Array {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU64(seed, true);
+    buffer.writeU8List(key2bytes(data));
+    return this._callOutput(73, buffer.cursor)!;
+  }
+
+  /**
+   * Starts syncing the trie at `root` from nodes fetched elsewhere, see
+   * `missingNodes` and `feedSync`. Returns the number of missing nodes, `0`
+   * when the trie is stored already and the sync completed.
+   */
+  public startSync(root: Uint8Array): number {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(root);
+    const missing = Number(this._call(55, buffer.cursor));
+    this._updateRoot();
+    return missing;
+  }
+
+  /** Hashes of up to `limit` nodes the running sync is waiting for. */
+  public missingNodes(limit = 256): Uint8Array[] {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(limit, true);
+    const output = this._callOutput(57, buffer.cursor)!;
+    const hashes: Uint8Array[] = [];
+    for (let offset = 0; offset < output.length; offset += 32) {
+      hashes.push(output.slice(offset, offset + 32));
+    }
+    return hashes;
+  }
+
+  /**
+   * Feeds nodes to the running sync, such as the ones listed by `nodesPage`
+   * on another instance. The trie moves to the synced root once nothing is
+   * missing anymore.
+   */
+  public feedSync(
+    nodes: [hash: Uint8Array, bytes: Uint8Array][],
+  ): SyncProgress {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    for (const [hash, bytes] of nodes) {
+      buffer.writeU8List(hash);
+      buffer.writeU32(bytes.length, true);
+      buffer.writeU8List(bytes);
+    }
+    const output = this._callOutput(56, buffer.cursor)!;
+    this._updateRoot();
+    const view = new DataView(output.buffer, output.byteOffset, 12);
+    return {
+      accepted: view.getUint32(0, true),
+      rejected: view.getUint32(4, true),
+      missing: view.getUint32(8, true),
+    };
+  }
+
+  /** Stops the running sync, dropping the nodes fed so far. */
+  public cancelSync(): void {
+    this.reset();
+    this._call(58, 0);
+  }
+
+  /**
+   * Enables the index from the Blake2 hash of the values to the keys holding
+   * them, building it from the current entries, or disables it. Every call
+   * changing the root keeps it up to date.
+   */
+  public setValueIndex(enabled: boolean): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8(enabled ? 1 : 0);
+    this._call(62, buffer.cursor);
+  }
+
+  /**
+   * Keys holding the value whose Blake2 hash is `valueHash`, as stored in the
+   * trie. Needs the value index, see `setValueIndex`.
+   */
+  public keysWithValueHash(valueHash: Uint8Array): Uint8Array[] {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(valueHash);
+    const reader = new OutputReader(
+      this._callOutput(63, buffer.cursor) ?? new Uint8Array(),
+    );
+    const keys: Uint8Array[] = [];
+    while (reader.hasMore()) {
+      keys.push(reader.readBytes());
+    }
+    return keys;
+  }
+
+  /**
+   * Heap usage of the last call. The memory isn't reset first, so it
+   * describes the call that ran before.
+   */
+  public memoryStats(): MemoryStats {
+    this.outputs = [];
+    HostFn.attach(this);
+    this.instance.__ext_memory_stats();
+    const view = new DataView(this.outputs[0].buffer);
+    return {
+      next: view.getUint32(0, true),
+      upperLimit: view.getUint32(4, true),
+      pagesGrown: view.getUint32(8, true),
+      peakUsage: view.getUint32(12, true),
+    };
+  }
+
+  /**
+   * Details of the failure of the last call, `undefined` when it succeeded.
+   * The memory isn't reset first, so the calls that trapped are described too.
+   */
+  public lastError(): ErrorDetail | undefined {
+    this.outputs = [];
+    HostFn.attach(this);
+    this.instance.__ext_last_error();
+    const output = this.outputs[0];
+    if (output === undefined || output.length === 0) {
+      return undefined;
+    }
+    const reader = new OutputReader(output);
+    const code = reader.readU8();
+    const subject = reader.readBytes();
+    return {
+      code,
+      subject,
+      message: decodeText(reader.readBytes()),
+      trieError: this.instance.__ext_trie_error_kind(),
+      hash: this.instance.__ext_trie_error_hash(),
+    };
+  }
+
+  /**
+   * Returns the heap allocations made since the last time the profile was
+   * read, then resets it. Requires a wasm module built with `alloc-profile`.
+   */
+  public allocationProfile(): AllocationProfile {
+    this.reset();
+    const memorySlice = this._callOutput(39, 0)!;
+    const view = new DataView(
+      memorySlice.buffer,
+      memorySlice.byteOffset,
+      memorySlice.byteLength,
+    );
+    const histogram = [];
+    for (let offset = 12; offset < memorySlice.byteLength; offset += 4) {
+      histogram.push(view.getUint32(offset, true));
+    }
+    return {
+      count: view.getUint32(0, true),
+      bytes: Number(view.getBigUint64(4, true)),
+      histogram,
+    };
+  }
+
+  /**
+   * Walks the trie and returns metrics of its stored nodes, each shared node
+   * counted once.
+   */
+  public stats(): TrieStats {
+    this.reset();
+    const memorySlice = this._callOutput(31, 0)!;
+    const view = new DataView(
+      memorySlice.buffer,
+      memorySlice.byteOffset,
+      memorySlice.byteLength,
+    );
+    const nodes = view.getUint32(0, true);
+    const bytes = Number(view.getBigUint64(4, true));
+    const counters = new Map<number, number>();
+    for (let offset = 28; offset < memorySlice.length; offset += 8) {
+      counters.set(
+        view.getInt32(offset, true),
+        view.getUint32(offset + 4, true),
+      );
+    }
+    return {
+      nodes,
+      bytes,
+      averageNodeSize: nodes === 0 ? 0 : bytes / nodes,
+      leaves: view.getUint32(12, true),
+      branches: view.getUint32(16, true),
+      extensions: view.getUint32(20, true),
+      values: view.getUint32(24, true),
+      counters,
+    };
+  }
+
+  /**
+   * Walks every position of the trie and returns its shape: the nodes per
+   * kind and level, the children per branch and the levels of the values.
+   */
+  public shape(): TrieShape {
+    this.reset();
+    const memorySlice = this._callOutput(43, 0)!;
+    const view = new DataView(
+      memorySlice.buffer,
+      memorySlice.byteOffset,
+      memorySlice.byteLength,
+    );
+    const counts = (offset: number, length: number) =>
+      Array.from({ length }, (_, i) => view.getUint32(offset + i * 4, true));
+    const [empty, leaf, extension, branch, nibbledBranch] = counts(0, 5);
+    const values = view.getUint32(152, true);
+    const valueDepths = Number(view.getBigUint64(156, true));
+    return {
+      kinds: { empty, leaf, extension, branch, nibbledBranch },
+      depths: counts(20, 16),
+      branching: counts(84, 17),
+      values,
+      averageValueDepth: values === 0 ? 0 : valueDepths / values,
+      maxValueDepth: view.getUint32(164, true),
+    };
+  }
+
+  /**
+   * Replaces the value hints of the current namespace, persisted for the
+   * following calls. The listings show the values whose key starts with the
+   * longest hinted prefix decoded in `decoded`, next to their bytes.
+   */
+  public setValueHints(hints: ValueHint[]): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    for (const hint of hints) {
+      const prefix = key2bytes(hint.prefix);
+      buffer.writeU8(VALUE_HINT_CODES[hint.kind]);
+      buffer.writeU32(prefix.length, true);
+      buffer.writeU8List(prefix);
+    }
+    this._call(45, buffer.cursor);
+  }
+
+  /**
+   * Sets the number of nodes the following calls keep cached after reading
+   * them from the storage, `0` disables the cache.
+   */
+  public setCacheCapacity(capacity: number): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(capacity, true);
+    this._call(29, buffer.cursor);
+  }
+
+  /**
+   * Configures the hasher, the node encoding and the key hashing of the
+   * storage, persisted for the following calls. Only switching between state
+   * versions `v0` and `v1` is allowed once the trie holds entries.
+   */
+  public configure(config: TrieConfig): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8(TRIE_HASHERS.indexOf(config.hasher));
+    buffer.writeU8(TRIE_LAYOUTS.indexOf(config.layout));
+    buffer.writeU8(config.secureKeys ? 1 : 0);
+    this._call(26, buffer.cursor);
+  }
+
+  public config(): TrieConfig {
+    this.reset();
+    const packed = Number(this._call(27, 0));
+    return {
+      hasher: TRIE_HASHERS[packed & 0xff],
+      layout: TRIE_LAYOUTS[(packed >> 8) & 0xff],
+      secureKeys: ((packed >> 16) & 0xff) === 1,
+    };
+  }
+
+  /**
+   * Switches to the Ethereum node encoding (RLP nodes hashed with Keccak-256,
+   * same roots as geth) or back, only allowed while the trie is empty.
+   */
+  public setEthereumMode(enabled: boolean): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8(enabled ? 1 : 0);
+    this._call(25, buffer.cursor);
+  }
+
+  /**
+   * Lists the keys added, removed or modified between two roots.
+   */
+  public diff(oldRoot: Uint8Array, newRoot: Uint8Array): TrieChange[] {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(oldRoot);
+    buffer.writeU8List(newRoot);
+    const memorySlice = this._callOutput(12, buffer.cursor);
+    const changes: TrieChange[] = [];
+    if (memorySlice === undefined) {
+      return changes;
+    }
+    const reader = new OutputReader(memorySlice);
+    while (reader.hasMore()) {
+      const kind = reader.readU8();
+      const key = reader.readBytes();
+      if (kind === 0) {
+        changes.push({ kind: "added", key, newValue: reader.readBytes() });
+      } else if (kind === 1) {
+        changes.push({ kind: "removed", key, oldValue: reader.readBytes() });
+      } else {
+        const oldValue = reader.readBytes();
+        const newValue = reader.readBytes();
+        changes.push({ kind: "modified", key, oldValue, newValue });
+      }
+    }
+    return changes;
+  }
+
+  /**
+   * Imports every entry reachable from `theirs` into the current trie, keys
+   * present in both tries are resolved by the conflict `policy`.
+   * Returns the number of keys written.
+   */
+  public merge(
+    theirs: Uint8Array,
+    policy: "keep-ours" | "take-theirs" = "keep-ours",
+  ): number {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8(policy === "take-theirs" ? 1 : 0);
+    buffer.writeU8List(theirs);
+    const count = this._call(13, buffer.cursor);
+    this._updateRoot();
+    return Number(count);
+  }
+
+  /**
+   * Generates a proof of inclusion, or non-inclusion, of the keys. Proofs
+   * can be generated for any `root` whose nodes are still in the storage,
+   * defaults to the current root.
+   */
+  public prove(keys: Key[], root?: Uint8Array): Uint8Array[] {
+    const at = root ?? this.root();
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(at);
+    for (const key of keys) {
+      const keyEncoded = key2bytes(key);
+      buffer.writeU32(keyEncoded.length, true);
+      buffer.writeU8List(keyEncoded);
+    }
+    const memorySlice = this._callOutput(14, buffer.cursor);
+    const nodes: Uint8Array[] = [];
+    if (memorySlice === undefined) {
+      return nodes;
+    }
+    const reader = new OutputReader(memorySlice);
+    while (reader.hasMore()) {
+      nodes.push(reader.readBytes());
+    }
+    return nodes;
+  }
+
+  /**
+   * Verifies a proof against `root` without touching the storage, the items
+   * must cover every key the proof was generated for.
+   */
+  public verifyProof(
+    root: Uint8Array,
+    items: ProofItem[],
+    proof: Uint8Array[],
+  ): boolean {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    WasmContext._writeProofItems(buffer, root, items);
+    for (const node of proof) {
+      buffer.writeU32(node.length, true);
+      buffer.writeU8List(node);
+    }
+    return this._call(15, buffer.cursor) == 1n;
+  }
+
+  /**
+   * Generates a single compact proof covering all keys, along with the
+   * value of each key, at `root` or the current root.
+   */
+  public proveCompact(
+    keys: Key[],
+    root?: Uint8Array,
+  ): { proof: Uint8Array; values: (Uint8Array | undefined)[] } {
+    const at = root ?? this.root();
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(at);
+    for (const key of keys) {
+      const keyEncoded = key2bytes(key);
+      buffer.writeU32(keyEncoded.length, true);
+      buffer.writeU8List(keyEncoded);
+    }
+    const memorySlice = this._callOutput(16, buffer.cursor)!;
+    const reader = new OutputReader(memorySlice);
+    const proof = reader.readBytes();
+    const values = keys.map(() =>
+      reader.readU8() === 1 ? reader.readBytes() : undefined
+    );
+    return { proof, values };
+  }
+
+  /**
+   * Verifies a compact proof generated by `proveCompact`.
+   */
+  public verifyCompactProof(
+    root: Uint8Array,
+    items: ProofItem[],
+    proof: Uint8Array,
+  ): boolean {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    WasmContext._writeProofItems(buffer, root, items);
+    buffer.writeU8List(proof);
+    return this._call(17, buffer.cursor) == 1n;
+  }
+
+  /**
+   * Converts a full storage proof, the set of encoded nodes, to a SCALE
+   * encoded compact proof.
+   */
+  public toCompactProof(
+    root: Uint8Array,
+    nodes: Uint8Array[],
+  ): ProofSizes & { proof: Uint8Array } {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(root);
+    for (const node of nodes) {
+      buffer.writeU32(node.length, true);
+      buffer.writeU8List(node);
+    }
+    const memorySlice = this._callOutput(19, buffer.cursor)!;
+    const view = new DataView(memorySlice.buffer, memorySlice.byteOffset, 8);
+    return {
+      fullSize: view.getUint32(0, true),
+      compactSize: view.getUint32(4, true),
+      proof: memorySlice.slice(8),
+    };
+  }
+
+  /**
+   * Expands a SCALE encoded compact proof back to the full storage proof,
+   * recovering the root it was generated for.
+   */
+  public fromCompactProof(
+    proof: Uint8Array,
+  ): ProofSizes & { root: Uint8Array; nodes: Uint8Array[] } {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(proof);
+    const memorySlice = this._callOutput(20, buffer.cursor)!;
+    const view = new DataView(memorySlice.buffer, memorySlice.byteOffset, 40);
+    const reader = new OutputReader(memorySlice);
+    reader.offset = 40;
+    const nodes: Uint8Array[] = [];
+    while (reader.hasMore()) {
+      nodes.push(reader.readBytes());
+    }
+    return {
+      root: memorySlice.slice(0, 32),
+      fullSize: view.getUint32(32, true),
+      compactSize: view.getUint32(36, true),
+      nodes,
+    };
+  }
+
+  /**
+   * Generates a proof of the entries following `start` in key order, at
+   * `root` or the current root. The proof stops growing once it exceeds
+   * `budget` bytes, the next proof should start at the last key proven.
+   */
+  public proveRange(start: Key, budget: number, root?: Uint8Array): RangeProof {
+    const at = root ?? this.root();
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(at);
+    const startEncoded = key2bytes(start);
+    buffer.writeU32(startEncoded.length, true);
+    buffer.writeU8List(startEncoded);
+    buffer.writeU32(budget, true);
+    const memorySlice = this._callOutput(21, buffer.cursor)!;
+    const view = new DataView(memorySlice.buffer, memorySlice.byteOffset, 5);
+    const reader = new OutputReader(memorySlice);
+    reader.offset = 5;
+    const nodes: Uint8Array[] = [];
+    while (reader.hasMore()) {
+      nodes.push(reader.readBytes());
+    }
+    return {
+      complete: view.getUint8(0) === 1,
+      count: view.getUint32(1, true),
+      nodes,
+    };
+  }
+
+  /**
+   * Verifies a range proof against `root` without touching the storage,
+   * returns the proven entries or `undefined` if the proof is invalid.
+   */
+  public verifyRangeProof(
+    root: Uint8Array,
+    start: Key,
+    proof: Uint8Array[],
+  ): VerifiedRange | undefined {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(root);
+    const startEncoded = key2bytes(start);
+    buffer.writeU32(startEncoded.length, true);
+    buffer.writeU8List(startEncoded);
+    for (const node of proof) {
+      buffer.writeU32(node.length, true);
+      buffer.writeU8List(node);
+    }
+    const memorySlice = this._callOutput(22, buffer.cursor)!;
+    const reader = new OutputReader(memorySlice);
+    const status = reader.readU8();
+    if (status === 0) {
+      return undefined;
+    }
+    const entries: [Uint8Array, Uint8Array][] = [];
+    while (reader.hasMore()) {
+      entries.push([reader.readBytes(), reader.readBytes()]);
+    }
+    return { complete: status === 2, entries };
+  }
+
+  private static _writeProofItems(
+    buffer: ByteBuffer,
+    root: Uint8Array,
+    items: ProofItem[],
+  ): void {
+    buffer.writeU8List(root);
+    buffer.writeU32(items.length, true);
+    for (const [key, value] of items) {
+      const keyEncoded = key2bytes(key);
+      buffer.writeU32(keyEncoded.length, true);
+      buffer.writeU8List(keyEncoded);
+      if (value === undefined) {
+        buffer.writeU8(0);
+      } else {
+        const valueEncoded = key2bytes(value);
+        buffer.writeU8(1);
+        buffer.writeU32(valueEncoded.length, true);
+        buffer.writeU8List(valueEncoded);
+      }
+    }
+  }
+
+  private _callWithKey(code: number, key: Key): bigint {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(key));
+    return this._call(code, buffer.cursor);
+  }
+
+  public contains(key: Key): boolean {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(key));
+    return this._call(2, buffer.cursor) == 1n;
+  }
+
+  public get(key: Key): Uint8Array | undefined {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(key));
+    return this._callOutput(3, buffer.cursor);
+  }
+
+  /**
+   * Removes `key` and returns its value.
+   */
+  public take(key: Key): Uint8Array | undefined {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(key));
+    const value = this._callOutput(37, buffer.cursor);
+    this._updateRoot();
+    return value;
+  }
+
+  /**
+   * Byte length of the value of `key`, without copying the value out of the
+   * wasm module.
+   */
+  public valueSize(key: Key): number | undefined {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(key));
+    const output = this._callOutput(36, buffer.cursor);
+    if (output === undefined) {
+      return undefined;
+    }
+    return new DataView(output.buffer, output.byteOffset, 4).getUint32(0, true);
+  }
+
+  /**
+   * Inserts into the child trie `storageKey`, created by its first insert.
+   * Its root is stored in the parent trie under
+   * `:child_storage:default:` followed by `storageKey`.
+   */
+  public childInsert(storageKey: Key, key: Key, value: Key): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    for (const bytes of [storageKey, key, value].map(key2bytes)) {
+      buffer.writeU32(bytes.length, true);
+      buffer.writeU8List(bytes);
+    }
+    this._call(32, buffer.cursor);
+    this._updateRoot();
+  }
+
+  public childGet(storageKey: Key, key: Key): Uint8Array | undefined {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    const storageKeyEncoded = key2bytes(storageKey);
+    buffer.writeU32(storageKeyEncoded.length, true);
+    buffer.writeU8List(storageKeyEncoded);
+    buffer.writeU8List(key2bytes(key));
+    const memorySlice = this._callOutput(33, buffer.cursor);
+    return memorySlice?.slice();
+  }
+
+  /**
+   * Removes from the child trie `storageKey`, the child trie is removed from
+   * the parent trie once empty.
+   */
+  public childRemove(storageKey: Key, key: Key): void {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    const storageKeyEncoded = key2bytes(storageKey);
+    buffer.writeU32(storageKeyEncoded.length, true);
+    buffer.writeU8List(storageKeyEncoded);
+    buffer.writeU8List(key2bytes(key));
+    this._call(34, buffer.cursor);
+    this._updateRoot();
+  }
+
+  /**
+   * Root of the child trie `storageKey`, the empty root when it doesn't exist.
+   */
+  public childRoot(storageKey: Key): Uint8Array {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(storageKey));
+    return this._callOutput(35, buffer.cursor)!.slice();
+  }
+
+  /**
+   * Lists the nodes of the child trie `storageKey` like `values`.
+   */
+  public childValues<O extends ListOptions = Record<never, never>>(
+    storageKey: Key,
+    options?: O,
+  ): ListedTrie<O> {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(storageKey));
+    this.inputLen = this._writeHeader(buffer.cursor);
+    HostFn.attach(this);
+    const trie = this._invoke(() =>
+      this.instance.__ext_list_child_nodes(this.inputLen, formatFlags(options))
+    );
+    this.inputLen = 0;
+    return trie as ListedTrie<O>;
+  }
+
+  /**
+   * Calls `visit` with each node of the trie, parents before their children,
+   * instead of building the whole tree like `values`. Returns the number of
+   * nodes, an exception thrown by `visit` stops the walk.
+   */
+  public streamNodes<O extends ListOptions = Record<never, never>>(
+    visit: (
+      node: StreamedNode<O extends { hex: true } ? string : Uint8Array>,
+    ) => void,
+    options?: O,
+  ): number {
+    this.reset();
+    this.inputLen = this._writeHeader(0);
+    HostFn.attach(this);
+    const count = this._invoke(() =>
+      this.instance.__ext_stream_nodes(
+        this.inputLen,
+        formatFlags(options),
+        (
+          index: number,
+          parent: number | null,
+          nibble: number | null,
+          id?: never,
+          nibbles?: never,
+          value?: never,
+        ) => visit({ index, parent, nibble, id, nibbles, value }),
+      )
+    );
+    this.inputLen = 0;
+    return count;
+  }
+
+  /**
+   * Encodes the nodes of the trie as a JSON tree built by the wasm module,
+   * each node with its `id`, `nibbles`, `value`, `rawBytes` as hex strings or
+   * `null`, `highlighted`, `children` as `{ nibble, node }` and `extension`.
+   */
+  public valuesJson(): string {
+    this.reset();
+    return decodeText(this._callOutput(40, 0)!);
+  }
+
+  /**
+   * Encodes the nodes of the trie as a CBOR document built by the wasm module,
+   * with the fields of `valuesJson` and the bytes as CBOR byte strings, for
+   * hosts decoding the tree without the TS classes.
+   */
+  public valuesCbor(): Uint8Array {
+    this.reset();
+    return this._callOutput(44, 0)!;
+  }
+
+  /**
+   * Lists up to `limit` nodes of the trie breadth-first, from the root or
+   * from the `token` returned with the previous page. A node shared by several
+   * parents can be listed again on a following page.
+   */
+  public nodesPage(limit: number = 256, token?: Uint8Array): NodePage {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU32(limit, true);
+    if (token) buffer.writeU8List(token);
+    const output = this._callOutput(42, buffer.cursor)!;
+    const view = new DataView(
+      output.buffer,
+      output.byteOffset,
+      output.byteLength,
+    );
+    const nodes = [];
+    let offset = 4;
+    for (let count = view.getUint32(0, true); count > 0; count--) {
+      const id = output.slice(offset, offset + 32);
+      const len = view.getUint32(offset + 32, true);
+      offset += 36;
+      nodes.push({ id, encoded: output.slice(offset, offset + len) });
+      offset += len;
+    }
+    const tokenLen = view.getUint32(offset, true);
+    offset += 4;
+    return {
+      nodes,
+      next: tokenLen > 0 ? output.slice(offset, offset + tokenLen) : undefined,
+    };
+  }
+
+  /**
+   * Renders the nodes of the trie as a Graphviz DOT document, labelled with
+   * the first bytes of their hash and their partial key.
+   */
+  public valuesDot(): string {
+    this.reset();
+    return decodeText(this._callOutput(41, 0)!);
+  }
+
+  /**
+   * Lists the nodes of the trie, their bytes are `Uint8Array`s unless the
+   * `hex` option is set.
+   */
+  public values<O extends CollapseOptions = Record<never, never>>(
+    options?: O,
+  ): ListedTrie<O> {
+    this.reset();
+    this.inputLen = this._writeHeader(0);
+    HostFn.attach(this);
+    const trie = this._invoke(() =>
+      this.instance.__ext_list_nodes(
+        this.inputLen,
+        formatFlags(options),
+        options?.maxDepth ?? UNLIMITED_DEPTH,
+        options?.collapse ?? false,
+      )
+    );
+    this.inputLen = 0;
+    return trie as ListedTrie<O>;
+  }
+
+  /**
+   * Lists the nodes below the node `hash`, like `values`. Expands a node
+   * `truncated` by the depth limit of a previous listing.
+   */
+  public expandNode<O extends DepthOptions = Record<never, never>>(
+    hash: Uint8Array,
+    options?: O,
+  ): ListedTrie<O> {
+    if (hash.length !== 32) {
+      throw new Error(`invalid node hash length ${hash.length}`);
+    }
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(hash);
+    this.inputLen = this._writeHeader(buffer.cursor);
+    HostFn.attach(this);
+    const trie = this._invoke(() =>
+      this.instance.__ext_expand_node(
+        this.inputLen,
+        formatFlags(options),
+        options?.maxDepth ?? UNLIMITED_DEPTH,
+      )
+    );
+    this.inputLen = 0;
+    return trie as ListedTrie<O>;
+  }
+
+  /**
+   * Lists the nodes of the tries at `oldRoot` and `newRoot` merged in one
+   * tree, each node with the `status` of its change. Subtrees shared by both
+   * roots are `unchanged` and `truncated`, see `expandNode`.
+   */
+  public diffNodes<O extends ListOptions = Record<never, never>>(
+    oldRoot: Uint8Array,
+    newRoot: Uint8Array,
+    options?: O,
+  ): ListedTrie<O> {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(oldRoot);
+    buffer.writeU8List(newRoot);
+    this.inputLen = this._writeHeader(buffer.cursor);
+    HostFn.attach(this);
+    const trie = this._invoke(() =>
+      this.instance.__ext_list_diff_nodes(this.inputLen, formatFlags(options))
+    );
+    this.inputLen = 0;
+    return trie as ListedTrie<O>;
+  }
+
+  /**
+   * Lists the nodes like `values`, with the `matchedNibbles` of the nodes on
+   * the route of a lookup of `key`, down to the node holding its value or the
+   * node where the key diverges when it is missing.
+   */
+  public lookupPath<O extends ListOptions = Record<never, never>>(
+    key: Key,
+    options?: O,
+  ): ListedTrie<O> {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    buffer.writeU8List(key2bytes(key));
+    this.inputLen = this._writeHeader(buffer.cursor);
+    HostFn.attach(this);
+    const trie = this._invoke(() =>
+      this.instance.__ext_list_lookup_nodes(this.inputLen, formatFlags(options))
+    );
+    this.inputLen = 0;
+    return trie as ListedTrie<O>;
+  }
+
+  /**
+   * Lists the nodes like `values`, with the nodes touched by a proof of
+   * `keys` flagged as `highlighted`.
+   */
+  public proofPath<O extends ListOptions = Record<never, never>>(
+    keys: Key[],
+    options?: O,
+  ): ListedTrie<O> {
+    this.reset();
+    const buffer = WasmContext.SHARED_INPUT_BUFFER;
+    buffer.cursor = 0;
+    for (const key of keys) {
+      const keyEncoded = key2bytes(key);
+      buffer.writeU32(keyEncoded.length, true);
+      buffer.writeU8List(keyEncoded);
+    }
+    this.inputLen = this._writeHeader(buffer.cursor);
+    HostFn.attach(this);
+    const trie = this._invoke(() =>
+      this.instance.__ext_list_proof_nodes(this.inputLen, formatFlags(options))
+    );
+    this.inputLen = 0;
+    return trie as ListedTrie<O>;
+  }
+
+  public getStorage(): TrieStorage {
+    return this.storage;
+  }
+
+  private reset() {
+    // A persistent arena is only discarded when a call trapped midway.
+    if (!this.persistentArena || this.trapped) {
+      const memory = this.getMemory();
+      memory.set(this.initialState);
+      if (memory.length > this.initialState.length) {
+        memory.fill(0, this.initialState.length, memory.length);
+      }
+      this.trapped = false;
+      if (this.persistentArena) {
+        this.instance.__ext_set_persistent(1);
+      }
+    }
+    // The limit lives in the wasm memory, restoring it clears the limit.
+    this.instance.__ext_set_heap_limit(this.heapLimit ?? 0xffffffff);
+    // So does the log filter, which older modules don't have.
+    if (this.abiVersion >= 7) {
+      this.instance.__ext_set_log_filter(...this.logFilter);
     }
     WasmContext.SHARED_INPUT_BUFFER.cursor = 0;
   }
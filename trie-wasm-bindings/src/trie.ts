@@ -1,31 +1,120 @@
 import { type Nib, TrieChildren } from "./nibbles.ts";
 
+/** Kind of a listed node. */
+export type NodeKind =
+  | "empty"
+  | "leaf"
+  | "extension"
+  | "branch"
+  | "nibbledBranch";
+
+/** Change of a node between two roots, see `WasmContext.diffNodes`. */
+export type NodeStatus = "unchanged" | "added" | "removed" | "modified";
+
 export interface MerklePatriciaTrieNode {
   id: string | null;
   depth: number;
   nibbles: string | null;
   value: string | null;
+  decoded: string | null;
   encoded: string | null;
+  kind: NodeKind;
+  inline: boolean;
+  encodedSize: number;
+  references: number;
+  highlighted: boolean;
+  truncated: boolean;
+  status: NodeStatus | null;
+  collapsed: boolean;
+  onPath: boolean;
+  matchedNibbles: number | null;
   children: { [key: Nib]: MerklePatriciaTrieNode };
+  /** Child of an extension node, which isn't labeled by a nibble. */
+  extension: MerklePatriciaTrieNode | null;
+}
+
+/**
+ * Bytes of a node as listed by the wasm module: a `0x` prefixed hex string,
+ * or a `Uint8Array` holding the bytes, one nibble per byte for `nibbles`.
+ */
+export type NodeBytes = string | Uint8Array;
+
+const HEX_DIGITS = "0123456789ABCDEF";
+
+/** Hex string of listed bytes, `nibbles` holds one digit per byte. */
+function toHex(bytes?: NodeBytes, nibbles: boolean = false): string | null {
+  if (bytes === undefined) return null;
+  if (typeof bytes === "string") return bytes;
+  let hex = "0x";
+  for (const byte of bytes) {
+    if (!nibbles) hex += HEX_DIGITS[byte >> 4];
+    hex += HEX_DIGITS[byte & 15];
+  }
+  return hex;
 }
 
-export class JSMerklePatriciaTrie {
-  readonly id?: string;
+export class JSMerklePatriciaTrie<B extends NodeBytes = NodeBytes> {
+  readonly id?: B;
   readonly depth: number;
-  readonly parent?: WeakRef<JSMerklePatriciaTrie>;
-  readonly nibbles?: string;
-  readonly value?: string;
-  readonly raw_bytes?: string;
-  readonly children: TrieChildren<JSMerklePatriciaTrie>;
+  readonly parent?: WeakRef<JSMerklePatriciaTrie<B>>;
+  readonly nibbles?: B;
+  readonly value?: B;
+  /** Value decoded by the hint of its key, see `WasmContext.setValueHints`. */
+  readonly decoded?: string;
+  readonly raw_bytes?: B;
+  /** Whether the node is on the path of the proof being visualized. */
+  readonly highlighted: boolean;
+  /**
+   * Whether children were left out by the depth limit of the listing, see
+   * `WasmContext.expandNode`.
+   */
+  readonly truncated: boolean;
+  readonly kind: NodeKind;
+  /** Whether the node is embedded in its parent instead of stored by hash. */
+  readonly inline: boolean;
+  /** Length of the encoded node in bytes. */
+  readonly encodedSize: number;
+  /**
+   * Reference counter of the stored node, above `1` when the node is shared
+   * by several parents or kept by a checkpoint. `0` for the inline nodes.
+   */
+  readonly references: number;
+  /** Change of the node, only set when listing the difference of two roots. */
+  readonly status?: NodeStatus;
+  /**
+   * Key nibbles matched up to the end of the partial key of the node, only set
+   * on the route of a lookup, see `WasmContext.lookupPath`.
+   */
+  readonly matchedNibbles?: number;
+  /**
+   * Whether single-child nodes above were merged into this one, their nibbles
+   * preceding its own in `nibbles`.
+   */
+  readonly collapsed: boolean;
+  /** Whether the node is on the route of a lookup. */
+  readonly onPath: boolean;
+  readonly children: TrieChildren<JSMerklePatriciaTrie<B>>;
+  /** Child of an extension node, which isn't labeled by a nibble. */
+  extension?: JSMerklePatriciaTrie<B>;
 
   constructor(
-    children: TrieChildren<JSMerklePatriciaTrie>,
+    children: TrieChildren<JSMerklePatriciaTrie<B>>,
     depth: number,
-    id?: string,
-    nibbles?: string,
-    value?: string,
-    raw_bytes?: string,
-    parent?: WeakRef<JSMerklePatriciaTrie>,
+    id?: B,
+    nibbles?: B,
+    value?: B,
+    raw_bytes?: B,
+    parent?: WeakRef<JSMerklePatriciaTrie<B>>,
+    highlighted: boolean = false,
+    truncated: boolean = false,
+    kind: NodeKind = "empty",
+    inline: boolean = false,
+    encodedSize: number = 0,
+    status?: NodeStatus,
+    matchedNibbles?: number,
+    collapsed: boolean = false,
+    references: number = 0,
+    decoded?: string,
   ) {
     this.id = id;
     this.depth = depth;
@@ -33,34 +122,80 @@ export class JSMerklePatriciaTrie {
     this.nibbles = nibbles;
     this.value = value;
     this.raw_bytes = raw_bytes;
+    this.highlighted = highlighted;
+    this.truncated = truncated;
+    this.kind = kind;
+    this.inline = inline;
+    this.encodedSize = encodedSize;
+    this.status = status;
+    this.matchedNibbles = matchedNibbles;
+    this.onPath = matchedNibbles !== undefined;
+    this.collapsed = collapsed;
+    this.references = references;
+    this.decoded = decoded;
     this.children = children;
   }
 
+  /** Plain object of the node, with its bytes as hex strings. */
   public toJSON(): MerklePatriciaTrieNode {
     return {
-      id: this.id ?? null,
+      id: toHex(this.id),
       depth: this.depth,
-      nibbles: this.nibbles ?? null,
-      value: this.value ?? null,
-      encoded: this.raw_bytes ?? null,
+      nibbles: toHex(this.nibbles, true),
+      value: toHex(this.value),
+      decoded: this.decoded ?? null,
+      encoded: toHex(this.raw_bytes),
+      kind: this.kind,
+      inline: this.inline,
+      encodedSize: this.encodedSize,
+      references: this.references,
+      highlighted: this.highlighted,
+      truncated: this.truncated,
+      status: this.status ?? null,
+      collapsed: this.collapsed,
+      onPath: this.onPath,
+      matchedNibbles: this.matchedNibbles ?? null,
       children: this.children.toObject(([, trie]) => trie.toJSON()),
+      extension: this.extension?.toJSON() ?? null,
     };
   }
 }
 
 export class JSTrieBuilder {
-  public id?: string;
-  public nibbles?: string;
-  public value?: string;
-  public raw_bytes?: string;
+  public id?: NodeBytes;
+  public nibbles?: NodeBytes;
+  public value?: NodeBytes;
+  public raw_bytes?: NodeBytes;
+  public highlighted: boolean;
+  public truncated: boolean;
+  public kind: NodeKind;
+  public inline: boolean;
+  public encoded_size: number;
+  public status?: NodeStatus;
+  public matched_nibbles?: number;
+  public collapsed: boolean;
+  public references: number;
+  public decoded?: string;
   public children: TrieChildren<JSTrieBuilder>;
+  public extension?: JSTrieBuilder;
 
   constructor() {
     this.id = undefined;
     this.nibbles = undefined;
     this.value = undefined;
     this.raw_bytes = undefined;
+    this.highlighted = false;
+    this.truncated = false;
+    this.kind = "empty";
+    this.inline = false;
+    this.encoded_size = 0;
+    this.status = undefined;
+    this.matched_nibbles = undefined;
+    this.collapsed = false;
+    this.references = 0;
+    this.decoded = undefined;
     this.children = new TrieChildren();
+    this.extension = undefined;
   }
 
   public push_child(nib: number, child: JSTrieBuilder) {
@@ -70,6 +205,13 @@ export class JSTrieBuilder {
     this.children.set(nib, child);
   }
 
+  public set_extension(child: JSTrieBuilder) {
+    if (!(child instanceof JSTrieBuilder)) {
+      throw new Error("child must be of type MerklePatriciaTrieBuilder");
+    }
+    this.extension = child;
+  }
+
   private _build(
     depth: number,
     parent?: WeakRef<JSMerklePatriciaTrie>,
@@ -83,11 +225,22 @@ export class JSTrieBuilder {
       this.value,
       this.raw_bytes,
       parent,
+      this.highlighted,
+      this.truncated,
+      this.kind,
+      this.inline,
+      this.encoded_size,
+      this.status,
+      this.matched_nibbles,
+      this.collapsed,
+      this.references,
+      this.decoded,
     );
     this.children.forEach(([nib, child]) => {
       const n = child._build(depth + 1, new WeakRef(root));
       children.set(nib, n);
     });
+    root.extension = this.extension?._build(depth + 1, new WeakRef(root));
     return root;
   }
 